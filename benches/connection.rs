@@ -0,0 +1,116 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BatchSize;
+use criterion::Criterion;
+
+use quiche::testing;
+
+const STREAM_ID: u64 = 4;
+
+const TRANSFER_LEN: usize = 1_000_000;
+
+fn config() -> quiche::Config {
+    let mut config = testing::default_config().unwrap();
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(10_000_000);
+    config.set_initial_max_stream_data_bidi_remote(10_000_000);
+
+    config
+}
+
+// Writes stream data into packets, until the congestion window is full.
+fn packetization(c: &mut Criterion) {
+    let mut config = config();
+    let data = vec![0xba; TRANSFER_LEN];
+
+    c.bench_function("packetization", |b| b.iter_batched(|| {
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.handshake().unwrap();
+
+        pipe.client.stream_send(STREAM_ID, &data, true).unwrap();
+
+        pipe
+    }, |mut pipe| {
+        let mut buf = [0; 1350];
+
+        while pipe.client.send(&mut buf).is_ok() {}
+
+        pipe
+    }, BatchSize::LargeInput));
+}
+
+// Sends data on a stream that the server writes back on the same stream.
+fn echo(c: &mut Criterion) {
+    let mut config = config();
+    let data = vec![0xba; TRANSFER_LEN];
+
+    c.bench_function("echo", |b| b.iter(|| {
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.handshake().unwrap();
+
+        let mut sent = 0;
+        let mut echo = Vec::new();
+        let mut echoed = 0;
+        let mut recv = 0;
+
+        while recv < data.len() {
+            if sent < data.len() {
+                sent += pipe.client
+                            .stream_send(STREAM_ID, &data[sent..], true)
+                            .unwrap_or(0);
+            }
+
+            pipe.advance().unwrap();
+
+            while let Ok(buf) = pipe.server.stream_recv(STREAM_ID,
+                                                        std::usize::MAX) {
+                echo.extend_from_slice(&buf);
+            }
+
+            if echoed < echo.len() {
+                let fin = echo.len() == data.len();
+
+                echoed += pipe.server
+                              .stream_send(STREAM_ID, &echo[echoed..], fin)
+                              .unwrap_or(0);
+            }
+
+            pipe.advance().unwrap();
+
+            while let Ok(buf) = pipe.client.stream_recv(STREAM_ID,
+                                                        std::usize::MAX) {
+                recv += buf.len();
+            }
+        }
+    }));
+}
+
+criterion_group!(benches, packetization, echo);
+criterion_main!(benches);
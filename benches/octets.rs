@@ -0,0 +1,73 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use quiche::octets;
+
+// One value for each of the varint encoded lengths.
+const VALUES: [u64; 4] = [37, 15_293, 494_878_333, 151_288_809_941_952_652];
+
+fn varint_encode(c: &mut Criterion) {
+    let mut buf = [0; 32];
+
+    c.bench_function("varint encode", |b| b.iter(|| {
+        let mut o = octets::OctetsMut::with_slice(&mut buf);
+
+        for v in VALUES.iter() {
+            o.put_varint(black_box(*v)).unwrap();
+        }
+    }));
+}
+
+fn varint_decode(c: &mut Criterion) {
+    let mut buf = [0; 32];
+
+    let len = {
+        let mut o = octets::OctetsMut::with_slice(&mut buf);
+
+        for v in VALUES.iter() {
+            o.put_varint(*v).unwrap();
+        }
+
+        o.off()
+    };
+
+    c.bench_function("varint decode", |b| b.iter(|| {
+        let mut o = octets::Octets::with_slice(black_box(&buf[..len]));
+
+        for _ in VALUES.iter() {
+            o.get_varint().unwrap();
+        }
+    }));
+}
+
+criterion_group!(benches, varint_encode, varint_decode);
+criterion_main!(benches);
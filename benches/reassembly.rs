@@ -0,0 +1,86 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::time;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use quiche::testing;
+
+const STREAM_ID: u64 = 4;
+
+const TRANSFER_LEN: usize = 1_000_000;
+
+// Sends `TRANSFER_LEN` bytes on a single stream over a network dropping
+// `loss` of the datagrams, so that the receiver has to reassemble the
+// stream out of order.
+fn transfer(loss: f64) {
+    let mut config = testing::default_config().unwrap();
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(10_000_000);
+    config.set_initial_max_stream_data_bidi_remote(10_000_000);
+
+    let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+    pipe.handshake().unwrap();
+
+    let model = testing::NetworkModel {
+        loss,
+        ..Default::default()
+    };
+
+    let data = vec![0xba; TRANSFER_LEN];
+
+    let mut sent = 0;
+    let mut recv = 0;
+
+    let done = pipe.simulate(&model, time::Duration::from_secs(60), |pipe| {
+        if sent < data.len() {
+            sent += pipe.client.stream_send(STREAM_ID, &data[sent..], true)
+                               .unwrap_or(0);
+        }
+
+        while let Ok(buf) = pipe.server.stream_recv(STREAM_ID,
+                                                    std::usize::MAX) {
+            recv += buf.len();
+        }
+
+        recv == data.len()
+    }).unwrap();
+
+    assert!(done);
+}
+
+fn reassembly(c: &mut Criterion) {
+    c.bench_function("reassembly no loss", |b| b.iter(|| transfer(0.0)));
+
+    c.bench_function("reassembly 1% loss", |b| b.iter(|| transfer(0.01)));
+}
+
+criterion_group!(benches, reassembly);
+criterion_main!(benches);
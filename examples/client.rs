@@ -28,6 +28,10 @@
 #[macro_use]
 extern crate log;
 
+use std::sync;
+
+use std::collections::HashMap;
+
 use ring::rand::*;
 
 const LOCAL_CONN_ID_LEN: usize = 16;
@@ -36,6 +40,9 @@ const MAX_DATAGRAM_SIZE: usize = 1452;
 
 const HTTP_REQ_STREAM_ID: u64 = 4;
 
+// Exit code the QUIC Interop Runner expects for unsupported test cases.
+const UNSUPPORTED_TESTCASE: i32 = 127;
+
 const USAGE: &str = "Usage:
   client [options] URL
   client -h | --help
@@ -44,13 +51,27 @@ Options:
   --http1                 Send HTTP/1.1 request instead of HTTP/0.9.
   --wire-version VERSION  The version number to send to the server [default: babababa].
   --no-verify             Don't verify server's certificate.
+  --testcase NAME         Interop Runner test case to run (defaults to $TESTCASE).
   -h --help               Show this screen.
 ";
 
-fn main() {
-    let mut buf = [0; 65535];
-    let mut out = [0; MAX_DATAGRAM_SIZE];
+// Keeps session tickets in memory, so they can be used by the following
+// connections made by the same process.
+#[derive(Default)]
+struct MemorySessionStore(sync::Mutex<HashMap<String, Vec<u8>>>);
+
+impl quiche::SessionStore for MemorySessionStore {
+    fn store(&self, server_name: &str, session: &[u8]) {
+        self.0.lock().unwrap().insert(server_name.to_string(),
+                                      session.to_vec());
+    }
+
+    fn load(&self, server_name: &str) -> Option<Vec<u8>> {
+        self.0.lock().unwrap().get(server_name).cloned()
+    }
+}
 
+fn main() {
     env_logger::init();
 
     let args = docopt::Docopt::new(USAGE)
@@ -59,20 +80,6 @@ fn main() {
 
     let url = url::Url::parse(args.get_str("URL")).unwrap();
 
-    let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
-    socket.connect(&url).unwrap();
-
-    let poll = mio::Poll::new().unwrap();
-    let mut events = mio::Events::with_capacity(1024);
-
-    let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
-    poll.register(&socket, mio::Token(0),
-                  mio::Ready::readable(),
-                  mio::PollOpt::edge()).unwrap();
-
-    let mut scid: [u8; LOCAL_CONN_ID_LEN] = [0; LOCAL_CONN_ID_LEN];
-    SystemRandom::new().fill(&mut scid[..]).unwrap();
-
     let version = args.get_str("--wire-version");
     let version = u32::from_str_radix(version, 16).unwrap();
 
@@ -98,7 +105,67 @@ fn main() {
         config.log_keys();
     }
 
-    let mut conn = quiche::connect(url.domain(), &scid, &mut config).unwrap();
+    let testcase = match args.get_str("--testcase") {
+        "" => std::env::var("TESTCASE").unwrap_or_default(),
+
+        v => v.to_string(),
+    };
+
+    // The number of connections to make, one after the other.
+    let mut conns = 1;
+
+    match testcase.as_str() {
+        "" | "handshake" | "transfer" | "retry" => (),
+
+        "resumption" => {
+            config.set_session_store(
+                sync::Arc::new(MemorySessionStore::default()));
+
+            conns = 2;
+        },
+
+        "chacha20" => {
+            config.set_cipher_suites(&[quiche::CipherSuite::ChaCha20Poly1305])
+                  .unwrap();
+        },
+
+        _ => {
+            error!("unsupported test case {}", testcase);
+            std::process::exit(UNSUPPORTED_TESTCASE);
+        },
+    }
+
+    for i in 0..conns {
+        let resumed = run(&url, &mut config, args.get_bool("--http1"));
+
+        if i > 0 && !resumed {
+            error!("connection {} was not resumed", i);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Fetches the given URL over a new connection, and returns whether the
+// connection was resumed.
+fn run(url: &url::Url, config: &mut quiche::Config, http1: bool) -> bool {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.connect(url).unwrap();
+
+    let poll = mio::Poll::new().unwrap();
+    let mut events = mio::Events::with_capacity(1024);
+
+    let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+    poll.register(&socket, mio::Token(0),
+                  mio::Ready::readable(),
+                  mio::PollOpt::edge()).unwrap();
+
+    let mut scid: [u8; LOCAL_CONN_ID_LEN] = [0; LOCAL_CONN_ID_LEN];
+    SystemRandom::new().fill(&mut scid[..]).unwrap();
+
+    let mut conn = quiche::connect(url.domain(), &scid, config).unwrap();
 
     let write = match conn.send(&mut out) {
         Ok(v) => v,
@@ -150,7 +217,8 @@ fn main() {
 
                 Err(e) => {
                     error!("{} recv failed: {:?}", conn.trace_id(), e);
-                    conn.close(false, e.to_wire(), b"fail").unwrap();
+                    // The connection might have already been closed by quiche.
+                    conn.close(false, e.to_wire(), b"fail").ok();
                     break 'read;
                 },
             };
@@ -158,38 +226,56 @@ fn main() {
             debug!("{} processed {} bytes", conn.trace_id(), read);
         }
 
-        if conn.is_closed() {
-            info!("{} connection closed, {:?}", conn.trace_id(), conn.stats());
-            break;
-        }
+        // Handle the events generated by the received packets and timeouts.
+        while let Some(ev) = conn.poll() {
+            match ev {
+                quiche::Event::HandshakeCompleted if !req_sent => {
+                    info!("{} sending HTTP request for {}",
+                          conn.trace_id(), url.path());
 
-        if conn.is_established() && !req_sent {
-            info!("{} sending HTTP request for {}", conn.trace_id(), url.path());
+                    let req = if http1 {
+                        format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: quiche\r\n\r\n",
+                            url.path(), url.host().unwrap())
+                    } else {
+                        format!("GET {}\r\n", url.path())
+                    };
 
-            let req = if args.get_bool("--http1") {
-                format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: quiche\r\n\r\n",
-                    url.path(), url.host().unwrap())
-            } else {
-                format!("GET {}\r\n", url.path())
-            };
+                    conn.stream_send(HTTP_REQ_STREAM_ID, req.as_bytes(), true)
+                        .unwrap();
 
-            conn.stream_send(HTTP_REQ_STREAM_ID, req.as_bytes(), true).unwrap();
+                    req_sent = true;
+                },
 
-            req_sent = true;
-        }
+                quiche::Event::StreamReadable(s) => {
+                    let data = match conn.stream_recv(s, std::usize::MAX) {
+                        Ok(v) => v,
+
+                        // The data might have been read already.
+                        Err(quiche::Error::Done) => continue,
+
+                        Err(e) => panic!("{} stream recv failed {:?}",
+                                         conn.trace_id(), e),
+                    };
+
+                    debug!("{} stream {} has {} bytes (fin? {})",
+                           conn.trace_id(), s, data.len(), data.fin());
 
-        let streams: Vec<u64> = conn.readable().collect();
-        for s in streams {
-            let data = conn.stream_recv(s, std::usize::MAX).unwrap();
+                    print!("{}", unsafe { std::str::from_utf8_unchecked(&data) });
 
-            debug!("{} stream {} has {} bytes (fin? {})",
-                   conn.trace_id(), s, data.len(), data.fin());
+                    if s == HTTP_REQ_STREAM_ID && data.fin() {
+                        info!("{} response received, closing..,",
+                              conn.trace_id());
+                        conn.close(true, 0x00, b"kthxbye").ok();
+                    }
+                },
 
-            print!("{}", unsafe { std::str::from_utf8_unchecked(&data) });
+                quiche::Event::ConnectionClosed => {
+                    info!("{} connection closed, {:?}",
+                          conn.trace_id(), conn.stats());
+                    return conn.is_resumed();
+                },
 
-            if s == HTTP_REQ_STREAM_ID && data.fin() {
-                info!("{} response received, closing..,", conn.trace_id());
-                conn.close(true, 0x00, b"kthxbye").unwrap();
+                _ => (),
             }
         }
 
@@ -204,7 +290,7 @@ fn main() {
 
                 Err(e) => {
                     error!("{} send failed: {:?}", conn.trace_id(), e);
-                    conn.close(false, e.to_wire(), b"fail").unwrap();
+                    conn.close(false, e.to_wire(), b"fail").ok();
                     break;
                 },
             };
@@ -220,4 +306,6 @@ fn main() {
             break;
         }
     }
+
+    conn.is_resumed()
 }
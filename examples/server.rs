@@ -38,6 +38,9 @@ const LOCAL_CONN_ID_LEN: usize = 16;
 
 const MAX_DATAGRAM_SIZE: usize = 1452;
 
+// Exit code the QUIC Interop Runner expects for unsupported test cases.
+const UNSUPPORTED_TESTCASE: i32 = 127;
+
 const USAGE: &str = "Usage:
   server [options]
   server -h | --help
@@ -48,10 +51,18 @@ Options:
   --key <file>      TLS certificate key path [default: examples/cert.key]
   --root <dir>      Root directory [default: examples/root/]
   --name <str>      Name of the server [default: quic.tech]
+  --max-conns <n>   Maximum number of concurrent connections [default: 1000]
+  --testcase <name> Interop Runner test case to run (defaults to $TESTCASE).
   -h --help         Show this screen.
 ";
 
-type ConnMap = HashMap<Vec<u8>, (net::SocketAddr, Box<quiche::Connection>)>;
+// Response bodies that didn't fit in the stream's flow control window, and
+// how much of them was already written.
+type PartialResponses = HashMap<u64, (Vec<u8>, usize)>;
+
+type ConnMap = HashMap<Vec<u8>, (net::SocketAddr,
+                                 Box<quiche::Connection>,
+                                 PartialResponses)>;
 
 fn main() {
     let mut buf = [0; 65535];
@@ -75,6 +86,8 @@ fn main() {
 
     let mut connections = ConnMap::new();
 
+    let max_conns: usize = args.get_str("--max-conns").parse().unwrap();
+
     let mut config = quiche::Config::new(quiche::VERSION_DRAFT17).unwrap();
 
     config.load_cert_chain_from_pem_file(args.get_str("--cert")).unwrap();
@@ -91,10 +104,29 @@ fn main() {
     config.set_initial_max_streams_uni(5);
     config.set_disable_migration(true);
 
+    let testcase = match args.get_str("--testcase") {
+        "" => std::env::var("TESTCASE").unwrap_or_default(),
+
+        v => v.to_string(),
+    };
+
+    // Stateless retry is always done, unless a test case that doesn't
+    // expect it is selected.
+    let do_retry = match testcase.as_str() {
+        "" | "retry" => true,
+
+        "handshake" | "transfer" | "resumption" | "chacha20" => false,
+
+        _ => {
+            error!("unsupported test case {}", testcase);
+            std::process::exit(UNSUPPORTED_TESTCASE);
+        },
+    };
+
     loop {
         // TODO: use event loop that properly supports timers
         let timeout = connections.values()
-                                 .filter_map(|(_, c)| c.timeout())
+                                 .filter_map(|(_, c, _)| c.timeout())
                                  .min();
 
         poll.poll(&mut events, timeout).unwrap();
@@ -103,7 +135,7 @@ fn main() {
             if events.is_empty() {
                 debug!("timed out");
 
-                connections.values_mut().for_each(|(_, c)| c.on_timeout());
+                connections.values_mut().for_each(|(_, c, _)| c.on_timeout());
 
                 break 'read;
             }
@@ -139,12 +171,17 @@ fn main() {
                 continue;
             }
 
-            let (_, conn) = if !connections.contains_key(&hdr.dcid) {
+            let (_, conn, partial_responses) = if !connections.contains_key(&hdr.dcid) {
                 if hdr.ty != quiche::Type::Initial {
                     error!("Packet is not Initial");
                     continue;
                 }
 
+                if connections.len() >= max_conns {
+                    warn!("Too many connections, dropping Initial");
+                    continue;
+                }
+
                 if hdr.version != quiche::VERSION_DRAFT17 {
                     warn!("Doing version negotiation");
 
@@ -163,7 +200,7 @@ fn main() {
                 // Token is always present in Initial packets.
                 let token = hdr.token.as_ref().unwrap();
 
-                if token.is_empty() {
+                if do_retry && token.is_empty() {
                     warn!("Doing stateless retry");
 
                     let new_token = mint_token(&hdr, &src);
@@ -178,7 +215,7 @@ fn main() {
 
                 let odcid = validate_token(&src, token);
 
-                if odcid == None {
+                if do_retry && odcid == None {
                     error!("Invalid address validation token");
                     continue;
                 }
@@ -190,7 +227,7 @@ fn main() {
 
                 let conn = quiche::accept(&scid, odcid, &mut config).unwrap();
 
-                connections.insert(scid.to_vec(), (src, conn));
+                connections.insert(scid.to_vec(), (src, conn, HashMap::new()));
 
                 connections.get_mut(&scid[..]).unwrap()
             } else {
@@ -208,20 +245,33 @@ fn main() {
 
                 Err(e) => {
                     error!("{} recv failed: {:?}", conn.trace_id(), e);
-                    conn.close(false, e.to_wire(), b"fail").unwrap();
+                    // The connection might have already been closed by quiche.
+                    conn.close(false, e.to_wire(), b"fail").ok();
                     break 'read;
                 },
             };
 
             debug!("{} processed {} bytes", conn.trace_id(), read);
 
-            let streams: Vec<u64> = conn.readable().collect();
-            for s in streams {
-                handle_stream(conn, s, args.get_str("--root"));
+            // Handle the events generated by the received packets.
+            while let Some(ev) = conn.poll() {
+                match ev {
+                    quiche::Event::HandshakeCompleted =>
+                        info!("{} connection established", conn.trace_id()),
+
+                    quiche::Event::StreamReadable(s) =>
+                        handle_stream(conn, partial_responses, s,
+                                      args.get_str("--root")),
+
+                    quiche::Event::StreamWritable(s) =>
+                        handle_writable(conn, partial_responses, s),
+
+                    _ => (),
+                }
             }
         }
 
-        for (peer, conn) in connections.values_mut() {
+        for (peer, conn, _) in connections.values_mut() {
             loop {
                 let write = match conn.send(&mut out) {
                     Ok(v) => v,
@@ -233,7 +283,7 @@ fn main() {
 
                     Err(e) => {
                         error!("{} send failed: {:?}", conn.trace_id(), e);
-                        conn.close(false, e.to_wire(), b"fail").unwrap();
+                        conn.close(false, e.to_wire(), b"fail").ok();
                         break;
                     },
                 };
@@ -246,7 +296,7 @@ fn main() {
         }
 
         // Garbage collect closed connections.
-        connections.retain(|_, (_, ref mut c)| {
+        connections.retain(|_, (_, ref mut c, _)| {
             debug!("Collecting garbage");
 
             if c.is_closed() {
@@ -258,7 +308,9 @@ fn main() {
     }
 }
 
-fn handle_stream(conn: &mut quiche::Connection, stream: u64, root: &str) {
+fn handle_stream(conn: &mut quiche::Connection,
+                 partial_responses: &mut PartialResponses, stream: u64,
+                 root: &str) {
     let stream_data = match conn.stream_recv(stream, std::usize::MAX) {
         Ok(v) => v,
 
@@ -293,12 +345,49 @@ fn handle_stream(conn: &mut quiche::Connection, stream: u64, root: &str) {
         info!("{} sending response of size {} on stream {}",
               conn.trace_id(), data.len(), stream);
 
-        if let Err(e) = conn.stream_send(stream, &data, true) {
-            error!("{} stream send failed {:?}", conn.trace_id(), e);
+        let written = match conn.stream_send(stream, &data, true) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => 0,
+
+            Err(e) => {
+                error!("{} stream send failed {:?}", conn.trace_id(), e);
+                return;
+            },
+        };
+
+        if written < data.len() {
+            partial_responses.insert(stream, (data, written));
         }
     }
 }
 
+fn handle_writable(conn: &mut quiche::Connection,
+                   partial_responses: &mut PartialResponses, stream: u64) {
+    debug!("{} stream {} is writable", conn.trace_id(), stream);
+
+    let (body, written) = match partial_responses.get_mut(&stream) {
+        Some(v) => v,
+
+        None => return,
+    };
+
+    match conn.stream_send(stream, &body[*written..], true) {
+        Ok(v) => *written += v,
+
+        Err(quiche::Error::Done) => (),
+
+        Err(e) => {
+            error!("{} stream send failed {:?}", conn.trace_id(), e);
+            return;
+        },
+    };
+
+    if *written == body.len() {
+        partial_responses.remove(&stream);
+    }
+}
+
 fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
     let mut token = Vec::new();
 
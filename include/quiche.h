@@ -80,6 +80,18 @@ enum quiche_error {
 
     // The peer violated the local stream limits.
     QUICHE_ERR_STREAM_LIMIT = -12,
+
+    // The handshake did not complete within the configured timeout.
+    QUICHE_ERR_HANDSHAKE_TIMEOUT = -13,
+
+    // The peer changed a stream's final size, or sent data beyond it.
+    QUICHE_ERR_FINAL_SIZE = -14,
+
+    // The operation would exceed the connection's memory budget.
+    QUICHE_ERR_MEMORY_LIMIT = -15,
+
+    // Too many server handshakes are in progress to accept a new one.
+    QUICHE_ERR_HANDSHAKE_LIMIT = -16,
 };
 
 // Enables logging. |cb| will be called with log messages
@@ -100,18 +112,96 @@ int quiche_config_load_cert_chain_from_pem_file(quiche_config *config,
 int quiche_config_load_priv_key_from_pem_file(quiche_config *config,
                                               const char *path);
 
+// Configures the given DER-encoded certificate chain.
+int quiche_config_load_cert_chain_from_der(quiche_config *config,
+                                           const uint8_t *der, size_t der_len);
+
+// Configures the given DER-encoded private key.
+int quiche_config_load_priv_key_from_der(quiche_config *config,
+                                         const uint8_t *der, size_t der_len);
+
 // Configures whether to verify the peer's certificate.
 void quiche_config_verify_peer(quiche_config *config, bool v);
 
 // Enables logging of secrets.
 void quiche_config_log_keys(quiche_config *config);
 
+// Sets the length of locally generated connection IDs.
+int quiche_config_set_cid_len(quiche_config *config, size_t v);
+
+// Writes a new connection ID of the configured length to `out`.
+ssize_t quiche_config_new_cid(quiche_config *config, uint8_t *out,
+                              size_t out_len);
+
+// Requires address validation once the number of half-open handshakes
+// reaches |v|.
+void quiche_config_set_retry_threshold(quiche_config *config, size_t v);
+
+// Returns whether a new client should be sent a Retry packet.
+bool quiche_config_retry_required(quiche_config *config);
+
+// Sets the maximum number of half-open handshakes, after which new server
+// connections are refused.
+void quiche_config_set_max_half_open_handshakes(quiche_config *config,
+                                                size_t v);
+
+// Sets the initial RTT estimate, in milliseconds.
+void quiche_config_set_initial_rtt(quiche_config *config, uint64_t v);
+
+// Sets the packet reordering threshold used by loss detection.
+void quiche_config_set_packet_reordering_threshold(quiche_config *config,
+                                                   uint64_t v);
+
+// Sets the time threshold multiplier used by loss detection.
+void quiche_config_set_time_threshold(quiche_config *config, double v);
+
+// Sets the handshake timeout, in milliseconds.
+void quiche_config_set_handshake_timeout(quiche_config *config, uint64_t v);
+
+// Enables sending keep-alive PING frames at the given interval, in
+// milliseconds.
+void quiche_config_enable_keepalive(quiche_config *config, uint64_t v);
+
+// Sets the maximum number of ranges of received packets to acknowledge.
+void quiche_config_set_max_ack_ranges(quiche_config *config, size_t v);
+
+// Sets the maximum amount of data a stream can send before yielding to the
+// other streams.
+void quiche_config_set_stream_burst(quiche_config *config, size_t v);
+
+// Sets how far ahead of the read offset a stream can buffer out-of-order data.
+void quiche_config_set_stream_reorder_window(quiche_config *config, size_t v);
+
+// Configures the padding added to outgoing packets. Handshake packets are
+// padded to the maximum size if |handshake| is set, and 1-RTT packets get
+// between |random_min| and |random_max| random bytes (none if |random_max|
+// is 0), and are then padded to a multiple of |bucket| (if not 0). Fails if
+// |random_min| is greater than |random_max|.
+int quiche_config_set_padding_policy(quiche_config *config, bool handshake,
+                                     size_t random_min, size_t random_max,
+                                     size_t bucket);
+
+// Sets the granularity used to coalesce timers, in milliseconds.
+void quiche_config_set_timer_granularity(quiche_config *config, uint64_t v);
+
+// Configures whether to pace outgoing packets.
+void quiche_config_enable_pacing(quiche_config *config, bool v);
+
+// Sets the maximum amount of memory, in bytes, a connection can use.
+void quiche_config_set_max_connection_memory(quiche_config *config, size_t v);
+
 // Sets the `idle_timeout` transport parameter.
 void quiche_config_set_idle_timeout(quiche_config *config, uint64_t v);
 
-// Sets the `max_packet_size` transport parameter.
+// Sets the `max_packet_size` transport parameter, i.e. the maximum UDP
+// payload size the local endpoint is willing to receive. Values lower than
+// 1200 are raised to 1200.
 void quiche_config_set_max_packet_size(quiche_config *config, uint64_t v);
 
+// Sets the maximum UDP payload size of outgoing packets.
+void quiche_config_set_max_send_udp_payload_size(quiche_config *config,
+                                                 size_t v);
+
 // Sets the `initial_max_stream_data_bidi_local` transport parameter.
 void quiche_config_set_initial_max_stream_data_bidi_local(quiche_config *config, uint64_t v);
 
@@ -130,6 +220,12 @@ void quiche_config_set_initial_max_streams_bidi(quiche_config *config, uint64_t
 // Sets the `initial_max_streams_uni` transport parameter.
 void quiche_config_set_initial_max_streams_uni(quiche_config *config, uint64_t v);
 
+// Sets the maximum size stream receive windows can grow to.
+void quiche_config_set_max_stream_window(quiche_config *config, uint64_t v);
+
+// Sets the maximum size the connection receive window can grow to.
+void quiche_config_set_max_connection_window(quiche_config *config, uint64_t v);
+
 // Sets the `ack_delay_exponent` transport parameter.
 void quiche_config_set_ack_delay_exponent(quiche_config *config, uint64_t v);
 
@@ -139,6 +235,52 @@ void quiche_config_set_max_ack_delay(quiche_config *config, uint64_t v);
 // Sets the `disable_migration` transport parameter.
 void quiche_config_set_disable_migration(quiche_config *config, bool v);
 
+// Sets the `active_connection_id_limit` transport parameter.
+void quiche_config_set_active_connection_id_limit(quiche_config *config,
+                                                  uint64_t v);
+
+// Sets the number of packets after which the destination connection ID is
+// rotated.
+void quiche_config_set_cid_rotation_packets(quiche_config *config, uint64_t v);
+
+// Sets the interval, in milliseconds, after which the destination connection
+// ID is rotated.
+void quiche_config_set_cid_rotation_interval(quiche_config *config,
+                                             uint64_t v);
+
+// Sets whether the destination connection ID is rotated when the peer's
+// address changes.
+void quiche_config_set_cid_rotation_on_migration(quiche_config *config,
+                                                 bool v);
+
+// Sets the key stateless reset tokens are derived from.
+void quiche_config_set_stateless_reset_key(quiche_config *config,
+                                           const uint8_t *v, size_t v_len);
+
+// Sets the DSCP value that outgoing packets should be marked with.
+void quiche_config_set_dscp(quiche_config *config, uint8_t v);
+
+// Configures the OCSP response to staple to the server's certificate.
+int quiche_config_set_ocsp_response(quiche_config *config, const uint8_t *v,
+                                    size_t v_len);
+
+// Configures the signed certificate timestamps to send with the certificate.
+int quiche_config_set_signed_cert_timestamps(quiche_config *config,
+                                             const uint8_t *v, size_t v_len);
+
+// Requests an OCSP response from the server during the handshake.
+void quiche_config_enable_ocsp_stapling(quiche_config *config);
+
+// Requests signed certificate timestamps from the server during the handshake.
+void quiche_config_enable_signed_cert_timestamps(quiche_config *config);
+
+// Configures the list of allowed TLS 1.3 cipher suites, by IANA identifier.
+int quiche_config_set_cipher_suites(quiche_config *config, const uint16_t *ids,
+                                    size_t ids_len);
+
+// Configures the colon-separated list of supported key exchange groups.
+int quiche_config_set_curves(quiche_config *config, const char *curves);
+
 // Frees the config object.
 void quiche_config_free(quiche_config *config);
 
@@ -162,6 +304,9 @@ quiche_conn *quiche_accept(const uint8_t *scid, size_t scid_len,
 quiche_conn *quiche_connect(const char *server_name, const uint8_t *scid,
                             size_t scid_len, quiche_config *config);
 
+// Returns true if the given protocol version is supported.
+bool quiche_version_is_supported(uint32_t version);
+
 // Writes a version negotiation packet.
 ssize_t quiche_negotiate_version(const uint8_t *scid, size_t scid_len,
                                  const uint8_t *dcid, size_t dcid_len,
@@ -185,6 +330,17 @@ ssize_t quiche_conn_recv(quiche_conn *conn, uint8_t *buf, size_t buf_len);
 // Writes a single QUIC packet to be sent to the peer.
 ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len);
 
+// Processes QUIC packets received from the peer, along with the ECN codepoint
+// of the datagram that carried them.
+ssize_t quiche_conn_recv_with_ecn(quiche_conn *conn, uint8_t *buf,
+                                  size_t buf_len, uint8_t ecn);
+
+// Writes a single QUIC packet to be sent to the peer, and returns the ECN
+// codepoint and DSCP value it should be sent with.
+ssize_t quiche_conn_send_with_info(quiche_conn *conn, uint8_t *out,
+                                   size_t out_len, uint8_t *ecn,
+                                   uint8_t *dscp);
+
 // Buffer holding data at a specific offset.
 typedef struct RangeBuf quiche_rangebuf;
 
@@ -196,6 +352,42 @@ quiche_rangebuf *quiche_conn_stream_recv(quiche_conn *conn, uint64_t stream_id,
 ssize_t quiche_conn_stream_send(quiche_conn *conn, uint64_t stream_id,
                                 const uint8_t *buf, size_t buf_len, bool fin);
 
+// Moves received data from one stream to the send buffer of another.
+ssize_t quiche_conn_stream_splice(quiche_conn *conn, uint64_t from_id,
+                                  uint64_t to_id, size_t max_len);
+
+// Returns true if all the data of the stream has been read.
+bool quiche_conn_stream_finished(quiche_conn *conn, uint64_t stream_id);
+
+// Returns true if the stream has outstanding data to read.
+bool quiche_conn_stream_readable(quiche_conn *conn, uint64_t stream_id);
+
+// Returns 1 if at least |len| bytes can be written to the stream, 0 if not,
+// or a negative error code.
+int quiche_conn_stream_writable(quiche_conn *conn, uint64_t stream_id,
+                                size_t len);
+
+// Returns the number of bidirectional streams that can still be opened.
+size_t quiche_conn_peer_streams_left_bidi(quiche_conn *conn);
+
+// Returns the number of unidirectional streams that can still be opened.
+size_t quiche_conn_peer_streams_left_uni(quiche_conn *conn);
+
+// Returns the number of bidirectional streams the peer can still open.
+size_t quiche_conn_local_streams_left_bidi(quiche_conn *conn);
+
+// Returns the number of unidirectional streams the peer can still open.
+size_t quiche_conn_local_streams_left_uni(quiche_conn *conn);
+
+// Returns the number of streams opened by the peer not yet accepted.
+size_t quiche_conn_pending_accepted_streams(quiche_conn *conn);
+
+// Returns the remaining connection-level flow control credit for sending.
+size_t quiche_conn_tx_credit(quiche_conn *conn);
+
+// Returns the remaining connection-level flow control credit for receiving.
+size_t quiche_conn_rx_credit(quiche_conn *conn);
+
 // Returns the data of the buffer.
 const uint8_t *quiche_rangebuf_data(quiche_rangebuf *b);
 
@@ -208,6 +400,71 @@ bool quiche_rangebuf_fin(quiche_rangebuf *b);
 // Frees the buffer object.
 void quiche_rangebuf_free(quiche_rangebuf *b);
 
+// Schedules a NEW_TOKEN frame carrying |token| to be sent to the client.
+int quiche_conn_send_new_token(quiche_conn *conn, const uint8_t *token,
+                               size_t token_len);
+
+// Configures the address validation token to present to the server.
+int quiche_conn_set_token(quiche_conn *conn, const uint8_t *token,
+                          size_t token_len);
+
+// Issues a new source connection ID to the peer, and returns its sequence
+// number. The `reset_token` must point to 16 bytes.
+int64_t quiche_conn_new_source_cid(quiche_conn *conn, const uint8_t *cid,
+                                   size_t cid_len, const uint8_t *reset_token);
+
+// Notifies the connection that the peer's address has changed.
+int quiche_conn_on_peer_migrated(quiche_conn *conn);
+
+// Sets the string used to represent the connection in logs. |trace_id| must
+// be a NUL-terminated UTF-8 string.
+void quiche_conn_set_trace_id(quiche_conn *conn, const char *trace_id);
+
+// Returns the source connection ID used by the local endpoint.
+void quiche_conn_source_id(quiche_conn *conn, const uint8_t **out,
+                           size_t *out_len);
+
+// Returns the latest token received in a NEW_TOKEN frame, if any.
+void quiche_conn_new_token(quiche_conn *conn, const uint8_t **out,
+                           size_t *out_len);
+
+enum quiche_event {
+    // The handshake completed.
+    QUICHE_EVENT_HANDSHAKE_COMPLETED = 0,
+
+    // The stream has outstanding data to read.
+    QUICHE_EVENT_STREAM_READABLE = 1,
+
+    // The peer raised the stream's flow control limit.
+    QUICHE_EVENT_STREAM_WRITABLE = 2,
+
+    // An address validation token was received from the server.
+    QUICHE_EVENT_NEW_TOKEN = 3,
+
+    // The connection was closed.
+    QUICHE_EVENT_CONNECTION_CLOSED = 4,
+
+    // The peer's address changed without the peer migrating.
+    QUICHE_EVENT_PEER_ADDRESS_CHANGED = 5,
+
+    // The destination connection ID was rotated, a new source connection ID
+    // should be issued with quiche_conn_new_source_cid().
+    QUICHE_EVENT_CONNECTION_ID_ROTATED = 6,
+};
+
+// Returns the next transport event as a |quiche_event| value, or
+// QUICHE_ERR_DONE if there are none. For stream events |stream_id| is set to
+// the stream's ID. Use quiche_conn_new_token() to retrieve the token of a
+// QUICHE_EVENT_NEW_TOKEN event, and quiche_conn_peer_addr() to retrieve the
+// new address of a QUICHE_EVENT_PEER_ADDRESS_CHANGED event.
+int quiche_conn_poll(quiche_conn *conn, uint64_t *stream_id);
+
+// Returns the IP address and port packets should be sent to, or
+// QUICHE_ERR_DONE if it isn't known. |ip| must point to 16 bytes, and
+// |ip_len| is set to 4 for IPv4 addresses and 16 for IPv6 ones.
+int quiche_conn_peer_addr(quiche_conn *conn, uint8_t *ip, size_t *ip_len,
+                          uint16_t *port);
+
 // An iterator over the streams that have outstanding data to read.
 typedef struct Readable quiche_readable;
 
@@ -221,12 +478,32 @@ bool quiche_readable_next(quiche_readable *iter, uint64_t *stream_id);
 // Frees the readable object.
 void quiche_readable_free(quiche_readable *r);
 
+typedef struct Accepted quiche_accepted;
+
+// Creates an iterator of streams opened by the peer since the last call.
+quiche_accepted *quiche_conn_accepted(quiche_conn *conn);
+
+// Fetches the next element from the stream iterator. Returns false if the
+// iterator is empty.
+bool quiche_accepted_next(quiche_accepted *iter, uint64_t *stream_id);
+
+// Frees the accepted object.
+void quiche_accepted_free(quiche_accepted *a);
+
 // Returns the amount of time until the next timeout event, as nanoseconds.
 uint64_t quiche_conn_timeout_as_nanos(quiche_conn *conn);
 
+// Returns the amount of time until the next batch of coalesced timeout
+// events, as nanoseconds.
+uint64_t quiche_conn_next_event_as_nanos(quiche_conn *conn);
+
 // Processes a timeout event.
 void quiche_conn_on_timeout(quiche_conn *conn);
 
+// Starts closing the connection gracefully, refusing new streams, within
+// |grace_ms| milliseconds at the latest.
+void quiche_conn_drain(quiche_conn *conn, uint64_t grace_ms);
+
 // Closes the connection with the given error and reason.
 int quiche_conn_close(quiche_conn *conn, bool app, uint16_t err,
                       const uint8_t *reason, size_t reason_len);
@@ -234,13 +511,57 @@ int quiche_conn_close(quiche_conn *conn, bool app, uint16_t err,
 // Returns true if the connection handshake is complete.
 bool quiche_conn_is_established(quiche_conn *conn);
 
+// Returns the IANA identifier of the negotiated cipher suite, or 0.
+uint16_t quiche_conn_cipher_suite(quiche_conn *conn);
+
+// Returns the number of certificates in the peer's certificate chain.
+size_t quiche_conn_peer_cert_chain_len(quiche_conn *conn);
+
+// Returns the DER-encoded certificate at index `idx` of the peer's chain.
+bool quiche_conn_peer_cert(quiche_conn *conn, size_t idx, const uint8_t **out,
+                           size_t *out_len);
+
+// Returns the OCSP response stapled by the server, if any.
+bool quiche_conn_ocsp_response(quiche_conn *conn, const uint8_t **out,
+                               size_t *out_len);
+
+// Returns the signed certificate timestamps sent by the server, if any.
+bool quiche_conn_signed_cert_timestamps(quiche_conn *conn, const uint8_t **out,
+                                        size_t *out_len);
+
 // Returns true if the connection is closed.
 bool quiche_conn_is_closed(quiche_conn *conn);
 
+// Returns true if the connection was closed because of a local TLS failure,
+// and writes the alert sent to the peer to `out`.
+bool quiche_conn_local_tls_alert(quiche_conn *conn, uint8_t *out);
+
+// Returns true if the peer closed the connection because of a TLS failure,
+// and writes the alert it sent to `out`.
+bool quiche_conn_peer_tls_alert(quiche_conn *conn, uint8_t *out);
+
 // Collects and returns statistics about the connection.
 void quiche_conn_stats_sent(quiche_conn *conn, uint64_t *out);
 void quiche_conn_stats_lost(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_spurious_lost(quiche_conn *conn, uint64_t *out);
 void quiche_conn_stats_rtt_as_nanos(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_min_rtt_as_nanos(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_rttvar_as_nanos(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_persistent_congestion(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_ect0_recv(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_ect1_recv(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_ce_recv(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_mem_usage(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_dup_recv(quiche_conn *conn, uint64_t *out);
+void quiche_conn_stats_rebinds(quiche_conn *conn, uint64_t *out);
+
+// Returns the congestion window and minimum RTT (in nanoseconds) of the
+// connection's path.
+void quiche_conn_path_params(quiche_conn *conn, size_t *cwnd, uint64_t *min_rtt);
+
+// Resumes the congestion window from a previous connection's path.
+void quiche_conn_set_resume_params(quiche_conn *conn, size_t cwnd,
+                                   uint64_t min_rtt);
 
 // Frees the connection object.
 void quiche_conn_free(quiche_conn *conn);
@@ -0,0 +1,348 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Alt-Svc and HTTPS record helpers.
+//!
+//! Clients usually discover that a server supports QUIC through an
+//! `Alt-Svc` header sent over HTTP/1.1 or HTTP/2, or through the `alpn`
+//! parameter of an HTTPS DNS record. The helpers in this module build and
+//! parse both from the same list of application protocols that is passed to
+//! [`set_application_protos()`], so that what is advertised matches what
+//! the QUIC endpoint actually accepts.
+//!
+//! ```
+//! let protos: &[&[u8]] = &[b"h3-17", b"hq-17"];
+//!
+//! let services = quiche::altsvc::from_protos(protos, 443);
+//!
+//! let value = quiche::altsvc::encode(&services);
+//! assert_eq!(value, "h3-17=\":443\", hq-17=\":443\"");
+//!
+//! let entries = quiche::altsvc::parse(&value).unwrap();
+//! assert_eq!(entries[0].alpn, b"h3-17");
+//! assert_eq!(entries[0].port, 443);
+//! ```
+//!
+//! [`set_application_protos()`]: ../struct.Config.html#method.set_application_protos
+
+use std::fmt;
+
+use crate::octets;
+
+/// A single alternative service.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AltSvc {
+    /// The ALPN protocol ID of the alternative service.
+    pub alpn: Vec<u8>,
+
+    /// The host of the alternative service, or an empty string if it's the
+    /// same as the origin's.
+    pub host: String,
+
+    /// The UDP port of the alternative service.
+    pub port: u16,
+
+    /// How long the alternative service can be cached for, in seconds.
+    pub max_age: Option<u64>,
+
+    /// Whether the alternative service should survive network changes.
+    pub persist: bool,
+}
+
+impl AltSvc {
+    /// Creates an alternative service for `alpn` on the origin's host.
+    pub fn new(alpn: &[u8], port: u16) -> AltSvc {
+        AltSvc {
+            alpn: alpn.to_vec(),
+            host: String::new(),
+            port,
+            max_age: None,
+            persist: false,
+        }
+    }
+}
+
+impl fmt::Display for AltSvc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &c in &self.alpn {
+            if is_tchar(c) && c != b'%' {
+                write!(f, "{}", c as char)?;
+            } else {
+                write!(f, "%{:02X}", c)?;
+            }
+        }
+
+        write!(f, "=\"{}:{}\"", self.host, self.port)?;
+
+        if let Some(ma) = self.max_age {
+            write!(f, "; ma={}", ma)?;
+        }
+
+        if self.persist {
+            write!(f, "; persist=1")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns an alternative service on `port` for each of `protos`.
+///
+/// `protos` is in the same format as the one accepted by
+/// [`set_application_protos()`].
+///
+/// [`set_application_protos()`]: ../struct.Config.html#method.set_application_protos
+pub fn from_protos(protos: &[&[u8]], port: u16) -> Vec<AltSvc> {
+    protos.iter().map(|p| AltSvc::new(p, port)).collect()
+}
+
+/// Returns the value of an `Alt-Svc` header advertising `services`.
+///
+/// An empty list of services is encoded as `clear`, which invalidates any
+/// alternative service previously cached by the client.
+pub fn encode(services: &[AltSvc]) -> String {
+    if services.is_empty() {
+        return "clear".to_string();
+    }
+
+    services.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+}
+
+/// Parses the value of an `Alt-Svc` header.
+///
+/// Unknown parameters are ignored. `None` is returned if the value is
+/// malformed, and an empty list if it is `clear`.
+pub fn parse(value: &str) -> Option<Vec<AltSvc>> {
+    let value = value.trim();
+
+    if value == "clear" {
+        return Some(Vec::new());
+    }
+
+    let mut services = Vec::new();
+
+    for entry in split_unquoted(value, ',') {
+        let mut params = split_unquoted(entry, ';').into_iter();
+
+        let (alpn, authority) = split_param(params.next()?)?;
+
+        let alpn = percent_decode(alpn)?;
+        if alpn.is_empty() {
+            return None;
+        }
+
+        let colon = authority.rfind(':')?;
+
+        let mut svc = AltSvc::new(&alpn, authority[colon + 1..].parse().ok()?);
+        svc.host = authority[..colon].to_string();
+
+        for param in params {
+            let (name, value) = split_param(param)?;
+
+            match name.to_ascii_lowercase().as_str() {
+                "ma" => svc.max_age = Some(value.parse().ok()?),
+
+                "persist" => svc.persist = value == "1",
+
+                _ => (),
+            }
+        }
+
+        services.push(svc);
+    }
+
+    Some(services)
+}
+
+/// Returns the value of the `alpn` parameter of an HTTPS DNS record
+/// advertising `protos`.
+///
+/// `None` is returned if any of the protocol IDs is empty or longer than 255
+/// bytes.
+pub fn alpn_hint(protos: &[&[u8]]) -> Option<Vec<u8>> {
+    let len: usize = protos.iter().map(|p| p.len() + 1).sum();
+    let mut out = vec![0; len];
+
+    let mut b = octets::OctetsMut::with_slice(&mut out);
+
+    for p in protos {
+        if p.is_empty() || p.len() > 255 {
+            return None;
+        }
+
+        b.put_u8(p.len() as u8).ok()?;
+        b.put_bytes(p).ok()?;
+    }
+
+    Some(out)
+}
+
+/// Parses the value of the `alpn` parameter of an HTTPS DNS record.
+pub fn parse_alpn_hint(buf: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut b = octets::Octets::with_slice(buf);
+
+    let mut protos = Vec::new();
+
+    while b.cap() > 0 {
+        let p = b.get_bytes_with_u8_length().ok()?;
+
+        if p.cap() == 0 {
+            return None;
+        }
+
+        protos.push(p.to_vec());
+    }
+
+    Some(protos)
+}
+
+fn is_tchar(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len());
+
+    let mut bytes = s.bytes();
+
+    while let Some(c) = bytes.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+
+        let hex = [bytes.next()?, bytes.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+
+        out.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+
+    Some(out)
+}
+
+/// Splits a `name=value` pair, removing the quotes around the value if any.
+fn split_param(param: &str) -> Option<(&str, &str)> {
+    let eq = param.find('=')?;
+
+    let name = param[..eq].trim();
+    let value = param[eq + 1..].trim();
+
+    let value = if value.len() >= 2 &&
+                   value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    };
+
+    Some((name, value))
+}
+
+/// Splits `s` on `sep`, ignoring separators inside quoted strings.
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            parts.push(s[start..i].trim());
+            start = i + 1;
+        }
+    }
+
+    parts.push(s[start..].trim());
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_parse() {
+        let mut svc = AltSvc::new(b"h3-17", 443);
+        svc.max_age = Some(86400);
+        svc.persist = true;
+
+        let mut other = AltSvc::new(b"h3=%", 8443);
+        other.host = "alt.example.com".to_string();
+
+        let value = encode(&[svc.clone(), other.clone()]);
+        assert_eq!(value, "h3-17=\":443\"; ma=86400; persist=1, \
+                           h3%3D%25=\"alt.example.com:8443\"");
+
+        assert_eq!(parse(&value), Some(vec![svc, other]));
+    }
+
+    #[test]
+    fn parse_ipv6() {
+        let svc = parse("h3-17=\"[::1]:443\"; ma=60").unwrap();
+
+        assert_eq!(svc.len(), 1);
+        assert_eq!(svc[0].host, "[::1]");
+        assert_eq!(svc[0].port, 443);
+        assert_eq!(svc[0].max_age, Some(60));
+    }
+
+    #[test]
+    fn clear() {
+        assert_eq!(encode(&[]), "clear");
+        assert_eq!(parse(" clear "), Some(Vec::new()));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("h3-17"), None);
+        assert_eq!(parse("h3-17=\":port\""), None);
+        assert_eq!(parse("h3-17=\":70000\""), None);
+        assert_eq!(parse("h3-17=\":443\"; ma=soon"), None);
+        assert_eq!(parse("h3%2=\":443\""), None);
+    }
+
+    #[test]
+    fn alpn() {
+        let protos: &[&[u8]] = &[b"h3-17", b"hq-17"];
+
+        let hint = alpn_hint(protos).unwrap();
+        assert_eq!(hint, b"\x05h3-17\x05hq-17");
+
+        assert_eq!(parse_alpn_hint(&hint),
+                   Some(vec![b"h3-17".to_vec(), b"hq-17".to_vec()]));
+
+        assert_eq!(alpn_hint(&[&b""[..]]), None);
+        assert_eq!(parse_alpn_hint(b"\x05h3"), None);
+        assert_eq!(parse_alpn_hint(b"\x00"), None);
+    }
+}
@@ -35,11 +35,79 @@ use crate::Error;
 
 use crate::octets;
 
-const INITIAL_SALT: [u8; 20] = [
+/// The error returned when a local cryptographic operation fails, reported
+/// as a TLS `internal_error` alert.
+pub const INTERNAL_ERROR: Error = Error::CryptoFail { alert: 80 };
+
+const INITIAL_SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17,
+    0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad, 0xcc, 0xbb, 0x7f, 0x0a,
+];
+
+const INITIAL_SALT_DRAFT29: [u8; 20] = [
+    0xaf, 0xbf, 0xec, 0x28, 0x99, 0x93, 0xd2, 0x4c, 0x9e, 0x97,
+    0x86, 0xf1, 0x9c, 0x61, 0x11, 0xe0, 0x43, 0x90, 0xa8, 0x99,
+];
+
+const INITIAL_SALT_DRAFT17: [u8; 20] = [
     0xef, 0x4f, 0xb0, 0xab, 0xb4, 0x74, 0x70, 0xc4, 0x1b, 0xef,
     0xcf, 0x80, 0x31, 0x33, 0x4f, 0xae, 0x48, 0x5e, 0x09, 0xa0,
 ];
 
+const NONCE_LEN: usize = 12;
+
+/// The constants of the key schedule that depend on the wire version.
+struct VersionParams {
+    version: u32,
+
+    /// The salt the Initial secret is extracted with.
+    initial_salt: &'static [u8; 20],
+
+    /// The HKDF labels of the packet protection key, IV and header
+    /// protection key.
+    key_label: &'static [u8],
+    iv_label: &'static [u8],
+    hp_label: &'static [u8],
+}
+
+/// The versions whose key schedule is known.
+///
+/// Adding a version only requires adding an entry here, which doesn't make
+/// it supported on the wire, see [`supported_versions()`].
+///
+/// [`supported_versions()`]: ../fn.supported_versions.html
+const VERSIONS: [VersionParams; 3] = [
+    VersionParams {
+        version: 0x0000_0001,
+        initial_salt: &INITIAL_SALT_V1,
+        key_label: b"quic key",
+        iv_label: b"quic iv",
+        hp_label: b"quic hp",
+    },
+
+    VersionParams {
+        version: 0xff00_001d,
+        initial_salt: &INITIAL_SALT_DRAFT29,
+        key_label: b"quic key",
+        iv_label: b"quic iv",
+        hp_label: b"quic hp",
+    },
+
+    VersionParams {
+        version: crate::VERSION_DRAFT17,
+        initial_salt: &INITIAL_SALT_DRAFT17,
+        key_label: b"quic key",
+        iv_label: b"quic iv",
+        hp_label: b"quic hp",
+    },
+];
+
+fn version_params(version: u32) -> Result<&'static VersionParams> {
+    VERSIONS.iter()
+            .find(|v| v.version == version)
+            .ok_or(Error::UnknownVersion)
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Level {
@@ -111,7 +179,8 @@ pub struct Open {
     alg: Algorithm,
     hp_key: aead::quic::HeaderProtectionKey,
     key: aead::OpeningKey,
-    nonce: Vec<u8>,
+    nonce: [u8; NONCE_LEN],
+    offload: Option<Box<dyn crate::PacketKey>>,
 }
 
 impl Open {
@@ -122,44 +191,41 @@ impl Open {
             hp_key: aead::quic::HeaderProtectionKey::new(alg.get_ring_hp(),
                                                          hp_key).unwrap(),
             key: aead::OpeningKey::new(alg.get_ring_aead(), &key).unwrap(),
-            nonce: Vec::from(iv),
+            nonce: make_iv(iv)?,
+            offload: None,
             alg,
         })
     }
 
+    /// Sets the key payloads are decrypted with instead of the built-in one.
+    pub fn set_offload(&mut self, key: Option<Box<dyn crate::PacketKey>>) {
+        self.offload = key;
+    }
+
     pub fn open(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8]) -> Result<usize> {
+        if let Some(ref key) = self.offload {
+            return key.open(nonce, ad, buf);
+        }
+
         let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
-                                .map_err(|_| Error::CryptoFail)?;
+                                .map_err(|_| INTERNAL_ERROR)?;
         let ad = aead::Aad::from(ad);
         let plain = aead::open_in_place(&self.key, nonce, ad, 0, buf)
-                         .map_err(|_| Error::CryptoFail)?;
+                         .map_err(|_| INTERNAL_ERROR)?;
 
         Ok(plain.len())
     }
 
     pub fn open_with_u64_counter(&self, counter: u64, ad: &[u8], buf: &mut [u8])
                                                             -> Result<usize> {
-        let mut counter_nonce: [u8; 12] = [0xba; 12];
-
-        {
-            let mut b = octets::Octets::with_slice(&mut counter_nonce);
-
-            b.put_u32(0).unwrap();
-            b.put_u64(counter).unwrap();
-        }
-
-        let mut nonce = self.nonce.clone();
-
-        for i in 0 .. nonce.len() {
-            nonce[i] ^= counter_nonce[i];
-        }
+        let nonce = make_nonce(&self.nonce, counter);
 
         self.open(&nonce, ad, buf)
     }
 
     pub fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
         let mask = self.hp_key.new_mask(sample)
-                              .map_err(|_| Error::CryptoFail)?;
+                              .map_err(|_| INTERNAL_ERROR)?;
 
         Ok(mask)
     }
@@ -173,7 +239,8 @@ pub struct Seal {
     alg: Algorithm,
     hp_key: aead::quic::HeaderProtectionKey,
     key: aead::SealingKey,
-    nonce: Vec<u8>,
+    nonce: [u8; NONCE_LEN],
+    offload: Option<Box<dyn crate::PacketKey>>,
 }
 
 impl Seal {
@@ -184,44 +251,41 @@ impl Seal {
             hp_key: aead::quic::HeaderProtectionKey::new(alg.get_ring_hp(),
                                                          hp_key).unwrap(),
             key: aead::SealingKey::new(alg.get_ring_aead(), key).unwrap(),
-            nonce: Vec::from(iv),
+            nonce: make_iv(iv)?,
+            offload: None,
             alg,
         })
     }
 
+    /// Sets the key payloads are encrypted with instead of the built-in one.
+    pub fn set_offload(&mut self, key: Option<Box<dyn crate::PacketKey>>) {
+        self.offload = key;
+    }
+
     pub fn seal(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8]) -> Result<usize> {
+        if let Some(ref key) = self.offload {
+            return key.seal(nonce, ad, buf);
+        }
+
         let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
-                                .map_err(|_| Error::CryptoFail)?;
+                                .map_err(|_| INTERNAL_ERROR)?;
         let ad = aead::Aad::from(ad);
         let cipher = aead::seal_in_place(&self.key, nonce, ad, buf, self.alg().tag_len())
-                          .map_err(|_| Error::CryptoFail)?;
+                          .map_err(|_| INTERNAL_ERROR)?;
 
         Ok(cipher)
     }
 
     pub fn seal_with_u64_counter(&self, counter: u64, ad: &[u8], buf: &mut [u8])
                                                             -> Result<usize> {
-        let mut counter_nonce: [u8; 12] = [0xba; 12];
-
-        {
-            let mut b = octets::Octets::with_slice(&mut counter_nonce);
-
-            b.put_u32(0).unwrap();
-            b.put_u64(counter).unwrap();
-        }
-
-        let mut nonce = self.nonce.clone();
-
-        for i in 0 .. nonce.len() {
-            nonce[i] ^= counter_nonce[i];
-        }
+        let nonce = make_nonce(&self.nonce, counter);
 
         self.seal(&nonce, ad, buf)
     }
 
     pub fn new_mask(&self, sample: &[u8]) -> Result<[u8; 5]> {
         let mask = self.hp_key.new_mask(sample)
-                              .map_err(|_| Error::CryptoFail)?;
+                              .map_err(|_| INTERNAL_ERROR)?;
 
         Ok(mask)
     }
@@ -231,7 +295,32 @@ impl Seal {
     }
 }
 
-pub fn derive_initial_key_material(cid: &[u8], is_server: bool)
+fn make_iv(iv: &[u8]) -> Result<[u8; NONCE_LEN]> {
+    if iv.len() != NONCE_LEN {
+        return Err(INTERNAL_ERROR);
+    }
+
+    let mut out = [0; NONCE_LEN];
+    out.copy_from_slice(iv);
+
+    Ok(out)
+}
+
+/// Computes the per-packet nonce, by XORing the packet number with the IV.
+///
+/// The nonce is built on the stack, so that no allocation is needed for
+/// each protected packet.
+fn make_nonce(iv: &[u8; NONCE_LEN], counter: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = *iv;
+
+    for (n, c) in nonce[4..].iter_mut().zip(counter.to_be_bytes().iter()) {
+        *n ^= c;
+    }
+
+    nonce
+}
+
+pub fn derive_initial_key_material(version: u32, cid: &[u8], is_server: bool)
                                                     -> Result<(Open, Seal)> {
     let mut secret: [u8; 32] =  [0; 32];
 
@@ -240,7 +329,7 @@ pub fn derive_initial_key_material(cid: &[u8], is_server: bool)
     let key_len = aead.key_len();
     let nonce_len = aead.nonce_len();
 
-    let initial_secret = derive_initial_secret(&cid)?;
+    let initial_secret = derive_initial_secret(version, &cid)?;
 
     // Client.
     let mut client_key = vec![0; key_len];
@@ -248,9 +337,9 @@ pub fn derive_initial_key_material(cid: &[u8], is_server: bool)
     let mut client_hp_key = vec![0; key_len];
 
     derive_client_initial_secret(&initial_secret, &mut secret)?;
-    derive_pkt_key(aead, &secret, &mut client_key)?;
-    derive_pkt_iv(aead, &secret, &mut client_iv)?;
-    derive_hdr_key(aead, &secret, &mut client_hp_key)?;
+    derive_pkt_key(version, aead, &secret, &mut client_key)?;
+    derive_pkt_iv(version, aead, &secret, &mut client_iv)?;
+    derive_hdr_key(version, aead, &secret, &mut client_hp_key)?;
 
     // Server.
     let mut server_key = vec![0; key_len];
@@ -258,9 +347,9 @@ pub fn derive_initial_key_material(cid: &[u8], is_server: bool)
     let mut server_hp_key = vec![0; key_len];
 
     derive_server_initial_secret(&initial_secret, &mut secret)?;
-    derive_pkt_key(aead, &secret, &mut server_key)?;
-    derive_pkt_iv(aead, &secret, &mut server_iv)?;
-    derive_hdr_key(aead, &secret, &mut server_hp_key)?;
+    derive_pkt_key(version, aead, &secret, &mut server_key)?;
+    derive_pkt_iv(version, aead, &secret, &mut server_iv)?;
+    derive_hdr_key(version, aead, &secret, &mut server_hp_key)?;
 
     let (open, seal) = if is_server {
         (Open::new(aead, &client_key, &client_iv, &client_hp_key)?,
@@ -273,8 +362,24 @@ pub fn derive_initial_key_material(cid: &[u8], is_server: bool)
     Ok((open, seal))
 }
 
-fn derive_initial_secret(secret: &[u8]) -> Result<hmac::SigningKey> {
-    let salt = hmac::SigningKey::new(&digest::SHA256, &INITIAL_SALT);
+/// Derives the stateless reset token of the connection ID `cid` from the
+/// static key `key`.
+pub fn derive_reset_token(key: &[u8], cid: &[u8]) -> [u8; 16] {
+    let key = hmac::SigningKey::new(&digest::SHA256, key);
+
+    let tag = hmac::sign(&key, cid);
+
+    let mut token = [0; 16];
+    token.copy_from_slice(&tag.as_ref()[..16]);
+
+    token
+}
+
+fn derive_initial_secret(version: u32, secret: &[u8])
+                                                -> Result<hmac::SigningKey> {
+    let params = version_params(version)?;
+
+    let salt = hmac::SigningKey::new(&digest::SHA256, params.initial_salt);
     Ok(hkdf::extract(&salt, secret))
 }
 
@@ -288,46 +393,46 @@ fn derive_server_initial_secret(prk: &hmac::SigningKey, out: &mut [u8]) -> Resul
     hkdf_expand_label(prk, LABEL, out)
 }
 
-pub fn derive_hdr_key(aead: Algorithm, secret: &[u8], out: &mut [u8])
-                                                                -> Result<()> {
-    const LABEL: &[u8] = b"quic hp";
+pub fn derive_hdr_key(version: u32, aead: Algorithm, secret: &[u8],
+                      out: &mut [u8]) -> Result<()> {
+    let label = version_params(version)?.hp_label;
 
     let key_len = aead.key_len();
 
     if key_len > out.len() {
-        return Err(Error::CryptoFail);
+        return Err(INTERNAL_ERROR);
     }
 
     let secret = hmac::SigningKey::new(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
+    hkdf_expand_label(&secret, label, &mut out[..key_len])
 }
 
-pub fn derive_pkt_key(aead: Algorithm, secret: &[u8], out: &mut [u8])
-                                                                -> Result<()> {
-    const LABEL: &[u8] = b"quic key";
+pub fn derive_pkt_key(version: u32, aead: Algorithm, secret: &[u8],
+                      out: &mut [u8]) -> Result<()> {
+    let label = version_params(version)?.key_label;
 
     let key_len = aead.key_len();
 
     if key_len > out.len() {
-        return Err(Error::CryptoFail);
+        return Err(INTERNAL_ERROR);
     }
 
     let secret = hmac::SigningKey::new(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..key_len])
+    hkdf_expand_label(&secret, label, &mut out[..key_len])
 }
 
-pub fn derive_pkt_iv(aead: Algorithm, secret: &[u8], out: &mut [u8])
-                                                                -> Result<()> {
-    const LABEL: &[u8] = b"quic iv";
+pub fn derive_pkt_iv(version: u32, aead: Algorithm, secret: &[u8],
+                     out: &mut [u8]) -> Result<()> {
+    let label = version_params(version)?.iv_label;
 
     let nonce_len = aead.nonce_len();
 
     if nonce_len > out.len() {
-        return Err(Error::CryptoFail);
+        return Err(INTERNAL_ERROR);
     }
 
     let secret = hmac::SigningKey::new(aead.get_ring_digest(), secret);
-    hkdf_expand_label(&secret, LABEL, &mut out[..nonce_len])
+    hkdf_expand_label(&secret, label, &mut out[..nonce_len])
 }
 
 fn hkdf_expand_label(prk: &hmac::SigningKey, label: &[u8],  out: &mut [u8])
@@ -337,14 +442,14 @@ fn hkdf_expand_label(prk: &hmac::SigningKey, label: &[u8],  out: &mut [u8])
     let mut info: [u8; 24] = [0; 24];
 
     let info_len = {
-        let mut b = octets::Octets::with_slice(&mut info);
+        let mut b = octets::OctetsMut::with_slice(&mut info);
 
         if b.put_u16(out.len() as u16).is_err() ||
            b.put_u8((LABEL_PREFIX.len() + label.len()) as u8).is_err() ||
            b.put_bytes(LABEL_PREFIX).is_err() ||
            b.put_bytes(label).is_err() ||
            b.put_u8(0).is_err() {
-            return Err(Error::CryptoFail);
+            return Err(INTERNAL_ERROR);
         }
 
         b.off()
@@ -360,6 +465,90 @@ fn hkdf_expand_label(prk: &hmac::SigningKey, label: &[u8],  out: &mut [u8])
 mod tests {
     use super::*;
 
+    #[test]
+    fn packet_nonce() {
+        let iv = [0xfa, 0x04, 0x4b, 0x2f, 0x42, 0xa3, 0xfd, 0x3b,
+                  0x46, 0xfb, 0x25, 0x5c];
+
+        assert_eq!(make_nonce(&iv, 0), iv);
+
+        assert_eq!(make_nonce(&iv, 2), [0xfa, 0x04, 0x4b, 0x2f, 0x42, 0xa3,
+                                        0xfd, 0x3b, 0x46, 0xfb, 0x25, 0x5e]);
+
+        assert_eq!(make_nonce(&iv, 0x0102_0304_0506_0708),
+                   [0xfa, 0x04, 0x4b, 0x2f, 0x43, 0xa1, 0xfe, 0x3f,
+                    0x43, 0xfd, 0x22, 0x54]);
+
+        assert!(make_iv(&iv[..8]).is_err());
+    }
+
+    #[test]
+    fn chacha20_poly1305() {
+        let secret = [
+            0x9a, 0xc3, 0x12, 0xa7, 0xf8, 0x77, 0x46, 0x8e, 0xbe, 0x69, 0x42,
+            0x27, 0x48, 0xad, 0x00, 0xa1, 0x54, 0x43, 0xf1, 0x82, 0x03, 0xa0,
+            0x7d, 0x60, 0x60, 0xf6, 0x88, 0xf3, 0x0f, 0x21, 0x63, 0x2b,
+        ];
+
+        let mut pkt_key: [u8; 32] = [0; 32];
+        let mut pkt_iv: [u8; 12] = [0; 12];
+        let mut hdr_key: [u8; 32] = [0; 32];
+
+        let version = crate::VERSION_DRAFT17;
+        let aead = Algorithm::ChaCha20_Poly1305;
+
+        assert!(derive_pkt_key(version, aead, &secret, &mut pkt_key).is_ok());
+        let expected_pkt_key: [u8; 32] = [
+            0xc6, 0xd9, 0x8f, 0xf3, 0x44, 0x1c, 0x3f, 0xe1,
+            0xb2, 0x18, 0x20, 0x94, 0xf6, 0x9c, 0xaa, 0x2e,
+            0xd4, 0xb7, 0x16, 0xb6, 0x54, 0x88, 0x96, 0x0a,
+            0x7a, 0x98, 0x49, 0x79, 0xfb, 0x23, 0xe1, 0xc8,
+        ];
+        assert_eq!(&pkt_key, &expected_pkt_key);
+
+        assert!(derive_pkt_iv(version, aead, &secret, &mut pkt_iv).is_ok());
+        let expected_pkt_iv: [u8; 12] = [
+            0xe0, 0x45, 0x9b, 0x34, 0x74, 0xbd, 0xd0, 0xe4,
+            0x4a, 0x41, 0xc1, 0x44,
+        ];
+        assert_eq!(&pkt_iv, &expected_pkt_iv);
+
+        assert!(derive_hdr_key(version, aead, &secret, &mut hdr_key).is_ok());
+        let expected_hdr_key: [u8; 32] = [
+            0x25, 0xa2, 0x82, 0xb9, 0xe8, 0x2f, 0x06, 0xf2,
+            0x1f, 0x48, 0x89, 0x17, 0xa4, 0xfc, 0x8f, 0x1b,
+            0x73, 0x57, 0x36, 0x85, 0x60, 0x85, 0x97, 0xd0,
+            0xef, 0xcb, 0x07, 0x6b, 0x0a, 0xb7, 0xa7, 0xa4,
+        ];
+        assert_eq!(&hdr_key, &expected_hdr_key);
+
+        let seal = Seal::new(aead, &pkt_key, &pkt_iv, &hdr_key).unwrap();
+        let open = Open::new(aead, &pkt_key, &pkt_iv, &hdr_key).unwrap();
+
+        // Short header packet carrying a single PING frame.
+        let hdr = [0x42, 0x00, 0xbf, 0xf4];
+        let pn = 654_360_564;
+
+        let mut payload = [0; 17];
+        payload[0] = 0x01;
+
+        assert_eq!(seal.seal_with_u64_counter(pn, &hdr, &mut payload), Ok(17));
+        let expected_payload = [
+            0x65, 0x5e, 0x5c, 0xd5, 0x5c, 0x41, 0xf6, 0x90, 0x80, 0x57, 0x5d,
+            0x79, 0x99, 0xc2, 0x5a, 0x5b, 0xfb,
+        ];
+        assert_eq!(payload, expected_payload);
+
+        // The header protection sample starts right after the 4 bytes
+        // assumed for the packet number.
+        let mask = seal.new_mask(&payload[1..]).unwrap();
+        assert_eq!(mask, [0xae, 0xfe, 0xfe, 0x7d, 0x03]);
+        assert_eq!(open.new_mask(&payload[1..]), Ok(mask));
+
+        assert_eq!(open.open_with_u64_counter(pn, &hdr, &mut payload), Ok(1));
+        assert_eq!(payload[0], 0x01);
+    }
+
     #[test]
     fn derive_initial_secrets() {
         let dcid: [u8; 8] = [0xc6, 0x54, 0xef, 0xd8, 0xa3, 0x1b, 0x47, 0x92];
@@ -369,9 +558,10 @@ mod tests {
         let mut pkt_iv: [u8; 12] = [0; 12];
         let mut hdr_key: [u8; 16] = [0; 16];
 
+        let version = crate::VERSION_DRAFT17;
         let aead = Algorithm::AES128_GCM;
 
-        let initial_secret = derive_initial_secret(&dcid).unwrap();
+        let initial_secret = derive_initial_secret(version, &dcid).unwrap();
 
         // Client.
         assert!(derive_client_initial_secret(&initial_secret, &mut secret).is_ok());
@@ -383,21 +573,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(version, aead, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key: [u8; 16] = [
             0x86, 0xd1, 0x83, 0x04, 0x80, 0xb4, 0x0f, 0x86,
             0xcf, 0x9d, 0x68, 0xdc, 0xad, 0xf3, 0x5d, 0xfe,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(version, aead, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv: [u8; 12] = [
             0x12, 0xf3, 0x93, 0x8a, 0xca, 0x34, 0xaa, 0x02,
             0x54, 0x31, 0x63, 0xd4,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(version, aead, &secret, &mut hdr_key).is_ok());
         let expected_cliet_hdr_key: [u8; 16] = [
             0xcd, 0x25, 0x3a, 0x36, 0xff, 0x93, 0x93, 0x7c,
             0x46, 0x93, 0x84, 0xa8, 0x23, 0xaf, 0x6c, 0x56,
@@ -414,21 +604,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(version, aead, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key: [u8; 16] = [
             0x2c, 0x78, 0x63, 0x3e, 0x20, 0x6e, 0x99, 0xad,
             0x25, 0x19, 0x64, 0xf1, 0x9f, 0x6d, 0xcd, 0x6d,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(version, aead, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv: [u8; 12] = [
             0x7b, 0x50, 0xbf, 0x36, 0x98, 0xa0, 0x6d, 0xfa,
             0xbf, 0x75, 0xf2, 0x87,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(version, aead, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key: [u8; 16] = [
             0x25, 0x79, 0xd8, 0x69, 0x6f, 0x85, 0xed, 0xa6,
             0x8d, 0x35, 0x02, 0xb6, 0x55, 0x96, 0x58, 0x6b,
@@ -445,9 +635,10 @@ mod tests {
         let mut pkt_iv: [u8; 12] = [0; 12];
         let mut hdr_key: [u8; 16] = [0; 16];
 
+        let version = crate::VERSION_DRAFT17;
         let aead = Algorithm::AES128_GCM;
 
-        let initial_secret = derive_initial_secret(&dcid).unwrap();
+        let initial_secret = derive_initial_secret(version, &dcid).unwrap();
 
         // Client.
         assert!(derive_client_initial_secret(&initial_secret, &mut secret).is_ok());
@@ -459,21 +650,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_client_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(version, aead, &secret, &mut pkt_key).is_ok());
         let expected_client_pkt_key: [u8; 16] = [
             0x98, 0xb0, 0xd7, 0xe5, 0xe7, 0xa4, 0x02, 0xc6,
             0x7c, 0x33, 0xf3, 0x50, 0xfa, 0x65, 0xea, 0x54,
         ];
         assert_eq!(&pkt_key, &expected_client_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(version, aead, &secret, &mut pkt_iv).is_ok());
         let expected_client_pkt_iv: [u8; 12] = [
             0x19, 0xe9, 0x43, 0x87, 0x80, 0x5e, 0xb0, 0xb4,
             0x6c, 0x03, 0xa7, 0x88,
         ];
         assert_eq!(&pkt_iv, &expected_client_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(version, aead, &secret, &mut hdr_key).is_ok());
         let expected_cliet_hdr_key: [u8; 16] = [
             0x0e, 0xdd, 0x98, 0x2a, 0x6a, 0xc5, 0x27, 0xf2,
             0xed, 0xdc, 0xbb, 0x73, 0x48, 0xde, 0xa5, 0xd7,
@@ -490,21 +681,21 @@ mod tests {
         ];
         assert_eq!(&secret, &expected_server_initial_secret);
 
-        assert!(derive_pkt_key(aead, &secret, &mut pkt_key).is_ok());
+        assert!(derive_pkt_key(version, aead, &secret, &mut pkt_key).is_ok());
         let expected_server_pkt_key: [u8; 16] = [
             0x9a, 0x8b, 0xe9, 0x02, 0xa9, 0xbd, 0xd9, 0x1d,
             0x16, 0x06, 0x4c, 0xa1, 0x18, 0x04, 0x5f, 0xb4,
         ];
         assert_eq!(&pkt_key, &expected_server_pkt_key);
 
-        assert!(derive_pkt_iv(aead, &secret, &mut pkt_iv).is_ok());
+        assert!(derive_pkt_iv(version, aead, &secret, &mut pkt_iv).is_ok());
         let expected_server_pkt_iv: [u8; 12] = [
             0x0a, 0x82, 0x08, 0x6d, 0x32, 0x20, 0x5b, 0xa2,
             0x22, 0x41, 0xd8, 0xdc,
         ];
         assert_eq!(&pkt_iv, &expected_server_pkt_iv);
 
-        assert!(derive_hdr_key(aead, &secret, &mut hdr_key).is_ok());
+        assert!(derive_hdr_key(version, aead, &secret, &mut hdr_key).is_ok());
         let expected_server_hdr_key: [u8; 16] = [
             0x94, 0xb9, 0x45, 0x2d, 0x2b, 0x3c, 0x7c, 0x7f,
             0x6d, 0xa7, 0xfd, 0xd8, 0x59, 0x35, 0x37, 0xfd,
@@ -26,6 +26,7 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::ffi;
+use std::net;
 use std::ptr;
 use std::slice;
 use std::sync::atomic;
@@ -101,6 +102,32 @@ pub extern fn quiche_config_load_priv_key_from_pem_file(config: &mut Config,
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_config_load_cert_chain_from_der(config: &mut Config,
+                                                     der: *const u8,
+                                                     der_len: usize) -> c_int {
+    let der = unsafe { slice::from_raw_parts(der, der_len) };
+
+    match config.load_cert_chain_from_der(der) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_load_priv_key_from_der(config: &mut Config,
+                                                   der: *const u8,
+                                                   der_len: usize) -> c_int {
+    let der = unsafe { slice::from_raw_parts(der, der_len) };
+
+    match config.load_priv_key_from_der(der) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_verify_peer(config: &mut Config, v: bool) {
     config.verify_peer(v);
@@ -111,6 +138,131 @@ pub extern fn quiche_config_log_keys(config: &mut Config) {
     config.log_keys();
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_cid_len(config: &mut Config, v: usize) -> c_int {
+    match config.set_cid_len(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_new_cid(config: &Config, out: *mut u8,
+                                    out_len: usize) -> ssize_t {
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    let cid = config.new_cid();
+
+    if cid.len() > out.len() {
+        return Error::BufferTooShort.to_c();
+    }
+
+    out[..cid.len()].copy_from_slice(&cid);
+
+    cid.len() as ssize_t
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_retry_threshold(config: &mut Config, v: usize) {
+    config.set_retry_threshold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_retry_required(config: &Config) -> bool {
+    config.retry_required()
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_half_open_handshakes(config: &mut Config,
+                                                        v: usize) {
+    config.set_max_half_open_handshakes(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_initial_rtt(config: &mut Config, v: u64) {
+    config.set_initial_rtt(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_packet_reordering_threshold(config: &mut Config,
+                                                            v: u64) {
+    config.set_packet_reordering_threshold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_time_threshold(config: &mut Config, v: f64) {
+    config.set_time_threshold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_handshake_timeout(config: &mut Config, v: u64) {
+    config.set_handshake_timeout(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_keepalive(config: &mut Config, v: u64) {
+    config.enable_keepalive(std::time::Duration::from_millis(v));
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_ack_ranges(config: &mut Config, v: usize) {
+    config.set_max_ack_ranges(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_stream_burst(config: &mut Config, v: usize) {
+    config.set_stream_burst(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_stream_reorder_window(config: &mut Config,
+                                                     v: usize) {
+    config.set_stream_reorder_window(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_padding_policy(config: &mut Config,
+                                              handshake: bool,
+                                              random_min: usize,
+                                              random_max: usize,
+                                              bucket: usize) -> c_int {
+    let random = match random_max {
+        0 => None,
+
+        _ => Some((random_min, random_max)),
+    };
+
+    let policy = PaddingPolicy {
+        handshake,
+        random,
+        bucket,
+    };
+
+    match config.set_padding_policy(policy) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_timer_granularity(config: &mut Config,
+                                                  v: u64) {
+    config.set_timer_granularity(std::time::Duration::from_millis(v));
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_pacing(config: &mut Config, v: bool) {
+    config.enable_pacing(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_connection_memory(config: &mut Config,
+                                                      v: usize) {
+    config.set_max_connection_memory(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_idle_timeout(config: &mut Config, v: u64) {
     config.set_idle_timeout(v);
@@ -136,6 +288,12 @@ pub extern fn quiche_config_set_initial_max_stream_data_uni(config: &mut Config,
     config.set_initial_max_stream_data_uni(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_max_send_udp_payload_size(config: &mut Config,
+                                                          v: usize) {
+    config.set_max_send_udp_payload_size(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_initial_max_data(config: &mut Config, v: u64) {
     config.set_initial_max_data(v);
@@ -151,6 +309,17 @@ pub extern fn quiche_config_set_initial_max_streams_uni(config: &mut Config, v:
     config.set_initial_max_streams_uni(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_max_stream_window(config: &mut Config, v: u64) {
+    config.set_max_stream_window(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_max_connection_window(config: &mut Config,
+                                                      v: u64) {
+    config.set_max_connection_window(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_ack_delay_exponent(config: &mut Config, v: u64) {
     config.set_ack_delay_exponent(v);
@@ -166,6 +335,120 @@ pub extern fn quiche_config_set_disable_migration(config: &mut Config, v: bool)
     config.set_disable_migration(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_active_connection_id_limit(config: &mut Config,
+                                                           v: u64) {
+    config.set_active_connection_id_limit(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_cid_rotation_packets(config: &mut Config,
+                                                     v: u64) {
+    config.set_cid_rotation_packets(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_cid_rotation_interval(config: &mut Config,
+                                                      v: u64) {
+    config.set_cid_rotation_interval(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_cid_rotation_on_migration(config: &mut Config,
+                                                          v: bool) {
+    config.set_cid_rotation_on_migration(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_stateless_reset_key(config: &mut Config,
+                                                    v: *const u8,
+                                                    v_len: usize) {
+    let v = unsafe { slice::from_raw_parts(v, v_len) };
+
+    config.set_stateless_reset_key(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_dscp(config: &mut Config, v: u8) {
+    config.set_dscp(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_ocsp_response(config: &mut Config,
+                                              v: *const u8, v_len: usize)
+                                                            -> c_int {
+    let v = unsafe { slice::from_raw_parts(v, v_len) };
+
+    match config.set_ocsp_response(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_signed_cert_timestamps(config: &mut Config,
+                                                       v: *const u8,
+                                                       v_len: usize) -> c_int {
+    let v = unsafe { slice::from_raw_parts(v, v_len) };
+
+    match config.set_signed_cert_timestamps(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_ocsp_stapling(config: &mut Config) {
+    config.enable_ocsp_stapling();
+}
+
+#[no_mangle]
+pub extern fn quiche_config_enable_signed_cert_timestamps(config: &mut Config) {
+    config.enable_signed_cert_timestamps();
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_cipher_suites(config: &mut Config,
+                                              ids: *const u16, ids_len: usize)
+                                                            -> c_int {
+    let ids = unsafe { slice::from_raw_parts(ids, ids_len) };
+
+    let mut suites = Vec::new();
+
+    for id in ids {
+        let suite = match id {
+            0x1301 => CipherSuite::Aes128Gcm,
+            0x1302 => CipherSuite::Aes256Gcm,
+            0x1303 => CipherSuite::ChaCha20Poly1305,
+            _ => return Error::TlsFail.to_c() as c_int,
+        };
+
+        suites.push(suite);
+    }
+
+    match config.set_cipher_suites(&suites) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_curves(config: &mut Config,
+                                       curves: *const c_char) -> c_int {
+    let curves = unsafe { ffi::CStr::from_ptr(curves).to_str().unwrap() };
+
+    let curves: Vec<&str> = curves.split(':').collect();
+
+    match config.set_curves(&curves) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_free(config: *mut Config) {
     unsafe { Box::from_raw(config) };
@@ -274,6 +557,11 @@ pub extern fn quiche_connect(server_name: *const c_char, scid: *const u8,
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_version_is_supported(version: u32) -> bool {
+    version_is_supported(version)
+}
+
 #[no_mangle]
 pub extern fn quiche_negotiate_version(scid: *const u8, scid_len: usize,
                                        dcid: *const u8, dcid_len: usize,
@@ -323,7 +611,8 @@ pub extern fn quiche_conn_new_with_tls(scid: *const u8, scid_len: usize,
 
     let tls = tls::Handshake::from_void(ssl);
 
-    match Connection::with_tls(scid, odcid, config, tls, is_server) {
+    match Connection::with_tls(scid, odcid, config, tls, is_server,
+                               std::time::Instant::now()) {
         Ok(c) => Box::into_raw(c),
 
         Err(_) => ptr::null_mut(),
@@ -354,6 +643,42 @@ pub extern fn quiche_conn_send(conn: &mut Connection, out: *mut u8,
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_recv_with_ecn(conn: &mut Connection, buf: *mut u8,
+                                        buf_len: usize, ecn: u8) -> ssize_t {
+    let buf = unsafe { slice::from_raw_parts_mut(buf, buf_len) };
+
+    let info = RecvInfo {
+        ecn: Ecn::from_tos(ecn),
+        from: None,
+        to: None,
+    };
+
+    match conn.recv_with_info(buf, info) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_with_info(conn: &mut Connection, out: *mut u8,
+                                         out_len: usize, ecn: &mut u8,
+                                         dscp: &mut u8) -> ssize_t {
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match conn.send_with_info(out) {
+        Ok((v, info)) => {
+            *ecn = info.ecn as u8;
+            *dscp = info.dscp;
+
+            v as ssize_t
+        },
+
+        Err(e) => e.to_c(),
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stream_recv(conn: &mut Connection, stream_id: u64,
                                       max_len: usize) -> *const RangeBuf {
@@ -377,6 +702,76 @@ pub extern fn quiche_conn_stream_send(conn: &mut Connection, stream_id: u64,
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stream_splice(conn: &mut Connection, from_id: u64,
+                                        to_id: u64, max_len: usize)
+                                                            -> ssize_t {
+    match conn.stream_splice(from_id, to_id, max_len) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stream_finished(conn: &Connection, stream_id: u64)
+                                                                    -> bool {
+    conn.stream_finished(stream_id)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stream_readable(conn: &Connection, stream_id: u64)
+                                                                    -> bool {
+    conn.stream_readable(stream_id)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stream_writable(conn: &mut Connection,
+                                          stream_id: u64, len: usize) -> c_int {
+    match conn.stream_writable(stream_id, len) {
+        Ok(true) => 1,
+
+        Ok(false) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_peer_streams_left_bidi(conn: &Connection) -> usize {
+    conn.peer_streams_left_bidi()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_peer_streams_left_uni(conn: &Connection) -> usize {
+    conn.peer_streams_left_uni()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_local_streams_left_bidi(conn: &Connection) -> usize {
+    conn.local_streams_left_bidi()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_local_streams_left_uni(conn: &Connection) -> usize {
+    conn.local_streams_left_uni()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_pending_accepted_streams(conn: &Connection) -> usize {
+    conn.pending_accepted_streams()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_tx_credit(conn: &Connection) -> usize {
+    conn.tx_credit()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_rx_credit(conn: &Connection) -> usize {
+    conn.rx_credit()
+}
+
 #[no_mangle]
 pub extern fn quiche_rangebuf_data(b: &mut RangeBuf) -> *const u8 {
     (&b).as_ptr()
@@ -397,6 +792,141 @@ pub extern fn quiche_rangebuf_free(b: *mut RangeBuf) {
     unsafe { Box::from_raw(b) };
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_send_new_token(conn: &mut Connection,
+                                         token: *const u8, token_len: usize)
+                                                            -> c_int {
+    let token = unsafe { slice::from_raw_parts(token, token_len) };
+
+    match conn.send_new_token(token) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_token(conn: &mut Connection,
+                                    token: *const u8, token_len: usize)
+                                                            -> c_int {
+    let token = unsafe { slice::from_raw_parts(token, token_len) };
+
+    match conn.set_token(token) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_new_source_cid(conn: &mut Connection,
+                                         cid: *const u8, cid_len: usize,
+                                         reset_token: *const u8) -> i64 {
+    let cid = unsafe { slice::from_raw_parts(cid, cid_len) };
+
+    let reset_token = unsafe { &*(reset_token as *const [u8; 16]) };
+
+    match conn.new_source_cid(cid, reset_token) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_on_peer_migrated(conn: &mut Connection) -> c_int {
+    match conn.on_peer_migrated() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_trace_id(conn: &mut Connection,
+                                       trace_id: *const c_char) {
+    let trace_id = unsafe { ffi::CStr::from_ptr(trace_id).to_str().unwrap() };
+    conn.set_trace_id(trace_id);
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_source_id(conn: &Connection, out: &mut *const u8,
+                                    out_len: &mut usize) {
+    let scid = conn.source_id();
+
+    *out = scid.as_ptr();
+    *out_len = scid.len();
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_new_token(conn: &mut Connection, out: &mut *const u8,
+                                    out_len: &mut usize) {
+    match conn.new_token() {
+        Some(token) => {
+            *out = token.as_ptr();
+            *out_len = token.len();
+        },
+
+        None => {
+            *out = ptr::null();
+            *out_len = 0;
+        },
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_poll(conn: &mut Connection, stream_id: &mut u64)
+                                                                    -> c_int {
+    match conn.poll() {
+        Some(Event::HandshakeCompleted) => 0,
+
+        Some(Event::StreamReadable(id)) => {
+            *stream_id = id;
+            1
+        },
+
+        Some(Event::StreamWritable(id)) => {
+            *stream_id = id;
+            2
+        },
+
+        Some(Event::NewToken(_)) => 3,
+
+        Some(Event::ConnectionClosed) => 4,
+
+        Some(Event::PeerAddressChanged(_)) => 5,
+
+        Some(Event::ConnectionIdRotated) => 6,
+
+        None => Error::Done.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_peer_addr(conn: &Connection, ip: *mut u8,
+                                    ip_len: &mut usize, port: &mut u16)
+                                                                    -> c_int {
+    let addr = match conn.peer_addr() {
+        Some(v) => v,
+
+        None => return Error::Done.to_c() as c_int,
+    };
+
+    let octets = match addr.ip() {
+        net::IpAddr::V4(v) => v.octets().to_vec(),
+
+        net::IpAddr::V6(v) => v.octets().to_vec(),
+    };
+
+    let ip = unsafe { slice::from_raw_parts_mut(ip, octets.len()) };
+    ip.copy_from_slice(&octets);
+
+    *ip_len = octets.len();
+    *port = addr.port();
+
+    0
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_readable(conn: &mut Connection) -> *mut Readable {
     let iter = conn.readable();
@@ -418,6 +948,34 @@ pub extern fn quiche_readable_free(i: *mut Readable) {
     unsafe { Box::from_raw(i) };
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_accepted(conn: &mut Connection) -> *mut Accepted {
+    let iter = conn.accepted();
+    Box::into_raw(Box::new(iter))
+}
+
+#[no_mangle]
+pub extern fn quiche_accepted_next(iter: &mut Accepted, stream_id: *mut u64) -> bool {
+    if let Some(v) = iter.next() {
+        unsafe { *stream_id = v };
+        return true;
+    }
+
+    false
+}
+
+#[no_mangle]
+pub extern fn quiche_accepted_free(i: *mut Accepted) {
+    unsafe { Box::from_raw(i) };
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_drain(conn: &mut Connection, grace_ms: u64) {
+    let grace = std::time::Duration::from_millis(grace_ms);
+
+    conn.drain(std::time::Instant::now() + grace);
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_close(conn: &mut Connection, app: bool, err: u16,
                                 reason: *const u8, reason_len: usize) -> c_int {
@@ -440,6 +998,24 @@ pub extern fn quiche_conn_timeout_as_nanos(conn: &mut Connection) -> u64 {
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_next_event_as_nanos(conn: &mut Connection) -> u64 {
+    let now = std::time::Instant::now();
+
+    match conn.next_event_time() {
+        Some(t) if t > now => {
+            let timeout = t.duration_since(now);
+
+            timeout.as_secs() * 1_000_000_000 +
+                u64::from(timeout.subsec_nanos())
+        },
+
+        Some(_) => 0,
+
+        None => std::u64::MAX,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_on_timeout(conn: &mut Connection) {
     conn.on_timeout()
@@ -450,11 +1026,97 @@ pub extern fn quiche_conn_is_established(conn: &mut Connection) -> bool {
     conn.is_established()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_cipher_suite(conn: &Connection) -> u16 {
+    conn.cipher_suite().map_or(0, CipherSuite::id)
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_peer_cert_chain_len(conn: &Connection) -> usize {
+    conn.peer_cert_chain().map_or(0, |chain| chain.len())
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_peer_cert(conn: &Connection, idx: usize,
+                                    out: &mut *const u8,
+                                    out_len: &mut usize) -> bool {
+    match conn.peer_cert_chain().and_then(|chain| chain.get(idx).cloned()) {
+        Some(v) => {
+            *out = v.as_ptr();
+            *out_len = v.len();
+
+            true
+        },
+
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_ocsp_response(conn: &Connection,
+                                        out: &mut *const u8,
+                                        out_len: &mut usize) -> bool {
+    match conn.ocsp_response() {
+        Some(v) => {
+            *out = v.as_ptr();
+            *out_len = v.len();
+
+            true
+        },
+
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_signed_cert_timestamps(conn: &Connection,
+                                                 out: &mut *const u8,
+                                                 out_len: &mut usize) -> bool {
+    match conn.signed_cert_timestamps() {
+        Some(v) => {
+            *out = v.as_ptr();
+            *out_len = v.len();
+
+            true
+        },
+
+        None => false,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_is_closed(conn: &mut Connection) -> bool {
     conn.is_closed()
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_local_tls_alert(conn: &Connection, out: &mut u8)
+                                                                    -> bool {
+    match conn.local_tls_alert() {
+        Some(v) => {
+            *out = v;
+
+            true
+        },
+
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_peer_tls_alert(conn: &Connection, out: &mut u8)
+                                                                    -> bool {
+    match conn.peer_tls_alert() {
+        Some(v) => {
+            *out = v;
+
+            true
+        },
+
+        None => false,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stats_sent(conn: &Connection, out: &mut u64) {
     *out = conn.stats().sent as u64;
@@ -465,6 +1127,11 @@ pub extern fn quiche_conn_stats_lost(conn: &Connection, out: &mut u64) {
     *out = conn.stats().lost as u64;
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stats_spurious_lost(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().spurious_lost as u64;
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_stats_rtt_as_nanos(conn: &Connection, out: &mut u64) {
     let rtt = conn.stats().rtt;
@@ -472,7 +1139,134 @@ pub extern fn quiche_conn_stats_rtt_as_nanos(conn: &Connection, out: &mut u64) {
     *out = rtt.as_secs() * 1_000_000_000 + u64::from(rtt.subsec_nanos());
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_stats_min_rtt_as_nanos(conn: &Connection,
+                                                 out: &mut u64) {
+    let min_rtt = conn.stats().min_rtt;
+
+    *out = min_rtt.as_secs() * 1_000_000_000 +
+        u64::from(min_rtt.subsec_nanos());
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_rttvar_as_nanos(conn: &Connection,
+                                                out: &mut u64) {
+    let rttvar = conn.stats().rttvar;
+
+    *out = rttvar.as_secs() * 1_000_000_000 +
+        u64::from(rttvar.subsec_nanos());
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_persistent_congestion(conn: &Connection,
+                                                      out: &mut u64) {
+    *out = conn.stats().persistent_congestion as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_ect0_recv(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().ect0_recv as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_ect1_recv(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().ect1_recv as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_ce_recv(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().ce_recv as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_mem_usage(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().mem_usage as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_dup_recv(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().dup_recv as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_stats_rebinds(conn: &Connection, out: &mut u64) {
+    *out = conn.stats().rebinds as u64;
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_path_params(conn: &Connection, cwnd: &mut usize,
+                                      min_rtt: &mut u64) {
+    let params = conn.path_params();
+
+    *cwnd = params.cwnd;
+    *min_rtt = params.min_rtt.as_secs() * 1_000_000_000 +
+        u64::from(params.min_rtt.subsec_nanos());
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_set_resume_params(conn: &mut Connection, cwnd: usize,
+                                            min_rtt: u64) {
+    conn.set_resume_params(PathParams {
+        cwnd,
+        min_rtt: std::time::Duration::from_nanos(min_rtt),
+    });
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_free(conn: *mut Connection) {
     unsafe { Box::from_raw(conn) };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_peer_address_changed() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut stream_id = 0;
+        let mut ip = [0; 16];
+        let mut ip_len = 0;
+        let mut port = 0;
+
+        while quiche_conn_poll(&mut pipe.server, &mut stream_id) >= 0 {}
+
+        assert_eq!(quiche_conn_peer_addr(&pipe.server, ip.as_mut_ptr(),
+                                         &mut ip_len, &mut port),
+                   Error::Done.to_c() as c_int);
+
+        for addr in &["127.0.0.1:1000", "127.0.0.1:2000"] {
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+
+            let info = RecvInfo {
+                ecn: Ecn::NotEct,
+                from: Some(addr.parse().unwrap()),
+                to: None,
+            };
+
+            for mut pkt in testing::emit_flight(&mut pipe.client).unwrap() {
+                let len = pkt.len();
+                assert_eq!(pipe.server.recv_with_info(&mut pkt, info), Ok(len));
+            }
+        }
+
+        let mut events = Vec::new();
+
+        loop {
+            match quiche_conn_poll(&mut pipe.server, &mut stream_id) {
+                e if e < 0 => break,
+
+                e => events.push(e),
+            }
+        }
+
+        assert!(events.contains(&5));
+
+        assert_eq!(quiche_conn_peer_addr(&pipe.server, ip.as_mut_ptr(),
+                                         &mut ip_len, &mut port), 0);
+        assert_eq!(&ip[..ip_len], &[127, 0, 0, 1]);
+        assert_eq!(port, 2000);
+    }
+}
@@ -25,6 +25,8 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::convert::TryFrom;
+
 use crate::Result;
 use crate::Error;
 
@@ -34,7 +36,9 @@ use crate::ranges;
 use crate::stream;
 
 pub const MAX_CRYPTO_OVERHEAD: usize = 8;
-pub const MAX_STREAM_OVERHEAD: usize = 12;
+
+// The largest offset of data carried by STREAM and CRYPTO frames.
+const MAX_STREAM_OFFSET: u64 = (1 << 62) - 1;
 
 #[derive(PartialEq)]
 pub enum Frame {
@@ -87,7 +91,7 @@ pub enum Frame {
     NewConnectionId {
         seq_num: u64,
         conn_id: Vec<u8>,
-        reset_token: Vec<u8>,
+        reset_token: [u8; 16],
     },
 
     RetireConnectionId {
@@ -95,11 +99,11 @@ pub enum Frame {
     },
 
     PathChallenge {
-        data: Vec<u8>,
+        data: [u8; 8],
     },
 
     PathResponse {
-        data: Vec<u8>,
+        data: [u8; 8],
     },
 
     ConnectionClose {
@@ -112,117 +116,45 @@ pub enum Frame {
         error_code: u16,
         reason: Vec<u8>,
     },
+
+    Extension {
+        ty: u64,
+        data: Vec<u8>,
+    },
 }
 
 impl Frame {
-    pub fn from_bytes(b: &mut octets::Octets, pkt: packet::Type) -> Result<Frame> {
-        let frame_type = b.get_varint()?;
-
-        // println!("GOT FRAME {:x}", frame_type);
-
-        let frame = match frame_type {
-            0x00 => {
-                let mut len = 1;
-
-                while b.peek_u8() == Ok(0x00) {
-                    b.get_u8()?;
-
-                    len += 1;
-                }
-
-                Frame::Padding { len }
-            },
-
-            0x01 => Frame::Ping,
-
-            0x02 => parse_ack_frame(frame_type, b)?,
-
-            0x05 => Frame::StopSending {
-                stream_id: b.get_varint()?,
-                error_code: b.get_u16()?,
-            },
-
-            0x06 => {
-                let offset = b.get_varint()? as usize;
-                let data = b.get_bytes_with_varint_length()?;
-                let data = stream::RangeBuf::from(data.as_ref(), offset, false);
-
-                Frame::Crypto { data }
-            },
-
-            0x07 => Frame::NewToken {
-                token: b.get_bytes_with_varint_length()?.to_vec(),
-            },
-
-            0x08 ... 0x0f => parse_stream_frame(frame_type, b)?,
-
-            0x10 => Frame::MaxData {
-                max: b.get_varint()?,
-            },
-
-            0x11 => Frame::MaxStreamData {
-                stream_id: b.get_varint()?,
-                max: b.get_varint()?,
-            },
-
-            0x12 => Frame::MaxStreamsBidi {
-                max: b.get_varint()?,
-            },
+    pub fn from_bytes(b: &mut octets::OctetsMut, pkt: packet::Type) -> Result<Frame> {
+        let frame_type = get_frame_type(b)?;
 
-            0x13 => Frame::MaxStreamsUni {
-                max: b.get_varint()?,
-            },
-
-            0x18 => Frame::NewConnectionId {
-                seq_num: b.get_varint()?,
-                conn_id: b.get_bytes_with_u8_length()?.to_vec(),
-                reset_token: b.get_bytes(16)?.to_vec(),
-            },
-
-            0x19 => Frame::RetireConnectionId {
-                seq_num: b.get_varint()?,
-            },
-
-            0x1a => Frame::PathChallenge {
-                data: b.get_bytes(8)?.to_vec(),
-            },
-
-            0x1b => Frame::PathResponse {
-                data: b.get_bytes(8)?.to_vec(),
-            },
-
-            0x1c => Frame::ConnectionClose {
-                error_code: b.get_u16()?,
-                frame_type: b.get_varint()?,
-                reason: b.get_bytes_with_varint_length()?.to_vec(),
-            },
-
-            0x1d => Frame::ApplicationClose {
-                error_code: b.get_u16()?,
-                reason: b.get_bytes_with_varint_length()?.to_vec(),
-            },
-
-            _    => return Err(Error::InvalidFrame),
-        };
+        let frame = parse_frame(frame_type, b)
+            .map_err(|e| encoding_error(frame_type, e))?;
 
         let allowed = match (pkt, &frame) {
-            // PADDING is allowed on all packet types.
+            // PADDING, PING and CONNECTION_CLOSE are allowed on all packet
+            // types.
             (_, Frame::Padding { .. }) => true,
+            (_, Frame::Ping) => true,
+            (_, Frame::ConnectionClose { .. }) => true,
 
-            // ACK, CRYPTO and CONNECTION_CLOSE frames are allowed on all
-            // packet types except 0-RTT.
+            // ACK, CRYPTO, NEW_TOKEN, PATH_RESPONSE and RETIRE_CONNECTION_ID
+            // are not allowed on 0-RTT packets, as they are only sent in
+            // response to the server, or require 1-RTT keys.
             (packet::Type::ZeroRTT, Frame::ACK { .. }) => false,
             (packet::Type::ZeroRTT, Frame::Crypto { .. }) => false,
-            (packet::Type::ZeroRTT, Frame::ConnectionClose { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::NewToken { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::PathResponse { .. }) => false,
+            (packet::Type::ZeroRTT, Frame::RetireConnectionId { .. }) => false,
 
+            // ACK and CRYPTO are also allowed on Initial and Handshake
+            // packets.
             (_, Frame::ACK { .. }) => true,
             (_, Frame::Crypto { .. }) => true,
-            (_, Frame::ConnectionClose { .. }) => true,
 
-            // All frames are allowed on Application packets.
+            // All other frames are only allowed on 0-RTT and 1-RTT packets.
+            (packet::Type::ZeroRTT, _) => true,
             (packet::Type::Application, _) => true,
 
-            // All other cases are forbidden.
             (_, _) => false,
         };
 
@@ -233,7 +165,7 @@ impl Frame {
         Ok(frame)
     }
 
-    pub fn to_bytes(&self, b: &mut octets::Octets) -> Result<usize> {
+    pub fn to_bytes(&self, b: &mut octets::OctetsMut) -> Result<usize> {
         let before = b.cap();
 
         match self {
@@ -388,6 +320,13 @@ impl Frame {
                 b.put_varint(reason.len() as u64)?;
                 b.put_bytes(reason.as_ref())?;
             },
+
+            Frame::Extension { ty, data } => {
+                b.put_varint(*ty)?;
+
+                b.put_varint(data.len() as u64)?;
+                b.put_bytes(data.as_ref())?;
+            },
         }
 
         Ok(before - b.cap())
@@ -514,6 +453,43 @@ impl Frame {
                 octets::varint_len(reason.len() as u64) + // reason_len
                 reason.len()                              // reason
             },
+
+            Frame::Extension { ty, data } => {
+                octets::varint_len(*ty) +               // frame type
+                octets::varint_len(data.len() as u64) + // length
+                data.len()                              // data
+            },
+        }
+    }
+
+    /// Returns whether the frame elicits an ACK from the receiver.
+    ///
+    /// Packets containing only non-eliciting frames (ACK, PADDING and
+    /// CONNECTION_CLOSE) are never acknowledged on their own, and don't
+    /// count towards bytes in flight.
+    pub fn ack_eliciting(&self) -> bool {
+        match self {
+            Frame::Padding { .. } |
+            Frame::ACK { .. } |
+            Frame::ConnectionClose { .. } |
+            Frame::ApplicationClose { .. } => false,
+
+            _ => true,
+        }
+    }
+
+    /// Returns the amount of heap memory held by the frame's payload.
+    pub fn mem_usage(&self) -> usize {
+        match self {
+            Frame::Crypto { data } => data.mem_usage(),
+
+            Frame::Stream { data, .. } => data.mem_usage(),
+
+            Frame::NewToken { token } => token.capacity(),
+
+            Frame::Extension { data, .. } => data.capacity(),
+
+            _ => 0,
         }
     }
 }
@@ -542,8 +518,8 @@ impl std::fmt::Debug for Frame {
                 write!(f, "CRYPTO off={} len={}", data.off(), data.len())?;
             },
 
-            Frame::NewToken { .. } => {
-                write!(f, "NEW_TOKEN (TODO)")?;
+            Frame::NewToken { token } => {
+                write!(f, "NEW_TOKEN len={}", token.len())?;
             },
 
             Frame::Stream { stream_id, data } => {
@@ -592,20 +568,188 @@ impl std::fmt::Debug for Frame {
                 write!(f, "APPLICATION_CLOSE err={:x} reason={:x?}",
                        error_code, reason)?;
             },
+
+            Frame::Extension { ty, data } => {
+                write!(f, "EXTENSION type={:x} len={}", ty, data.len())?;
+            },
         }
 
         Ok(())
     }
 }
 
-fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+/// Parses the body of a frame of type `ty`.
+fn parse_frame(frame_type: u64, b: &mut octets::OctetsMut) -> Result<Frame> {
+    let frame = match frame_type {
+        0x00 => {
+            let mut len = 1;
+
+            while b.peek_u8() == Ok(0x00) {
+                b.get_u8()?;
+
+                len += 1;
+            }
+
+            Frame::Padding { len }
+        },
+
+        0x01 => Frame::Ping,
+
+        0x02 => parse_ack_frame(frame_type, b)?,
+
+        0x05 => Frame::StopSending {
+            stream_id: b.get_varint()?,
+            error_code: b.get_u16()?,
+        },
+
+        0x06 => {
+            let offset = b.get_varint()?;
+            let data = b.get_bytes_with_varint_length()?;
+            let offset = stream_offset(frame_type, offset, data.len())?;
+            let data = stream::RangeBuf::from(data.as_ref(), offset, false);
+
+            Frame::Crypto { data }
+        },
+
+        0x07 => {
+            let token = b.get_bytes_with_varint_length()?.to_vec();
+
+            // An empty token is a protocol violation.
+            if token.is_empty() {
+                return Err(Error::InvalidFrame { ty: frame_type });
+            }
+
+            Frame::NewToken { token }
+        },
+
+        0x08 ... 0x0f => parse_stream_frame(frame_type, b)?,
+
+        0x10 => Frame::MaxData {
+            max: b.get_varint()?,
+        },
+
+        0x11 => Frame::MaxStreamData {
+            stream_id: b.get_varint()?,
+            max: b.get_varint()?,
+        },
+
+        0x12 => Frame::MaxStreamsBidi {
+            max: max_streams(frame_type, b.get_varint()?)?,
+        },
+
+        0x13 => Frame::MaxStreamsUni {
+            max: max_streams(frame_type, b.get_varint()?)?,
+        },
+
+        0x18 => {
+            let seq_num = b.get_varint()?;
+            let conn_id = b.get_bytes_with_u8_length()?.to_vec();
+
+            if conn_id.len() < crate::MIN_CONN_ID_LEN ||
+               conn_id.len() > crate::MAX_CONN_ID_LEN {
+                return Err(Error::InvalidFrame { ty: frame_type });
+            }
+
+            let mut reset_token = [0; 16];
+            reset_token.copy_from_slice(b.get_bytes(16)?.as_ref());
+
+            Frame::NewConnectionId { seq_num, conn_id, reset_token }
+        },
+
+        0x19 => Frame::RetireConnectionId {
+            seq_num: b.get_varint()?,
+        },
+
+        0x1a => {
+            let mut data = [0; 8];
+            data.copy_from_slice(b.get_bytes(8)?.as_ref());
+
+            Frame::PathChallenge { data }
+        },
+
+        0x1b => {
+            let mut data = [0; 8];
+            data.copy_from_slice(b.get_bytes(8)?.as_ref());
+
+            Frame::PathResponse { data }
+        },
+
+        0x1c => Frame::ConnectionClose {
+            error_code: b.get_u16()?,
+            frame_type: b.get_varint()?,
+            reason: b.get_bytes_with_varint_length()?.to_vec(),
+        },
+
+        0x1d => Frame::ApplicationClose {
+            error_code: b.get_u16()?,
+            reason: b.get_bytes_with_varint_length()?.to_vec(),
+        },
+
+        _    => return Err(Error::InvalidFrame { ty: frame_type }),
+    };
+
+    Ok(frame)
+}
+
+/// Reads a frame type, which must use the shortest possible encoding.
+pub fn get_frame_type(b: &mut octets::OctetsMut) -> Result<u64> {
+    let off = b.off();
+
+    let ty = b.get_varint()?;
+
+    if b.off() - off != octets::varint_len(ty) {
+        return Err(Error::InvalidFrame { ty });
+    }
+
+    Ok(ty)
+}
+
+/// Reports truncated frames as malformed, rather than as a local buffer
+/// error.
+fn encoding_error(ty: u64, e: Error) -> Error {
+    match e {
+        Error::BufferTooShort => Error::InvalidFrame { ty },
+
+        e => e,
+    }
+}
+
+/// Validates the offset of `len` bytes of STREAM or CRYPTO data.
+fn stream_offset(ty: u64, off: u64, len: usize) -> Result<usize> {
+    let end = off.checked_add(len as u64)
+                 .filter(|&end| end <= MAX_STREAM_OFFSET)
+                 .and_then(|end| usize::try_from(end).ok());
+
+    match end {
+        Some(_) => Ok(off as usize),
+
+        None => Err(Error::InvalidFrame { ty }),
+    }
+}
+
+/// Validates the stream limit of a MAX_STREAMS frame.
+fn max_streams(ty: u64, max: u64) -> Result<u64> {
+    if max > crate::MAX_STREAMS {
+        return Err(Error::InvalidFrame { ty });
+    }
+
+    Ok(max)
+}
+
+fn parse_ack_frame(ty: u64, b: &mut octets::OctetsMut) -> Result<Frame> {
     let largest_ack = b.get_varint()?;
     let ack_delay = b.get_varint()?;
     let block_count = b.get_varint()?;
     let ack_block = b.get_varint()?;
 
+    // Each block takes at least two bytes, so don't even start parsing if
+    // the declared count can't possibly fit.
+    if block_count > (b.cap() / 2) as u64 {
+        return Err(Error::InvalidFrame { ty });
+    }
+
     if largest_ack < ack_block {
-        return Err(Error::InvalidFrame);
+        return Err(Error::InvalidFrame { ty });
     }
 
     let mut smallest_ack = largest_ack - ack_block;
@@ -618,15 +762,15 @@ fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
     for _i in 0..block_count {
         let gap = b.get_varint()?;
 
-        if smallest_ack - gap < 2 {
-            return Err(Error::InvalidFrame);
+        if smallest_ack < gap + 2 {
+            return Err(Error::InvalidFrame { ty });
         }
 
         let largest_ack = (smallest_ack - gap) - 2;
         let ack_block = b.get_varint()?;
 
         if largest_ack < ack_block {
-            return Err(Error::InvalidFrame);
+            return Err(Error::InvalidFrame { ty });
         }
 
         smallest_ack = largest_ack - ack_block;
@@ -638,29 +782,74 @@ fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
     Ok(Frame::ACK { ack_delay, ranges })
 }
 
-fn parse_stream_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+/// Parses the body of an extension frame of type `ty`, made of a
+/// length-prefixed payload.
+pub fn parse_extension_frame(ty: u64, b: &mut octets::OctetsMut)
+                                                        -> Result<Frame> {
+    let data = b.get_bytes_with_varint_length()
+                .map_err(|e| encoding_error(ty, e))?;
+
+    Ok(Frame::Extension { ty, data: data.to_vec() })
+}
+
+fn parse_stream_frame(ty: u64, b: &mut octets::OctetsMut) -> Result<Frame> {
+    let (stream_id, offset, data, fin) = parse_stream_frame_ref(ty, b)?;
+
+    let data = stream::RangeBuf::from(data.as_ref(), offset, fin);
+
+    Ok(Frame::Stream { stream_id, data })
+}
+
+/// Returns whether the given frame type is a STREAM frame.
+pub fn is_stream(ty: u64) -> bool {
+    ty >= 0x08 && ty <= 0x0f
+}
+
+/// Returns the size of the header of a STREAM frame for the given stream and
+/// offset, carrying at most `max_len` bytes of data.
+pub fn stream_frame_overhead(stream_id: u64, off: usize, max_len: usize)
+                                                                    -> usize {
+    1 +                                     // frame type
+    octets::varint_len(stream_id) +         // stream_id
+    octets::varint_len(off as u64) +        // offset
+    octets::varint_len(max_len as u64)      // length
+}
+
+/// Parses the body of a STREAM frame of type `ty` without copying its data.
+///
+/// Returns the stream ID, offset, data and fin flag of the frame. The data
+/// references the input buffer.
+pub fn parse_stream_frame_ref<'b>(ty: u64, b: &'b mut octets::OctetsMut)
+                        -> Result<(u64, usize, octets::OctetsMut<'b>, bool)> {
     let first = ty as u8;
 
-    let stream_id = b.get_varint()?;
+    let stream_id = b.get_varint().map_err(|e| encoding_error(ty, e))?;
 
     let offset = if first & 0x04 != 0 {
-        b.get_varint()?
+        b.get_varint().map_err(|e| encoding_error(ty, e))?
     } else {
         0
     };
 
     let len = if first & 0x02 != 0 {
-        b.get_varint()? as usize
+        let len = b.get_varint().map_err(|e| encoding_error(ty, e))?;
+
+        if len > b.cap() as u64 {
+            return Err(Error::InvalidFrame { ty });
+        }
+
+        len as usize
     } else {
         b.cap()
     };
 
     let fin = first & 0x01 != 0;
 
-    let data = b.get_bytes(len)?;
-    let data = stream::RangeBuf::from(data.as_ref(), offset as usize, fin);
+    let offset = stream_offset(ty, offset, len)?;
 
-    Ok(Frame::Stream { stream_id, data })
+    let data = b.get_bytes(len).map_err(|e| encoding_error(ty, e))?;
+
+    Ok((stream_id, offset, data, fin))
 }
 
 
@@ -677,23 +866,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 128);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
@@ -704,25 +893,25 @@ mod tests {
         let frame = Frame::Ping;
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 1);
         assert_eq!(&d[..wire_len], [0x01 as u8]);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
     #[test]
@@ -741,23 +930,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 17);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
@@ -771,23 +960,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 7);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -802,23 +991,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 18);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
@@ -831,23 +1020,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 17);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -863,23 +1052,25 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 19);
+        assert_eq!(stream_frame_overhead(32, 1230976, data.len()),
+                   wire_len - data.len());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -892,23 +1083,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 5);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -922,23 +1113,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 7);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -951,23 +1142,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 5);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -980,23 +1171,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 5);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -1007,27 +1198,27 @@ mod tests {
         let frame = Frame::NewConnectionId {
             seq_num: 123_213,
             conn_id: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
-            reset_token: vec![0x42; 16],
+            reset_token: [0x42; 16],
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 37);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -1040,23 +1231,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 5);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -1065,27 +1256,27 @@ mod tests {
         let mut d: [u8; 128] = [42; 128];
 
         let frame = Frame::PathChallenge {
-            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            data: [1, 2, 3, 4, 5, 6, 7, 8],
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 9);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -1094,27 +1285,27 @@ mod tests {
         let mut d: [u8; 128] = [42; 128];
 
         let frame = Frame::PathResponse {
-            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            data: [1, 2, 3, 4, 5, 6, 7, 8],
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 9);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
@@ -1129,23 +1320,23 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 20);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
@@ -1159,23 +1350,205 @@ mod tests {
         };
 
         let wire_len = {
-            let mut b = octets::Octets::with_slice(&mut d);
+            let mut b = octets::OctetsMut::with_slice(&mut d);
             frame.to_bytes(&mut b).unwrap()
         };
 
         assert_eq!(wire_len, 16);
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
                    Ok(frame));
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
 
-        let mut b = octets::Octets::with_slice(&mut d);
-        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
+
+    #[test]
+    fn extension() {
+        let mut d: [u8; 128] = [42; 128];
+
+        let frame = Frame::Extension {
+            ty: 0x4242,
+            data: vec![1, 2, 3, 4, 5],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 8);
+        assert_eq!(frame.wire_len(), 8);
+
+        // The generic parser doesn't know about extension frames.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x4242 }));
+
+        let mut b = octets::OctetsMut::with_slice(&mut d[..wire_len]);
+        let ty = get_frame_type(&mut b).unwrap();
+        assert_eq!(parse_extension_frame(ty, &mut b), Ok(frame));
+
+        let mut b = octets::OctetsMut::with_slice(&mut d[..wire_len - 1]);
+        let ty = get_frame_type(&mut b).unwrap();
+        assert_eq!(parse_extension_frame(ty, &mut b),
+                   Err(Error::InvalidFrame { ty: 0x4242 }));
+    }
+
+    #[test]
+    fn ack_eliciting() {
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(0..1);
+
+        assert!(!Frame::Padding { len: 10 }.ack_eliciting());
+        assert!(!Frame::ACK { ack_delay: 0, ranges }.ack_eliciting());
+        assert!(!Frame::ConnectionClose {
+            error_code: 0,
+            frame_type: 0,
+            reason: Vec::new(),
+        }.ack_eliciting());
+        assert!(!Frame::ApplicationClose {
+            error_code: 0,
+            reason: Vec::new(),
+        }.ack_eliciting());
+
+        assert!(Frame::Ping.ack_eliciting());
+        assert!(Frame::MaxData { max: 10 }.ack_eliciting());
+        assert!(Frame::PathResponse { data: [0; 8] }.ack_eliciting());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(4..7);
+        ranges.insert(3000..5000);
+
+        let frames = [
+            Frame::ACK { ack_delay: 874_656_534, ranges },
+            Frame::Crypto {
+                data: stream::RangeBuf::from(&[1, 2, 3, 4], 1230976, false),
+            },
+            Frame::Stream {
+                stream_id: 32,
+                data: stream::RangeBuf::from(&[1, 2, 3, 4], 1230976, true),
+            },
+            Frame::MaxStreamData { stream_id: 12_321, max: 128_318_273 },
+            Frame::NewConnectionId {
+                seq_num: 123_213,
+                conn_id: vec![0xba; 8],
+                reset_token: [0x42; 16],
+            },
+            Frame::ConnectionClose {
+                error_code: 0xbeef,
+                frame_type: 523_423,
+                reason: vec![1, 2, 3, 4],
+            },
+        ];
+
+        for frame in frames.iter() {
+            let mut d: [u8; 128] = [42; 128];
+
+            let wire_len = {
+                let mut b = octets::OctetsMut::with_slice(&mut d);
+                frame.to_bytes(&mut b).unwrap()
+            };
+
+            for len in 1..wire_len {
+                let mut prefix = d[..len].to_vec();
+
+                let mut b = octets::OctetsMut::with_slice(&mut prefix);
+                assert!(Frame::from_bytes(&mut b, packet::Type::Application)
+                            .is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn non_minimal_frame_type() {
+        // PING encoded in two bytes.
+        let mut d = [0x40, 0x01];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x01 }));
+    }
+
+    #[test]
+    fn stream_offset_overflow() {
+        // STREAM frame with OFF and LEN bits, at the maximum offset.
+        let mut d = [
+            0x0e, 0x04, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x02, 0x01, 0x02,
+        ];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x0e }));
+
+        // CRYPTO frame at the maximum offset.
+        let mut d = [
+            0x06, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x02, 0x01, 0x02,
+        ];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x06 }));
+    }
+
+    #[test]
+    fn stream_length_overflow() {
+        // STREAM frame claiming more data than the packet carries.
+        let mut d = [0x0a, 0x04, 0xbf, 0xff, 0xff, 0xff, 0x01, 0x02];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x0a }));
+    }
+
+    #[test]
+    fn new_connection_id_bad_length() {
+        for &len in [0, 3, 19, 255].iter() {
+            let mut d = vec![0x18, 0x01, len];
+            d.extend_from_slice(&[0xba; 255][..len as usize]);
+            d.extend_from_slice(&[0x42; 16]);
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                       Err(Error::InvalidFrame { ty: 0x18 }));
+        }
+    }
+
+    #[test]
+    fn max_streams_overflow() {
+        let mut d: [u8; 128] = [42; 128];
+
+        let frame = Frame::MaxStreamsBidi { max: crate::MAX_STREAMS + 1 };
+
+        {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap();
+        }
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x12 }));
+    }
+
+    #[test]
+    fn ack_block_count_overflow() {
+        // ACK frame claiming 2^22 blocks.
+        let mut d = [0x02, 0x40, 0x64, 0x00, 0x80, 0x40, 0x00, 0x00, 0x00];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Application),
+                   Err(Error::InvalidFrame { ty: 0x02 }));
+    }
 }
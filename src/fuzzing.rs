@@ -0,0 +1,136 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Entry points for fuzzing the parsers.
+//!
+//! Each function feeds arbitrary input to one of the parsers exposed to the
+//! network, and re-encodes whatever was successfully parsed. The functions
+//! are deterministic and must never panic, whatever the input, so any panic
+//! found by a fuzzer is a bug.
+//!
+//! This module is only available when the `fuzzing` feature is enabled.
+
+use crate::frame;
+use crate::octets;
+use crate::packet;
+
+/// Parses a packet header.
+pub fn packet_header(buf: &[u8]) {
+    let mut buf = buf.to_vec();
+
+    let hdr = match packet::Header::from_slice(&mut buf, crate::MAX_CONN_ID_LEN) {
+        Ok(v) => v,
+
+        Err(_) => return,
+    };
+
+    let mut out = [0; 512];
+    let mut b = octets::OctetsMut::with_slice(&mut out);
+
+    hdr.to_bytes(&mut b).ok();
+}
+
+/// Parses a sequence of frames, as carried by each packet type.
+pub fn frames(buf: &[u8]) {
+    let types = [
+        packet::Type::Initial,
+        packet::Type::Handshake,
+        packet::Type::ZeroRTT,
+        packet::Type::Application,
+    ];
+
+    for ty in types.iter() {
+        let mut buf = buf.to_vec();
+        let mut b = octets::OctetsMut::with_slice(&mut buf);
+
+        while b.cap() > 0 {
+            let frame = match frame::Frame::from_bytes(&mut b, *ty) {
+                Ok(v) => v,
+
+                Err(_) => break,
+            };
+
+            let mut out = vec![0; frame.wire_len()];
+            let mut o = octets::OctetsMut::with_slice(&mut out);
+
+            frame.to_bytes(&mut o).ok();
+        }
+    }
+}
+
+/// Parses transport parameters, as sent by either the client or the server.
+pub fn transport_params(buf: &[u8]) {
+    for is_server in [false, true].iter() {
+        let mut buf = buf.to_vec();
+
+        let tp = match crate::TransportParams::decode(&mut buf,
+                                                      crate::VERSION_DRAFT17,
+                                                      *is_server) {
+            Ok(v) => v,
+
+            Err(_) => continue,
+        };
+
+        let mut out = [0; 512];
+
+        crate::TransportParams::encode(&tp, crate::VERSION_DRAFT17,
+                                       !*is_server, &mut out).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input() {
+        packet_header(&[]);
+        frames(&[]);
+        transport_params(&[]);
+    }
+
+    #[test]
+    fn ack_gap_underflow() {
+        // ACK frame with largest=5, block=0, followed by a gap that is larger
+        // than the smallest acknowledged packet.
+        frames(&[0x02, 0x05, 0x00, 0x01, 0x00, 0x3f, 0x00]);
+    }
+
+    #[test]
+    fn truncated_inputs() {
+        let input = [
+            0xc0, 0xff, 0x00, 0x00, 0x11, 0x55, 0xba, 0xba, 0xba, 0xba, 0xba,
+            0xba, 0xba, 0xba, 0xba, 0xba, 0x00, 0x44, 0x00, 0x08, 0x00, 0x04,
+        ];
+
+        for i in 0..input.len() {
+            packet_header(&input[..i]);
+            frames(&input[..i]);
+            transport_params(&input[..i]);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! QUIC-LB compatible connection ID encoding.
+//!
+//! Connection IDs generated with an [`LbConfig`] encode a server ID that
+//! load balancers implementing the QUIC-LB draft can extract in order to
+//! route packets to the right server, even after the client's address
+//! changes.
+//!
+//! The first octet of the connection ID carries the config rotation bits in
+//! its 3 most significant bits, followed by the server ID and a nonce that
+//! are optionally encrypted with a key shared with the load balancer.
+//!
+//! [`LbConfig`]: struct.LbConfig.html
+
+use libc::c_int;
+use libc::c_uint;
+
+use crate::Error;
+use crate::Result;
+
+use crate::crypto;
+use crate::rand;
+
+/// The config rotation codepoint reserved for unroutable connection IDs.
+pub const UNROUTABLE_CONFIG_ID: u8 = 0x07;
+
+const AES_BLOCK_LEN: usize = 16;
+
+const MAX_SERVER_ID_LEN: usize = 15;
+
+const MIN_NONCE_LEN: usize = 4;
+
+/// A QUIC-LB connection ID configuration.
+///
+/// The same configuration (config ID, server ID length, nonce length and
+/// key) needs to be shared with the load balancer.
+pub struct LbConfig {
+    config_id: u8,
+
+    server_id: Vec<u8>,
+
+    nonce_len: usize,
+
+    len_self_encoding: bool,
+
+    key: Option<Key>,
+}
+
+impl LbConfig {
+    /// Creates a new QUIC-LB configuration.
+    ///
+    /// The `config_id` is the value of the config rotation bits and must be
+    /// lower than [`UNROUTABLE_CONFIG_ID`]. The `server_id` is the value
+    /// encoded in the generated connection IDs, followed by `nonce_len`
+    /// random bytes.
+    ///
+    /// When `key` is set, the server ID and nonce are encrypted with AES-128,
+    /// in which case their combined length must not exceed 16 bytes.
+    ///
+    /// [`UNROUTABLE_CONFIG_ID`]: constant.UNROUTABLE_CONFIG_ID.html
+    pub fn new(config_id: u8, server_id: &[u8], nonce_len: usize,
+               key: Option<&[u8; 16]>) -> Result<LbConfig> {
+        if config_id >= UNROUTABLE_CONFIG_ID {
+            return Err(Error::InvalidState);
+        }
+
+        if server_id.is_empty() || server_id.len() > MAX_SERVER_ID_LEN {
+            return Err(Error::InvalidState);
+        }
+
+        if nonce_len < MIN_NONCE_LEN ||
+           1 + server_id.len() + nonce_len > crate::MAX_CONN_ID_LEN {
+            return Err(Error::InvalidState);
+        }
+
+        if key.is_some() && server_id.len() + nonce_len > AES_BLOCK_LEN {
+            return Err(Error::InvalidState);
+        }
+
+        let key = match key {
+            Some(v) => Some(Key::new(v)?),
+
+            None => None,
+        };
+
+        Ok(LbConfig {
+            config_id,
+            server_id: server_id.to_vec(),
+            nonce_len,
+            len_self_encoding: false,
+            key,
+        })
+    }
+
+    /// Configures whether the connection ID length is encoded in the first
+    /// octet.
+    ///
+    /// This allows load balancers to extract the connection ID from short
+    /// header packets without knowing its length in advance. The default
+    /// value is `false`, in which case the bits are random.
+    pub fn set_length_self_encoding(&mut self, v: bool) {
+        self.len_self_encoding = v;
+    }
+
+    /// Returns the length of the connection IDs generated by this config.
+    pub fn cid_len(&self) -> usize {
+        1 + self.server_id.len() + self.nonce_len
+    }
+
+    /// Generates a new connection ID into `cid`.
+    ///
+    /// If `cid` is longer than [`cid_len()`], the remaining bytes are filled
+    /// with random data.
+    ///
+    /// [`cid_len()`]: struct.LbConfig.html#method.cid_len
+    pub fn encode(&self, cid: &mut [u8]) -> Result<()> {
+        if cid.len() < self.cid_len() {
+            return Err(Error::BufferTooShort);
+        }
+
+        rand::rand_bytes(cid);
+
+        self.encode_with_nonce(cid);
+
+        Ok(())
+    }
+
+    // Encodes the connection ID in place, using the nonce and the random
+    // bits that are already in `cid`.
+    fn encode_with_nonce(&self, cid: &mut [u8]) {
+        cid[0] = if self.len_self_encoding {
+            (self.config_id << 5) | ((cid.len() - 1) as u8 & 0x1f)
+        } else {
+            (self.config_id << 5) | (cid[0] & 0x1f)
+        };
+
+        let sid_len = self.server_id.len();
+        let plaintext_len = sid_len + self.nonce_len;
+
+        // The nonce was already filled in with random bytes above.
+        cid[1..=sid_len].copy_from_slice(&self.server_id);
+
+        if let Some(ref key) = self.key {
+            key.encrypt(&mut cid[1..=plaintext_len]);
+        }
+    }
+
+    /// Extracts the server ID from a connection ID generated with this
+    /// config.
+    ///
+    /// [`InvalidPacket`] is returned if the connection ID was generated with
+    /// a different config rotation value, or is too short.
+    ///
+    /// [`InvalidPacket`]: ../enum.Error.html#variant.InvalidPacket
+    pub fn decode_server_id(&self, cid: &[u8]) -> Result<Vec<u8>> {
+        if cid.len() < self.cid_len() ||
+           config_rotation(cid) != Some(self.config_id) {
+            return Err(Error::InvalidPacket);
+        }
+
+        let sid_len = self.server_id.len();
+        let plaintext_len = sid_len + self.nonce_len;
+
+        let mut plaintext = cid[1..=plaintext_len].to_vec();
+
+        if let Some(ref key) = self.key {
+            key.decrypt(&mut plaintext);
+        }
+
+        plaintext.truncate(sid_len);
+
+        Ok(plaintext)
+    }
+}
+
+/// Returns the config rotation value encoded in the given connection ID.
+pub fn config_rotation(cid: &[u8]) -> Option<u8> {
+    cid.first().map(|b| b >> 5)
+}
+
+struct Key {
+    enc: AES_KEY,
+    dec: AES_KEY,
+}
+
+impl Key {
+    fn new(key: &[u8; 16]) -> Result<Key> {
+        let mut k = Key {
+            enc: AES_KEY::default(),
+            dec: AES_KEY::default(),
+        };
+
+        let bits = (key.len() * 8) as c_uint;
+
+        unsafe {
+            if AES_set_encrypt_key(key.as_ptr(), bits, &mut k.enc) != 0 ||
+               AES_set_decrypt_key(key.as_ptr(), bits, &mut k.dec) != 0 {
+                return Err(crypto::INTERNAL_ERROR);
+            }
+        }
+
+        Ok(k)
+    }
+
+    fn ecb_encrypt(&self, block: &[u8; AES_BLOCK_LEN]) -> [u8; AES_BLOCK_LEN] {
+        let mut out = [0; AES_BLOCK_LEN];
+
+        unsafe {
+            AES_encrypt(block.as_ptr(), out.as_mut_ptr(), &self.enc);
+        }
+
+        out
+    }
+
+    fn encrypt(&self, buf: &mut [u8]) {
+        if buf.len() == AES_BLOCK_LEN {
+            let mut block = [0; AES_BLOCK_LEN];
+            block.copy_from_slice(buf);
+
+            unsafe {
+                AES_encrypt(block.as_ptr(), buf.as_mut_ptr(), &self.enc);
+            }
+
+            return;
+        }
+
+        let (left, right) = self.split(buf);
+
+        let right = xor(&right, &self.truncate_right(buf.len(), &left, 1));
+        let left = xor(&left, &self.truncate_left(buf.len(), &right, 2));
+        let right = xor(&right, &self.truncate_right(buf.len(), &left, 3));
+        let left = xor(&left, &self.truncate_left(buf.len(), &right, 4));
+
+        self.join(buf, &left, &right);
+    }
+
+    fn decrypt(&self, buf: &mut [u8]) {
+        if buf.len() == AES_BLOCK_LEN {
+            let mut block = [0; AES_BLOCK_LEN];
+            block.copy_from_slice(buf);
+
+            unsafe {
+                AES_decrypt(block.as_ptr(), buf.as_mut_ptr(), &self.dec);
+            }
+
+            return;
+        }
+
+        let (left, right) = self.split(buf);
+
+        let left = xor(&left, &self.truncate_left(buf.len(), &right, 4));
+        let right = xor(&right, &self.truncate_right(buf.len(), &left, 3));
+        let left = xor(&left, &self.truncate_left(buf.len(), &right, 2));
+        let right = xor(&right, &self.truncate_right(buf.len(), &left, 1));
+
+        self.join(buf, &left, &right);
+    }
+
+    // Splits the buffer into two halves for the four-pass Feistel network.
+    // When the length is odd, the middle octet is shared between the two
+    // halves, each taking one nibble.
+    fn split(&self, buf: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let half_len = (buf.len() + 1) / 2;
+
+        let mut left = buf[..half_len].to_vec();
+        let mut right = buf[buf.len() - half_len..].to_vec();
+
+        if buf.len() % 2 == 1 {
+            left[half_len - 1] &= 0xf0;
+            right[0] &= 0x0f;
+        }
+
+        (left, right)
+    }
+
+    fn join(&self, buf: &mut [u8], left: &[u8], right: &[u8]) {
+        let len = buf.len();
+        let half_len = left.len();
+
+        buf[len - half_len..].copy_from_slice(right);
+
+        if len % 2 == 1 {
+            buf[half_len - 1] |= left[half_len - 1];
+            buf[..half_len - 1].copy_from_slice(&left[..half_len - 1]);
+        } else {
+            buf[..half_len].copy_from_slice(left);
+        }
+    }
+
+    fn expand(&self, plaintext_len: usize, half: &[u8], pass: u8)
+                                                    -> [u8; AES_BLOCK_LEN] {
+        let mut block = [0; AES_BLOCK_LEN];
+
+        block[..half.len()].copy_from_slice(half);
+        block[AES_BLOCK_LEN - 2] = plaintext_len as u8;
+        block[AES_BLOCK_LEN - 1] = pass;
+
+        self.ecb_encrypt(&block)
+    }
+
+    fn truncate_left(&self, plaintext_len: usize, half: &[u8], pass: u8)
+                                                                -> Vec<u8> {
+        let mut out = self.expand(plaintext_len, half, pass)[..half.len()]
+                          .to_vec();
+
+        if plaintext_len % 2 == 1 {
+            out[half.len() - 1] &= 0xf0;
+        }
+
+        out
+    }
+
+    fn truncate_right(&self, plaintext_len: usize, half: &[u8], pass: u8)
+                                                                -> Vec<u8> {
+        // Like truncate_left(), this uses the leading octets of the AES
+        // output, only the masked nibble differs.
+        let mut out = self.expand(plaintext_len, half, pass)[..half.len()]
+                          .to_vec();
+
+        if plaintext_len % 2 == 1 {
+            out[0] &= 0x0f;
+        }
+
+        out
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(x, y)| x ^ y).collect()
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct AES_KEY {
+    rd_key: [u32; 60],
+    rounds: c_uint,
+}
+
+impl Default for AES_KEY {
+    fn default() -> AES_KEY {
+        AES_KEY {
+            rd_key: [0; 60],
+            rounds: 0,
+        }
+    }
+}
+
+extern {
+    fn AES_set_encrypt_key(key: *const u8, bits: c_uint, aeskey: *mut AES_KEY)
+                                                                    -> c_int;
+
+    fn AES_set_decrypt_key(key: *const u8, bits: c_uint, aeskey: *mut AES_KEY)
+                                                                    -> c_int;
+
+    fn AES_encrypt(inp: *const u8, out: *mut u8, key: *const AES_KEY);
+
+    fn AES_decrypt(inp: *const u8, out: *mut u8, key: *const AES_KEY);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [
+        0xfd, 0xf7, 0x26, 0xa9, 0x89, 0x3e, 0xc0, 0x5c,
+        0x06, 0x32, 0xd3, 0x95, 0x66, 0x80, 0xba, 0xf0,
+    ];
+
+    // Test vectors from draft-ietf-quic-load-balancers, appendix B.
+    const KAT_KEY: [u8; 16] = [
+        0x8f, 0x95, 0xf0, 0x92, 0x45, 0x76, 0x5f, 0x80,
+        0x25, 0x69, 0x34, 0xe5, 0x0c, 0x66, 0x20, 0x7f,
+    ];
+
+    fn kat(config_id: u8, sid: &[u8], nonce: &[u8], key: Option<&[u8; 16]>,
+           expected: &[u8]) {
+        let mut lb = LbConfig::new(config_id, sid, nonce.len(), key).unwrap();
+        lb.set_length_self_encoding(true);
+
+        let mut cid = vec![0; lb.cid_len()];
+        cid[1 + sid.len()..].copy_from_slice(nonce);
+
+        lb.encode_with_nonce(&mut cid);
+        assert_eq!(&cid[..], expected);
+
+        assert_eq!(lb.decode_server_id(&cid), Ok(sid.to_vec()));
+    }
+
+    #[test]
+    fn kat_unencrypted() {
+        kat(0, &[0xc4, 0x60, 0x5e], &[0x45, 0x04, 0xcc, 0x4f], None, &[
+            0x07, 0xc4, 0x60, 0x5e, 0x45, 0x04, 0xcc, 0x4f,
+        ]);
+    }
+
+    #[test]
+    fn kat_single_pass() {
+        let sid = [0xed, 0x79, 0x3a, 0x51, 0xd4, 0x9b, 0x8f, 0x5f];
+        let nonce = [0xee, 0x08, 0x0d, 0xbf, 0x48, 0xc0, 0xd1, 0xe5];
+
+        kat(2, &sid, &nonce, Some(&KAT_KEY), &[
+            0x50, 0x4d, 0xd2, 0xd0, 0x5a, 0x7b, 0x0d, 0xe9, 0xb2, 0xb9, 0x90,
+            0x7a, 0xfb, 0x5e, 0xcf, 0x8c, 0xc3,
+        ]);
+    }
+
+    #[test]
+    fn kat_four_pass() {
+        let sid = [0xed, 0x79, 0x3a];
+        let nonce = [0xee, 0x08, 0x0d, 0xbf];
+
+        kat(0, &sid, &nonce, Some(&KAT_KEY), &[
+            0x07, 0x20, 0xb1, 0xd0, 0x7b, 0x35, 0x9d, 0x3c,
+        ]);
+
+        let sid = [0xed, 0x79, 0x3a, 0x51, 0xd4, 0x9b, 0x8f, 0x5f, 0xab, 0x65];
+        let nonce = [0xee, 0x08, 0x0d, 0xbf, 0x48];
+
+        kat(1, &sid, &nonce, Some(&KAT_KEY), &[
+            0x2f, 0xcc, 0x38, 0x1b, 0xc7, 0x4c, 0xb4, 0xfb, 0xad, 0x28, 0x23,
+            0xa3, 0xd1, 0xf8, 0xfe, 0xd2,
+        ]);
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(LbConfig::new(UNROUTABLE_CONFIG_ID, &[1], 8, None).is_err());
+        assert!(LbConfig::new(0, &[], 8, None).is_err());
+        assert!(LbConfig::new(0, &[1], 2, None).is_err());
+        assert!(LbConfig::new(0, &[1; 8], 10, None).is_err());
+        assert!(LbConfig::new(0, &[1; 8], 9, Some(&KEY)).is_err());
+    }
+
+    #[test]
+    fn plaintext() {
+        let mut lb = LbConfig::new(2, &[0xaa, 0xbb, 0xcc], 5, None).unwrap();
+        lb.set_length_self_encoding(true);
+
+        let mut cid = [0; 9];
+        assert_eq!(lb.encode(&mut cid), Ok(()));
+
+        assert_eq!(cid[0], (2 << 5) | 8);
+        assert_eq!(&cid[1..4], &[0xaa, 0xbb, 0xcc]);
+        assert_eq!(config_rotation(&cid), Some(2));
+
+        assert_eq!(lb.decode_server_id(&cid), Ok(vec![0xaa, 0xbb, 0xcc]));
+
+        let mut short = [0; 4];
+        assert_eq!(lb.encode(&mut short), Err(Error::BufferTooShort));
+    }
+
+    #[test]
+    fn single_pass() {
+        let sid = [0x31, 0x44, 0x1a, 0x9c, 0x69, 0xc2, 0x75];
+        let lb = LbConfig::new(0, &sid, 9, Some(&KEY)).unwrap();
+
+        let mut cid = [0; 17];
+        assert_eq!(lb.encode(&mut cid), Ok(()));
+
+        assert_eq!(lb.decode_server_id(&cid), Ok(sid.to_vec()));
+    }
+
+    #[test]
+    fn four_pass() {
+        // Both even and odd plaintext lengths.
+        for nonce_len in 4..9 {
+            let sid = [0xed, 0x79, 0x3a];
+            let lb = LbConfig::new(1, &sid, nonce_len, Some(&KEY)).unwrap();
+
+            let mut cid = vec![0; lb.cid_len()];
+            assert_eq!(lb.encode(&mut cid), Ok(()));
+
+            assert_eq!(config_rotation(&cid), Some(1));
+            assert_eq!(lb.decode_server_id(&cid), Ok(sid.to_vec()));
+
+            let other = LbConfig::new(2, &sid, nonce_len, Some(&KEY)).unwrap();
+            assert_eq!(other.decode_server_id(&cid), Err(Error::InvalidPacket));
+        }
+    }
+}
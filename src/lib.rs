@@ -174,27 +174,227 @@
 extern crate log;
 
 use std::cmp;
+use std::io;
 use std::mem;
+use std::net;
+use std::sync;
 use std::time;
 
 use std::collections::hash_map;
+use std::collections::VecDeque;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 /// The current QUIC wire version.
 pub const VERSION_DRAFT17: u32 = 0xff00_0011;
 
+// The wire versions supported by this build, in order of preference.
+const SUPPORTED_VERSIONS: [u32; 1] = [VERSION_DRAFT17];
+
 /// The maximum length of a connection ID.
 pub const MAX_CONN_ID_LEN: usize = 18;
 
+/// The minimum length of a non-empty connection ID.
+const MIN_CONN_ID_LEN: usize = 4;
+
+/// The default length of locally generated connection IDs.
+const DEFAULT_CONN_ID_LEN: usize = 16;
+
 const CLIENT_INITIAL_MIN_LEN: usize = 1200;
 
+// The maximum value of the `ack_delay_exponent` transport parameter.
+const MAX_ACK_DELAY_EXPONENT: u64 = 20;
+
+// The `max_ack_delay` transport parameter must be lower than 2^14.
+const MAX_ACK_DELAY: u64 = 1 << 14;
+
+// The maximum value of the `initial_max_streams_*` transport parameters.
+const MAX_STREAMS: u64 = 1 << 60;
+
+// The minimum value of the peer's `max_packet_size` transport parameter.
+const MIN_UDP_PAYLOAD_SIZE: u64 = 1200;
+
+const DEFAULT_MAX_SEND_UDP_PAYLOAD_SIZE: usize = 1200;
+
 const PAYLOAD_MIN_LEN: usize = 4;
 
-// TODO: calculate draining timer as 3 * RTO
-const DRAINING_TIMEOUT: time::Duration = time::Duration::from_millis(200);
+// The minimum interval between validations of new peer addresses, to avoid
+// being used to flood spoofed addresses with PATH_CHALLENGE frames.
+const PATH_VALIDATION_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+// The maximum number of paths tracked by a connection.
+const MAX_PATHS: usize = 4;
+
+// The default maximum number of ACK ranges tracked per packet number space.
+const DEFAULT_MAX_ACK_RANGES: usize = 68;
+
+// The default amount of data a stream can send before yielding to the other
+// streams, matching HTTP/2's default frame size so that concurrent requests
+// are interleaved at a similar granularity.
+const DEFAULT_STREAM_BURST: usize = 16384;
+
+// The largest frame type and transport parameter defined by QUIC, which
+// can't be claimed by extensions.
+const MAX_FRAME_TYPE: u64 = 0x1d;
+const MAX_TRANSPORT_PARAM: u16 = 0x000e;
+
+// The maximum number of transport events queued for the application.
+const MAX_EVENTS: usize = 1024;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// A callback invoked when a new address validation token is received from
+/// the server.
+pub type NewTokenCallback = sync::Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// A callback used to fill the buffer passed to it with a new connection ID.
+pub type ConnectionIdGenerator = sync::Arc<dyn Fn(&mut [u8]) + Send + Sync>;
+
+/// A callback deciding whether new clients must validate their address.
+///
+/// The argument is the number of server connections created from the config
+/// that haven't completed the handshake yet.
+pub type RetryPolicy = sync::Arc<dyn Fn(usize) -> bool + Send + Sync>;
+
+/// A callback invoked on packet-level events.
+///
+/// The first argument is the connection's trace ID.
+pub type PacketTraceCallback =
+    sync::Arc<dyn Fn(&str, &PacketTrace) + Send + Sync>;
+
+/// A sink for connection metrics.
+///
+/// The connection calls these methods as it makes progress, so that
+/// embedders can bridge them to their metrics system of choice. All methods
+/// do nothing by default.
+///
+/// See [`set_metrics()`].
+///
+/// [`set_metrics()`]: struct.Config.html#method.set_metrics
+pub trait Metrics: Send + Sync {
+    /// Counts a packet of `len` bytes sent.
+    fn packet_sent(&self, _len: usize) {}
+
+    /// Counts a packet of `len` bytes received and processed.
+    fn packet_received(&self, _len: usize) {}
+
+    /// Counts a packet declared lost, and the `len` bytes in flight it
+    /// accounted for.
+    fn packet_lost(&self, _len: usize) {}
+
+    /// Counts a completed handshake.
+    fn handshake_completed(&self) {}
+
+    /// Counts a stream opened, either locally or by the peer.
+    fn stream_opened(&self) {}
+
+    /// Updates the congestion window gauge, in bytes.
+    fn cwnd(&self, _v: usize) {}
+
+    /// Updates the smoothed round-trip time gauge.
+    fn rtt(&self, _v: time::Duration) {}
+}
+
+/// An AEAD key protecting packet payloads.
+///
+/// See [`AeadProvider`].
+///
+/// [`AeadProvider`]: trait.AeadProvider.html
+pub trait PacketKey: Send + Sync {
+    /// Encrypts a packet payload in place.
+    ///
+    /// The `buf` parameter contains the plaintext followed by enough space
+    /// for the authentication tag. On success the length of the ciphertext,
+    /// including the tag, is returned.
+    fn seal(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8]) -> Result<usize>;
+
+    /// Decrypts a packet payload in place.
+    ///
+    /// On success the length of the plaintext is returned.
+    fn open(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A provider of packet protection keys.
+///
+/// This allows deployments with crypto accelerators to take over the
+/// encryption and decryption of 1-RTT packets, which are the bulk of the
+/// traffic. Handshake packets and header protection always use the built-in
+/// implementation.
+///
+/// See [`set_aead_provider()`].
+///
+/// [`set_aead_provider()`]: struct.Config.html#method.set_aead_provider
+pub trait AeadProvider: Send + Sync {
+    /// Returns a key for the given cipher suite and key material, or `None`
+    /// to use the built-in implementation.
+    fn new_key(&self, suite: CipherSuite, key: &[u8])
+                                            -> Option<Box<dyn PacketKey>>;
+}
+
+/// Persistent storage for TLS session tickets.
+///
+/// Clients store the session tickets sent by servers, and use them to resume
+/// the session the next time they connect to the same server, saving a round
+/// trip. Sessions are keyed by the server name passed to [`connect()`].
+///
+/// See [`set_session_store()`].
+///
+/// [`connect()`]: fn.connect.html
+/// [`set_session_store()`]: struct.Config.html#method.set_session_store
+pub trait SessionStore: Send + Sync {
+    /// Stores a serialized session received from `server_name`.
+    fn store(&self, server_name: &str, session: &[u8]);
+
+    /// Returns the serialized session previously stored for `server_name`.
+    fn load(&self, server_name: &str) -> Option<Vec<u8>>;
+}
+
+/// A source of cryptographically secure random bytes.
+///
+/// By default random values are generated by BoringSSL. A custom source can
+/// be used to make tests deterministic, or to satisfy deployments that
+/// require a specific (e.g. FIPS validated) entropy source.
+///
+/// See [`set_random()`].
+///
+/// [`set_random()`]: struct.Config.html#method.set_random
+pub trait SecureRandom: Send + Sync {
+    /// Fills `buf` with random bytes.
+    fn fill(&self, buf: &mut [u8]);
+}
+
+/// A handler for frames of an extension frame type.
+///
+/// Extension frames are only exchanged once both endpoints advertised the
+/// extension's transport parameter, and are encoded as the frame type,
+/// followed by the length of the payload as a variable-length integer and
+/// the payload itself. They are only carried by 1-RTT packets, and are not
+/// retransmitted when lost.
+///
+/// See [`register_frame_extension()`] and [`send_extension_frame()`].
+///
+/// [`register_frame_extension()`]:
+/// struct.Config.html#method.register_frame_extension
+/// [`send_extension_frame()`]:
+/// struct.Connection.html#method.send_extension_frame
+pub trait FrameExtension: Send + Sync {
+    /// Processes the payload of a received frame of type `ty`.
+    ///
+    /// Returning an error closes the connection.
+    fn on_frame(&self, ty: u64, payload: &[u8]) -> Result<()>;
+}
+
+/// An extension frame type registered on a config.
+#[derive(Clone)]
+struct Extension {
+    frame_type: u64,
+
+    param_id: u16,
+
+    handler: sync::Arc<dyn FrameExtension>,
+}
+
 /// A QUIC error.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Error {
@@ -209,7 +409,10 @@ pub enum Error {
 
     /// The provided packet cannot be parsed because it contains an invalid
     /// frame.
-    InvalidFrame,
+    InvalidFrame {
+        /// The type of the offending frame.
+        ty: u64,
+    },
 
     /// The provided packet cannot be parsed.
     InvalidPacket,
@@ -226,7 +429,10 @@ pub enum Error {
     InvalidTransportParam,
 
     /// A cryptographic operation failed.
-    CryptoFail,
+    CryptoFail {
+        /// The TLS alert describing the failure.
+        alert: u8,
+    },
 
     /// The TLS handshake failed.
     TlsFail,
@@ -236,19 +442,35 @@ pub enum Error {
 
     /// The peer violated the local stream limits.
     StreamLimit,
+
+    /// The peer changed a stream's final size, or sent data beyond it.
+    FinalSize,
+
+    /// The handshake did not complete within the configured timeout.
+    HandshakeTimeout,
+
+    /// The operation would exceed the connection's memory budget.
+    MemoryLimit,
+
+    /// Too many server handshakes are in progress to accept a new one.
+    HandshakeLimit,
 }
 
 impl Error {
+    /// Returns the QUIC transport error code matching the error, to be sent
+    /// to the peer in a CONNECTION_CLOSE frame.
     pub fn to_wire(&self) -> u16 {
         match self {
             Error::Done => 0x0,
-            Error::InvalidFrame => 0x7,
+            Error::InvalidFrame { .. } => 0x7,
             Error::InvalidStreamState => 0x5,
             Error::InvalidTransportParam => 0x8,
-            Error::CryptoFail => 0x100,
+            Error::CryptoFail { alert } => 0x100 + u16::from(*alert),
             Error::TlsFail => 0x100,
             Error::FlowControl => 0x3,
             Error::StreamLimit => 0x4,
+            Error::FinalSize => 0x6,
+            Error::MemoryLimit => 0x1,
             _ => 0xa,
         }
     }
@@ -258,19 +480,35 @@ impl Error {
             Error::Done => -1,
             Error::BufferTooShort => -2,
             Error::UnknownVersion => -3,
-            Error::InvalidFrame => -4,
+            Error::InvalidFrame { .. } => -4,
             Error::InvalidPacket => -5,
             Error::InvalidState => -6,
             Error::InvalidStreamState => -7,
             Error::InvalidTransportParam => -8,
-            Error::CryptoFail => -9,
+            Error::CryptoFail { .. } => -9,
             Error::TlsFail => -10,
             Error::FlowControl => -11,
             Error::StreamLimit => -12,
+            Error::HandshakeTimeout => -13,
+            Error::FinalSize => -14,
+            Error::MemoryLimit => -15,
+            Error::HandshakeLimit => -16,
         }
     }
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
 /// Stores configuration shared between multiple connections.
 pub struct Config {
     local_transport_params: TransportParams,
@@ -280,6 +518,72 @@ pub struct Config {
     tls_ctx: tls::Context,
 
     application_protos: Vec<Vec<u8>>,
+
+    new_token_cb: Option<NewTokenCallback>,
+
+    cid_len: usize,
+
+    cid_generator: Option<ConnectionIdGenerator>,
+
+    reset_key: Option<Vec<u8>>,
+
+    packet_trace_cb: Option<PacketTraceCallback>,
+
+    metrics: Option<sync::Arc<dyn Metrics>>,
+
+    session_store: Option<sync::Arc<dyn SessionStore>>,
+
+    aead_provider: Option<sync::Arc<dyn AeadProvider>>,
+
+    random: Option<sync::Arc<dyn SecureRandom>>,
+
+    extensions: Vec<Extension>,
+
+    initial_rtt: time::Duration,
+
+    pkt_thresh: u64,
+
+    time_thresh: f64,
+
+    handshake_timeout: Option<time::Duration>,
+
+    max_send_udp_payload_size: usize,
+
+    keepalive: Option<time::Duration>,
+
+    timer_granularity: time::Duration,
+
+    cid_rotation_packets: u64,
+
+    cid_rotation_interval: Option<time::Duration>,
+
+    cid_rotation_on_migration: bool,
+
+    dscp: u8,
+
+    cipher_suites: Option<Vec<CipherSuite>>,
+
+    pacing: bool,
+
+    max_conn_mem: Option<usize>,
+
+    max_stream_window: usize,
+
+    max_conn_window: usize,
+
+    max_ack_ranges: usize,
+
+    stream_burst: usize,
+
+    stream_reorder_window: usize,
+
+    padding_policy: PaddingPolicy,
+
+    retry_policy: Option<RetryPolicy>,
+
+    half_open: sync::Arc<sync::atomic::AtomicUsize>,
+
+    max_half_open: Option<usize>,
 }
 
 impl Config {
@@ -293,6 +597,39 @@ impl Config {
             version,
             tls_ctx,
             application_protos: Vec::new(),
+            new_token_cb: None,
+            cid_len: DEFAULT_CONN_ID_LEN,
+            cid_generator: None,
+            reset_key: None,
+            packet_trace_cb: None,
+            metrics: None,
+            session_store: None,
+            aead_provider: None,
+            random: None,
+            extensions: Vec::new(),
+            initial_rtt: recovery::INITIAL_RTT,
+            pkt_thresh: recovery::PACKET_THRESHOLD,
+            time_thresh: recovery::TIME_THRESHOLD,
+            handshake_timeout: None,
+            max_send_udp_payload_size: DEFAULT_MAX_SEND_UDP_PAYLOAD_SIZE,
+            keepalive: None,
+            timer_granularity: time::Duration::from_millis(0),
+            cid_rotation_packets: 0,
+            cid_rotation_interval: None,
+            cid_rotation_on_migration: false,
+            dscp: 0,
+            cipher_suites: None,
+            pacing: true,
+            max_conn_mem: None,
+            max_stream_window: 0,
+            max_conn_window: 0,
+            max_ack_ranges: DEFAULT_MAX_ACK_RANGES,
+            stream_burst: DEFAULT_STREAM_BURST,
+            stream_reorder_window: std::usize::MAX,
+            padding_policy: PaddingPolicy::default(),
+            retry_policy: None,
+            half_open: sync::Arc::new(sync::atomic::AtomicUsize::new(0)),
+            max_half_open: None,
         })
     }
 
@@ -313,6 +650,25 @@ impl Config {
                     .map_err(|_| Error::TlsFail)
     }
 
+    /// Configures the given certificate chain.
+    ///
+    /// The content of `der` is parsed as a DER-encoded leaf certificate,
+    /// optionally followed by DER-encoded intermediate certificates, all
+    /// concatenated together.
+    pub fn load_cert_chain_from_der(&mut self, der: &[u8]) -> Result<()> {
+        self.tls_ctx.use_certificate_chain_der(der)
+                    .map_err(|_| Error::TlsFail)
+    }
+
+    /// Configures the given private key.
+    ///
+    /// The content of `der` is parsed as a DER-encoded private key, either
+    /// in PKCS#8 or in a key-type specific format (e.g. PKCS#1 for RSA).
+    pub fn load_priv_key_from_der(&mut self, der: &[u8]) -> Result<()> {
+        self.tls_ctx.use_privkey_der(der)
+                    .map_err(|_| Error::TlsFail)
+    }
+
     /// Configures whether to verify the peer's certificate.
     pub fn verify_peer(&mut self, verify: bool) {
         self.tls_ctx.set_verify(verify);
@@ -322,13 +678,90 @@ impl Config {
     ///
     /// A connection's cryptographic secrets will be logged in the [keylog]
     /// format in the file pointed to by the `SSLKEYLOGFILE` environment
-    /// variable.
+    /// variable, or written to the connection's own writer if one was set
+    /// with [`set_keylog()`].
     ///
+    /// [`set_keylog()`]: struct.Connection.html#method.set_keylog
     /// [keylog]: https://developer.mozilla.org/en-US/docs/Mozilla/Projects/NSS/Key_Log_Format
     pub fn log_keys(&mut self) {
         self.tls_ctx.enable_keylog();
     }
 
+    /// Configures the OCSP response to staple to the server's certificate.
+    ///
+    /// The response is sent as-is to clients that request it, so it should
+    /// be refreshed periodically by the application.
+    pub fn set_ocsp_response(&mut self, v: &[u8]) -> Result<()> {
+        self.tls_ctx.set_ocsp_response(v)
+                    .map_err(|_| Error::TlsFail)
+    }
+
+    /// Configures the list of signed certificate timestamps to send along
+    /// with the server's certificate.
+    ///
+    /// The value must be a serialized `SignedCertificateTimestampList`, as
+    /// defined in RFC 6962.
+    pub fn set_signed_cert_timestamps(&mut self, v: &[u8]) -> Result<()> {
+        self.tls_ctx.set_signed_cert_timestamp_list(v)
+                    .map_err(|_| Error::TlsFail)
+    }
+
+    /// Requests an OCSP response from the server during the handshake.
+    ///
+    /// The response can be retrieved with [`ocsp_response()`] once the
+    /// handshake completes. It is not validated by the library.
+    ///
+    /// [`ocsp_response()`]: struct.Connection.html#method.ocsp_response
+    pub fn enable_ocsp_stapling(&mut self) {
+        self.tls_ctx.enable_ocsp_stapling();
+    }
+
+    /// Requests signed certificate timestamps from the server during the
+    /// handshake.
+    ///
+    /// The timestamps can be retrieved with [`signed_cert_timestamps()`]
+    /// once the handshake completes. They are not validated by the library.
+    ///
+    /// [`signed_cert_timestamps()`]: struct.Connection.html#method.signed_cert_timestamps
+    pub fn enable_signed_cert_timestamps(&mut self) {
+        self.tls_ctx.enable_signed_cert_timestamps();
+    }
+
+    /// Configures the list of allowed TLS 1.3 cipher suites.
+    ///
+    /// BoringSSL always offers (and accepts) its full set of TLS 1.3 cipher
+    /// suites, so the restriction is enforced once the cipher is negotiated:
+    /// if the peer picks a suite that is not in the list, the handshake is
+    /// aborted with a `handshake_failure` alert.
+    ///
+    /// By default all supported cipher suites are allowed. Clients without
+    /// AES hardware support prefer ChaCha20-Poly1305, which can be required
+    /// by only allowing [`CipherSuite::ChaCha20Poly1305`].
+    ///
+    /// [`CipherSuite::ChaCha20Poly1305`]:
+    /// enum.CipherSuite.html#variant.ChaCha20Poly1305
+    pub fn set_cipher_suites(&mut self, suites: &[CipherSuite]) -> Result<()> {
+        if suites.is_empty() {
+            return Err(Error::TlsFail);
+        }
+
+        self.cipher_suites = Some(suites.to_vec());
+
+        Ok(())
+    }
+
+    /// Configures the list of supported key exchange groups, in order of
+    /// preference.
+    ///
+    /// Groups are identified by their TLS library names, e.g. `X25519`,
+    /// `P-256` or `P-384`. Post-quantum hybrid groups such as
+    /// `X25519Kyber768Draft00` can be used if the linked BoringSSL supports
+    /// them. An error is returned if any of the groups is not recognized.
+    pub fn set_curves(&mut self, curves: &[&str]) -> Result<()> {
+        self.tls_ctx.set_curves(&curves.join(":"))
+                    .map_err(|_| Error::TlsFail)
+    }
+
     /// Configures the list of support application protocolos.
     ///
     /// On the client this configures the list of protocols to send to the
@@ -343,1505 +776,4833 @@ impl Config {
                     .map_err(|_| Error::TlsFail)
     }
 
-    /// Sets the `idle_timeout` transport parameter.
-    pub fn set_idle_timeout(&mut self, v: u64) {
-        self.local_transport_params.idle_timeout = v;
+    /// Configures the callback used to store address validation tokens.
+    ///
+    /// On the client, the callback is invoked every time a `NEW_TOKEN` frame
+    /// is received from the server. The application can store the token and
+    /// present it on future connections to the same server using the
+    /// [`set_token()`] method, to skip address validation.
+    ///
+    /// [`set_token()`]: struct.Connection.html#method.set_token
+    pub fn set_new_token_cb<F>(&mut self, cb: F)
+                                    where F: Fn(&[u8]) + Send + Sync + 'static {
+        self.new_token_cb = Some(sync::Arc::new(cb));
     }
 
-    /// Sets the `stateless_reset_token` transport parameter.
-    pub fn set_stateless_reset_token(&mut self, v: &[u8; 16]) {
-        self.local_transport_params.stateless_reset_token = Some(v.to_vec());
+    /// Sets the callback invoked on packet-level events.
+    ///
+    /// The callback is invoked every time a packet is sent, received or
+    /// declared lost, and can be used for custom telemetry or pcap-style
+    /// logging.
+    pub fn set_packet_trace_cb<F>(&mut self, cb: F)
+                    where F: Fn(&str, &PacketTrace) + Send + Sync + 'static {
+        self.packet_trace_cb = Some(sync::Arc::new(cb));
     }
 
-    /// Sets the `max_packet_size transport` parameter.
-    pub fn set_max_packet_size(&mut self, v: u64) {
-        self.local_transport_params.max_packet_size = v;
+    /// Sets the sink connection metrics are reported to.
+    ///
+    /// The same sink can be shared by multiple connections.
+    pub fn set_metrics(&mut self, metrics: sync::Arc<dyn Metrics>) {
+        self.metrics = Some(metrics);
     }
 
-    /// Sets the `initial_max_data` transport parameter.
-    pub fn set_initial_max_data(&mut self, v: u64) {
-        self.local_transport_params.initial_max_data = v;
+    /// Sets the provider of the keys protecting 1-RTT packets.
+    ///
+    /// The same provider can be shared by multiple connections.
+    pub fn set_aead_provider(&mut self, provider: sync::Arc<dyn AeadProvider>) {
+        self.aead_provider = Some(provider);
     }
 
-    /// Sets the `initial_max_stream_data_bidi_local` transport parameter.
-    pub fn set_initial_max_stream_data_bidi_local(&mut self, v: u64) {
-        self.local_transport_params.initial_max_stream_data_bidi_local = v;
+    /// Sets the source of the random values generated by connections.
+    ///
+    /// This is used for the connection IDs returned by [`new_cid()`] (unless
+    /// a custom generator is set), the client's initial destination
+    /// connection ID, and the data of PATH_CHALLENGE frames. The same source
+    /// can be shared by multiple connections.
+    ///
+    /// [`new_cid()`]: struct.Config.html#method.new_cid
+    pub fn set_random(&mut self, random: sync::Arc<dyn SecureRandom>) {
+        self.random = Some(random);
     }
 
-    /// Sets the `initial_max_stream_data_bidi_remote` transport parameter.
-    pub fn set_initial_max_stream_data_bidi_remote(&mut self, v: u64) {
-        self.local_transport_params.initial_max_stream_data_bidi_remote = v;
-    }
+    /// Registers a handler for frames of type `frame_type`.
+    ///
+    /// The extension is advertised to the peer with the `param_id` transport
+    /// parameter carrying `param`, and frames of this type are only accepted
+    /// and sent when the peer advertised the same parameter.
+    ///
+    /// Frame types and transport parameters defined by QUIC itself, or
+    /// already registered, can't be used and [`InvalidState`] is returned.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn register_frame_extension(&mut self, frame_type: u64, param_id: u16,
+                                    param: &[u8],
+                                    handler: sync::Arc<dyn FrameExtension>)
+                                                            -> Result<()> {
+        if frame_type <= MAX_FRAME_TYPE || param_id <= MAX_TRANSPORT_PARAM {
+            return Err(Error::InvalidState);
+        }
 
-    /// Sets the `initial_max_stream_data_uni` transport parameter.
-    pub fn set_initial_max_stream_data_uni(&mut self, v: u64) {
-        self.local_transport_params.initial_max_stream_data_uni = v;
-    }
+        if self.extensions.iter().any(|e| e.frame_type == frame_type ||
+                                          e.param_id == param_id) {
+            return Err(Error::InvalidState);
+        }
 
-    /// Sets the `initial_max_streams_bidi` transport parameter.
-    pub fn set_initial_max_streams_bidi(&mut self, v: u64) {
-        self.local_transport_params.initial_max_streams_bidi = v;
-    }
+        self.extensions.push(Extension { frame_type, param_id, handler });
 
-    /// Sets the `initial_max_streams_uni` transport parameter.
-    pub fn set_initial_max_streams_uni(&mut self, v: u64) {
-        self.local_transport_params.initial_max_streams_uni = v;
-    }
+        self.local_transport_params.extensions.push((param_id, param.to_vec()));
 
-    /// Sets the `ack_delay_exponent` transport parameter.
-    pub fn set_ack_delay_exponent(&mut self, v: u64) {
-        self.local_transport_params.ack_delay_exponent = v;
+        Ok(())
     }
 
-    /// Sets the `max_ack_delay` transport parameter.
-    pub fn set_max_ack_delay(&mut self, v: u64) {
-        self.local_transport_params.max_ack_delay = v;
-    }
+    /// Sets the storage session tickets received by clients are saved to.
+    ///
+    /// Client connections created with a server name will try to resume the
+    /// session previously stored for that name, if any.
+    pub fn set_session_store(&mut self, store: sync::Arc<dyn SessionStore>) {
+        self.tls_ctx.enable_session_cache();
 
-    /// Sets the `disable_migration` transport parameter.
-    pub fn set_disable_migration(&mut self, v: bool) {
-        self.local_transport_params.disable_migration = v;
+        self.session_store = Some(store);
     }
-}
-
-/// A QUIC connection.
-pub struct Connection {
-    version: u32,
 
-    dcid: Vec<u8>,
-    scid: Vec<u8>,
+    /// Sets the length of locally generated connection IDs.
+    ///
+    /// The length must be either 0, or between 4 and [`MAX_CONN_ID_LEN`]
+    /// bytes, otherwise [`InvalidState`] is returned. The default value is 16.
+    ///
+    /// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_cid_len(&mut self, v: usize) -> Result<()> {
+        if v != 0 && (v < MIN_CONN_ID_LEN || v > MAX_CONN_ID_LEN) {
+            return Err(Error::InvalidState);
+        }
 
-    trace_id: String,
+        self.cid_len = v;
 
-    initial: packet::PktNumSpace,
-    handshake: packet::PktNumSpace,
-    application: packet::PktNumSpace,
+        Ok(())
+    }
 
-    peer_transport_params: TransportParams,
+    /// Returns the length of locally generated connection IDs.
+    ///
+    /// Servers can use this when parsing the header of incoming short header
+    /// packets with [`Header::from_slice()`].
+    ///
+    /// [`Header::from_slice()`]: struct.Header.html#method.from_slice
+    pub fn cid_len(&self) -> usize {
+        self.cid_len
+    }
 
-    local_transport_params: TransportParams,
+    /// Configures the callback used to generate new connection IDs.
+    ///
+    /// The callback is given a buffer of the configured connection ID length
+    /// to fill, e.g. with an encoded server ID that a load balancer can use
+    /// to route packets. By default connection IDs are generated randomly.
+    pub fn set_cid_generator<F>(&mut self, cb: F)
+                              where F: Fn(&mut [u8]) + Send + Sync + 'static {
+        self.cid_generator = Some(sync::Arc::new(cb));
+    }
 
-    tls_state: tls::Handshake,
+    /// Configures QUIC-LB compatible connection ID generation.
+    ///
+    /// This sets both the connection ID length and the generator, so that
+    /// connection IDs returned by [`new_cid()`] encode the server ID
+    /// configured in `lb`.
+    ///
+    /// [`new_cid()`]: struct.Config.html#method.new_cid
+    pub fn set_lb_config(&mut self, lb: lb::LbConfig) {
+        self.cid_len = lb.cid_len();
 
-    recovery: recovery::Recovery,
+        self.set_cid_generator(move |cid| lb.encode(cid).unwrap());
+    }
 
-    application_protos: Vec<Vec<u8>>,
+    /// Generates a new connection ID.
+    ///
+    /// The returned connection ID can be passed to [`accept()`], [`connect()`]
+    /// or [`retry()`].
+    ///
+    /// [`accept()`]: fn.accept.html
+    /// [`connect()`]: fn.connect.html
+    /// [`retry()`]: fn.retry.html
+    pub fn new_cid(&self) -> Vec<u8> {
+        let mut cid = vec![0; self.cid_len];
 
-    sent_count: usize,
-    lost_count: usize,
+        match self.cid_generator {
+            Some(ref cb) => cb(&mut cid),
 
-    rx_data: usize,
-    max_rx_data: usize,
-    new_max_rx_data: usize,
+            None => fill_random(&self.random, &mut cid),
+        }
 
-    tx_data: usize,
-    max_tx_data: usize,
+        cid
+    }
 
-    streams: HashMap<u64, stream::Stream>,
+    /// Configures the callback used to decide whether to require address
+    /// validation.
+    ///
+    /// The callback is given the number of half-open handshakes, that is
+    /// server connections created from this config that haven't completed
+    /// the handshake yet, and is invoked by [`retry_required()`]. This can
+    /// be used to only send Retry packets under load, to mitigate floods of
+    /// Initial packets without penalizing clients otherwise.
+    ///
+    /// [`retry_required()`]: struct.Config.html#method.retry_required
+    pub fn set_retry_policy<F>(&mut self, cb: F)
+                                where F: Fn(usize) -> bool + Send + Sync + 'static {
+        self.retry_policy = Some(sync::Arc::new(cb));
+    }
 
-    local_max_streams_bidi: usize,
-    local_max_streams_uni: usize,
+    /// Requires address validation once the number of half-open handshakes
+    /// reaches `v`.
+    ///
+    /// This is a shorthand for [`set_retry_policy()`] with a fixed
+    /// threshold.
+    ///
+    /// [`set_retry_policy()`]: struct.Config.html#method.set_retry_policy
+    pub fn set_retry_threshold(&mut self, v: usize) {
+        self.set_retry_policy(move |half_open| half_open >= v);
+    }
 
-    peer_max_streams_bidi: usize,
-    peer_max_streams_uni: usize,
+    /// Returns whether a new client should be sent a Retry packet.
+    ///
+    /// Servers should call this when receiving an Initial packet without an
+    /// address validation token, and reply with [`retry()`] instead of
+    /// calling [`accept()`] if it returns `true`. Without a policy this
+    /// always returns `false`.
+    ///
+    /// [`retry()`]: fn.retry.html
+    /// [`accept()`]: fn.accept.html
+    pub fn retry_required(&self) -> bool {
+        match self.retry_policy {
+            Some(ref cb) => cb(self.half_open_handshakes()),
 
-    odcid: Option<Vec<u8>>,
+            None => false,
+        }
+    }
 
-    token: Option<Vec<u8>>,
+    /// Returns the number of server connections created from this config
+    /// that haven't completed the handshake yet.
+    pub fn half_open_handshakes(&self) -> usize {
+        self.half_open.load(sync::atomic::Ordering::SeqCst)
+    }
 
-    error: Option<u16>,
+    /// Sets the maximum number of half-open handshakes.
+    ///
+    /// Once the limit is reached, [`accept()`] fails with [`HandshakeLimit`]
+    /// until some of the pending handshakes complete or their connections
+    /// are dropped, so that a flood of Initial packets can't make the server
+    /// run out of memory. Excess clients can either be ignored, or be sent
+    /// a Retry packet, which doesn't require any state (see
+    /// [`set_retry_threshold()`]). The default value is 0, which disables
+    /// the limit.
+    ///
+    /// [`accept()`]: fn.accept.html
+    /// [`HandshakeLimit`]: enum.Error.html#variant.HandshakeLimit
+    /// [`set_retry_threshold()`]:
+    /// struct.Config.html#method.set_retry_threshold
+    pub fn set_max_half_open_handshakes(&mut self, v: usize) {
+        self.max_half_open = match v {
+            0 => None,
+
+            v => Some(v),
+        };
+    }
 
-    app_error: Option<u16>,
-    app_reason: Vec<u8>,
+    /// Sets the initial RTT estimate, in milliseconds.
+    ///
+    /// This is used before any RTT sample has been collected, e.g. to compute
+    /// the handshake retransmission timeout. The default value is 100ms.
+    pub fn set_initial_rtt(&mut self, v: u64) {
+        self.initial_rtt = time::Duration::from_millis(v);
+    }
 
-    challenge: Option<Vec<u8>>,
+    /// Sets the packet reordering threshold used by loss detection.
+    ///
+    /// A packet is declared lost when a packet sent this many packets after it
+    /// is acknowledged. The default value is 3.
+    pub fn set_packet_reordering_threshold(&mut self, v: u64) {
+        self.pkt_thresh = cmp::max(v, 1);
+    }
 
-    idle_timer: Option<time::Instant>,
+    /// Sets the time threshold multiplier used by loss detection.
+    ///
+    /// A packet is declared lost when it was sent this many RTTs before an
+    /// acknowledged packet. The default value is 9/8, and values lower than
+    /// 1 are ignored.
+    pub fn set_time_threshold(&mut self, v: f64) {
+        if v >= 1.0 {
+            self.time_thresh = v;
+        }
+    }
 
-    draining_timer: Option<time::Instant>,
+    /// Sets the handshake timeout, in milliseconds.
+    ///
+    /// Connections that haven't completed the handshake within this time are
+    /// closed, and [`recv()`] and [`send()`] return [`HandshakeTimeout`]. The
+    /// default value is 0, which disables the timeout.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`HandshakeTimeout`]: enum.Error.html#variant.HandshakeTimeout
+    pub fn set_handshake_timeout(&mut self, v: u64) {
+        self.handshake_timeout = match v {
+            0 => None,
 
-    is_server: bool,
+            v => Some(time::Duration::from_millis(v)),
+        };
+    }
 
-    derived_initial_secrets: bool,
+    /// Enables sending keep-alive PING frames.
+    ///
+    /// Once the handshake is complete, a PING frame is sent whenever no
+    /// packet has been sent or received for the given `interval`. This
+    /// should be lower than the idle timeout, so that the connection (and
+    /// any NAT binding along the path) is kept alive.
+    pub fn enable_keepalive(&mut self, interval: time::Duration) {
+        self.keepalive = Some(interval);
+    }
 
-    did_version_negotiation: bool,
+    /// Sets the granularity used to coalesce timers.
+    ///
+    /// Timers that expire within `v` of the earliest one are reported as a
+    /// single event by [`next_event_time()`], so that an event loop can wake
+    /// up once for all of them (e.g. for a few paced packets and a delayed
+    /// ACK) rather than once per packet. Timers can then fire up to `v`
+    /// late.
+    ///
+    /// The default value is zero, which disables coalescing.
+    ///
+    /// [`next_event_time()`]: struct.Connection.html#method.next_event_time
+    pub fn set_timer_granularity(&mut self, v: time::Duration) {
+        self.timer_granularity = v;
+    }
 
-    did_retry: bool,
+    /// Configures whether to pace outgoing packets.
+    ///
+    /// When enabled, [`send()`] spreads ack-eliciting 1-RTT packets over the
+    /// RTT, after an initial burst, and returns [`Done`] when the next packet
+    /// is not due yet. The time at which sending can resume is reported by
+    /// [`timeout()`].
+    ///
+    /// The default value is `true`.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    pub fn enable_pacing(&mut self, v: bool) {
+        self.pacing = v;
+    }
 
-    got_peer_conn_id: bool,
+    /// Sets the maximum amount of memory, in bytes, a connection can use.
+    ///
+    /// The budget covers stream receive buffers, data written to streams
+    /// that hasn't been acknowledged yet, and the connection's own state,
+    /// as reported by [`stats()`]. Writes to streams are truncated to fit in
+    /// the budget and fail with [`MemoryLimit`] once it's exhausted, while
+    /// the connection is closed if the stream data received from the peer
+    /// and not read yet exceeds it. The default value is 0, which disables
+    /// the limit.
+    ///
+    /// [`stats()`]: struct.Connection.html#method.stats
+    /// [`MemoryLimit`]: enum.Error.html#variant.MemoryLimit
+    pub fn set_max_connection_memory(&mut self, v: usize) {
+        self.max_conn_mem = match v {
+            0 => None,
 
-    handshake_completed: bool,
+            v => Some(v),
+        };
+    }
 
-    draining: bool,
+    /// Sets the `idle_timeout` transport parameter.
+    pub fn set_idle_timeout(&mut self, v: u64) {
+        self.local_transport_params.idle_timeout = v;
+    }
 
-    closed: bool,
-}
+    /// Sets the `stateless_reset_token` transport parameter.
+    ///
+    /// This takes precedence over the token derived from the key set with
+    /// [`set_stateless_reset_key()`].
+    ///
+    /// [`set_stateless_reset_key()`]:
+    /// struct.Config.html#method.set_stateless_reset_key
+    pub fn set_stateless_reset_token(&mut self, v: &[u8; 16]) {
+        self.local_transport_params.stateless_reset_token = Some(v.to_vec());
+    }
 
-/// Creates a new server-side connection.
-///
-/// The `scid` parameter represents the server's source connection ID, while
-/// the optional `odcid` parameter represents the original destination ID the
-/// client sent before a stateless retry (this is only required when using
-/// the [`retry()`] function).
-///
-/// [`retry()`]: fn.retry.html
-pub fn accept(scid: &[u8], odcid: Option<&[u8]>, config: &mut Config) -> Result<Box<Connection>> {
-    let conn = Connection::new(scid, odcid, config, true)?;
+    /// Sets the key stateless reset tokens are derived from.
+    ///
+    /// The token of each connection ID is derived deterministically from the
+    /// key, so servers sharing the same key can generate valid stateless
+    /// resets for connections they don't own, see [`stateless_reset_token()`].
+    /// Server connections advertise the token of their initial connection
+    /// ID in the `stateless_reset_token` transport parameter.
+    ///
+    /// The key should be at least 32 bytes of secret random data.
+    ///
+    /// [`stateless_reset_token()`]:
+    /// struct.Config.html#method.stateless_reset_token
+    pub fn set_stateless_reset_key(&mut self, key: &[u8]) {
+        self.reset_key = Some(key.to_vec());
+    }
 
-    Ok(conn)
-}
+    /// Returns the stateless reset token of the given connection ID, derived
+    /// from the key set with [`set_stateless_reset_key()`].
+    ///
+    /// This can also be used for the connection IDs passed to
+    /// [`new_source_cid()`]. `None` is returned if no key was set.
+    ///
+    /// [`set_stateless_reset_key()`]:
+    /// struct.Config.html#method.set_stateless_reset_key
+    /// [`new_source_cid()`]: struct.Connection.html#method.new_source_cid
+    pub fn stateless_reset_token(&self, cid: &[u8]) -> Option<[u8; 16]> {
+        self.reset_key.as_ref()
+                      .map(|key| crypto::derive_reset_token(key, cid))
+    }
 
-/// Creates a new client-side connection.
-///
-/// The `scid` parameter is used as the connection's source connection ID,
-/// while the optional `server_name` parameter is used to verify the peer's
-/// certificate.
-pub fn connect(server_name: Option<&str>, scid: &[u8], config: &mut Config)
-                                                -> Result<Box<Connection>> {
-    let conn = Connection::new(scid, None, config, false)?;
+    /// Sets the `max_packet_size transport` parameter.
+    ///
+    /// This is the maximum UDP payload size the local endpoint is willing to
+    /// receive. Values lower than 1200, the minimum allowed by the protocol,
+    /// are raised to 1200.
+    pub fn set_max_packet_size(&mut self, v: u64) {
+        self.local_transport_params.max_packet_size =
+            cmp::max(v, MIN_UDP_PAYLOAD_SIZE);
+    }
 
-    if server_name.is_some() {
-        conn.tls_state.set_host_name(server_name.unwrap())
-                      .map_err(|_| Error::TlsFail)?;
+    /// Sets the maximum UDP payload size of outgoing packets.
+    ///
+    /// Packets are also limited by the peer's `max_packet_size` transport
+    /// parameter. The default value is 1200, and lower values are ignored.
+    pub fn set_max_send_udp_payload_size(&mut self, v: usize) {
+        self.max_send_udp_payload_size =
+            cmp::max(v, DEFAULT_MAX_SEND_UDP_PAYLOAD_SIZE);
     }
 
-    Ok(conn)
-}
+    /// Sets the `initial_max_data` transport parameter.
+    pub fn set_initial_max_data(&mut self, v: u64) {
+        self.local_transport_params.initial_max_data = v;
+    }
 
-/// Writes a version negotiation packet.
-///
-/// The `scid` and `dcid` parameters are the source connection ID and the
-/// destination connection ID extracted from the received client's Initial
-/// packet that advertises an unsupported version.
-pub fn negotiate_version(scid: &[u8], dcid: &[u8], out: &mut [u8]) -> Result<usize> {
-    packet::negotiate_version(scid, dcid, out)
-}
+    /// Sets the `initial_max_stream_data_bidi_local` transport parameter.
+    pub fn set_initial_max_stream_data_bidi_local(&mut self, v: u64) {
+        self.local_transport_params.initial_max_stream_data_bidi_local = v;
+    }
 
-/// Writes a retry packet.
-///
-/// The `scid` and `dcid` parameters are the source connection ID and the
-/// destination connection ID extracted from the received client's Initial
-/// packet, while `new_scid` is the server's new source connection ID and
-/// `token` is the address verification token the client needs to echo back.
-pub fn retry(scid: &[u8], dcid: &[u8], new_scid: &[u8], token: &[u8], out: &mut [u8]) -> Result<usize> {
-    packet::retry(scid, dcid, new_scid, token, out)
-}
+    /// Sets the `initial_max_stream_data_bidi_remote` transport parameter.
+    pub fn set_initial_max_stream_data_bidi_remote(&mut self, v: u64) {
+        self.local_transport_params.initial_max_stream_data_bidi_remote = v;
+    }
 
-impl Connection {
-    #[allow(clippy::new_ret_no_self)]
-    fn new(scid: &[u8], odcid: Option<&[u8]>, config: &mut Config,
-           is_server: bool) -> Result<Box<Connection>> {
+    /// Sets the `initial_max_stream_data_uni` transport parameter.
+    pub fn set_initial_max_stream_data_uni(&mut self, v: u64) {
+        self.local_transport_params.initial_max_stream_data_uni = v;
+    }
 
-        let tls = config.tls_ctx.new_handshake().map_err(|_| Error::TlsFail)?;
-        Connection::with_tls(scid, odcid, config, tls, is_server)
+    /// Sets the `initial_max_streams_bidi` transport parameter.
+    pub fn set_initial_max_streams_bidi(&mut self, v: u64) {
+        self.local_transport_params.initial_max_streams_bidi = v;
     }
 
-    #[doc(hidden)]
-    pub fn with_tls(scid: &[u8], odcid: Option<&[u8]>, config: &mut Config,
-                    tls: tls::Handshake, is_server: bool) -> Result<Box<Connection>> {
-        let max_rx_data = config.local_transport_params.initial_max_data;
+    /// Sets the `initial_max_streams_uni` transport parameter.
+    pub fn set_initial_max_streams_uni(&mut self, v: u64) {
+        self.local_transport_params.initial_max_streams_uni = v;
+    }
 
-        let scid_as_hex: Vec<String> = scid.iter()
-                                           .map(|b| format!("{:02x}", b))
-                                           .collect();
+    /// Sets the maximum size, in bytes, stream receive windows can grow to.
+    ///
+    /// Stream windows start at the size set by the `initial_max_stream_data`
+    /// transport parameters, and are doubled whenever the application reads
+    /// a full window of data in less than two round trips, until they reach
+    /// this size. The default value is 0, which disables auto-tuning.
+    pub fn set_max_stream_window(&mut self, v: u64) {
+        self.max_stream_window = v as usize;
+    }
 
-        let mut conn = Box::new(Connection {
-            version: config.version,
+    /// Sets the maximum size, in bytes, the connection receive window can
+    /// grow to.
+    ///
+    /// This is the same as [`set_max_stream_window()`], but for the window
+    /// set by the `initial_max_data` transport parameter.
+    ///
+    /// [`set_max_stream_window()`]: struct.Config.html#method.set_max_stream_window
+    pub fn set_max_connection_window(&mut self, v: u64) {
+        self.max_conn_window = v as usize;
+    }
 
-            dcid: Vec::new(),
-            scid: scid.to_vec(),
+    /// Sets the maximum number of ranges of received packets to acknowledge.
+    ///
+    /// When heavy loss or reordering causes more ranges than this to be
+    /// pending, the ones with the smallest packet numbers are forgotten, so
+    /// that the memory used to track them and the size of ACK frames stay
+    /// bounded. The value is raised to 1 if lower.
+    ///
+    /// The default value is 68.
+    pub fn set_max_ack_ranges(&mut self, v: usize) {
+        self.max_ack_ranges = cmp::max(v, 1);
+    }
 
-            trace_id: scid_as_hex.join(""),
+    /// Sets the maximum amount of data, in bytes, a stream can send before
+    /// yielding to the other streams.
+    ///
+    /// Writable streams take turns sending data, each sending at most this
+    /// much per round, so that a stream with a lot of buffered data can't
+    /// starve the others. The value can be overridden for individual streams
+    /// with [`stream_burst()`].
+    ///
+    /// The default value is 16384.
+    ///
+    /// [`stream_burst()`]: struct.Connection.html#method.stream_burst
+    pub fn set_stream_burst(&mut self, v: usize) {
+        self.stream_burst = v;
+    }
 
-            initial: packet::PktNumSpace::new(crypto::Level::Initial),
-            handshake: packet::PktNumSpace::new(crypto::Level::Handshake),
-            application: packet::PktNumSpace::new(crypto::Level::Application),
+    /// Sets how far ahead of the read offset, in bytes, a stream can buffer
+    /// out-of-order data.
+    ///
+    /// Stream data past this limit, that isn't contiguous with the data
+    /// already received, is dropped, and the packet carrying it isn't
+    /// acknowledged, so that the peer retransmits it once the gap is filled.
+    /// The other frames in the packet are still processed. This bounds the
+    /// memory used for reassembly independently of the flow control limits.
+    ///
+    /// By default the amount of out-of-order data is only limited by flow
+    /// control.
+    pub fn set_stream_reorder_window(&mut self, v: usize) {
+        self.stream_reorder_window = v;
+    }
 
-            peer_transport_params: TransportParams::default(),
+    /// Configures the padding added to outgoing packets.
+    ///
+    /// Padding hides the size of the data carried by packets from on-path
+    /// observers, at the cost of bandwidth. The padding is limited by the
+    /// maximum packet size and the congestion window, and the number of
+    /// bytes added is reported in [`stats()`].
+    ///
+    /// By default no padding is added, other than the padding required by
+    /// the protocol. [`InvalidState`] is returned if the lower bound of the
+    /// random padding is greater than the upper bound.
+    ///
+    /// [`stats()`]: struct.Connection.html#method.stats
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_padding_policy(&mut self, policy: PaddingPolicy) -> Result<()> {
+        if let Some((min, max)) = policy.random {
+            if min > max {
+                return Err(Error::InvalidState);
+            }
+        }
 
-            local_transport_params: config.local_transport_params.clone(),
+        self.padding_policy = policy;
 
-            tls_state: tls,
+        Ok(())
+    }
 
-            recovery: recovery::Recovery::default(),
+    /// Sets the `ack_delay_exponent` transport parameter.
+    ///
+    /// Values above 20 are capped.
+    pub fn set_ack_delay_exponent(&mut self, v: u64) {
+        self.local_transport_params.ack_delay_exponent =
+            cmp::min(v, MAX_ACK_DELAY_EXPONENT);
+    }
 
-            application_protos: config.application_protos.clone(),
+    /// Sets the `max_ack_delay` transport parameter, in milliseconds.
+    ///
+    /// This is also the maximum amount of time an ACK for a received
+    /// Application packet is delayed. Values of 2^14 and above are capped.
+    pub fn set_max_ack_delay(&mut self, v: u64) {
+        self.local_transport_params.max_ack_delay =
+            cmp::min(v, MAX_ACK_DELAY - 1);
+    }
 
-            sent_count: 0,
-            lost_count: 0,
+    /// Sets the DSCP value that outgoing packets should be marked with.
+    ///
+    /// The value is not used by the library itself, but is reported back to
+    /// the application as part of [`SendInfo`], so that it can be set on the
+    /// socket. Only the 6 least significant bits are used.
+    ///
+    /// [`SendInfo`]: struct.SendInfo.html
+    pub fn set_dscp(&mut self, v: u8) {
+        self.dscp = v & 0x3f;
+    }
 
-            rx_data: 0,
-            max_rx_data: max_rx_data as usize,
-            new_max_rx_data: max_rx_data as usize,
+    /// Sets the `disable_migration` transport parameter.
+    pub fn set_disable_migration(&mut self, v: bool) {
+        self.local_transport_params.disable_migration = v;
+    }
 
-            tx_data: 0,
-            max_tx_data: 0,
+    /// Sets the `active_connection_id_limit` transport parameter.
+    ///
+    /// This is the maximum number of connection IDs, including the one used
+    /// during the handshake, that the peer is allowed to issue to us.
+    pub fn set_active_connection_id_limit(&mut self, v: u64) {
+        self.local_transport_params.active_conn_id_limit = v;
+    }
 
-            streams: HashMap::new(),
+    /// Sets the number of packets after which the destination connection ID
+    /// is rotated.
+    ///
+    /// Switching to a fresh connection ID issued by the peer makes it harder
+    /// for on-path observers to link the packets of a connection. Rotation
+    /// only happens once the handshake has been completed, and as long as
+    /// the peer issued spare connection IDs. The default value is 0, which
+    /// disables rotation based on the number of packets.
+    pub fn set_cid_rotation_packets(&mut self, v: u64) {
+        self.cid_rotation_packets = v;
+    }
 
-            local_max_streams_bidi:
-                config.local_transport_params.initial_max_streams_bidi as usize,
-            local_max_streams_uni:
-                config.local_transport_params.initial_max_streams_uni as usize,
+    /// Sets the interval, in milliseconds, after which the destination
+    /// connection ID is rotated.
+    ///
+    /// See [`set_cid_rotation_packets()`]. The default value is 0, which
+    /// disables rotation based on time.
+    ///
+    /// [`set_cid_rotation_packets()`]:
+    /// struct.Config.html#method.set_cid_rotation_packets
+    pub fn set_cid_rotation_interval(&mut self, v: u64) {
+        self.cid_rotation_interval = match v {
+            0 => None,
 
-            peer_max_streams_bidi: 0,
-            peer_max_streams_uni: 0,
+            v => Some(time::Duration::from_millis(v)),
+        };
+    }
 
-            odcid: None,
+    /// Sets whether the destination connection ID is rotated when the
+    /// peer's address changes, so that packets sent to different addresses
+    /// can't be linked by their connection ID.
+    ///
+    /// See [`set_cid_rotation_packets()`]. The default value is `false`.
+    ///
+    /// [`set_cid_rotation_packets()`]:
+    /// struct.Config.html#method.set_cid_rotation_packets
+    pub fn set_cid_rotation_on_migration(&mut self, v: bool) {
+        self.cid_rotation_on_migration = v;
+    }
+}
 
-            token: None,
+/// The kind of a traced packet event.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacketEvent {
+    /// The packet was sent.
+    Sent,
 
-            error: None,
+    /// The packet was received and successfully processed.
+    Received,
 
-            app_error: None,
-            app_reason: Vec::new(),
+    /// The packet was declared lost.
+    Lost,
+}
 
-            challenge: None,
+/// Information about a packet, passed to the packet trace callback.
+///
+/// See [`set_packet_trace_cb()`].
+///
+/// [`set_packet_trace_cb()`]: struct.Config.html#method.set_packet_trace_cb
+#[derive(Clone, Debug, PartialEq)]
+pub struct PacketTrace {
+    /// The kind of event.
+    pub event: PacketEvent,
 
-            idle_timer: None,
+    /// The type of the packet.
+    pub ty: Type,
 
-            draining_timer: None,
+    /// The packet number.
+    pub pkt_num: u64,
 
-            is_server,
+    /// The length of the packet payload, or 0 for lost packets.
+    pub len: usize,
 
-            derived_initial_secrets: false,
+    /// A summary of the frames in the packet. This is empty for lost packets.
+    pub frames: Vec<String>,
+}
 
-            did_version_negotiation: false,
+/// A transport event.
+///
+/// Events are queued as the connection makes progress, and can be retrieved
+/// using the [`poll()`] method.
+///
+/// [`poll()`]: struct.Connection.html#method.poll
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// The handshake completed.
+    HandshakeCompleted,
 
-            did_retry: false,
+    /// The stream has outstanding data to read.
+    StreamReadable(u64),
 
-            got_peer_conn_id: false,
+    /// The peer raised the stream's flow control limit, so more data can be
+    /// written to it.
+    StreamWritable(u64),
 
-            handshake_completed: false,
+    /// An address validation token was received from the server.
+    NewToken(Vec<u8>),
 
-            draining: false,
+    /// The peer's address changed without the peer migrating, e.g. because
+    /// of NAT rebinding. The new address is validated in the background.
+    PeerAddressChanged(net::SocketAddr),
 
-            closed: false,
-        });
+    /// The destination connection ID was rotated. The application should
+    /// issue a fresh source connection ID with [`new_source_cid()`], so that
+    /// the peer can rotate its own.
+    ///
+    /// [`new_source_cid()`]: struct.Connection.html#method.new_source_cid
+    ConnectionIdRotated,
 
-        if let Some(odcid) = odcid {
-            conn.local_transport_params.original_connection_id =
-                Some(odcid.to_vec());
-        }
+    /// The connection was closed. This is always the last event.
+    ConnectionClosed,
+}
 
-        conn.tls_state.init(&conn).map_err(|_| Error::TlsFail)?;
+/// The transport events pending delivery to the application.
+///
+/// An event that is already pending isn't queued again, and at most
+/// `MAX_EVENTS` events are kept, so the peer can't make the queue grow
+/// without bound.
+#[derive(Default)]
+struct EventQueue {
+    queue: VecDeque<Event>,
+
+    queued: HashSet<Event>,
+}
 
-        // Derive initial secrets for the client. We can do this here because
-        // we already generated the random destination connection ID.
-        if !is_server {
-            let mut dcid: [u8; 16] = [0; 16];
-            rand::rand_bytes(&mut dcid[..]);
+impl EventQueue {
+    fn push(&mut self, ev: Event) {
+        if self.queue.len() >= MAX_EVENTS || self.queued.contains(&ev) {
+            return;
+        }
 
-            let (aead_open, aead_seal) =
-                crypto::derive_initial_key_material(&dcid, conn.is_server)?;
+        self.queued.insert(ev.clone());
+        self.queue.push_back(ev);
+    }
 
-            conn.dcid.extend_from_slice(&dcid);
+    fn pop(&mut self) -> Option<Event> {
+        let ev = self.queue.pop_front()?;
 
-            conn.initial.crypto_open = Some(aead_open);
-            conn.initial.crypto_seal = Some(aead_seal);
-
-            conn.derived_initial_secrets = true;
-        }
+        self.queued.remove(&ev);
 
-        Ok(conn)
+        Some(ev)
     }
+}
 
-    /// Processes QUIC packets received from the peer.
-    ///
-    /// On success the number of bytes processed from the input buffer is
-    /// returned, or [`Done`].
-    ///
-    /// Coalesced packets will be processed as necessary.
-    ///
-    /// Note that the contents of the input buffer `buf` might be modified by
-    /// this function due to, for example, in-place decryption.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let len = buf.len();
+/// A TLS 1.3 cipher suite.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CipherSuite {
+    /// TLS_AES_128_GCM_SHA256.
+    Aes128Gcm,
 
-        let mut done = 0;
-        let mut left = len;
+    /// TLS_AES_256_GCM_SHA384.
+    Aes256Gcm,
 
-        // Process coalesced packets.
-        while left > 0 {
-            let read = self.recv_single(&mut buf[len - left..len])?;
+    /// TLS_CHACHA20_POLY1305_SHA256.
+    ChaCha20Poly1305,
+}
 
-            done += read;
-            left -= read;
+impl CipherSuite {
+    /// Returns the IANA identifier of the cipher suite.
+    pub fn id(self) -> u16 {
+        match self {
+            CipherSuite::Aes128Gcm => 0x1301,
+            CipherSuite::Aes256Gcm => 0x1302,
+            CipherSuite::ChaCha20Poly1305 => 0x1303,
         }
-
-        Ok(done)
     }
 
-    /// Processes a single QUIC packet received from the peer.
-    fn recv_single(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let now = time::Instant::now();
-
-        if buf.is_empty() {
-            return Err(Error::BufferTooShort);
+    #[cfg(any(test, feature = "testing"))]
+    fn algorithm(self) -> crypto::Algorithm {
+        match self {
+            CipherSuite::Aes128Gcm => crypto::Algorithm::AES128_GCM,
+            CipherSuite::Aes256Gcm => crypto::Algorithm::AES256_GCM,
+            CipherSuite::ChaCha20Poly1305 =>
+                crypto::Algorithm::ChaCha20_Poly1305,
         }
+    }
 
-        if self.draining {
-            return Err(Error::Done);
+    fn from_algorithm(alg: crypto::Algorithm) -> Option<CipherSuite> {
+        match alg {
+            crypto::Algorithm::AES128_GCM => Some(CipherSuite::Aes128Gcm),
+            crypto::Algorithm::AES256_GCM => Some(CipherSuite::Aes256Gcm),
+            crypto::Algorithm::ChaCha20_Poly1305 =>
+                Some(CipherSuite::ChaCha20Poly1305),
+            crypto::Algorithm::Null => None,
         }
+    }
+}
 
-        self.do_handshake()?;
+/// An ECN codepoint, as carried in the IP header.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ecn {
+    /// Not ECN-Capable Transport.
+    NotEct = 0,
 
-        let is_closing = self.error.is_some() || self.app_error.is_some();
+    /// ECN Capable Transport, ECT(1).
+    Ect1   = 1,
 
-        if is_closing {
-            return Err(Error::Done);
+    /// ECN Capable Transport, ECT(0).
+    Ect0   = 2,
+
+    /// Congestion Experienced.
+    Ce     = 3,
+}
+
+impl Ecn {
+    /// Returns the ECN codepoint matching the 2 least significant bits of the
+    /// given IP TOS / traffic class byte.
+    pub fn from_tos(tos: u8) -> Ecn {
+        match tos & 0x03 {
+            1 => Ecn::Ect1,
+            2 => Ecn::Ect0,
+            3 => Ecn::Ce,
+            _ => Ecn::NotEct,
         }
+    }
+}
 
-        let mut b = octets::Octets::with_slice(buf);
+impl Default for Ecn {
+    fn default() -> Ecn {
+        Ecn::NotEct
+    }
+}
 
-        let mut hdr = Header::from_bytes(&mut b, self.scid.len())?;
+/// Metadata about a packet written by [`send_with_info()`].
+///
+/// [`send_with_info()`]: struct.Connection.html#method.send_with_info
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SendInfo {
+    /// The ECN codepoint the packet should be sent with.
+    ///
+    /// ECN validation is not implemented, so this is currently always
+    /// [`NotEct`].
+    ///
+    /// [`NotEct`]: enum.Ecn.html#variant.NotEct
+    pub ecn: Ecn,
 
-        if hdr.ty == packet::Type::VersionNegotiation {
-            // Version negotiation packets can only be sent by the server.
-            if self.is_server {
-                return Err(Error::Done);
-            }
+    /// The DSCP value the packet should be marked with, as configured with
+    /// [`set_dscp()`].
+    ///
+    /// [`set_dscp()`]: struct.Config.html#method.set_dscp
+    pub dscp: u8,
 
-            // Ignore duplicate version negotiation.
-            if self.did_version_negotiation {
-                return Err(Error::Done);
-            }
+    /// The local address the packet should be sent from, i.e. the address
+    /// the peer last sent packets to, if known.
+    pub from: Option<net::SocketAddr>,
 
-            if hdr.dcid != self.scid {
-                return Err(Error::Done);
-            }
+    /// The address the packet should be sent to, if known.
+    pub to: Option<net::SocketAddr>,
+}
 
-            if hdr.scid != self.dcid {
-                return Err(Error::Done);
-            }
+impl SendInfo {
+    /// Returns the IP TOS / traffic class byte combining the DSCP and ECN
+    /// fields.
+    pub fn tos(&self) -> u8 {
+        (self.dscp << 2) | self.ecn as u8
+    }
+}
 
-            trace!("{} rx pkt {:?}", self.trace_id, hdr);
+/// Metadata about a datagram passed to [`recv_with_info()`].
+///
+/// [`recv_with_info()`]: struct.Connection.html#method.recv_with_info
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RecvInfo {
+    /// The ECN codepoint the datagram was received with.
+    pub ecn: Ecn,
+
+    /// The address the datagram was received from, used to detect changes
+    /// of the peer's address.
+    pub from: Option<net::SocketAddr>,
+
+    /// The local address the datagram was received on. On multi-homed hosts
+    /// this is used to reply from the address the peer is sending to.
+    pub to: Option<net::SocketAddr>,
+}
 
-            let versions = match hdr.versions {
-                Some(ref v) => v,
-                None => return Err(Error::InvalidPacket),
-            };
+/// A QUIC connection.
+pub struct Connection {
+    version: u32,
 
-            let mut new_version = 0;
-            for v in versions.iter() {
-                if *v == VERSION_DRAFT17 {
-                    new_version = *v;
-                }
-            }
+    dcid: Vec<u8>,
+    scid: Vec<u8>,
 
-            // We don't support any of the versions offfered.
-            if new_version == 0 {
-                return Err(Error::UnknownVersion);
-            }
+    trace_id: String,
 
-            self.version = new_version;
-            self.did_version_negotiation = true;
+    initial: packet::PktNumSpace,
+    handshake: packet::PktNumSpace,
+    application: packet::PktNumSpace,
 
-            // Reset connection state to force sending another Initial packet.
-            self.got_peer_conn_id = false;
-            self.recovery.drop_unacked_data(&mut self.initial.flight);
-            self.initial.clear();
-            self.tls_state.clear()
-                .map_err(|_| Error::TlsFail)?;
+    peer_transport_params: TransportParams,
 
-            return Err(Error::Done);
-        }
+    local_transport_params: TransportParams,
 
-        if hdr.ty == packet::Type::Retry {
-            // Retry packets can only be sent by the server.
-            if self.is_server {
-                return Err(Error::Done);
-            }
+    tls_state: tls::Handshake,
 
-            // Ignore duplicate retry.
-            if self.did_retry {
-                return Err(Error::Done);
-            }
+    recovery: recovery::Recovery,
 
-            if hdr.odcid.as_ref() != Some(&self.dcid) {
-                return Err(Error::Done);
-            }
+    application_protos: Vec<Vec<u8>>,
 
-            trace!("{} rx pkt {:?}", self.trace_id, hdr);
+    sent_count: usize,
+    lost_count: usize,
+    dup_recv_count: usize,
 
-            self.token = hdr.token;
-            self.did_retry = true;
+    ecn_recv_count: [usize; 4],
 
-            // Remember peer's new connection ID.
-            self.odcid = Some(self.dcid.clone());
+    dscp: u8,
 
-            self.dcid.resize(hdr.scid.len(), 0);
-            self.dcid.copy_from_slice(&hdr.scid);
+    cipher_suites: Option<Vec<CipherSuite>>,
 
-            // Derive Initial secrets using the new connection ID.
-            let (aead_open, aead_seal) =
-                crypto::derive_initial_key_material(&hdr.scid, self.is_server)?;
+    rx_data: usize,
+    max_rx_data: usize,
+    new_max_rx_data: usize,
 
-            self.initial.crypto_open = Some(aead_open);
-            self.initial.crypto_seal = Some(aead_seal);
+    rx_window: usize,
+    max_rx_window: usize,
+    last_rx_window_update: Option<time::Instant>,
 
-            // Reset connection state to force sending another Initial packet.
-            self.got_peer_conn_id = false;
-            self.recovery.drop_unacked_data(&mut self.initial.flight);
-            self.initial.clear();
-            self.tls_state.clear()
-                .map_err(|_| Error::TlsFail)?;
+    max_stream_window: usize,
 
-            return Err(Error::Done);
-        }
+    tx_data: usize,
+    max_tx_data: usize,
 
-        if hdr.ty != packet::Type::Application && hdr.version != self.version {
-            return Err(Error::UnknownVersion);
-        }
+    streams: HashMap<u64, stream::Stream>,
 
-        // Long header packets have an explicit payload length, but short
-        // packets don't so just use the remaining capacity in the buffer.
-        let payload_len = if hdr.ty == packet::Type::Application {
-            b.cap()
-        } else  {
-            b.get_varint()? as usize
-        };
+    stream_burst: usize,
 
-        if b.cap() < payload_len {
-            return Err(Error::BufferTooShort);
-        }
+    stream_reorder_window: usize,
 
-        if !self.is_server && !self.got_peer_conn_id {
-            // Replace the randomly generated destination connection ID with
-            // the one supplied by the server.
-            self.dcid.resize(hdr.scid.len(), 0);
-            self.dcid.copy_from_slice(&hdr.scid);
+    padding_policy: PaddingPolicy,
 
-            self.got_peer_conn_id = true;
-        }
+    padding_sent: usize,
 
-        // Derive initial secrets on the server.
-        if !self.derived_initial_secrets {
-            let (aead_open, aead_seal) =
-                crypto::derive_initial_key_material(&hdr.dcid, self.is_server)?;
+    local_max_streams_bidi: usize,
+    local_max_streams_uni: usize,
 
-            self.initial.crypto_open = Some(aead_open);
-            self.initial.crypto_seal = Some(aead_seal);
+    peer_max_streams_bidi: usize,
+    peer_max_streams_uni: usize,
 
-            self.derived_initial_secrets = true;
+    local_opened_streams_bidi: usize,
+    local_opened_streams_uni: usize,
 
-            self.dcid.extend_from_slice(&hdr.scid);
-            self.got_peer_conn_id = true;
-        }
+    peer_opened_streams_bidi: usize,
+    peer_opened_streams_uni: usize,
 
-        // Select packet number space context based on the input packet type.
-        let space = match hdr.ty {
-            packet::Type::Initial => &mut self.initial,
+    odcid: Option<Vec<u8>>,
 
-            packet::Type::Handshake => &mut self.handshake,
+    token: Option<Vec<u8>>,
 
-            packet::Type::Application => &mut self.application,
+    new_tokens: Vec<Vec<u8>>,
 
-            _ => return Err(Error::InvalidPacket),
-        };
+    new_scids: Vec<(u64, Vec<u8>, [u8; 16])>,
 
-        let aead = match space.crypto_open {
-            Some(ref v) => v,
+    next_scid_seq: u64,
 
-            None => {
-                trace!("{} dropped undecryptable packet type={:?} len={}",
-                       self.trace_id, hdr.ty, payload_len);
+    active_scid_seqs: BTreeSet<u64>,
 
-                return Ok(b.off() + payload_len)
-            },
-        };
+    dcid_seq: u64,
 
-        packet::decrypt_hdr(&mut b, &mut hdr, &aead)?;
+    spare_dcids: VecDeque<(u64, Vec<u8>)>,
 
-        let pn = packet::decode_pkt_num(space.largest_rx_pkt_num,
-                                        hdr.pkt_num, hdr.pkt_num_len);
+    retired_dcid_seqs: Vec<u64>,
 
-        trace!("{} rx pkt {:?} len={} pn={}", self.trace_id, hdr,
-               payload_len, pn);
+    dcid_sent_count: u64,
 
-        let mut payload = packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len,
-                                              payload_len, &aead)?;
+    dcid_since: Option<time::Instant>,
 
-        if space.recv_pkt_num.contains(pn) {
-            trace!("{} ignored duplicate packet {}", self.trace_id, pn);
-            return Err(Error::Done);
-        }
+    dcid_rotation_pending: bool,
 
-        // To avoid sending an ACK in response to an ACK-only packet, we need
-        // to keep track of whether this packet contains any frame other than
-        // ACK.
-        let mut do_ack = false;
+    cid_rotation_packets: u64,
 
-        // Process packet payload.
-        while payload.cap() > 0 {
-            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+    cid_rotation_interval: Option<time::Duration>,
 
-            trace!("{} rx frm {:?}", self.trace_id, frame);
+    cid_rotation_on_migration: bool,
 
-            match frame {
-                frame::Frame::Padding { .. } => (),
+    peer_token: Option<Vec<u8>>,
 
-                frame::Frame::Ping => {
-                    do_ack = true;
-                },
+    new_token_cb: Option<NewTokenCallback>,
 
-                frame::Frame::ACK { ranges, ack_delay } => {
-                    let ack_delay =
-                        ack_delay * 2_u64.pow(self.peer_transport_params
-                                                  .ack_delay_exponent as u32);
+    packet_trace_cb: Option<PacketTraceCallback>,
 
-                    self.recovery.on_ack_received(&ranges, ack_delay,
-                                                  &mut space.flight,
-                                                  now, &self.trace_id);
-                },
+    metrics: Option<sync::Arc<dyn Metrics>>,
 
-                frame::Frame::StopSending { stream_id, .. } => {
-                    // STOP_SENDING on a receive-only stream is a fatal error.
-                    if !stream::is_local(stream_id, self.is_server) &&
-                       !stream::is_bidi(stream_id) {
-                        return Err(Error::InvalidPacket);
-                    }
+    session_store: Option<sync::Arc<dyn SessionStore>>,
 
-                    do_ack = true;
-                },
+    aead_provider: Option<sync::Arc<dyn AeadProvider>>,
 
-                frame::Frame::Crypto { data } => {
-                    // Push the data to the stream so it can be re-ordered.
-                    space.crypto_stream.recv_push(data)?;
+    random: Option<sync::Arc<dyn SecureRandom>>,
 
-                    // Feed crypto data to the TLS state, if there's data
-                    // available at the expected offset.
-                    if space.crypto_stream.readable() {
-                        let buf = space.crypto_stream.recv_pop(std::usize::MAX)?;
-                        let level = space.crypto_level;
+    extensions: Vec<Extension>,
 
-                        self.tls_state.provide_data(level, &buf)
-                                      .map_err(|_| Error::TlsFail)?;
-                    }
+    extension_frames: VecDeque<frame::Frame>,
 
-                    do_ack = true;
-                },
+    server_name: Option<String>,
 
-                // TODO: implement stateless retry
-                frame::Frame::NewToken { .. } => {
-                    do_ack = true;
-                },
+    keylog: Option<Box<dyn io::Write + Send + Sync>>,
 
-                frame::Frame::Stream { stream_id, data } => {
-                    // Peer can't send on our unidirectional streams.
-                    if !stream::is_bidi(stream_id) &&
-                        stream::is_local(stream_id, self.is_server) {
-                        return Err(Error::InvalidStreamState);
-                    }
+    half_open: Option<sync::Arc<sync::atomic::AtomicUsize>>,
 
-                    let max_rx_data =
-                        self.local_transport_params
-                            .initial_max_stream_data_bidi_remote as usize;
-                    let max_tx_data =
-                        self.peer_transport_params
-                            .initial_max_stream_data_bidi_local as usize;
-
-                    // Get existing stream or create a new one.
-                    let stream = match self.streams.entry(stream_id) {
-                        hash_map::Entry::Vacant(v) => {
-                            // Peer is not supposed to create this stream.
-                            if stream::is_local(stream_id, self.is_server) {
-                                return Err(Error::InvalidStreamState);
-                            }
-
-                            // Enforce stream count limits.
-                            if stream::is_bidi(stream_id) {
-                                self.local_max_streams_bidi
-                                    .checked_sub(1)
-                                    .ok_or(Error::StreamLimit)?;
-                            } else {
-                                self.local_max_streams_uni
-                                    .checked_sub(1)
-                                    .ok_or(Error::StreamLimit)?;
-                            }
-
-                            let s = stream::Stream::new(max_rx_data, max_tx_data);
-                            v.insert(s)
-                        },
-
-                        hash_map::Entry::Occupied(v) => v.into_mut(),
-                    };
+    error: Option<u16>,
 
-                    self.rx_data += data.len();
+    error_frame_type: u64,
 
-                    if self.rx_data > self.max_rx_data {
-                        return Err(Error::FlowControl);
-                    }
+    app_error: Option<u16>,
+    app_reason: Vec<u8>,
 
-                    stream.recv_push(data)?;
+    peer_error: Option<u16>,
 
-                    do_ack = true;
-                },
+    challenge: Option<[u8; 8]>,
 
-                frame::Frame::MaxData { max } => {
-                    self.max_tx_data = cmp::max(self.max_tx_data,
-                                                max as usize);
+    peer_addr: Option<net::SocketAddr>,
 
-                    do_ack = true;
-                },
+    local_addr: Option<net::SocketAddr>,
 
-                frame::Frame::MaxStreamData { stream_id, max } => {
-                    let max_rx_data =
-                        self.local_transport_params
-                            .initial_max_stream_data_bidi_remote as usize;
-                    let max_tx_data =
-                        self.peer_transport_params
-                            .initial_max_stream_data_bidi_local as usize;
-
-                    // Get existing stream or create a new one.
-                    let stream = match self.streams.entry(stream_id) {
-                        hash_map::Entry::Vacant(v) => {
-                            // Peer is not supposed to create this stream.
-                            if stream::is_local(stream_id, self.is_server) {
-                                return Err(Error::InvalidStreamState);
-                            }
-
-                            // Enforce stream count limits.
-                            if stream::is_bidi(stream_id) {
-                                self.local_max_streams_bidi
-                                    .checked_sub(1)
-                                    .ok_or(Error::StreamLimit)?;
-                            } else {
-                                self.local_max_streams_uni
-                                    .checked_sub(1)
-                                    .ok_or(Error::StreamLimit)?;
-                            }
-
-                            let s = stream::Stream::new(max_rx_data, max_tx_data);
-                            v.insert(s)
-                        },
-
-                        hash_map::Entry::Occupied(v) => v.into_mut(),
-                    };
+    validated_addr: Option<net::SocketAddr>,
 
-                    stream.send_max_data(max as usize);
+    path_challenge: Option<[u8; 8]>,
 
-                    do_ack = true;
-                },
+    path_challenge_pending: bool,
 
-                frame::Frame::MaxStreamsBidi { max } => {
-                    self.peer_max_streams_bidi =
-                        cmp::max(self.peer_max_streams_bidi, max as usize);
+    path_validation_timer: Option<time::Instant>,
 
-                    do_ack = true;
-                },
+    last_path_validation: Option<time::Instant>,
 
-                frame::Frame::MaxStreamsUni { max } => {
-                    self.peer_max_streams_uni =
-                        cmp::max(self.peer_max_streams_uni, max as usize);
+    rebind_count: usize,
 
-                    do_ack = true;
-                },
+    paths: Vec<PathStats>,
 
-                // TODO: implement connection migration
-                frame::Frame::NewConnectionId { .. } => {
-                    do_ack = true;
-                },
+    idle_timer: Option<time::Instant>,
 
-                // TODO: implement connection migration
-                frame::Frame::RetireConnectionId { .. } => {
-                    do_ack = true;
-                },
+    draining_timer: Option<time::Instant>,
 
-                frame::Frame::PathChallenge { data } => {
-                    self.challenge = Some(data);
+    handshake_timer: Option<time::Instant>,
 
-                    do_ack = true;
-                },
+    handshake_timed_out: bool,
 
-                frame::Frame::PathResponse { .. } => {
-                    do_ack = true;
-                },
+    drain_timer: Option<time::Instant>,
 
-                frame::Frame::ConnectionClose { .. } => {
-                    self.draining = true;
-                    self.draining_timer = Some(now + DRAINING_TIMEOUT);
-                },
+    ack_timer: Option<time::Instant>,
 
-                frame::Frame::ApplicationClose { .. } => {
-                    self.draining = true;
-                    self.draining_timer = Some(now + DRAINING_TIMEOUT);
-                },
-            }
-        }
+    ack_eliciting_since_ack: usize,
 
-        // Process ACK'd frames.
-        for acked in space.flight.acked.drain(..) {
-            match acked {
-                // Stop acknowledging packets less than or equal to the
-                // largest acknowledged in the sent ACK frame that, in
-                // turn, got ACK'd.
-                frame::Frame::ACK { ranges, .. } => {
-                    let largest_acked = ranges.largest().unwrap();
-                    space.recv_pkt_need_ack.remove_until(largest_acked);
-                },
+    ack_eliciting_sent: bool,
 
-                // This does nothing. It's here to avoid a warning.
-                frame::Frame::Ping => (),
+    keepalive: Option<time::Duration>,
 
-                _ => (),
-            }
-        }
+    keepalive_timer: Option<time::Instant>,
 
-        // We only record the time of arrival of the largest packet number
-        // that still needs to be ACK'd, to be used for ACK delay calculation.
-        if space.recv_pkt_need_ack.largest() < Some(pn) {
-            space.largest_rx_pkt_time = now;
-        }
+    timer_granularity: time::Duration,
 
-        space.recv_pkt_num.insert(pn);
+    pacing_timer: Option<time::Instant>,
 
-        space.recv_pkt_need_ack.push_item(pn);
-        space.do_ack = cmp::max(space.do_ack, do_ack);
+    rate_limiter: Option<sync::Arc<dyn RateLimiter>>,
 
-        space.largest_rx_pkt_num = cmp::max(space.largest_rx_pkt_num, pn);
+    rate_limit_timer: Option<time::Instant>,
 
-        self.idle_timer =
-            Some(now + time::Duration::from_secs(
-                self.local_transport_params.idle_timeout));
+    ping_pending: bool,
 
-        let read = b.off() + aead.alg().tag_len();
+    max_send_udp_payload_size: usize,
 
-        // On the server, drop initial state after receiving and successfully
-        // processing an Handshake packet.
-        if self.is_server && hdr.ty == packet::Type::Handshake {
-            self.drop_initial_state();
-        }
+    max_conn_mem: Option<usize>,
 
-        Ok(read)
-    }
+    rx_buffered: usize,
 
-    /// Writes a single QUIC packet to be sent to the peer.
-    ///
-    /// On success the number of bytes processed from the input buffer is
-    /// returned, or [`Done`].
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    pub fn send(&mut self, out: &mut [u8]) -> Result<usize> {
-        let now = time::Instant::now();
+    is_server: bool,
 
-        if out.is_empty() {
-            return Err(Error::BufferTooShort);
-        }
+    derived_initial_secrets: bool,
 
-        if self.draining {
-            return Err(Error::Done);
-        }
+    did_version_negotiation: bool,
 
-        let is_closing = self.error.is_some() || self.app_error.is_some();
+    did_retry: bool,
 
-        if !is_closing {
-            self.do_handshake()?;
-        }
+    got_peer_conn_id: bool,
 
-        let max_pkt_len = self.peer_transport_params.max_packet_size as usize;
+    handshake_completed: bool,
 
-        // Cap output buffer to respect peer's max_packet_size limit.
-        let avail = cmp::min(max_pkt_len, out.len());
+    draining: bool,
 
-        let mut b = octets::Octets::with_slice(&mut out[..avail]);
+    closing: bool,
 
-        let pkt_type = self.select_egress_pkt_type()?;
+    close_pending: bool,
 
-        let space = match pkt_type {
-            packet::Type::Initial => &mut self.initial,
+    closing_rx_count: u64,
 
-            packet::Type::Handshake => &mut self.handshake,
+    closed: bool,
 
-            packet::Type::Application => &mut self.application,
+    events: EventQueue,
 
-            _ => unreachable!(),
-        };
+    closed_event: bool,
+}
 
-        // Process lost frames.
-        for lost in space.flight.lost.drain(..) {
-            match lost {
-                frame::Frame::Crypto { data } => {
-                    space.crypto_stream.send_push_front(data)?;
-                },
+/// Creates a new server-side connection.
+///
+/// The `scid` parameter represents the server's source connection ID, while
+/// the optional `odcid` parameter represents the original destination ID the
+/// client sent before a stateless retry (this is only required when using
+/// the [`retry()`] function).
+///
+/// [`HandshakeLimit`] is returned if the config's limit of half-open
+/// handshakes was reached, see [`set_max_half_open_handshakes()`].
+///
+/// [`retry()`]: fn.retry.html
+/// [`HandshakeLimit`]: enum.Error.html#variant.HandshakeLimit
+/// [`set_max_half_open_handshakes()`]:
+/// struct.Config.html#method.set_max_half_open_handshakes
+pub fn accept(scid: &[u8], odcid: Option<&[u8]>, config: &mut Config) -> Result<Box<Connection>> {
+    if let Some(max) = config.max_half_open {
+        if config.half_open_handshakes() >= max {
+            return Err(Error::HandshakeLimit);
+        }
+    }
 
-                frame::Frame::Stream { stream_id, data } => {
-                    let stream = match self.streams.get_mut(&stream_id) {
-                        Some(v) => v,
-                        None => continue,
-                    };
+    let conn = Connection::new(scid, odcid, config, true,
+                               time::Instant::now())?;
 
-                    self.tx_data -= data.len();
+    Ok(conn)
+}
 
-                    stream.send_push_front(data)?;
-                },
+/// Creates a new client-side connection.
+///
+/// The `scid` parameter is used as the connection's source connection ID,
+/// while the optional `server_name` parameter is used to verify the peer's
+/// certificate.
+pub fn connect(server_name: Option<&str>, scid: &[u8], config: &mut Config)
+                                                -> Result<Box<Connection>> {
+    let mut conn = Connection::new(scid, None, config, false,
+                                   time::Instant::now())?;
 
-                frame::Frame::ACK { .. } => {
-                    space.do_ack = true;
-                },
+    if server_name.is_some() {
+        conn.tls_state.set_host_name(server_name.unwrap())
+                      .map_err(|_| Error::TlsFail)?;
 
-                _ => (),
-            }
-        }
+        conn.server_name = server_name.map(String::from);
 
-        // Update global lost packets counter. This prevents us from losing
-        // information when the Initial state is dropped.
-        self.lost_count += space.flight.lost_count;
-        space.flight.lost_count = 0;
+        conn.resume_session();
+    }
 
-        // Calculate available space in the packet based on congestion window.
-        let mut left = cmp::min(self.recovery.cwnd(), b.cap());
+    Ok(conn)
+}
 
-        let pn = space.next_pkt_num;
-        let pn_len = packet::pkt_num_len(pn)?;
+/// Writes a version negotiation packet.
+///
+/// The `scid` and `dcid` parameters are the source connection ID and the
+/// destination connection ID extracted from the received client's Initial
+/// packet that advertises an unsupported version.
+pub fn negotiate_version(scid: &[u8], dcid: &[u8], out: &mut [u8]) -> Result<usize> {
+    packet::negotiate_version(scid, dcid, out)
+}
 
-        let hdr = Header {
-            ty: pkt_type,
-            version: self.version,
-            dcid: self.dcid.clone(),
-            scid: self.scid.clone(),
-            pkt_num: 0,
-            pkt_num_len: pn_len,
-            odcid: None,
-            token: self.token.clone(),
-            versions: None,
-            key_phase: false,
-        };
+/// Returns the wire versions supported by this build, in order of
+/// preference.
+///
+/// These are the versions advertised by [`negotiate_version()`].
+///
+/// [`negotiate_version()`]: fn.negotiate_version.html
+pub fn supported_versions() -> &'static [u32] {
+    &SUPPORTED_VERSIONS
+}
 
-        hdr.to_bytes(&mut b)?;
+/// Returns true if the given wire version is supported by this build.
+pub fn version_is_supported(version: u32) -> bool {
+    SUPPORTED_VERSIONS.contains(&version)
+}
 
-        // Make sure we have enough space left for the header, the payload
-        // length, the packet number and the AEAD overhead.
-        if left < b.off() + 4 + pn_len + space.overhead() {
-            return Err(Error::Done);
-        }
+/// Writes a retry packet.
+///
+/// The `scid` and `dcid` parameters are the source connection ID and the
+/// destination connection ID extracted from the received client's Initial
+/// packet, while `new_scid` is the server's new source connection ID and
+/// `token` is the address verification token the client needs to echo back.
+pub fn retry(scid: &[u8], dcid: &[u8], new_scid: &[u8], token: &[u8], out: &mut [u8]) -> Result<usize> {
+    packet::retry(scid, dcid, new_scid, token, out)
+}
 
-        left -= b.off() + 4 + pn_len + space.overhead();
+impl Connection {
+    #[allow(clippy::new_ret_no_self)]
+    fn new(scid: &[u8], odcid: Option<&[u8]>, config: &mut Config,
+           is_server: bool, now: time::Instant) -> Result<Box<Connection>> {
 
-        let mut frames: Vec<frame::Frame> = Vec::new();
+        let tls = config.tls_ctx.new_handshake().map_err(|_| Error::TlsFail)?;
+        Connection::with_tls(scid, odcid, config, tls, is_server, now)
+    }
 
-        let mut ack_eliciting = false;
-        let mut is_crypto = false;
+    #[doc(hidden)]
+    pub fn with_tls(scid: &[u8], odcid: Option<&[u8]>, config: &mut Config,
+                    tls: tls::Handshake, is_server: bool, now: time::Instant)
+                                                -> Result<Box<Connection>> {
+        if scid.len() > MAX_CONN_ID_LEN {
+            return Err(Error::InvalidState);
+        }
 
-        let mut payload_len = 0;
+        let max_rx_data = config.local_transport_params.initial_max_data;
 
-        // Create ACK frame.
-        if space.do_ack {
-            let ack_delay = space.largest_rx_pkt_time.elapsed();
+        let scid_as_hex: Vec<String> = scid.iter()
+                                           .map(|b| format!("{:02x}", b))
+                                           .collect();
 
-            let ack_delay = ack_delay.as_secs() * 1_000_000 +
-                            u64::from(ack_delay.subsec_micros());
+        let mut conn = Box::new(Connection {
+            version: config.version,
 
-            let ack_delay = 
-                ack_delay / 2_u64.pow(self.local_transport_params
-                                          .ack_delay_exponent as u32);
+            dcid: Vec::new(),
+            scid: scid.to_vec(),
 
-            let frame = frame::Frame::ACK {
-                ack_delay,
-                ranges: space.recv_pkt_need_ack.clone(),
-            };
+            trace_id: scid_as_hex.join(""),
 
-            if frame.wire_len() <= left {
-                space.do_ack = false;
+            initial: packet::PktNumSpace::new(crypto::Level::Initial,
+                                              config.max_ack_ranges, now),
+            handshake: packet::PktNumSpace::new(crypto::Level::Handshake,
+                                                config.max_ack_ranges, now),
+            application:
+                packet::PktNumSpace::new(crypto::Level::Application,
+                                         config.max_ack_ranges, now),
 
-                payload_len += frame.wire_len();
-                left -= frame.wire_len();
+            peer_transport_params: TransportParams::default(),
 
-                frames.push(frame);
-            }
-        }
+            local_transport_params: config.local_transport_params.clone(),
 
-        // Create MAX_DATA frame, when the new limit is at least double the
-        // amount of data that can be received before blocking.
-        if pkt_type == packet::Type::Application && !is_closing
-            && (self.new_max_rx_data != self.max_rx_data &&
-                self.new_max_rx_data / 2 > self.max_rx_data - self.rx_data)
-        {
-            let frame = frame::Frame::MaxData {
-                max: self.new_max_rx_data as u64,
-            };
+            tls_state: tls,
 
-            if frame.wire_len() <= left {
-                self.max_rx_data = self.new_max_rx_data;
+            recovery: recovery::Recovery::new(config, now),
 
-                payload_len += frame.wire_len();
-                left -= frame.wire_len();
+            application_protos: config.application_protos.clone(),
 
-                frames.push(frame);
+            sent_count: 0,
+            lost_count: 0,
+            dup_recv_count: 0,
 
-                ack_eliciting = true;
-            }
-        }
+            ecn_recv_count: [0; 4],
 
-        // Create MAX_STREAM_DATA frames as needed.
-        if pkt_type == packet::Type::Application && !is_closing {
-            for (id, stream) in self.streams.iter_mut()
-                                            .filter(|(_, s)| s.more_credit()) {
-                let frame = frame::Frame::MaxStreamData {
-                    stream_id: *id,
-                    max: stream.recv_update_max_data() as u64,
-                };
+            dscp: config.dscp,
 
-                if frame.wire_len() > left {
-                    break;
-                }
+            cipher_suites: config.cipher_suites.clone(),
 
-                payload_len += frame.wire_len();
-                left -= frame.wire_len();
+            rx_data: 0,
+            max_rx_data: max_rx_data as usize,
+            new_max_rx_data: max_rx_data as usize,
 
-                frames.push(frame);
+            rx_window: max_rx_data as usize,
+            max_rx_window: config.max_conn_window,
+            last_rx_window_update: None,
 
-                ack_eliciting = true;
-            }
-        }
+            max_stream_window: config.max_stream_window,
 
-        // Create PING and PADDING for TLP.
-        if self.recovery.probes > 0 && left >= 1 {
-            let frame = frame::Frame::Ping;
+            tx_data: 0,
+            max_tx_data: 0,
 
-            payload_len += frame.wire_len();
-            left -= frame.wire_len();
+            streams: HashMap::new(),
 
-            frames.push(frame);
+            stream_burst: config.stream_burst,
 
-            self.recovery.probes -= 1;
+            stream_reorder_window: config.stream_reorder_window,
 
-            ack_eliciting = true;
-        }
+            padding_policy: config.padding_policy,
 
-        // Create CONNECTION_CLOSE frame.
-        if let Some(err) = self.error {
-            let frame = frame::Frame::ConnectionClose {
-                error_code: err,
-                frame_type: 0,
-                reason: Vec::new(),
-            };
+            padding_sent: 0,
 
-            payload_len += frame.wire_len();
-            left -= frame.wire_len();
+            local_max_streams_bidi:
+                config.local_transport_params.initial_max_streams_bidi as usize,
+            local_max_streams_uni:
+                config.local_transport_params.initial_max_streams_uni as usize,
 
-            frames.push(frame);
+            peer_max_streams_bidi: 0,
+            peer_max_streams_uni: 0,
 
-            self.draining = true;
-            self.draining_timer = Some(now + DRAINING_TIMEOUT);
-        }
+            local_opened_streams_bidi: 0,
+            local_opened_streams_uni: 0,
 
-        // Create APPLICAtiON_CLOSE frame.
-        if let Some(err) = self.app_error {
-            let frame = frame::Frame::ApplicationClose {
-                error_code: err,
-                reason: self.app_reason.clone(),
-            };
+            peer_opened_streams_bidi: 0,
+            peer_opened_streams_uni: 0,
 
-            payload_len += frame.wire_len();
-            left -= frame.wire_len();
+            odcid: None,
 
-            frames.push(frame);
+            token: None,
 
-            self.draining = true;
-            self.draining_timer = Some(now + DRAINING_TIMEOUT);
-        }
+            new_tokens: Vec::new(),
 
-        // Create PATH_RESPONSE frame.
-        if let Some(ref challenge) = self.challenge {
-            let frame = frame::Frame::PathResponse {
-                data: challenge.clone(),
-            };
+            new_scids: Vec::new(),
 
-            payload_len += frame.wire_len();
-            left -= frame.wire_len();
+            // The connection ID used during the handshake has sequence
+            // number 0.
+            next_scid_seq: 1,
 
-            frames.push(frame);
+            active_scid_seqs: [0].iter().cloned().collect(),
 
-            self.challenge = None;
-        }
+            dcid_seq: 0,
 
-        // Create CRYPTO frame.
-        if space.crypto_stream.writable() && !is_closing {
-            let crypto_len = left - frame::MAX_CRYPTO_OVERHEAD;
-            let crypto_buf = space.crypto_stream.send_pop(crypto_len)?;
+            spare_dcids: VecDeque::new(),
 
-            let frame = frame::Frame::Crypto {
-                data: crypto_buf,
-            };
+            retired_dcid_seqs: Vec::new(),
 
-            payload_len += frame.wire_len();
-            left -= frame.wire_len();
+            dcid_sent_count: 0,
 
-            frames.push(frame);
+            dcid_since: None,
 
-            ack_eliciting = true;
-            is_crypto = true;
-        }
+            dcid_rotation_pending: false,
 
-        // Create a single STREAM frame for the first stream that is writable.
-        if pkt_type == packet::Type::Application && !is_closing
-            && self.max_tx_data > self.tx_data
-            && left > frame::MAX_STREAM_OVERHEAD
-        {
-            // TODO: round-robin selected stream instead of picking the first
-            for (id, stream) in self.streams.iter_mut()
-                                            .filter(|(_, s)| s.writable()) {
-                // Make sure we can fit the data in the packet.
-                let stream_len = cmp::min(left - frame::MAX_STREAM_OVERHEAD,
-                                          self.max_tx_data - self.tx_data);
+            cid_rotation_packets: config.cid_rotation_packets,
 
-                let stream_buf = stream.send_pop(stream_len)?;
+            cid_rotation_interval: config.cid_rotation_interval,
 
-                if stream_buf.is_empty() {
-                    continue;
-                }
+            cid_rotation_on_migration: config.cid_rotation_on_migration,
 
-                self.tx_data += stream_buf.len();
+            peer_token: None,
 
-                let frame = frame::Frame::Stream {
-                    stream_id: *id,
-                    data: stream_buf,
-                };
+            new_token_cb: config.new_token_cb.clone(),
 
-                payload_len += frame.wire_len();
-                left -= frame.wire_len();
+            packet_trace_cb: config.packet_trace_cb.clone(),
 
-                frames.push(frame);
+            metrics: config.metrics.clone(),
 
-                ack_eliciting = true;
-                break;
-            }
-        }
+            session_store: config.session_store.clone(),
 
-        if frames.is_empty() {
-            return Err(Error::Done);
-        }
+            aead_provider: config.aead_provider.clone(),
 
-        // Pad the client's initial packet.
-        if !self.is_server && pkt_type == packet::Type::Initial {
-            let pkt_len = pn_len + payload_len + space.overhead();
+            random: config.random.clone(),
 
-            let frame = frame::Frame::Padding {
-                len: cmp::min(CLIENT_INITIAL_MIN_LEN - pkt_len, left),
-            };
+            extensions: config.extensions.clone(),
 
-            payload_len += frame.wire_len();
+            extension_frames: VecDeque::new(),
 
-            frames.push(frame);
-        }
+            server_name: None,
 
-        // Pad payload so that it's always at least 4 bytes.
-        if payload_len < PAYLOAD_MIN_LEN {
-            let frame = frame::Frame::Padding {
-                len: PAYLOAD_MIN_LEN - payload_len,
-            };
+            keylog: None,
 
-            payload_len += frame.wire_len();
+            half_open: None,
 
-            frames.push(frame);
-        }
+            error: None,
 
-        payload_len += space.overhead();
+            app_error: None,
+            peer_error: None,
+            error_frame_type: 0,
 
-        // Only long header packets have an explicit length field.
-        if pkt_type != packet::Type::Application {
-            let len = pn_len + payload_len;
-            b.put_varint(len as u64)?;
-        }
+            app_reason: Vec::new(),
 
-        packet::encode_pkt_num(pn, &mut b)?;
+            challenge: None,
 
-        let payload_offset = b.off();
+            peer_addr: None,
 
-        trace!("{} tx pkt {:?} len={} pn={}", self.trace_id, hdr,
-               payload_len, pn);
+            local_addr: None,
 
-        // Encode frames into the output packet.
-        for frame in &frames {
-            trace!("{} tx frm {:?}", self.trace_id, frame);
+            validated_addr: None,
 
-            frame.to_bytes(&mut b)?;
-        }
+            path_challenge: None,
 
-        let aead = match space.crypto_seal {
-            Some(ref v) => v,
-            None        => return Err(Error::InvalidState),
-        };
+            path_challenge_pending: false,
 
-        let written = packet::encrypt_pkt(&mut b, pn, pn_len, payload_len,
-                                          payload_offset, aead)?;
+            path_validation_timer: None,
 
-        let sent_pkt = recovery::Sent::new(pn, frames, written, ack_eliciting,
-                                           is_crypto, now);
+            last_path_validation: None,
 
-        self.recovery.on_packet_sent(sent_pkt, &mut space.flight, now,
-                                     &self.trace_id);
+            rebind_count: 0,
 
-        space.next_pkt_num += 1;
+            paths: Vec::new(),
 
-        self.sent_count += 1;
+            idle_timer: None,
 
-        // On the client, drop initial state after sending an Handshake packet.
-        if !self.is_server && hdr.ty == packet::Type::Handshake {
-            self.drop_initial_state();
-        }
+            draining_timer: None,
 
-        Ok(written)
-    }
+            handshake_timer: config.handshake_timeout
+                                   .map(|t| now + t),
 
-    /// Reads contiguous data from a stream.
-    ///
-    /// The returned buffer will contain at most `max_len` bytes.
-    ///
-    /// On success the stream data is returned, or [`Done`] if there is no data
-    /// to read.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    pub fn stream_recv(&mut self, stream_id: u64, max_len: usize) -> Result<RangeBuf> {
-        let stream = match self.streams.get_mut(&stream_id) {
-            Some(v) => v,
-            None => return Err(Error::InvalidStreamState),
-        };
+            handshake_timed_out: false,
 
-        if !stream.readable() {
-            return Err(Error::Done);
-        }
+            drain_timer: None,
 
-        let buf = stream.recv_pop(max_len)?;
+            ack_timer: None,
 
-        self.new_max_rx_data = self.max_rx_data + buf.len();
+            ack_eliciting_since_ack: 0,
 
-        Ok(buf)
-    }
+            ack_eliciting_sent: false,
 
-    /// Writes data to a stream.
-    ///
-    /// On success the number of bytes written is returned.
-    pub fn stream_send(&mut self, stream_id: u64, buf: &[u8], fin: bool)
-                                                            -> Result<usize> {
-        // We can't write on the peer's unidirectional streams.
-        if !stream::is_bidi(stream_id) &&
-           !stream::is_local(stream_id, self.is_server) {
-            return Err(Error::InvalidStreamState);
-        }
+            keepalive: config.keepalive,
 
-        let max_rx_data = self.local_transport_params
-                              .initial_max_stream_data_bidi_local as usize;
-        let max_tx_data = self.peer_transport_params
-                              .initial_max_stream_data_bidi_remote as usize;
+            keepalive_timer: None,
 
-        // Get existing stream or create a new one.
-        let stream = match self.streams.entry(stream_id) {
-            hash_map::Entry::Vacant(v) => {
-                if !stream::is_local(stream_id, self.is_server) {
-                    return Err(Error::InvalidStreamState);
-                }
+            timer_granularity: config.timer_granularity,
 
-                // Enforce stream count limits.
-                if stream::is_bidi(stream_id) {
-                    self.peer_max_streams_bidi.checked_sub(1)
-                                              .ok_or(Error::StreamLimit)?;
-                } else {
-                    self.peer_max_streams_uni.checked_sub(1)
-                                             .ok_or(Error::StreamLimit)?;
-                }
+            pacing_timer: None,
 
-                let s = stream::Stream::new(max_rx_data, max_tx_data);
-                v.insert(s)
-            },
+            rate_limiter: None,
 
-            hash_map::Entry::Occupied(v) => v.into_mut(),
-        };
+            rate_limit_timer: None,
 
-        // TODO: implement backpressure based on peer's flow control
+            ping_pending: false,
 
-        stream.send_push(buf, fin)?;
+            max_send_udp_payload_size: config.max_send_udp_payload_size,
 
-        Ok(buf.len())
-    }
+            max_conn_mem: config.max_conn_mem,
 
-    /// Creates an iterator over streams that have outstanding data to read.
-    pub fn readable(&mut self) -> Readable {
-        stream::Readable::new(&self.streams)
-    }
+            rx_buffered: 0,
 
-    /// Returns the amount of time until the next timeout event.
-    ///
-    /// Once the given duration has elapsed, the [`on_timeout()`] method should
-    /// be called. A timeout of `None` means that the timer should be disarmed.
-    ///
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    pub fn timeout(&self) -> Option<std::time::Duration> {
-        if self.closed {
-            return None;
-        }
+            is_server,
 
-        let timeout = if self.draining {
-            self.draining_timer
-        } else if self.recovery.loss_detection_timer().is_some() {
-            self.recovery.loss_detection_timer()
-        } else if self.idle_timer.is_some() {
-            self.idle_timer
-        } else {
-            None
-        };
+            derived_initial_secrets: false,
 
-        if let Some(timeout) = timeout {
-            let now = time::Instant::now();
+            did_version_negotiation: false,
 
-            if timeout <= now {
-                return Some(std::time::Duration::new(0, 0));
-            }
+            did_retry: false,
 
-            return Some(timeout.duration_since(now));
-        }
+            got_peer_conn_id: false,
 
-        None
-    }
+            handshake_completed: false,
 
-    /// Processes a timeout event.
-    ///
-    /// If no timeout has occurred it does nothing.
-    pub fn on_timeout(&mut self) {
-        let now = time::Instant::now();
+            draining: false,
 
-        if self.draining {
-            if self.draining_timer.is_some() &&
-               self.draining_timer.unwrap() <= now {
-                trace!("{} draining timeout expired", self.trace_id);
+            closing: false,
 
-                self.closed = true;
-            }
+            close_pending: false,
 
-            return;
+            closing_rx_count: 0,
+
+            closed: false,
+
+            events: EventQueue::default(),
+
+            closed_event: false,
+        });
+
+        if let Some(odcid) = odcid {
+            conn.local_transport_params.original_connection_id =
+                Some(odcid.to_vec());
         }
 
-        if self.idle_timer.is_some() && self.idle_timer.unwrap() <= now {
-            trace!("{} idle timeout expired", self.trace_id);
+        if is_server && conn.local_transport_params.stateless_reset_token
+                                                   .is_none() {
+            conn.local_transport_params.stateless_reset_token =
+                config.stateless_reset_token(scid).map(|t| t.to_vec());
+        }
 
-            self.closed = true;
-            return;
+        conn.tls_state.init(&conn).map_err(|_| Error::TlsFail)?;
+
+        // Count the server's handshake as half-open until it completes.
+        if is_server {
+            config.half_open.fetch_add(1, sync::atomic::Ordering::SeqCst);
+
+            conn.half_open = Some(config.half_open.clone());
         }
 
-        if self.recovery.loss_detection_timer().is_some() &&
-           self.recovery.loss_detection_timer().unwrap() <= now {
-            trace!("{} loss detection timeout expired", self.trace_id);
+        // Derive initial secrets for the client. We can do this here because
+        // we already generated the random destination connection ID.
+        if !is_server {
+            let mut dcid: [u8; 16] = [0; 16];
+            fill_random(&conn.random, &mut dcid[..]);
 
-            self.recovery.on_loss_detection_timer(&mut self.initial.flight,
-                                                  &mut self.handshake.flight,
-                                                  &mut self.application.flight,
-                                                  now, &self.trace_id);
-            return;
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                conn.version, &dcid, conn.is_server)?;
+
+            conn.dcid.extend_from_slice(&dcid);
+
+            conn.initial.crypto_open = Some(aead_open);
+            conn.initial.crypto_seal = Some(aead_seal);
+
+            conn.derived_initial_secrets = true;
         }
+
+        Ok(conn)
     }
 
-    /// Closes the connection with the given error and reason.
+    /// Processes QUIC packets received from the peer.
     ///
-    /// The `app` parameter specifies whether an application close should be
-    /// sent to the peer. Otherwise a normal connection close is sent.
+    /// On success the number of bytes processed from the input buffer is
+    /// returned, or [`Done`].
     ///
-    /// Returns [`Done`] if the connection had already been closed.
+    /// Coalesced packets will be processed as necessary.
     ///
-    /// Note that the connection will not be closed immediately. An application
-    /// should continue calling [`recv()`], [`send()`] and [`timeout()`] as
-    /// normal, until the [`is_closed()`] method returns `true`.
+    /// Note that the contents of the input buffer `buf` might be modified by
+    /// this function due to, for example, in-place decryption.
     ///
     /// [`Done`]: enum.Error.html#variant.Done
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.recv_at(buf, time::Instant::now())
+    }
+
+    /// Processes QUIC packets stored in a [`PacketBuf`].
+    ///
+    /// This is the same as [`recv()`], but the packets are processed directly
+    /// from the buffer's memory.
+    ///
+    /// [`PacketBuf`]: trait.PacketBuf.html
     /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`send()`]: struct.Connection.html#method.send
-    /// [`timeout()`]: struct.Connection.html#method.timeout
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    pub fn close(&mut self, app: bool, err: u16, reason: &[u8]) -> Result<()> {
-        if self.draining {
-            return Err(Error::Done);
-        }
+    pub fn recv_buf<B: PacketBuf>(&mut self, buf: &mut B) -> Result<usize> {
+        let len = buf.len();
 
-        if self.error.is_some() || self.app_error.is_some() {
-            return Err(Error::Done);
-        }
+        self.recv(&mut buf.buf_mut()[..len])
+    }
 
-        if app {
-            self.app_error = Some(err);
-            self.app_reason.extend_from_slice(reason);
-        } else {
-            self.error = Some(err);
-        }
+    /// Processes QUIC packets stored in a buffer leased from `pool`, and
+    /// releases the buffer back to the pool.
+    ///
+    /// This is the same as [`recv_buf()`].
+    ///
+    /// [`recv_buf()`]: struct.Connection.html#method.recv_buf
+    pub fn recv_leased<P: BufferPool>(&mut self, pool: &P, mut buf: P::Buf)
+                                                        -> Result<usize> {
+        let res = self.recv_buf(&mut buf);
 
-        Ok(())
+        pool.release(buf);
+
+        res
     }
 
-    /// Returns a string uniquely representing the connection.
+    /// Processes QUIC packets received from the peer at the given time.
     ///
-    /// This can be used for logging purposes to differentiate between multiple
-    /// connections.
-    pub fn trace_id(&self) -> &str {
-        &self.trace_id
+    /// This is the same as [`recv()`], but uses `now` as the current time
+    /// instead of reading the system clock, so that timers can be driven
+    /// deterministically (e.g. in tests and simulations). `now` should never
+    /// go backwards between calls.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    pub fn recv_at(&mut self, buf: &mut [u8], now: time::Instant)
+                                                            -> Result<usize> {
+        self.recv_coalesced(buf, RecvInfo::default(), now)
     }
 
-    /// Returns the negotiated ALPN protocol.
+    /// Processes QUIC packets received from the peer, along with the metadata
+    /// of the datagram that carried them.
     ///
-    /// If no protocol has been negotiated, the returned value is empty.
-    pub fn application_proto(&self) -> &[u8] {
-        self.tls_state.get_alpn_protocol()
+    /// This is the same as [`recv()`], but additionally records the datagram's
+    /// ECN codepoint for each packet processed, as reported by [`stats()`],
+    /// and tracks changes of the local and peer addresses of the path.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`stats()`]: struct.Connection.html#method.stats
+    pub fn recv_with_info(&mut self, buf: &mut [u8], info: RecvInfo)
+                                                            -> Result<usize> {
+        self.recv_with_info_at(buf, info, time::Instant::now())
     }
 
-    /// Returns true if the connection handshake is complete.
-    pub fn is_established(&self) -> bool {
-        self.handshake_completed
+    /// Processes QUIC packets received from the peer at the given time, along
+    /// with the metadata of the datagram that carried them.
+    ///
+    /// This combines [`recv_at()`] and [`recv_with_info()`].
+    ///
+    /// [`recv_at()`]: struct.Connection.html#method.recv_at
+    /// [`recv_with_info()`]: struct.Connection.html#method.recv_with_info
+    pub fn recv_with_info_at(&mut self, buf: &mut [u8], info: RecvInfo,
+                             now: time::Instant) -> Result<usize> {
+        self.recv_coalesced(buf, info, now)
     }
 
-    /// Returns true if the connection is resumed.
-    pub fn is_resumed(&self) -> bool {
-        self.tls_state.is_resumed()
-    }
+    /// Processes all the coalesced QUIC packets in a datagram.
+    fn recv_coalesced(&mut self, buf: &mut [u8], info: RecvInfo,
+                      now: time::Instant) -> Result<usize> {
+        let len = buf.len();
 
-    /// Returns true if the connection is closed.
-    ///
-    /// If this returns true, the connection object can be dropped.
-    pub fn is_closed(&self) -> bool {
-        self.closed
-    }
+        let mut done = 0;
+        let mut left = len;
 
-    /// Collects and returns statistics about the connection.
-    pub fn stats(&self) -> Stats {
-        Stats {
-            sent: self.sent_count,
-            lost: self.lost_count,
-            rtt: self.recovery.rtt(),
+        let largest_rx_pkt_num = self.application.largest_rx_pkt_num;
+
+        // Process coalesced packets.
+        while left > 0 {
+            let read = self.recv_single(&mut buf[len - left..len], now)?;
+
+            self.ecn_recv_count[info.ecn as usize] += 1;
+
+            done += read;
+            left -= read;
+        }
+
+        if let Some(from) = info.from {
+            // Only the packet with the largest number can change the peer's
+            // address, so that reordered packets don't make it flap.
+            let largest = self.application.largest_rx_pkt_num >
+                          largest_rx_pkt_num;
+
+            self.on_peer_addr(info.to, from, largest, now);
+
+            let local = info.to.or(self.local_addr);
+
+            self.path_mut(local, from).recv_bytes += done;
         }
+
+        Ok(done)
     }
 
-    /// Continues the handshake.
-    ///
-    /// If the connection is already established, it does nothing.
-    fn do_handshake(&mut self) -> Result<()> {
-        if !self.handshake_completed {
-            match self.tls_state.do_handshake() {
-                Ok(_) => {
-                    // Handshake is complete!
-                    self.handshake_completed = true;
+    /// Processes a single QUIC packet received from the peer.
+    fn recv_single(&mut self, buf: &mut [u8], now: time::Instant)
+                                                            -> Result<usize> {
+        if buf.is_empty() {
+            return Err(Error::BufferTooShort);
+        }
 
-                    let mut raw_params =
-                        self.tls_state.get_quic_transport_params().to_vec();
+        if self.handshake_timed_out {
+            return Err(Error::HandshakeTimeout);
+        }
 
-                    let peer_params = TransportParams::decode(&mut raw_params,
-                                                              self.version,
-                                                              self.is_server)?;
+        if self.draining {
+            return Err(Error::Done);
+        }
 
-                    if peer_params.original_connection_id != self.odcid {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        // In the closing state, incoming packets are ignored, but trigger a
+        // retransmission of the CONNECTION_CLOSE frame. To avoid amplifying
+        // the peer's traffic, the frame is only retransmitted after an
+        // exponentially increasing number of packets has been received.
+        if self.closing {
+            self.closing_rx_count += 1;
 
-                    self.max_tx_data = peer_params.initial_max_data as usize;
+            if self.closing_rx_count.is_power_of_two() {
+                self.close_pending = true;
+            }
 
-                    self.peer_max_streams_bidi =
-                        peer_params.initial_max_streams_bidi as usize;
-                    self.peer_max_streams_uni =
-                        peer_params.initial_max_streams_uni as usize;
+            return Ok(buf.len());
+        }
 
-                    self.recovery.max_ack_delay =
-                        time::Duration::from_millis(peer_params.max_ack_delay);
+        self.do_handshake()?;
 
-                    self.peer_transport_params = peer_params;
+        let is_closing = self.error.is_some() || self.app_error.is_some();
 
-                    trace!("{} connection established: cipher={:?} proto={:?} resumed={}",
-                           &self.trace_id,
-                           self.tls_state.cipher(),
-                           std::str::from_utf8(self.application_proto()),
-                           self.is_resumed());
-                },
+        if is_closing {
+            return Err(Error::Done);
+        }
 
-                Err(tls::Error::TlsFail) => {
-                    // If we have an error to send (e.g. a TLS alert), ignore
-                    // the error so we send a CONNECTION_CLOSE to the peer.
-                    if self.error.is_none() {
-                        return Err(Error::TlsFail);
-                    }
-                },
+        let mut b = octets::OctetsMut::with_slice(buf);
 
-                Err(tls::Error::SyscallFail) => return Err(Error::TlsFail),
+        let mut hdr = Header::from_bytes(&mut b, self.scid.len())?;
 
-                Err(_) => (),
+        if hdr.ty == packet::Type::VersionNegotiation {
+            // Version negotiation packets can only be sent by the server.
+            if self.is_server {
+                return Err(Error::Done);
             }
-        }
 
-        Ok(())
-    }
+            // Ignore duplicate version negotiation.
+            if self.did_version_negotiation {
+                return Err(Error::Done);
+            }
 
-    /// Selects the type for the outgoing packet depending on whether there is
-    /// handshake data to send, whether there are packets to ACK, or whether
-    /// there are streams that can be written or that needs to increase flow
-    /// control credit.
-    fn select_egress_pkt_type(&self) -> Result<Type> {
-        let ty =
-            // On error or probe, send packet in the latest space available.
-            if self.error.is_some() || self.recovery.probes > 0 {
-                match self.tls_state.get_write_level() {
-                    crypto::Level::Initial     => Type::Initial,
-                    crypto::Level::ZeroRTT     => unreachable!(),
-                    crypto::Level::Handshake   => Type::Handshake,
-                    crypto::Level::Application => Type::Application,
-                }
-            } else if self.initial.ready() {
-                Type::Initial
-            } else if self.handshake.ready() {
-                Type::Handshake
-            } else if self.handshake_completed &&
-                      (self.application.ready() ||
-                       self.streams.values().any(|s| s.writable()) ||
-                       self.streams.values().any(|s| s.more_credit())) {
-                Type::Application
-            } else {
+            if hdr.dcid != self.scid {
+                return Err(Error::Done);
+            }
+
+            if hdr.scid != self.dcid {
                 return Err(Error::Done);
+            }
+
+            trace!("{} rx pkt {:?}", self.trace_id, hdr);
+
+            let versions = match hdr.versions {
+                Some(ref v) => v,
+                None => return Err(Error::InvalidPacket),
             };
 
-        Ok(ty)
-    }
+            // Pick our most preferred version among the offered ones.
+            let new_version = SUPPORTED_VERSIONS.iter()
+                                                .find(|v| versions.contains(v))
+                                                .cloned()
+                                                .unwrap_or(0);
 
-    /// Drops the initial keys and recovery state.
-    fn drop_initial_state(&mut self) {
-        if self.initial.crypto_open.is_none() {
-            return;
+            // We don't support any of the versions offfered.
+            if new_version == 0 {
+                return Err(Error::UnknownVersion);
+            }
+
+            self.version = new_version;
+            self.did_version_negotiation = true;
+
+            // Reset connection state to force sending another Initial packet.
+            self.got_peer_conn_id = false;
+            self.recovery.drop_unacked_data(&mut self.initial.flight);
+            self.initial.clear();
+            self.tls_state.clear()
+                .map_err(|_| Error::TlsFail)?;
+
+            return Err(Error::Done);
         }
 
-        self.recovery.drop_unacked_data(&mut self.initial.flight);
-        self.initial.crypto_open = None;
-        self.initial.crypto_seal = None;
-        self.initial.clear();
+        if hdr.ty == packet::Type::Retry {
+            // Retry packets can only be sent by the server.
+            if self.is_server {
+                return Err(Error::Done);
+            }
 
-        trace!("{} dropped initial state", self.trace_id);
-    }
-}
+            // Ignore duplicate retry.
+            if self.did_retry {
+                return Err(Error::Done);
+            }
 
-/// Statistics about the connection.
-///
-/// A connections's statistics can be collected using the [`stats()`] method.
-///
-/// [`stats()`]: struct.Connection.html#method.stats
-#[derive(Clone)]
-pub struct Stats {
-    /// The number of QUIC packets sent on this connection.
-    pub sent: usize,
+            // Ignore retry received after the server's Initial was already
+            // processed.
+            if self.got_peer_conn_id {
+                return Err(Error::Done);
+            }
 
-    /// The number of QUIC packets that were lost.
-    pub lost: usize,
+            // Retry packets without a token are invalid.
+            if hdr.token.as_ref().map_or(true, |t| t.is_empty()) {
+                return Err(Error::Done);
+            }
 
-    /// The estimated rounf-trip time of the connection.
-    pub rtt: time::Duration,
-}
+            if hdr.odcid.as_ref() != Some(&self.dcid) {
+                return Err(Error::Done);
+            }
 
-impl std::fmt::Debug for Stats {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "sent={} lost={} rtt={:?}", self.sent, self.lost, self.rtt)
-    }
-}
+            trace!("{} rx pkt {:?}", self.trace_id, hdr);
 
-#[derive(Clone, Debug, PartialEq)]
-struct TransportParams {
-    pub original_connection_id: Option<Vec<u8>>,
-    pub idle_timeout: u64,
-    pub stateless_reset_token: Option<Vec<u8>>,
-    pub max_packet_size: u64,
-    pub initial_max_data: u64,
-    pub initial_max_stream_data_bidi_local: u64,
+            self.token = hdr.token;
+            self.did_retry = true;
+
+            // Remember peer's new connection ID.
+            self.odcid = Some(self.dcid.clone());
+
+            self.dcid.resize(hdr.scid.len(), 0);
+            self.dcid.copy_from_slice(&hdr.scid);
+
+            // Derive Initial secrets using the new connection ID.
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                self.version, &hdr.scid, self.is_server)?;
+
+            self.initial.crypto_open = Some(aead_open);
+            self.initial.crypto_seal = Some(aead_seal);
+
+            // Reset connection state to force sending another Initial packet.
+            self.got_peer_conn_id = false;
+            self.recovery.drop_unacked_data(&mut self.initial.flight);
+            self.initial.clear();
+            self.tls_state.clear()
+                .map_err(|_| Error::TlsFail)?;
+
+            return Err(Error::Done);
+        }
+
+        if hdr.ty != packet::Type::Application && hdr.version != self.version {
+            return Err(Error::UnknownVersion);
+        }
+
+        // Long header packets have an explicit payload length, but short
+        // packets don't so just use the remaining capacity in the buffer.
+        let payload_len = if hdr.ty == packet::Type::Application {
+            b.cap()
+        } else  {
+            let len = b.get_varint()?;
+
+            if len > b.cap() as u64 {
+                return Err(Error::BufferTooShort);
+            }
+
+            len as usize
+        };
+
+        if !self.is_server && !self.got_peer_conn_id {
+            // Replace the randomly generated destination connection ID with
+            // the one supplied by the server.
+            self.dcid.resize(hdr.scid.len(), 0);
+            self.dcid.copy_from_slice(&hdr.scid);
+
+            self.got_peer_conn_id = true;
+        }
+
+        // Derive initial secrets on the server.
+        if !self.derived_initial_secrets {
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                self.version, &hdr.dcid, self.is_server)?;
+
+            self.initial.crypto_open = Some(aead_open);
+            self.initial.crypto_seal = Some(aead_seal);
+
+            self.derived_initial_secrets = true;
+
+            self.dcid.extend_from_slice(&hdr.scid);
+            self.got_peer_conn_id = true;
+        }
+
+        // Select packet number space context based on the input packet type.
+        let space = match hdr.ty {
+            packet::Type::Initial => &mut self.initial,
+
+            packet::Type::Handshake => &mut self.handshake,
+
+            packet::Type::Application => &mut self.application,
+
+            _ => return Err(Error::InvalidPacket),
+        };
+
+        let aead = match space.crypto_open {
+            Some(ref v) => v,
+
+            None => {
+                trace!("{} dropped undecryptable packet type={:?} len={}",
+                       self.trace_id, hdr.ty, payload_len);
+
+                return Ok(b.off() + payload_len)
+            },
+        };
+
+        packet::decrypt_hdr(&mut b, &mut hdr, &aead)?;
+
+        let pn = packet::decode_pkt_num(space.largest_rx_pkt_num,
+                                        hdr.pkt_num, hdr.pkt_num_len);
+
+        trace!("{} rx pkt {:?} len={} pn={}", self.trace_id, hdr,
+               payload_len, pn);
+
+        let mut payload = packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len,
+                                              payload_len, &aead)?;
+
+        let tag_len = aead.alg().tag_len();
+
+        // Duplicate packets are dropped before any of their frames are
+        // processed. Packets older than the window are treated as duplicates.
+        if space.recv_pkt_num.contains(pn) {
+            trace!("{} ignored duplicate packet {}", self.trace_id, pn);
+
+            self.dup_recv_count += 1;
+
+            return Err(Error::Done);
+        }
+
+        // To avoid sending an ACK in response to an ACK-only packet, we need
+        // to keep track of whether this packet contains any frame other than
+        // ACK.
+        let mut do_ack = false;
+
+        // The path validated by a PATH_RESPONSE frame, if any. Its state is
+        // only updated once the packet number space isn't borrowed anymore.
+        let mut validated_path = None;
+
+        let mut frame_summaries = Vec::new();
+
+        // Whether stream data in the packet was dropped for being past the
+        // reorder window, in which case the packet isn't acknowledged.
+        let mut exceeded_reorder_window = false;
+
+        // Process packet payload.
+        while payload.cap() > 0 {
+            let frame_type = payload.peek_varint().unwrap_or(0);
+
+            // STREAM frames are processed directly from the decrypted payload
+            // rather than being parsed into an owned frame, so that only the
+            // data that wasn't already read by the application is copied into
+            // the stream's receive buffer.
+            if frame::is_stream(frame_type) &&
+               (hdr.ty == packet::Type::Application ||
+                hdr.ty == packet::Type::ZeroRTT) {
+                let parsed =
+                    frame::get_frame_type(&mut payload).and_then(|ty| {
+                        frame::parse_stream_frame_ref(ty, &mut payload)
+                    });
+
+                let (stream_id, off, data, fin) = match parsed {
+                    Ok(v) => v,
+
+                    // Close the connection, reporting the offending frame
+                    // type.
+                    Err(e) => {
+                        self.error = Some(e.to_wire());
+                        self.error_frame_type = frame_type;
+
+                        return Err(e);
+                    },
+                };
+
+                trace!("{} rx frm STREAM id={} off={} len={} fin={}",
+                       self.trace_id, stream_id, off, data.len(), fin);
+
+                if self.packet_trace_cb.is_some() {
+                    frame_summaries.push(
+                        format!("STREAM id={} off={} len={} fin={}",
+                                stream_id, off, data.len(), fin));
+                }
+
+                // Peer can't send on our unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    stream::is_local(stream_id, self.is_server) {
+                    return Err(Error::InvalidStreamState);
+                }
+
+                // Stream data past the reorder window is dropped, and the
+                // packet isn't acknowledged so that the peer retransmits it
+                // once the gap in the stream is filled. The other frames in
+                // the packet are still processed.
+                if self.exceeds_reorder_window(stream_id, off, data.len()) {
+                    trace!("{} dropped stream {} data past reorder window",
+                           self.trace_id, stream_id);
+
+                    exceeded_reorder_window = true;
+
+                    continue;
+                }
+
+                // Close the connection if buffering the data would exceed
+                // the memory budget. Only stream data is accounted for here,
+                // using a running counter, as computing the full memory
+                // usage for every frame would be too expensive.
+                if let Some(max) = self.max_conn_mem {
+                    if self.rx_buffered + data.len() > max {
+                        let e = Error::MemoryLimit;
+
+                        self.error = Some(e.to_wire());
+
+                        return Err(e);
+                    }
+                }
+
+                // Only data past the largest offset already received on the
+                // stream counts against flow control, so that late
+                // retransmissions, e.g. of data that was already read, don't
+                // use up credit.
+                let max_off = self.streams.get(&stream_id)
+                                  .map_or(0, |s| s.recv_max_off());
+
+                self.rx_data += (off + data.len()).saturating_sub(max_off);
+
+                if self.rx_data > self.max_rx_data {
+                    return Err(Error::FlowControl);
+                }
+
+                // Refused streams aren't created, but their data is still
+                // acknowledged so the peer doesn't retransmit it.
+                if self.is_refused_stream(stream_id) {
+                    trace!("{} ignored refused stream {}", self.trace_id,
+                           stream_id);
+
+                    do_ack = true;
+
+                    continue;
+                }
+
+                let stream = self.get_or_create_stream(stream_id, false)?;
+
+                let buffered = stream.recv_buffered();
+
+                stream.recv_push_slice(data.as_ref(), off, fin)?;
+
+                let buffered = stream.recv_buffered() - buffered;
+                let readable = stream.readable();
+
+                self.rx_buffered += buffered;
+
+                if readable {
+                    self.events.push(Event::StreamReadable(stream_id));
+                }
+
+                do_ack = true;
+
+                continue;
+            }
+
+            // Frames of negotiated extension types are parsed here, as their
+            // type isn't known to the frame parser.
+            let handler = if hdr.ty == packet::Type::Application {
+                extension_handler(&self.extensions,
+                                  &self.peer_transport_params, frame_type)
+            } else {
+                None
+            };
+
+            let frame = if handler.is_some() {
+                frame::get_frame_type(&mut payload).and_then(|ty| {
+                    frame::parse_extension_frame(ty, &mut payload)
+                })
+            } else {
+                frame::Frame::from_bytes(&mut payload, hdr.ty)
+            };
+
+            let frame = match frame {
+                Ok(v) => v,
+
+                // Close the connection, reporting the offending frame type.
+                Err(e) => {
+                    self.error = Some(e.to_wire());
+                    self.error_frame_type = frame_type;
+
+                    return Err(e);
+                },
+            };
+
+            trace!("{} rx frm {:?}", self.trace_id, frame);
+
+            if self.packet_trace_cb.is_some() {
+                frame_summaries.push(format!("{:?}", frame));
+            }
+
+            if frame.ack_eliciting() {
+                do_ack = true;
+            }
+
+            match frame {
+                frame::Frame::Padding { .. } => (),
+
+                frame::Frame::Ping => (),
+
+                frame::Frame::Extension { ty, data } => {
+                    if let Some(ref handler) = handler {
+                        handler.on_frame(ty, &data)?;
+                    }
+                },
+
+                frame::Frame::ACK { ranges, ack_delay } => {
+                    let ack_delay = ack_delay.checked_mul(
+                        2_u64.pow(self.peer_transport_params
+                                      .ack_delay_exponent as u32))
+                        .unwrap_or(std::u64::MAX);
+
+                    // The packet number space is borrowed again for each
+                    // frame that needs it, so that streams can be created
+                    // while the payload is processed.
+                    let space = match hdr.ty {
+                        packet::Type::Initial => &mut self.initial,
+
+                        packet::Type::Handshake => &mut self.handshake,
+
+                        _ => &mut self.application,
+                    };
+
+                    self.recovery.on_ack_received(&ranges, ack_delay,
+                                                  &mut space.flight,
+                                                  now, &self.trace_id);
+                },
+
+                frame::Frame::StopSending { stream_id, .. } => {
+                    // STOP_SENDING on a receive-only stream is a fatal error.
+                    if !stream::is_local(stream_id, self.is_server) &&
+                       !stream::is_bidi(stream_id) {
+                        return Err(Error::InvalidPacket);
+                    }
+                },
+
+                frame::Frame::Crypto { data } => {
+                    let space = match hdr.ty {
+                        packet::Type::Initial => &mut self.initial,
+
+                        packet::Type::Handshake => &mut self.handshake,
+
+                        _ => &mut self.application,
+                    };
+
+                    // Push the data to the stream so it can be re-ordered.
+                    space.crypto_stream.recv_push(data)?;
+
+                    // Feed crypto data to the TLS state, if there's data
+                    // available at the expected offset.
+                    if space.crypto_stream.readable() {
+                        let buf = space.crypto_stream.recv_pop(std::usize::MAX)?;
+                        let level = space.crypto_level;
+
+                        self.tls_state.provide_data(level, &buf)
+                                      .map_err(|_| Error::TlsFail)?;
+                    }
+                },
+
+                frame::Frame::NewToken { token } => {
+                    // NEW_TOKEN frames can only be sent by the server.
+                    if self.is_server {
+                        self.error = Some(Error::InvalidPacket.to_wire());
+                        self.error_frame_type = frame_type;
+
+                        return Err(Error::InvalidPacket);
+                    }
+
+                    if let Some(ref cb) = self.new_token_cb {
+                        cb(&token);
+                    }
+
+                    self.events.push(Event::NewToken(token.clone()));
+
+                    self.peer_token = Some(token);
+                },
+
+                // STREAM frames are processed in the fast path above, and
+                // aren't allowed in other packet types.
+                frame::Frame::Stream { .. } =>
+                    return Err(Error::InvalidFrame { ty: frame_type }),
+
+                frame::Frame::MaxData { max } => {
+                    self.max_tx_data = cmp::max(self.max_tx_data,
+                                                max as usize);
+                },
+
+                frame::Frame::MaxStreamData { stream_id, max } => {
+                    if self.is_refused_stream(stream_id) {
+                        continue;
+                    }
+
+                    let stream = self.get_or_create_stream(stream_id, false)?;
+
+                    if stream.send_max_data(max as usize) {
+                        self.events.push(Event::StreamWritable(stream_id));
+                    }
+                },
+
+                frame::Frame::MaxStreamsBidi { max } => {
+                    self.peer_max_streams_bidi =
+                        cmp::max(self.peer_max_streams_bidi, max as usize);
+                },
+
+                frame::Frame::MaxStreamsUni { max } => {
+                    self.peer_max_streams_uni =
+                        cmp::max(self.peer_max_streams_uni, max as usize);
+                },
+
+                frame::Frame::NewConnectionId { seq_num, conn_id, .. } => {
+                    // Connection IDs beyond the limit we advertised are
+                    // ignored, they can still be used once the peer
+                    // retransmits them.
+                    let limit = self.local_transport_params
+                                    .active_conn_id_limit as usize;
+
+                    if seq_num > self.dcid_seq &&
+                       self.spare_dcids.len() < limit.saturating_sub(1) &&
+                       !self.spare_dcids.iter().any(|(s, _)| *s == seq_num) {
+                        self.spare_dcids.push_back((seq_num, conn_id));
+                    }
+                },
+
+                frame::Frame::RetireConnectionId { seq_num } => {
+                    if seq_num >= self.next_scid_seq {
+                        return Err(Error::InvalidFrame { ty: frame_type });
+                    }
+
+                    self.active_scid_seqs.remove(&seq_num);
+                },
+
+                frame::Frame::PathChallenge { data } => {
+                    self.challenge = Some(data);
+                },
+
+                frame::Frame::PathResponse { data } => {
+                    if self.path_challenge == Some(data) {
+                        trace!("{} path validated addr={:?}",
+                               self.trace_id, self.peer_addr);
+
+                        self.validated_addr = self.peer_addr;
+
+                        validated_path = self.peer_addr;
+
+                        self.path_challenge = None;
+                        self.path_challenge_pending = false;
+                        self.path_validation_timer = None;
+                    }
+                },
+
+                frame::Frame::ConnectionClose { error_code, .. } => {
+                    self.peer_error = Some(error_code);
+
+                    self.draining = true;
+                    self.draining_timer =
+                        Some(now + self.recovery.pto() * 3);
+                },
+
+                frame::Frame::ApplicationClose { .. } => {
+                    self.draining = true;
+                    self.draining_timer =
+                        Some(now + self.recovery.pto() * 3);
+                },
+            }
+        }
+
+        if let Some(ref cb) = self.packet_trace_cb {
+            cb(&self.trace_id, &PacketTrace {
+                event: PacketEvent::Received,
+                ty: hdr.ty,
+                pkt_num: pn,
+                len: payload_len,
+                frames: frame_summaries,
+            });
+        }
+
+        let space = match hdr.ty {
+            packet::Type::Initial => &mut self.initial,
+
+            packet::Type::Handshake => &mut self.handshake,
+
+            _ => &mut self.application,
+        };
+
+        // Process ACK'd frames.
+        for acked in space.flight.acked.drain(..) {
+            match acked {
+                // Stop acknowledging packets less than or equal to the
+                // largest acknowledged in the sent ACK frame that, in
+                // turn, got ACK'd.
+                frame::Frame::ACK { ranges, .. } => {
+                    let largest_acked = ranges.largest().unwrap();
+                    space.recv_pkt_need_ack.remove_until(largest_acked);
+                },
+
+                // This does nothing. It's here to avoid a warning.
+                frame::Frame::Ping => (),
+
+                _ => (),
+            }
+        }
+
+        // Packets with stream data past the reorder window are not recorded
+        // as received, so they're not acknowledged.
+        if !exceeded_reorder_window {
+            // Packets that arrive out of order, or after a gap in the packet
+            // numbers, are ACK'd immediately so the peer can detect loss
+            // quickly.
+            let reordered = pn < space.largest_rx_pkt_num ||
+                            pn > space.largest_rx_pkt_num + 1;
+
+            // We only record the time of arrival of the largest packet
+            // number that still needs to be ACK'd, to be used for ACK delay
+            // calculation.
+            if space.recv_pkt_need_ack.largest() < Some(pn) {
+                space.largest_rx_pkt_time = now;
+            }
+
+            space.recv_pkt_num.insert(pn);
+
+            space.recv_pkt_need_ack.push_item(pn);
+
+            // Application packets don't need to be ACK'd immediately.
+            // Instead every other ack-eliciting packet is ACK'd, or at the
+            // latest after the local max_ack_delay expires. Packets that are
+            // not ack-eliciting never trigger an ACK on their own.
+            if do_ack && hdr.ty == packet::Type::Application {
+                self.ack_eliciting_since_ack += 1;
+
+                if self.ack_eliciting_since_ack >= 2 || reordered {
+                    space.do_ack = true;
+                } else if self.ack_timer.is_none() {
+                    let max_ack_delay = time::Duration::from_millis(
+                        self.local_transport_params.max_ack_delay);
+
+                    self.ack_timer = Some(now + max_ack_delay);
+                }
+            } else {
+                space.do_ack = cmp::max(space.do_ack, do_ack);
+            }
+
+            space.largest_rx_pkt_num = cmp::max(space.largest_rx_pkt_num, pn);
+        }
+
+        // An idle timeout of 0 means the idle timer is disabled.
+        if self.local_transport_params.idle_timeout > 0 {
+            self.idle_timer =
+                Some(now + time::Duration::from_secs(
+                    self.local_transport_params.idle_timeout));
+        }
+
+        self.ack_eliciting_sent = false;
+
+        self.keepalive_timer = self.keepalive.map(|k| now + k);
+
+        let read = b.off() + tag_len;
+
+        if let Some(addr) = validated_path {
+            self.path_mut(self.local_addr, addr).state = PathState::Validated;
+        }
+
+        if let Some(ref m) = self.metrics {
+            m.packet_received(read);
+            m.rtt(self.recovery.rtt());
+        }
+
+        // On the server, drop initial state after receiving and successfully
+        // processing an Handshake packet.
+        if self.is_server && hdr.ty == packet::Type::Handshake {
+            self.drop_initial_state();
+        }
+
+        // Once the peer acknowledged a 1-RTT packet after the handshake
+        // completed, it won't need any more Handshake packets, so drop the
+        // handshake state.
+        if self.handshake_completed &&
+           self.application.flight.largest_acked.is_some() {
+            self.drop_initial_state();
+            self.drop_handshake_state();
+        }
+
+        Ok(read)
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer.
+    ///
+    /// On success the number of bytes processed from the input buffer is
+    /// returned, or [`Done`].
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn send(&mut self, out: &mut [u8]) -> Result<usize> {
+        self.send_at(out, time::Instant::now())
+    }
+
+    /// Writes a single QUIC packet into a [`PacketBuf`].
+    ///
+    /// This is the same as [`send()`], but the packet is written directly into
+    /// the buffer's memory, and the buffer's length is set to the size of the
+    /// packet.
+    ///
+    /// [`PacketBuf`]: trait.PacketBuf.html
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn send_buf<B: PacketBuf>(&mut self, buf: &mut B) -> Result<usize> {
+        let written = self.send(buf.buf_mut())?;
+
+        buf.set_len(written);
+
+        Ok(written)
+    }
+
+    /// Writes a single QUIC packet into a buffer leased from `pool`.
+    ///
+    /// On success the buffer containing the packet is returned, and it's up
+    /// to the application to release it back to the pool once the packet has
+    /// been sent. If no packet is written the buffer is released immediately.
+    ///
+    /// [`BufferTooShort`] is returned if the pool has no buffer available.
+    ///
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    pub fn send_leased<P: BufferPool>(&mut self, pool: &P) -> Result<P::Buf> {
+        let mut buf = pool.lease().ok_or(Error::BufferTooShort)?;
+
+        match self.send_buf(&mut buf) {
+            Ok(_) => Ok(buf),
+
+            Err(e) => {
+                pool.release(buf);
+
+                Err(e)
+            },
+        }
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer, and returns the
+    /// metadata it should be sent with.
+    ///
+    /// This is the same as [`send()`], but also returns a [`SendInfo`] that
+    /// the application can use to set the ECN and DSCP fields of the outgoing
+    /// datagram.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`SendInfo`]: struct.SendInfo.html
+    pub fn send_with_info(&mut self, out: &mut [u8])
+                                            -> Result<(usize, SendInfo)> {
+        let written = self.send(out)?;
+
+        let info = SendInfo {
+            ecn: Ecn::NotEct,
+            dscp: self.dscp,
+            from: self.local_addr,
+            to: self.peer_addr,
+        };
+
+        Ok((written, info))
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer at the given time.
+    ///
+    /// This is the same as [`send()`], but uses `now` as the current time
+    /// instead of reading the system clock.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn send_at(&mut self, out: &mut [u8], now: time::Instant)
+                                                            -> Result<usize> {
+        if out.is_empty() {
+            return Err(Error::BufferTooShort);
+        }
+
+        if self.handshake_timed_out {
+            return Err(Error::HandshakeTimeout);
+        }
+
+        if self.draining {
+            return Err(Error::Done);
+        }
+
+        if self.closing && !self.close_pending {
+            return Err(Error::Done);
+        }
+
+        self.report_lost_packets();
+
+        if self.drain_timer.is_some() && self.is_drained() {
+            trace!("{} drained", self.trace_id);
+
+            self.drain_timer = None;
+            self.close(false, 0x0, b"").ok();
+        }
+
+        let is_closing = self.error.is_some() || self.app_error.is_some();
+
+        if !is_closing {
+            self.do_handshake()?;
+        }
+
+        let max_pkt_len = cmp::min(
+            self.peer_transport_params.max_packet_size as usize,
+            self.max_send_udp_payload_size);
+
+        // Cap output buffer to respect peer's max_packet_size limit, as well
+        // as the local send limit.
+        let avail = cmp::min(max_pkt_len, out.len());
+
+        let mut b = octets::OctetsMut::with_slice(&mut out[..avail]);
+
+        let pkt_type = match self.select_egress_pkt_type() {
+            Ok(v) => v,
+
+            Err(e) => {
+                // Nothing left to send, so this is a good time to release
+                // the memory held by idle buffers.
+                self.shrink_to_fit();
+
+                return Err(e);
+            },
+        };
+
+        if pkt_type == packet::Type::Application {
+            if self.dcid_rotation_due(now) {
+                self.rotate_dcid(now);
+            }
+
+            if self.dcid_since.is_none() {
+                self.dcid_since = Some(now);
+            }
+        }
+
+        let space = match pkt_type {
+            packet::Type::Initial => &mut self.initial,
+
+            packet::Type::Handshake => &mut self.handshake,
+
+            packet::Type::Application => &mut self.application,
+
+            _ => unreachable!(),
+        };
+
+        // Process lost frames.
+        for lost in space.flight.lost.drain(..) {
+            match lost {
+                frame::Frame::Crypto { data } => {
+                    space.crypto_stream.send_push_front(data)?;
+                },
+
+                frame::Frame::Stream { stream_id, data } => {
+                    let stream = match self.streams.get_mut(&stream_id) {
+                        Some(v) => v,
+                        None => continue,
+                    };
+
+                    self.tx_data -= data.len();
+
+                    stream.send_push_front(data)?;
+                },
+
+                frame::Frame::ACK { .. } => {
+                    space.do_ack = true;
+                },
+
+                frame::Frame::NewToken { token } => {
+                    self.new_tokens.push(token);
+                },
+
+                frame::Frame::RetireConnectionId { seq_num } => {
+                    self.retired_dcid_seqs.push(seq_num);
+                },
+
+                frame::Frame::NewConnectionId {
+                    seq_num, conn_id, reset_token
+                } => {
+                    if self.active_scid_seqs.contains(&seq_num) {
+                        self.new_scids.push((seq_num, conn_id, reset_token));
+                    }
+                },
+
+                _ => (),
+            }
+        }
+
+        // Update global lost packets counter. This prevents us from losing
+        // information when the Initial state is dropped.
+        self.lost_count += space.flight.lost_count;
+        space.flight.lost_count = 0;
+
+        // Calculate available space in the packet based on congestion window.
+        let mut left = cmp::min(self.recovery.cwnd(), b.cap());
+
+        // Only 1-RTT packets are paced, and nothing else can be sent until
+        // the pacer allows it.
+        self.pacing_timer = if pkt_type == packet::Type::Application {
+            self.recovery.pacing_time(now)
+        } else {
+            None
+        };
+
+        // The rate limiter, if any, can hold back 1-RTT packets further.
+        self.rate_limit_timer = match self.rate_limiter {
+            Some(ref rl) if pkt_type == packet::Type::Application =>
+                rl.send_time(now),
+
+            _ => None,
+        };
+
+        if self.pacing_timer.is_some() || self.rate_limit_timer.is_some() {
+            left = 0;
+        }
+
+        let pn = space.next_pkt_num;
+        let pn_len = packet::pkt_num_len(pn, space.flight.largest_acked)?;
+
+        let hdr = Header {
+            ty: pkt_type,
+            version: self.version,
+            dcid: self.dcid.clone(),
+            scid: self.scid.clone(),
+            pkt_num: 0,
+            pkt_num_len: pn_len,
+            odcid: None,
+            token: self.token.clone(),
+            versions: None,
+            key_phase: false,
+        };
+
+        hdr.to_bytes(&mut b)?;
+
+        let overhead = b.off() + 4 + pn_len + space.overhead();
+
+        // Ack-eliciting frames can only be sent while congestion control and
+        // pacing allow it.
+        let mut can_send_data = !is_closing;
+
+        // Make sure we have enough space left for the header, the payload
+        // length, the packet number and the AEAD overhead. ACK and
+        // CONNECTION_CLOSE frames are not subject to congestion control, so
+        // they can still use the whole packet.
+        if left < overhead {
+            if !space.do_ack && !is_closing {
+                return Err(Error::Done);
+            }
+
+            left = avail;
+
+            can_send_data = false;
+        }
+
+        if left < overhead {
+            return Err(Error::Done);
+        }
+
+        left -= overhead;
+
+        let mut frames: Vec<frame::Frame> = Vec::new();
+
+        let mut is_crypto = false;
+        let mut sent_close = false;
+
+        let mut payload_len = 0;
+
+        // Create ACK frame. A pending delayed ACK is also sent along with any
+        // other Application packet.
+        if space.do_ack ||
+           (pkt_type == packet::Type::Application && self.ack_timer.is_some()) {
+            let ack_delay = now.duration_since(space.largest_rx_pkt_time);
+
+            let ack_delay = ack_delay.as_secs() * 1_000_000 +
+                            u64::from(ack_delay.subsec_micros());
+
+            let ack_delay = 
+                ack_delay / 2_u64.pow(self.local_transport_params
+                                          .ack_delay_exponent as u32);
+
+            let frame = frame::Frame::ACK {
+                ack_delay,
+                ranges: space.recv_pkt_need_ack.clone(),
+            };
+
+            if frame.wire_len() <= left {
+                space.do_ack = false;
+
+                if pkt_type == packet::Type::Application {
+                    self.ack_timer = None;
+                    self.ack_eliciting_since_ack = 0;
+                }
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create MAX_DATA frame, when the new limit is at least double the
+        // amount of data that can be received before blocking.
+        if pkt_type == packet::Type::Application && can_send_data
+            && (self.new_max_rx_data != self.max_rx_data &&
+                self.new_max_rx_data / 2 > self.max_rx_data - self.rx_data)
+        {
+            let window = autotune_window(self.rx_window, self.max_rx_window,
+                                         self.last_rx_window_update,
+                                         self.recovery.rtt(), now);
+
+            let max = self.new_max_rx_data
+                          .saturating_add(window - self.rx_window);
+
+            let frame = frame::Frame::MaxData { max: max as u64 };
+
+            // Only grow the window once the update is actually sent.
+            if frame.wire_len() <= left {
+                if window > self.rx_window {
+                    self.rx_window = window;
+
+                    trace!("{} rx window={}", self.trace_id, self.rx_window);
+                }
+
+                self.new_max_rx_data = max;
+                self.max_rx_data = max;
+                self.last_rx_window_update = Some(now);
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create MAX_STREAM_DATA frames as needed.
+        if pkt_type == packet::Type::Application && can_send_data {
+            let rtt = self.recovery.rtt();
+
+            for (id, stream) in self.streams.iter_mut()
+                                            .filter(|(_, s)| s.more_credit()) {
+                let window =
+                    stream.autotune_window(now, rtt, self.max_stream_window);
+
+                let frame = frame::Frame::MaxStreamData {
+                    stream_id: *id,
+                    max: stream.recv_max_data_next(window) as u64,
+                };
+
+                if frame.wire_len() > left {
+                    break;
+                }
+
+                stream.recv_update_max_data(window, now);
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create NEW_TOKEN frames as needed.
+        if pkt_type == packet::Type::Application && can_send_data {
+            while let Some(token) = self.new_tokens.pop() {
+                let frame = frame::Frame::NewToken { token };
+
+                if frame.wire_len() > left {
+                    if let frame::Frame::NewToken { token } = frame {
+                        self.new_tokens.push(token);
+                    }
+
+                    break;
+                }
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create NEW_CONNECTION_ID frames as needed.
+        if pkt_type == packet::Type::Application && can_send_data {
+            while let Some((seq_num, conn_id, reset_token)) =
+                self.new_scids.pop() {
+                let frame = frame::Frame::NewConnectionId {
+                    seq_num,
+                    conn_id,
+                    reset_token,
+                };
+
+                if frame.wire_len() > left {
+                    if let frame::Frame::NewConnectionId {
+                        seq_num, conn_id, reset_token
+                    } = frame {
+                        self.new_scids.push((seq_num, conn_id, reset_token));
+                    }
+
+                    break;
+                }
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create RETIRE_CONNECTION_ID frames as needed.
+        if pkt_type == packet::Type::Application && can_send_data {
+            while let Some(seq_num) = self.retired_dcid_seqs.pop() {
+                let frame = frame::Frame::RetireConnectionId { seq_num };
+
+                if frame.wire_len() > left {
+                    self.retired_dcid_seqs.push(seq_num);
+
+                    break;
+                }
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create extension frames as needed.
+        if pkt_type == packet::Type::Application && can_send_data {
+            while let Some(frame) = self.extension_frames.pop_front() {
+                if frame.wire_len() > left {
+                    self.extension_frames.push_front(frame);
+
+                    break;
+                }
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create PING and PADDING for TLP.
+        if self.recovery.probes > 0 && left >= 1 {
+            let frame = frame::Frame::Ping;
+
+            payload_len += frame.wire_len();
+            left -= frame.wire_len();
+
+            frames.push(frame);
+
+            self.recovery.probes -= 1;
+        }
+
+        // Create CONNECTION_CLOSE frame.
+        if let Some(err) = self.error {
+            let frame = frame::Frame::ConnectionClose {
+                error_code: err,
+                frame_type: self.error_frame_type,
+                reason: Vec::new(),
+            };
+
+            payload_len += frame.wire_len();
+            left -= frame.wire_len();
+
+            frames.push(frame);
+
+            sent_close = true;
+        }
+
+        // Create APPLICAtiON_CLOSE frame.
+        if let Some(err) = self.app_error {
+            let frame = frame::Frame::ApplicationClose {
+                error_code: err,
+                reason: self.app_reason.clone(),
+            };
+
+            payload_len += frame.wire_len();
+            left -= frame.wire_len();
+
+            frames.push(frame);
+
+            sent_close = true;
+        }
+
+        // Create PATH_RESPONSE frame.
+        if let Some(challenge) = self.challenge {
+            let frame = frame::Frame::PathResponse {
+                data: challenge,
+            };
+
+            payload_len += frame.wire_len();
+            left -= frame.wire_len();
+
+            frames.push(frame);
+
+            self.challenge = None;
+        }
+
+        // Create PATH_CHALLENGE frame.
+        if pkt_type == packet::Type::Application && self.path_challenge_pending {
+            if let Some(data) = self.path_challenge {
+                let frame = frame::Frame::PathChallenge { data };
+
+                if frame.wire_len() <= left {
+                    payload_len += frame.wire_len();
+                    left -= frame.wire_len();
+
+                    frames.push(frame);
+
+                    self.path_challenge_pending = false;
+                }
+            }
+        }
+
+        // Create CRYPTO frame.
+        if space.crypto_stream.writable() && can_send_data {
+            let crypto_len = left - frame::MAX_CRYPTO_OVERHEAD;
+            let crypto_buf = space.crypto_stream.send_pop(crypto_len)?;
+
+            let frame = frame::Frame::Crypto {
+                data: crypto_buf,
+            };
+
+            payload_len += frame.wire_len();
+            left -= frame.wire_len();
+
+            frames.push(frame);
+
+            is_crypto = true;
+        }
+
+        // Create STREAM frames for the writable streams, until the packet is
+        // full. Each stream's data is split at the packet boundary, and the
+        // rest is sent in later packets.
+        //
+        // Streams take turns: each one sends at most its burst of data per
+        // round, and a new round starts once all the writable streams used up
+        // their burst. The loop runs twice so that a round ending in the
+        // middle of a packet doesn't leave the rest of the packet empty.
+        for _ in 0..2 {
+            if pkt_type != packet::Type::Application || !can_send_data {
+                break;
+            }
+
+            if !self.streams.values()
+                            .any(|s| s.writable() && s.burst_left() > 0) {
+                for stream in self.streams.values_mut() {
+                    stream.reset_burst();
+                }
+            }
+
+            for (id, stream) in self.streams.iter_mut().filter(|(_, s)| {
+                s.writable() && s.burst_left() > 0
+            }) {
+                if self.max_tx_data <= self.tx_data {
+                    break;
+                }
+
+                // Make sure we can fit the frame header and at least one byte
+                // of data in the packet.
+                let overhead = frame::stream_frame_overhead(
+                    *id, stream.send_off(), left);
+
+                if left <= overhead {
+                    break;
+                }
+
+                let stream_len = cmp::min(left - overhead,
+                                          self.max_tx_data - self.tx_data);
+                let stream_len = cmp::min(stream_len, stream.burst_left());
+
+                let stream_buf = stream.send_pop(stream_len)?;
+
+                if stream_buf.is_empty() {
+                    continue;
+                }
+
+                self.tx_data += stream_buf.len();
+
+                // Data following a STREAM frame of the same stream already in
+                // the packet, e.g. from the previous round, extends that frame
+                // instead of paying for the header of a new one.
+                let prev = frames.iter().position(|f| match f {
+                    frame::Frame::Stream { stream_id, data } =>
+                        stream_id == id && data.max_off() == stream_buf.off(),
+
+                    _ => false,
+                });
+
+                if let Some(i) = prev {
+                    let before = frames[i].wire_len();
+
+                    if let frame::Frame::Stream { data, .. } = &mut frames[i] {
+                        data.append(stream_buf);
+                    }
+
+                    let after = frames[i].wire_len();
+
+                    payload_len += after - before;
+                    left -= after - before;
+
+                    continue;
+                }
+
+                let frame = frame::Frame::Stream {
+                    stream_id: *id,
+                    data: stream_buf,
+                };
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+        }
+
+        // Create PING frame for keep-alive, unless the packet is already
+        // ack-eliciting.
+        if pkt_type == packet::Type::Application && can_send_data &&
+           self.ping_pending && left >= 1 {
+            if !frames.iter().any(frame::Frame::ack_eliciting) {
+                let frame = frame::Frame::Ping;
+
+                payload_len += frame.wire_len();
+                left -= frame.wire_len();
+
+                frames.push(frame);
+            }
+
+            self.ping_pending = false;
+        }
+
+        if frames.is_empty() {
+            return Err(Error::Done);
+        }
+
+        let mut padding = 0;
+
+        // Pad the client's initial packet.
+        if !self.is_server && pkt_type == packet::Type::Initial {
+            let pkt_len = pn_len + payload_len + space.overhead();
+
+            padding = CLIENT_INITIAL_MIN_LEN - pkt_len;
+        }
+
+        // Pad the packet further as required by the padding policy, and
+        // account for the bytes added on top of the required padding.
+        let pkt_len = b.off() + pn_len + payload_len + space.overhead();
+
+        let extra = policy_padding(&self.padding_policy, &self.random,
+                                   pkt_type, pkt_len, left);
+
+        if extra > padding {
+            self.padding_sent += extra - padding;
+
+            padding = extra;
+        }
+
+        if padding > 0 {
+            let frame = frame::Frame::Padding {
+                len: cmp::min(padding, left),
+            };
+
+            payload_len += frame.wire_len();
+
+            frames.push(frame);
+        }
+
+        // Pad payload so that it's always at least 4 bytes.
+        if payload_len < PAYLOAD_MIN_LEN {
+            let frame = frame::Frame::Padding {
+                len: PAYLOAD_MIN_LEN - payload_len,
+            };
+
+            payload_len += frame.wire_len();
+
+            frames.push(frame);
+        }
+
+        payload_len += space.overhead();
+
+        // Only long header packets have an explicit length field.
+        if pkt_type != packet::Type::Application {
+            let len = pn_len + payload_len;
+            b.put_varint(len as u64)?;
+        }
+
+        packet::encode_pkt_num(pn, pn_len, &mut b)?;
+
+        let payload_offset = b.off();
+
+        trace!("{} tx pkt {:?} len={} pn={}", self.trace_id, hdr,
+               payload_len, pn);
+
+        // Encode frames into the output packet.
+        for frame in &frames {
+            trace!("{} tx frm {:?}", self.trace_id, frame);
+
+            frame.to_bytes(&mut b)?;
+        }
+
+        let aead = match space.crypto_seal {
+            Some(ref v) => v,
+            None        => return Err(Error::InvalidState),
+        };
+
+        let written = packet::encrypt_pkt(&mut b, pn, pn_len, payload_len,
+                                          payload_offset, aead)?;
+
+        if let Some(ref cb) = self.packet_trace_cb {
+            cb(&self.trace_id, &PacketTrace {
+                event: PacketEvent::Sent,
+                ty: pkt_type,
+                pkt_num: pn,
+                len: payload_len,
+                frames: frames.iter().map(|f| format!("{:?}", f)).collect(),
+            });
+        }
+
+        let ack_eliciting = frames.iter().any(frame::Frame::ack_eliciting);
+
+        let sent_pkt = recovery::Sent::new(pn, frames, written, ack_eliciting,
+                                           is_crypto, now);
+
+        self.recovery.on_packet_sent(sent_pkt, &mut space.flight, now,
+                                     &self.trace_id);
+
+        space.next_pkt_num += 1;
+
+        self.sent_count += 1;
+
+        if pkt_type == packet::Type::Application {
+            self.dcid_sent_count += 1;
+        }
+
+        if let Some(addr) = self.peer_addr {
+            self.path_mut(self.local_addr, addr).sent_bytes += written;
+        }
+
+        if let Some(ref m) = self.metrics {
+            m.packet_sent(written);
+            m.cwnd(self.recovery.cwnd());
+        }
+
+        if sent_close {
+            self.enter_closing(now);
+        }
+
+        if ack_eliciting {
+            self.keepalive_timer = self.keepalive.map(|k| now + k);
+
+            if let Some(ref rl) = self.rate_limiter {
+                if pkt_type == packet::Type::Application {
+                    rl.on_sent(written, now);
+                }
+            }
+
+            // The idle timer is also restarted by the first ack-eliciting
+            // packet sent after receiving one, but not by ACK-only packets.
+            if !self.ack_eliciting_sent &&
+               self.local_transport_params.idle_timeout > 0 {
+                self.idle_timer =
+                    Some(now + time::Duration::from_secs(
+                        self.local_transport_params.idle_timeout));
+            }
+
+            self.ack_eliciting_sent = true;
+        }
+
+        // On the client, drop initial state after sending an Handshake packet.
+        if !self.is_server && hdr.ty == packet::Type::Handshake {
+            self.drop_initial_state();
+        }
+
+        Ok(written)
+    }
+
+    /// Reports the packets declared lost since the last call to the packet
+    /// trace callback and metrics sink.
+    fn report_lost_packets(&mut self) {
+        let mut spaces = [
+            (packet::Type::Initial, &mut self.initial),
+            (packet::Type::Handshake, &mut self.handshake),
+            (packet::Type::Application, &mut self.application),
+        ];
+
+        for (ty, space) in spaces.iter_mut() {
+            for (pkt_num, size) in space.flight.lost_pkts.drain(..) {
+                if let Some(ref m) = self.metrics {
+                    m.packet_lost(size);
+                }
+
+                if let Some(ref cb) = self.packet_trace_cb {
+                    cb(&self.trace_id, &PacketTrace {
+                        event: PacketEvent::Lost,
+                        ty: *ty,
+                        pkt_num,
+                        len: 0,
+                        frames: Vec::new(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Reads contiguous data from a stream.
+    ///
+    /// The returned buffer will contain at most `max_len` bytes.
+    ///
+    /// On success the stream data is returned, or [`Done`] if there is no data
+    /// to read.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_recv(&mut self, stream_id: u64, max_len: usize) -> Result<RangeBuf> {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
+            None => return Err(Error::InvalidStreamState),
+        };
+
+        if !stream.readable() {
+            return Err(Error::Done);
+        }
+
+        let buffered = stream.recv_buffered();
+
+        let buf = stream.recv_pop(max_len)?;
+
+        self.rx_buffered -= buffered - stream.recv_buffered();
+
+        trace!("{} stream recv id={} off={} len={} fin={}", self.trace_id,
+               stream_id, buf.off(), buf.len(), buf.fin());
+
+        self.new_max_rx_data += buf.len();
+
+        Ok(buf)
+    }
+
+    /// Writes data to a stream.
+    ///
+    /// On success the number of bytes written is returned. This can be lower
+    /// than the length of `buf` when the peer's flow control credit for the
+    /// stream, or the connection's memory budget, doesn't allow buffering
+    /// all of it, in which case `fin` is ignored. The application should
+    /// retry writing the rest of the data later, e.g. after receiving a
+    /// [`StreamWritable`] event. [`Done`] is returned if no data at all
+    /// could be written.
+    ///
+    /// [`StreamWritable`]: enum.Event.html#variant.StreamWritable
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_send(&mut self, stream_id: u64, buf: &[u8], fin: bool)
+                                                            -> Result<usize> {
+        let cap = self.stream_send_capacity(stream_id)?;
+
+        if cap == 0 && !buf.is_empty() {
+            return Err(Error::Done);
+        }
+
+        let len = cmp::min(buf.len(), cap);
+        let fin = fin && len == buf.len();
+
+        let stream = self.streams.get_mut(&stream_id)
+                                 .ok_or(Error::InvalidStreamState)?;
+
+        stream.send_push(&buf[..len], fin)?;
+
+        trace!("{} stream send id={} len={} fin={}", self.trace_id, stream_id,
+               len, fin);
+
+        Ok(len)
+    }
+
+    /// Moves data received on a stream to the send buffer of another stream.
+    ///
+    /// Up to `max_len` bytes are read from `from_id` and written to `to_id`,
+    /// as if by calling [`stream_recv()`] followed by [`stream_send()`], but
+    /// without copying the data through the application. Buffered data is
+    /// moved to the destination stream as is, except when only part of a
+    /// received range is moved, in which case the rest of it is copied. The
+    /// FIN is forwarded along with the last byte. The two IDs can be the
+    /// same, to echo data back to the peer on a bidirectional stream.
+    ///
+    /// On success the number of bytes moved is returned. No more data than
+    /// `to_id` can accept is read, so the rest is left in `from_id` until
+    /// the stream becomes writable again. [`Done`] is returned if there is
+    /// no data to move, or no room for it.
+    ///
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`Done`]: enum.Error.html#variant.Done
+    pub fn stream_splice(&mut self, from_id: u64, to_id: u64, max_len: usize)
+                                                            -> Result<usize> {
+        match self.streams.get(&from_id) {
+            Some(v) if v.readable() => (),
+
+            Some(_) => return Err(Error::Done),
+
+            None => return Err(Error::InvalidStreamState),
+        };
+
+        // This also creates the destination stream if needed, so everything
+        // that can fail is checked before any data is read, and data read
+        // from `from_id` can't be lost.
+        let cap = self.stream_send_capacity(to_id)?;
+
+        if cap == 0 {
+            return Err(Error::Done);
+        }
+
+        let from = self.streams.get_mut(&from_id)
+                               .ok_or(Error::InvalidStreamState)?;
+
+        let buffered = from.recv_buffered();
+
+        let buf = from.recv_pop(cmp::min(max_len, cap))?;
+
+        self.rx_buffered -= buffered - from.recv_buffered();
+
+        self.new_max_rx_data += buf.len();
+
+        let len = buf.len();
+
+        trace!("{} stream splice from={} to={} len={} fin={}", self.trace_id,
+               from_id, to_id, len, buf.fin());
+
+        if let Some(to) = self.streams.get_mut(&to_id) {
+            to.send_push_buf(buf);
+        }
+
+        Ok(len)
+    }
+
+    /// Returns how much data can be written to the given stream, creating
+    /// the stream if needed.
+    fn stream_send_capacity(&mut self, stream_id: u64) -> Result<usize> {
+        // We can't write on the peer's unidirectional streams.
+        if !stream::is_bidi(stream_id) &&
+           !stream::is_local(stream_id, self.is_server) {
+            return Err(Error::InvalidStreamState);
+        }
+
+        // Refuse to buffer data, or to open new streams, once the memory
+        // budget is exhausted.
+        let mem_cap = match self.max_conn_mem {
+            Some(max) => max.saturating_sub(self.mem_usage()),
+
+            None => std::usize::MAX,
+        };
+
+        if mem_cap == 0 {
+            return Err(Error::MemoryLimit);
+        }
+
+        let stream = self.get_or_create_stream(stream_id, true)?;
+
+        // Only accept as much data as can be sent without exceeding the
+        // peer's flow control credit or the memory budget, so that the
+        // application is pushed back.
+        Ok(cmp::min(stream.send_capacity(), mem_cap))
+    }
+
+    /// Returns the given stream, creating it if needed.
+    ///
+    /// The `local` parameter tells whether the stream is being used by the
+    /// local endpoint, or by the peer. Only the endpoint that initiates a
+    /// stream can create it, and doing so implicitly opens all the streams
+    /// of the same type with a lower ID, so stream limits are enforced
+    /// against the stream ID rather than against the number of streams
+    /// created so far.
+    fn get_or_create_stream(&mut self, stream_id: u64, local: bool)
+                                        -> Result<&mut stream::Stream> {
+        let (max_rx_data, max_tx_data) = if local {
+            (self.local_transport_params.initial_max_stream_data_bidi_local,
+             self.peer_transport_params.initial_max_stream_data_bidi_remote)
+        } else {
+            (self.local_transport_params.initial_max_stream_data_bidi_remote,
+             self.peer_transport_params.initial_max_stream_data_bidi_local)
+        };
+
+        match self.streams.entry(stream_id) {
+            hash_map::Entry::Vacant(v) => {
+                if stream::is_local(stream_id, self.is_server) != local {
+                    return Err(Error::InvalidStreamState);
+                }
+
+                let (opened, max) =
+                    match (local, stream::is_bidi(stream_id)) {
+                        (true, true) => (&mut self.local_opened_streams_bidi,
+                                         self.peer_max_streams_bidi),
+
+                        (true, false) => (&mut self.local_opened_streams_uni,
+                                          self.peer_max_streams_uni),
+
+                        (false, true) => (&mut self.peer_opened_streams_bidi,
+                                          self.local_max_streams_bidi),
+
+                        (false, false) => (&mut self.peer_opened_streams_uni,
+                                           self.local_max_streams_uni),
+                    };
+
+                // Stream IDs of each type are allocated sequentially, so the
+                // ID tells how many streams of that type are open.
+                let n = stream_id / 4 + 1;
+
+                if n > max as u64 {
+                    return Err(Error::StreamLimit);
+                }
+
+                *opened = cmp::max(*opened, n as usize);
+
+                if let Some(ref m) = self.metrics {
+                    m.stream_opened();
+                }
+
+                let mut s = stream::Stream::new(max_rx_data as usize,
+                                                max_tx_data as usize);
+                s.set_max_burst(self.stream_burst);
+
+                Ok(v.insert(s))
+            },
+
+            hash_map::Entry::Occupied(v) => Ok(v.into_mut()),
+        }
+    }
+
+    /// Sets the maximum amount of data, in bytes, the given stream can send
+    /// before yielding to the other streams.
+    ///
+    /// This overrides the value set with [`set_stream_burst()`] for a single
+    /// stream. [`InvalidStreamState`] is returned if the stream doesn't
+    /// exist.
+    ///
+    /// [`set_stream_burst()`]: struct.Config.html#method.set_stream_burst
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_burst(&mut self, stream_id: u64, v: usize) -> Result<()> {
+        let stream = self.streams.get_mut(&stream_id)
+                                 .ok_or(Error::InvalidStreamState)?;
+
+        stream.set_max_burst(v);
+
+        Ok(())
+    }
+
+    /// Returns true if all the data of the given stream has been read by the
+    /// application, including the FIN.
+    ///
+    /// Unknown streams are reported as not finished.
+    pub fn stream_finished(&self, stream_id: u64) -> bool {
+        match self.streams.get(&stream_id) {
+            Some(v) => v.is_finished(),
+
+            None => false,
+        }
+    }
+
+    /// Returns true if the given stream has outstanding data to read.
+    ///
+    /// This is the same check used by [`readable()`], for a single stream.
+    ///
+    /// [`readable()`]: struct.Connection.html#method.readable
+    pub fn stream_readable(&self, stream_id: u64) -> bool {
+        match self.streams.get(&stream_id) {
+            Some(v) => v.readable(),
+
+            None => false,
+        }
+    }
+
+    /// Returns true if at least `len` bytes can be written to the given
+    /// stream without exceeding the peer's flow control credit, for the
+    /// stream and for the connection, or the connection's memory budget.
+    ///
+    /// If the stream doesn't exist, or the local endpoint can't write to it,
+    /// [`InvalidStreamState`] is returned.
+    ///
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_writable(&mut self, stream_id: u64, len: usize)
+                                                            -> Result<bool> {
+        if !self.streams.contains_key(&stream_id) {
+            return Err(Error::InvalidStreamState);
+        }
+
+        let cap = match self.stream_send_capacity(stream_id) {
+            Ok(v) => v,
+
+            Err(Error::MemoryLimit) => 0,
+
+            Err(e) => return Err(e),
+        };
+
+        Ok(cmp::min(cap, self.tx_credit()) >= len)
+    }
+
+    /// Returns the number of bidirectional streams that can still be opened
+    /// by the local endpoint, before reaching the peer's limit.
+    pub fn peer_streams_left_bidi(&self) -> usize {
+        self.peer_max_streams_bidi
+            .saturating_sub(self.local_opened_streams_bidi)
+    }
+
+    /// Returns the number of unidirectional streams that can still be opened
+    /// by the local endpoint, before reaching the peer's limit.
+    pub fn peer_streams_left_uni(&self) -> usize {
+        self.peer_max_streams_uni
+            .saturating_sub(self.local_opened_streams_uni)
+    }
+
+    /// Returns the number of bidirectional streams the peer can still open,
+    /// before reaching the local limit.
+    pub fn local_streams_left_bidi(&self) -> usize {
+        self.local_max_streams_bidi
+            .saturating_sub(self.peer_opened_streams_bidi)
+    }
+
+    /// Returns the number of unidirectional streams the peer can still open,
+    /// before reaching the local limit.
+    pub fn local_streams_left_uni(&self) -> usize {
+        self.local_max_streams_uni
+            .saturating_sub(self.peer_opened_streams_uni)
+    }
+
+    /// Returns the number of streams opened by the peer that haven't been
+    /// returned by [`accepted()`] yet.
+    ///
+    /// [`accepted()`]: struct.Connection.html#method.accepted
+    pub fn pending_accepted_streams(&self) -> usize {
+        let is_server = self.is_server;
+
+        self.streams.iter()
+            .filter(|(id, s)| !stream::is_local(**id, is_server) &&
+                              !s.is_accepted())
+            .count()
+    }
+
+    /// Returns how much more stream data can be sent before exhausting the
+    /// peer's connection-level flow control credit.
+    pub fn tx_credit(&self) -> usize {
+        self.max_tx_data.saturating_sub(self.tx_data)
+    }
+
+    /// Returns how much more stream data the peer can send before exhausting
+    /// the local connection-level flow control credit.
+    pub fn rx_credit(&self) -> usize {
+        self.max_rx_data.saturating_sub(self.rx_data)
+    }
+
+    /// Schedules a `NEW_TOKEN` frame carrying the given address validation
+    /// token to be sent to the client.
+    ///
+    /// This can only be called on the server once the handshake has been
+    /// completed, otherwise [`InvalidState`] is returned. The token is opaque
+    /// to quiche, and it's up to the application to generate and later
+    /// validate it.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn send_new_token(&mut self, token: &[u8]) -> Result<()> {
+        if !self.is_server || !self.handshake_completed || token.is_empty() {
+            return Err(Error::InvalidState);
+        }
+
+        self.new_tokens.push(token.to_vec());
+
+        Ok(())
+    }
+
+    /// Schedules a frame of the extension type `ty` carrying `payload` to be
+    /// sent to the peer.
+    ///
+    /// The frame type must have been registered with
+    /// [`register_frame_extension()`], and the peer must have advertised the
+    /// extension, otherwise [`InvalidState`] is returned.
+    ///
+    /// [`register_frame_extension()`]:
+    /// struct.Config.html#method.register_frame_extension
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn send_extension_frame(&mut self, ty: u64, payload: &[u8])
+                                                            -> Result<()> {
+        if extension_handler(&self.extensions, &self.peer_transport_params,
+                             ty).is_none() {
+            return Err(Error::InvalidState);
+        }
+
+        self.extension_frames.push_back(frame::Frame::Extension {
+            ty,
+            data: payload.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    /// Returns the transport parameters advertised by the peer.
+    ///
+    /// `None` is returned until the handshake completes, as the parameters
+    /// are only validated at that point.
+    pub fn peer_transport_params(&self) -> Option<PeerTransportParams> {
+        if !self.handshake_completed {
+            return None;
+        }
+
+        let tp = &self.peer_transport_params;
+
+        Some(PeerTransportParams {
+            idle_timeout: tp.idle_timeout,
+            max_packet_size: tp.max_packet_size,
+            initial_max_data: tp.initial_max_data,
+            initial_max_stream_data_bidi_local:
+                tp.initial_max_stream_data_bidi_local,
+            initial_max_stream_data_bidi_remote:
+                tp.initial_max_stream_data_bidi_remote,
+            initial_max_stream_data_uni: tp.initial_max_stream_data_uni,
+            initial_max_streams_bidi: tp.initial_max_streams_bidi,
+            initial_max_streams_uni: tp.initial_max_streams_uni,
+            ack_delay_exponent: tp.ack_delay_exponent,
+            max_ack_delay: tp.max_ack_delay,
+            disable_migration: tp.disable_migration,
+            active_conn_id_limit: tp.active_conn_id_limit,
+        })
+    }
+
+    /// Returns the value of the peer's transport parameter `id`, if it was
+    /// sent and isn't one of the parameters defined by QUIC itself.
+    pub fn peer_extension_param(&self, id: u16) -> Option<&[u8]> {
+        self.peer_transport_params.extensions.iter()
+            .find(|(param_id, _)| *param_id == id)
+            .map(|(_, val)| val.as_ref())
+    }
+
+    /// Issues a new source connection ID to the peer.
+    ///
+    /// A `NEW_CONNECTION_ID` frame carrying `cid` and the associated
+    /// stateless reset token is scheduled to be sent, and the sequence number
+    /// assigned to the connection ID is returned.
+    ///
+    /// This can only be called once the handshake has been completed, and as
+    /// long as the number of active connection IDs doesn't exceed the peer's
+    /// `active_connection_id_limit` transport parameter, otherwise
+    /// [`InvalidState`] is returned.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn new_source_cid(&mut self, cid: &[u8], reset_token: &[u8; 16])
+                                                            -> Result<u64> {
+        if !self.handshake_completed ||
+           cid.len() < MIN_CONN_ID_LEN || cid.len() > MAX_CONN_ID_LEN {
+            return Err(Error::InvalidState);
+        }
+
+        let limit = self.peer_transport_params.active_conn_id_limit;
+
+        if self.active_scid_seqs.len() as u64 >= cmp::max(limit, 1) {
+            return Err(Error::InvalidState);
+        }
+
+        let seq_num = self.next_scid_seq;
+
+        self.next_scid_seq += 1;
+
+        self.active_scid_seqs.insert(seq_num);
+
+        self.new_scids.push((seq_num, cid.to_vec(), *reset_token));
+
+        Ok(seq_num)
+    }
+
+    /// Notifies the connection that the peer's address has changed.
+    ///
+    /// The application should call this when it receives packets for this
+    /// connection from a different address than before. If the local
+    /// endpoint disabled active migration using the `disable_migration`
+    /// transport parameter, [`InvalidState`] is returned once the handshake
+    /// has been completed, and the application should discard packets
+    /// received from the new address.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn on_peer_migrated(&mut self) -> Result<()> {
+        if self.handshake_completed &&
+           self.local_transport_params.disable_migration {
+            trace!("{} rejected peer migration", self.trace_id);
+
+            return Err(Error::InvalidState);
+        }
+
+        Ok(())
+    }
+
+    /// Returns statistics about the network paths used by the connection.
+    ///
+    /// Paths are only tracked when the peer's address is passed to
+    /// [`recv_with_info()`], and at most one path is active at a time.
+    ///
+    /// [`recv_with_info()`]: struct.Connection.html#method.recv_with_info
+    pub fn paths(&self) -> Vec<PathStats> {
+        self.paths.iter().map(|p| {
+            let mut p = p.clone();
+
+            if p.active {
+                p.rtt = self.recovery.rtt();
+                p.cwnd = self.recovery.path_params().cwnd;
+            }
+
+            p
+        }).collect()
+    }
+
+    /// Returns the peer's current address.
+    ///
+    /// This is the address of the last datagram passed to
+    /// [`recv_with_info()`], unless it's still being validated and the
+    /// validation failed, in which case it's the last validated address.
+    /// Packets should be sent to this address.
+    ///
+    /// [`recv_with_info()`]: struct.Connection.html#method.recv_with_info
+    pub fn peer_addr(&self) -> Option<net::SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns the local address the peer is currently sending to.
+    ///
+    /// This is the local address of the last datagram passed to
+    /// [`recv_with_info()`] that had one. Packets should be sent from this
+    /// address.
+    ///
+    /// [`recv_with_info()`]: struct.Connection.html#method.recv_with_info
+    pub fn local_addr(&self) -> Option<net::SocketAddr> {
+        self.local_addr
+    }
+
+    /// Sets the rate limiter consulted before sending data.
+    ///
+    /// Once the limiter is exhausted, [`send()`] only sends packets carrying
+    /// ACK or CONNECTION_CLOSE frames, and returns [`Done`] otherwise. The
+    /// time at which sending can resume is reported by [`timeout()`]. The
+    /// same limiter can be shared by multiple connections.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    pub fn set_rate_limiter(&mut self, limiter: sync::Arc<dyn RateLimiter>) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    /// Configures the address validation token to present to the server.
+    ///
+    /// The token is sent in the client's Initial packets, and should be one
+    /// previously received from the same server in a `NEW_TOKEN` frame. This
+    /// can only be called on the client before the first packet is sent,
+    /// otherwise [`InvalidState`] is returned.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn set_token(&mut self, token: &[u8]) -> Result<()> {
+        if self.is_server || self.initial.next_pkt_num > 0 {
+            return Err(Error::InvalidState);
+        }
+
+        self.token = Some(token.to_vec());
+
+        Ok(())
+    }
+
+    /// Returns the latest address validation token received from the server.
+    ///
+    /// If no `NEW_TOKEN` frame has been received, `None` is returned.
+    pub fn new_token(&self) -> Option<&[u8]> {
+        self.peer_token.as_ref().map(|v| v.as_slice())
+    }
+
+    /// Creates an iterator over streams that have outstanding data to read.
+    pub fn readable(&mut self) -> Readable {
+        stream::Readable::new(&self.streams)
+    }
+
+    /// Creates an iterator over the streams opened by the peer since the
+    /// last call to this method.
+    ///
+    /// Each stream is returned exactly once, in order of stream ID, so this
+    /// can be used to set up per-stream application state.
+    pub fn accepted(&mut self) -> Accepted {
+        let is_server = self.is_server;
+
+        // Streams are never removed, so the streams left to accept are
+        // found in the stream map rather than kept in a separate queue.
+        let mut streams: Vec<u64> = self.streams.iter_mut()
+            .filter(|(id, _)| !stream::is_local(**id, is_server))
+            .filter_map(|(id, s)| if s.accept() { Some(*id) } else { None })
+            .collect();
+
+        streams.sort();
+
+        stream::Accepted::new(streams)
+    }
+
+    /// Returns the next transport event, if any.
+    ///
+    /// Events are returned in the order they happened. Once the connection is
+    /// closed, [`ConnectionClosed`] is returned after all other pending
+    /// events, and `None` from then on.
+    ///
+    /// At most 1024 events are kept pending, and further ones are dropped
+    /// until the application catches up. Stream events can always be
+    /// recovered using the [`readable()`] iterator.
+    ///
+    /// [`ConnectionClosed`]: enum.Event.html#variant.ConnectionClosed
+    /// [`readable()`]: struct.Connection.html#method.readable
+    pub fn poll(&mut self) -> Option<Event> {
+        if let Some(ev) = self.events.pop() {
+            return Some(ev);
+        }
+
+        if self.closed && !self.closed_event {
+            self.closed_event = true;
+
+            return Some(Event::ConnectionClosed);
+        }
+
+        None
+    }
+
+    /// Returns the amount of time until the next timeout event.
+    ///
+    /// Once the given duration has elapsed, the [`on_timeout()`] method should
+    /// be called. A timeout of `None` means that the timer should be disarmed.
+    ///
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        let timeout = self.timeout_instant()?;
+
+        let now = time::Instant::now();
+
+        if timeout <= now {
+            return Some(std::time::Duration::new(0, 0));
+        }
+
+        Some(timeout.duration_since(now))
+    }
+
+    /// Returns the time at which the next timeout event will occur.
+    ///
+    /// Once that time is reached, the [`on_timeout_at()`] method should be
+    /// called. `None` means that the timer should be disarmed.
+    ///
+    /// [`on_timeout_at()`]: struct.Connection.html#method.on_timeout_at
+    pub fn timeout_instant(&self) -> Option<time::Instant> {
+        // Use the lowest timer value among the ones that are set.
+        self.timers().iter().filter_map(|&t| t).min()
+    }
+
+    /// Returns the time at which the next batch of timeout events will occur.
+    ///
+    /// This is the same as [`timeout_instant()`], except that timers
+    /// expiring within the [timer granularity] of the earliest one are
+    /// coalesced: the returned time is that of the last timer in the batch,
+    /// so that a single call to [`on_timeout_at()`] processes all of them.
+    ///
+    /// [`timeout_instant()`]: struct.Connection.html#method.timeout_instant
+    /// [timer granularity]: struct.Config.html#method.set_timer_granularity
+    /// [`on_timeout_at()`]: struct.Connection.html#method.on_timeout_at
+    pub fn next_event_time(&self) -> Option<time::Instant> {
+        let timers = self.timers();
+
+        let first = timers.iter().filter_map(|&t| t).min()?;
+
+        let window = first + self.timer_granularity;
+
+        timers.iter().filter_map(|&t| t).filter(|&t| t <= window).max()
+    }
+
+    /// Returns all of the connection's timers, set or not.
+    fn timers(&self) -> [Option<time::Instant>; 9] {
+        if self.closed {
+            return [None; 9];
+        }
+
+        if self.draining || self.closing {
+            return [self.draining_timer, None, None, None,
+                    None, None, None, None, None];
+        }
+
+        let keepalive_timer = if self.handshake_completed {
+            self.keepalive_timer
+        } else {
+            None
+        };
+
+        [self.recovery.loss_detection_timer(), self.idle_timer,
+         self.handshake_timer, self.ack_timer, keepalive_timer,
+         self.pacing_timer, self.rate_limit_timer,
+         self.path_validation_timer, self.drain_timer]
+    }
+
+    /// Processes a timeout event.
+    ///
+    /// If no timeout has occurred it does nothing.
+    pub fn on_timeout(&mut self) {
+        self.on_timeout_at(time::Instant::now())
+    }
+
+    /// Processes a timeout event at the given time.
+    ///
+    /// This is the same as [`on_timeout()`], but uses `now` as the current
+    /// time instead of reading the system clock.
+    ///
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn on_timeout_at(&mut self, now: time::Instant) {
+        if self.draining || self.closing {
+            if self.draining_timer.is_some() &&
+               self.draining_timer.unwrap() <= now {
+                trace!("{} draining timeout expired", self.trace_id);
+
+                self.closed = true;
+            }
+
+            return;
+        }
+
+        if self.idle_timer.is_some() && self.idle_timer.unwrap() <= now {
+            trace!("{} idle timeout expired", self.trace_id);
+
+            self.closed = true;
+            return;
+        }
+
+        if self.handshake_timer.is_some() &&
+           self.handshake_timer.unwrap() <= now {
+            trace!("{} handshake timeout expired", self.trace_id);
+
+            self.handshake_timed_out = true;
+            self.closed = true;
+            return;
+        }
+
+        if self.drain_timer.is_some() && self.drain_timer.unwrap() <= now {
+            trace!("{} drain timeout expired", self.trace_id);
+
+            self.drain_timer = None;
+            self.close(false, 0x0, b"").ok();
+        }
+
+        if self.handshake_completed && self.keepalive_timer.is_some() &&
+           self.keepalive_timer.unwrap() <= now {
+            trace!("{} keep-alive timeout expired", self.trace_id);
+
+            self.keepalive_timer = None;
+            self.ping_pending = true;
+        }
+
+        if self.pacing_timer.is_some() && self.pacing_timer.unwrap() <= now {
+            self.pacing_timer = None;
+        }
+
+        if self.rate_limit_timer.is_some() &&
+           self.rate_limit_timer.unwrap() <= now {
+            self.rate_limit_timer = None;
+        }
+
+        if self.path_validation_timer.is_some() &&
+           self.path_validation_timer.unwrap() <= now {
+            trace!("{} path validation failed addr={:?}",
+                   self.trace_id, self.peer_addr);
+
+            if let Some(addr) = self.peer_addr {
+                self.path_mut(self.local_addr, addr).state = PathState::Failed;
+            }
+
+            // Fall back to the last address known to work.
+            if let Some(addr) = self.validated_addr {
+                self.set_active_path(addr);
+            }
+
+            self.path_challenge = None;
+            self.path_challenge_pending = false;
+            self.path_validation_timer = None;
+        }
+
+        if self.ack_timer.is_some() && self.ack_timer.unwrap() <= now {
+            trace!("{} ack timeout expired", self.trace_id);
+
+            self.ack_timer = None;
+            self.application.do_ack = true;
+        }
+
+        if self.recovery.loss_detection_timer().is_some() &&
+           self.recovery.loss_detection_timer().unwrap() <= now {
+            trace!("{} loss detection timeout expired", self.trace_id);
+
+            self.recovery.on_loss_detection_timer(&mut self.initial.flight,
+                                                  &mut self.handshake.flight,
+                                                  &mut self.application.flight,
+                                                  now, &self.trace_id);
+            return;
+        }
+    }
+
+    /// Starts closing the connection gracefully, e.g. before a restart.
+    ///
+    /// From then on, streams opened by the peer are refused: their frames are
+    /// ignored, though their data still counts against the connection's flow
+    /// control. The existing streams are served as usual, and the connection
+    /// is closed with the `NO_ERROR` error code once all of them are
+    /// complete, or when `deadline` is reached, whichever comes first. A
+    /// stream is complete once the application read all of the peer's data,
+    /// and all of its own data, up to a FIN, was acknowledged.
+    ///
+    /// The transport has no way to tell the peer to stop opening streams, so
+    /// the application protocol should signal it on its own.
+    pub fn drain(&mut self, deadline: time::Instant) {
+        self.drain_timer = Some(deadline);
+    }
+
+    /// Returns true if the connection is draining, and all of its streams
+    /// are complete.
+    fn is_drained(&self) -> bool {
+        if self.application.flight.ack_eliciting_in_flight() {
+            return false;
+        }
+
+        let is_server = self.is_server;
+
+        self.streams.iter().all(|(id, s)| {
+            let local = stream::is_local(*id, is_server);
+            let bidi = stream::is_bidi(*id);
+
+            // Unidirectional streams are only used in one direction.
+            let recv_done = (local && !bidi) || s.is_finished();
+            let send_done = (!local && !bidi) || s.is_flushed();
+
+            recv_done && send_done
+        })
+    }
+
+    /// Returns true if the given stream was opened by the peer after the
+    /// connection started draining.
+    fn is_refused_stream(&self, stream_id: u64) -> bool {
+        self.drain_timer.is_some() &&
+            !stream::is_local(stream_id, self.is_server) &&
+            !self.streams.contains_key(&stream_id)
+    }
+
+    /// Returns true if buffering `len` bytes of data received at `off` on the
+    /// given stream would exceed the stream reorder window.
+    fn exceeds_reorder_window(&self, stream_id: u64, off: usize,
+                              len: usize) -> bool {
+        let max_reorder = self.stream_reorder_window;
+
+        if max_reorder == std::usize::MAX {
+            return false;
+        }
+
+        match self.streams.get(&stream_id) {
+            Some(s) => s.exceeds_reorder_window(off, len, max_reorder),
+
+            // Streams that weren't opened yet have no data buffered.
+            None => off > 0 && off + len > max_reorder,
+        }
+    }
+
+    /// Closes the connection with the given error and reason.
+    ///
+    /// The `app` parameter specifies whether an application close should be
+    /// sent to the peer. Otherwise a normal connection close is sent.
+    ///
+    /// Returns [`Done`] if the connection had already been closed.
+    ///
+    /// Note that the connection will not be closed immediately. An application
+    /// should continue calling [`recv()`], [`send()`] and [`timeout()`] as
+    /// normal, until the [`is_closed()`] method returns `true`.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    pub fn close(&mut self, app: bool, err: u16, reason: &[u8]) -> Result<()> {
+        if self.draining {
+            return Err(Error::Done);
+        }
+
+        if self.error.is_some() || self.app_error.is_some() {
+            return Err(Error::Done);
+        }
+
+        if app {
+            self.app_error = Some(err);
+            self.app_reason.extend_from_slice(reason);
+        } else {
+            self.error = Some(err);
+        }
+
+        Ok(())
+    }
+
+    /// Returns a string uniquely representing the connection.
+    ///
+    /// This can be used for logging purposes to differentiate between multiple
+    /// connections.
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Sets the string used to represent the connection in logs.
+    ///
+    /// By default the trace ID is the hex-encoded source connection ID. An
+    /// application can replace it with its own identifier (e.g. a request
+    /// ID), so that the library's logs can be correlated with its own.
+    pub fn set_trace_id(&mut self, trace_id: &str) {
+        self.trace_id = trace_id.to_string();
+    }
+
+    /// Sets the writer the connection's secrets are logged to, instead of
+    /// the `SSLKEYLOGFILE` file.
+    ///
+    /// This only has an effect if key logging was enabled with
+    /// [`log_keys()`].
+    ///
+    /// [`log_keys()`]: struct.Config.html#method.log_keys
+    pub fn set_keylog(&mut self, writer: Box<dyn io::Write + Send + Sync>) {
+        self.keylog = Some(writer);
+    }
+
+    /// Returns the source connection ID used by the local endpoint.
+    pub fn source_id(&self) -> &[u8] {
+        &self.scid
+    }
+
+    /// Returns the destination connection ID used by the local endpoint.
+    ///
+    /// On the client this is initially a random value, which is replaced by
+    /// the server's chosen connection ID once a packet from it is received.
+    pub fn destination_id(&self) -> &[u8] {
+        &self.dcid
+    }
+
+    /// Returns the negotiated ALPN protocol.
+    ///
+    /// If no protocol has been negotiated, the returned value is empty.
+    pub fn application_proto(&self) -> &[u8] {
+        self.tls_state.get_alpn_protocol()
+    }
+
+    /// Returns the negotiated TLS cipher suite.
+    ///
+    /// If the handshake hasn't negotiated a cipher suite yet, `None` is
+    /// returned.
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.tls_state.cipher().ok().and_then(CipherSuite::from_algorithm)
+    }
+
+    /// Returns the certificate chain presented by the peer, if any.
+    ///
+    /// Each certificate is DER-encoded, and the peer's own certificate comes
+    /// first. This is only available once the peer's certificate has been
+    /// received during the handshake.
+    pub fn peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
+        self.tls_state.get_peer_cert_chain()
+    }
+
+    /// Returns the OCSP response stapled by the server, if any.
+    ///
+    /// This is only available on clients that called
+    /// [`enable_ocsp_stapling()`].
+    ///
+    /// [`enable_ocsp_stapling()`]: struct.Config.html#method.enable_ocsp_stapling
+    pub fn ocsp_response(&self) -> Option<&[u8]> {
+        self.tls_state.get_ocsp_response()
+    }
+
+    /// Returns the serialized list of signed certificate timestamps sent by
+    /// the server, if any.
+    ///
+    /// This is only available on clients that called
+    /// [`enable_signed_cert_timestamps()`].
+    ///
+    /// [`enable_signed_cert_timestamps()`]: struct.Config.html#method.enable_signed_cert_timestamps
+    pub fn signed_cert_timestamps(&self) -> Option<&[u8]> {
+        self.tls_state.get_signed_cert_timestamp_list()
+    }
+
+    /// Returns whether the given algorithm is allowed by the configured list
+    /// of cipher suites.
+    fn is_cipher_allowed(&self, alg: crypto::Algorithm) -> bool {
+        let suites = match self.cipher_suites {
+            Some(ref v) => v,
+
+            None => return true,
+        };
+
+        match CipherSuite::from_algorithm(alg) {
+            Some(suite) => suites.contains(&suite),
+
+            None => false,
+        }
+    }
+
+    /// Returns true if the connection handshake is complete.
+    pub fn is_established(&self) -> bool {
+        self.handshake_completed
+    }
+
+    /// Returns true if the connection is resumed.
+    pub fn is_resumed(&self) -> bool {
+        self.tls_state.is_resumed()
+    }
+
+    /// Returns true if the connection is draining.
+    ///
+    /// A connection is draining after a CONNECTION_CLOSE or APPLICATION_CLOSE
+    /// frame has been sent or received. In this state no new data is sent or
+    /// processed, and the connection state is kept around until the draining
+    /// timer expires, after which [`is_closed()`] returns `true`.
+    ///
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    pub fn is_draining(&self) -> bool {
+        self.draining || self.closing
+    }
+
+    /// Returns true if the connection is closed.
+    ///
+    /// If this returns true, the connection object can be dropped.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns the TLS alert sent to the peer, if the connection was closed
+    /// because of a TLS failure on the local side.
+    ///
+    /// The alert is carried by the CONNECTION_CLOSE frame as a `CRYPTO_ERROR`
+    /// (i.e. `0x100` plus the alert code).
+    pub fn local_tls_alert(&self) -> Option<u8> {
+        crypto_alert(self.error?)
+    }
+
+    /// Returns the TLS alert received from the peer, if the peer closed the
+    /// connection because of a TLS failure on its side.
+    pub fn peer_tls_alert(&self) -> Option<u8> {
+        crypto_alert(self.peer_error?)
+    }
+
+    /// Collects and returns statistics about the connection.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            sent: self.sent_count,
+            lost: self.lost_count,
+            spurious_lost: self.recovery.spurious_lost_count(),
+            rtt: self.recovery.rtt(),
+            min_rtt: self.recovery.min_rtt(),
+            rttvar: self.recovery.rttvar(),
+            persistent_congestion:
+                self.recovery.persistent_congestion_count(),
+            ect0_recv: self.ecn_recv_count[Ecn::Ect0 as usize],
+            ect1_recv: self.ecn_recv_count[Ecn::Ect1 as usize],
+            ce_recv: self.ecn_recv_count[Ecn::Ce as usize],
+            mem_usage: self.mem_usage(),
+            dup_recv: self.dup_recv_count,
+            rebinds: self.rebind_count,
+            padding: self.padding_sent,
+        }
+    }
+
+    /// Returns the current characteristics of the connection's path.
+    ///
+    /// These are typically saved when the connection is closed, and passed
+    /// to [`set_resume_params()`] on a later connection to the same peer.
+    ///
+    /// [`set_resume_params()`]: struct.Connection.html#method.set_resume_params
+    pub fn path_params(&self) -> PathParams {
+        self.recovery.path_params()
+    }
+
+    /// Resumes the congestion window from a previous connection's path.
+    ///
+    /// This must be called before the first RTT sample is taken, i.e. before
+    /// the handshake starts, and is ignored otherwise. The congestion window
+    /// jumps to half the saved window once the first RTT sample confirms
+    /// that the path is similar to the saved one, and is left unchanged if
+    /// the RTT differs too much.
+    ///
+    /// It's up to the application to decide whether saved parameters are
+    /// still relevant, e.g. by expiring them after some time.
+    pub fn set_resume_params(&mut self, params: PathParams) {
+        self.recovery.set_resume_params(params);
+    }
+
+    /// Returns an estimate of the memory used by the connection's stream
+    /// buffers and retransmission state.
+    fn mem_usage(&self) -> usize {
+        let spaces = self.initial.mem_usage() +
+                     self.handshake.mem_usage() +
+                     self.application.mem_usage();
+
+        let streams = self.streams.capacity() *
+                      mem::size_of::<(u64, stream::Stream)>() +
+                      self.streams.values()
+                                  .map(|s| s.mem_usage())
+                                  .sum::<usize>();
+
+        mem::size_of::<Connection>() + spaces + streams
+    }
+
+    /// Releases the memory held by empty stream and retransmission buffers.
+    fn shrink_to_fit(&mut self) {
+        self.initial.shrink_to_fit();
+        self.handshake.shrink_to_fit();
+        self.application.shrink_to_fit();
+
+        for stream in self.streams.values_mut() {
+            stream.shrink_to_fit();
+        }
+    }
+
+    /// Tracks the addresses of the path packets are received on, and
+    /// validates the peer's address when it changes without the peer
+    /// migrating, e.g. because of NAT rebinding.
+    fn on_peer_addr(&mut self, to: Option<net::SocketAddr>,
+                    from: net::SocketAddr, largest: bool, now: time::Instant) {
+        if self.peer_addr.is_none() {
+            self.local_addr = to;
+            self.validated_addr = Some(from);
+
+            self.set_active_path(from);
+            self.path_mut(to, from).state = PathState::Validated;
+            return;
+        }
+
+        if !largest || !self.handshake_completed {
+            return;
+        }
+
+        // The peer sending to another one of our addresses doesn't make its
+        // own address any less reachable, so the new path inherits the state
+        // of the current one, and only replies move to the new address.
+        if to.is_some() && to != self.local_addr {
+            trace!("{} local address changed from={:?} to={:?}",
+                   self.trace_id, self.local_addr, to);
+
+            let state = if self.peer_addr == Some(from) {
+                Some(self.path_mut(self.local_addr, from).state)
+            } else {
+                None
+            };
+
+            self.local_addr = to;
+
+            if let Some(state) = state {
+                self.set_active_path(from);
+                self.path_mut(to, from).state = state;
+                return;
+            }
+        }
+
+        if self.peer_addr == Some(from) {
+            return;
+        }
+
+        trace!("{} peer address changed from={:?} to={}",
+               self.trace_id, self.peer_addr, from);
+
+        self.set_active_path(from);
+        self.rebind_count += 1;
+
+        if self.cid_rotation_on_migration {
+            self.dcid_rotation_pending = true;
+        }
+
+        self.events.push(Event::PeerAddressChanged(from));
+
+        // Going back to an address that was already validated doesn't need
+        // another validation.
+        if self.validated_addr == Some(from) {
+            self.path_mut(self.local_addr, from).state = PathState::Validated;
+
+            self.path_challenge = None;
+            self.path_challenge_pending = false;
+            self.path_validation_timer = None;
+            return;
+        }
+
+        let rate_limited = match self.last_path_validation {
+            Some(last) => last + PATH_VALIDATION_INTERVAL > now,
+
+            None => false,
+        };
+
+        // Without validation the address is only used until the timer
+        // expires.
+        if rate_limited {
+            trace!("{} path validation rate limited", self.trace_id);
+
+            self.path_mut(self.local_addr, from).state = PathState::Unvalidated;
+
+            self.path_challenge = None;
+            self.path_challenge_pending = false;
+            self.path_validation_timer = Some(now + self.recovery.pto() * 3);
+            return;
+        }
+
+        let mut data = [0; 8];
+        fill_random(&self.random, &mut data);
+
+        self.path_mut(self.local_addr, from).state = PathState::Validating;
+
+        self.path_challenge = Some(data);
+        self.path_challenge_pending = true;
+        self.path_validation_timer = Some(now + self.recovery.pto() * 3);
+        self.last_path_validation = Some(now);
+    }
+
+    /// Returns whether the destination connection ID should be rotated
+    /// according to the configured policy.
+    fn dcid_rotation_due(&self, now: time::Instant) -> bool {
+        if !self.handshake_completed || self.spare_dcids.is_empty() {
+            return false;
+        }
+
+        if self.dcid_rotation_pending {
+            return true;
+        }
+
+        if self.cid_rotation_packets > 0 &&
+           self.dcid_sent_count >= self.cid_rotation_packets {
+            return true;
+        }
+
+        match (self.cid_rotation_interval, self.dcid_since) {
+            (Some(interval), Some(since)) => now >= since + interval,
+
+            _ => false,
+        }
+    }
+
+    /// Switches to the next connection ID issued by the peer, and retires
+    /// the current one.
+    fn rotate_dcid(&mut self, now: time::Instant) {
+        let (seq_num, cid) = match self.spare_dcids.pop_front() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        trace!("{} rotated dcid seq={} to seq={}",
+               self.trace_id, self.dcid_seq, seq_num);
+
+        self.retired_dcid_seqs.push(self.dcid_seq);
+
+        self.dcid = cid;
+        self.dcid_seq = seq_num;
+
+        self.dcid_sent_count = 0;
+        self.dcid_since = Some(now);
+        self.dcid_rotation_pending = false;
+
+        self.events.push(Event::ConnectionIdRotated);
+    }
+
+    /// Makes the path to `addr` the one packets are sent on.
+    fn set_active_path(&mut self, addr: net::SocketAddr) {
+        let params = self.recovery.path_params();
+        let rtt = self.recovery.rtt();
+
+        // Keep the last estimates of the path being left.
+        for path in self.paths.iter_mut().filter(|p| p.active) {
+            path.active = false;
+            path.rtt = rtt;
+            path.cwnd = params.cwnd;
+        }
+
+        self.path_mut(self.local_addr, addr).active = true;
+
+        self.peer_addr = Some(addr);
+    }
+
+    /// Returns the path between the `local` and `peer` addresses, starting
+    /// to track it if needed.
+    ///
+    /// When too many paths are tracked, the least used inactive one is
+    /// forgotten.
+    fn path_mut(&mut self, local: Option<net::SocketAddr>,
+                peer: net::SocketAddr) -> &mut PathStats {
+        if let Some(i) = self.paths.iter().position(|p| p.local_addr == local &&
+                                                        p.peer_addr == peer) {
+            return &mut self.paths[i];
+        }
+
+        if self.paths.len() >= MAX_PATHS {
+            let evict = self.paths.iter()
+                                  .enumerate()
+                                  .filter(|(_, p)| !p.active)
+                                  .min_by_key(|(_, p)| p.sent_bytes +
+                                                       p.recv_bytes)
+                                  .map(|(i, _)| i);
+
+            if let Some(i) = evict {
+                self.paths.remove(i);
+            }
+        }
+
+        self.paths.push(PathStats {
+            local_addr: local,
+            peer_addr: peer,
+            state: PathState::Unvalidated,
+            active: false,
+            rtt: self.recovery.rtt(),
+            cwnd: self.recovery.path_params().cwnd,
+            sent_bytes: 0,
+            recv_bytes: 0,
+        });
+
+        self.paths.last_mut().unwrap()
+    }
+
+    /// Tries to resume the session stored for the server, if any.
+    fn resume_session(&mut self) {
+        let session = match (&self.session_store, &self.server_name) {
+            (Some(store), Some(name)) => store.load(name),
+
+            _ => None,
+        };
+
+        if let Some(session) = session {
+            // A stale or corrupted session just means a full handshake.
+            if self.tls_state.set_session(&session).is_err() {
+                trace!("{} failed to load stored session", self.trace_id);
+            }
+        }
+    }
+
+    /// Saves a session ticket received from the server to the session store.
+    fn store_session(&self, session: &[u8]) {
+        if let (Some(store), Some(name)) =
+            (&self.session_store, &self.server_name)
+        {
+            store.store(name, session);
+        }
+    }
+
+    /// Continues the handshake.
+    ///
+    /// If the connection is already established, it processes post-handshake
+    /// messages (e.g. session tickets) instead.
+    fn do_handshake(&mut self) -> Result<()> {
+        if !self.handshake_completed {
+            match self.tls_state.do_handshake() {
+                Ok(_) => {
+                    // Handshake is complete!
+                    self.handshake_completed = true;
+
+                    self.handshake_timer = None;
+
+                    self.release_half_open();
+
+                    let mut raw_params =
+                        self.tls_state.get_quic_transport_params().to_vec();
+
+                    let peer_params =
+                        match TransportParams::decode(&mut raw_params,
+                                                      self.version,
+                                                      self.is_server) {
+                            Ok(v) => v,
+
+                            Err(_) => {
+                                let e = Error::InvalidTransportParam;
+
+                                self.error = Some(e.to_wire());
+
+                                return Err(e);
+                            },
+                        };
+
+                    if peer_params.original_connection_id != self.odcid {
+                        let e = Error::InvalidTransportParam;
+
+                        self.error = Some(e.to_wire());
+
+                        return Err(e);
+                    }
+
+                    self.max_tx_data = peer_params.initial_max_data as usize;
+
+                    self.peer_max_streams_bidi =
+                        peer_params.initial_max_streams_bidi as usize;
+                    self.peer_max_streams_uni =
+                        peer_params.initial_max_streams_uni as usize;
+
+                    self.recovery.max_ack_delay =
+                        time::Duration::from_millis(peer_params.max_ack_delay);
+
+                    self.peer_transport_params = peer_params;
+
+                    self.events.push(Event::HandshakeCompleted);
+
+                    if let Some(ref m) = self.metrics {
+                        m.handshake_completed();
+                    }
+
+                    trace!("{} connection established: cipher={:?} proto={:?} resumed={}",
+                           &self.trace_id,
+                           self.tls_state.cipher(),
+                           std::str::from_utf8(self.application_proto()),
+                           self.is_resumed());
+                },
+
+                Err(tls::Error::TlsFail) => {
+                    // If we have an error to send (e.g. a TLS alert), ignore
+                    // the error so we send a CONNECTION_CLOSE to the peer.
+                    if self.error.is_none() {
+                        // The TLS stack failed without sending an alert, so
+                        // still tell the peer with an internal_error one.
+                        self.error = Some(crypto::INTERNAL_ERROR.to_wire());
+
+                        return Err(Error::TlsFail);
+                    }
+                },
+
+                Err(tls::Error::SyscallFail) => return Err(Error::TlsFail),
+
+                Err(_) => (),
+            }
+        } else {
+            self.tls_state.process_post_handshake()
+                          .map_err(|_| Error::TlsFail)?;
+        }
+
+        Ok(())
+    }
+
+    /// Selects the type for the outgoing packet depending on whether there is
+    /// handshake data to send, whether there are packets to ACK, or whether
+    /// there are streams that can be written or that needs to increase flow
+    /// control credit.
+    fn select_egress_pkt_type(&self) -> Result<Type> {
+        let ty =
+            // On error or probe, send packet in the latest space available.
+            if self.error.is_some() || self.recovery.probes > 0 {
+                match self.tls_state.get_write_level() {
+                    crypto::Level::Initial     => Type::Initial,
+                    crypto::Level::ZeroRTT     => unreachable!(),
+                    crypto::Level::Handshake   => Type::Handshake,
+                    crypto::Level::Application => Type::Application,
+                }
+            } else if self.initial.ready() {
+                Type::Initial
+            } else if self.handshake.ready() {
+                Type::Handshake
+            } else if self.handshake_completed &&
+                      (self.application.ready() ||
+                       !self.new_tokens.is_empty() ||
+                       !self.new_scids.is_empty() ||
+                       !self.retired_dcid_seqs.is_empty() ||
+                       !self.extension_frames.is_empty() ||
+                       self.ping_pending ||
+                       self.app_error.is_some() ||
+                       self.streams.values().any(|s| s.writable()) ||
+                       self.streams.values().any(|s| s.more_credit())) {
+                Type::Application
+            } else {
+                return Err(Error::Done);
+            };
+
+        Ok(ty)
+    }
+
+    /// Enters the closing state after sending a CONNECTION_CLOSE or
+    /// APPLICATION_CLOSE frame.
+    fn enter_closing(&mut self, now: time::Instant) {
+        if !self.closing {
+            trace!("{} entering closing state", self.trace_id);
+
+            self.closing = true;
+            self.draining_timer = Some(now + self.recovery.pto() * 3);
+        }
+
+        self.close_pending = false;
+    }
+
+    /// Drops the initial keys and recovery state.
+    fn drop_initial_state(&mut self) {
+        if self.initial.crypto_open.is_none() {
+            return;
+        }
+
+        self.recovery.drop_unacked_data(&mut self.initial.flight);
+        self.initial.crypto_open = None;
+        self.initial.crypto_seal = None;
+        self.initial.clear();
+
+        trace!("{} dropped initial state", self.trace_id);
+    }
+
+    /// Stops counting the connection as a half-open handshake.
+    fn release_half_open(&mut self) {
+        if let Some(half_open) = self.half_open.take() {
+            half_open.fetch_sub(1, sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    /// Drops the handshake keys and recovery state.
+    fn drop_handshake_state(&mut self) {
+        if self.handshake.crypto_open.is_none() {
+            return;
+        }
+
+        self.recovery.drop_unacked_data(&mut self.handshake.flight);
+        self.handshake.crypto_open = None;
+        self.handshake.crypto_seal = None;
+        self.handshake.clear();
+
+        trace!("{} dropped handshake state", self.trace_id);
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.release_half_open();
+    }
+}
+
+/// Returns the handler of the extension frame type `ty`, if the peer
+/// advertised the extension.
+fn extension_handler(extensions: &[Extension], peer_params: &TransportParams,
+                     ty: u64) -> Option<sync::Arc<dyn FrameExtension>> {
+    let e = extensions.iter().find(|e| e.frame_type == ty)?;
+
+    if !peer_params.extensions.iter().any(|(id, _)| *id == e.param_id) {
+        return None;
+    }
+
+    Some(e.handler.clone())
+}
+
+/// Returns the connection receive window to use when sending a MAX_DATA
+/// update at `now`. The window is doubled, up to `max_window`, if the
+/// previous update was sent less than two RTTs ago, i.e. if the application
+/// reads data fast enough that the window would limit throughput.
+fn autotune_window(window: usize, max_window: usize,
+                   last_update: Option<time::Instant>, rtt: time::Duration,
+                   now: time::Instant) -> usize {
+    match last_update {
+        Some(last) if last + rtt * 2 > now && window < max_window =>
+            cmp::min(window.saturating_mul(2), max_window),
+
+        _ => window,
+    }
+}
+
+/// Fills `buf` with random bytes from the given source, or from BoringSSL.
+fn fill_random(random: &Option<sync::Arc<dyn SecureRandom>>, buf: &mut [u8]) {
+    match random {
+        Some(r) => r.fill(buf),
+
+        None => rand::rand_bytes(buf),
+    }
+}
+
+/// Returns the number of padding bytes the given policy adds to a packet of
+/// type `ty` and length `pkt_len`, where `left` bytes are still available.
+fn policy_padding(policy: &PaddingPolicy,
+                  random: &Option<sync::Arc<dyn SecureRandom>>,
+                  ty: packet::Type, pkt_len: usize, left: usize) -> usize {
+    if ty != packet::Type::Application {
+        return if policy.handshake { left } else { 0 };
+    }
+
+    let mut len = pkt_len;
+
+    if let Some((min, max)) = policy.random {
+        let mut buf = [0; 8];
+        fill_random(random, &mut buf);
+
+        let range = max.saturating_sub(min).saturating_add(1);
+
+        let random = (u64::from_be_bytes(buf) % range as u64) as usize;
+
+        len = len.saturating_add(min.saturating_add(random));
+    }
+
+    if policy.bucket > 0 {
+        len = len.saturating_add(policy.bucket - 1) / policy.bucket *
+              policy.bucket;
+    }
+
+    cmp::min(len.saturating_sub(pkt_len), left)
+}
+
+/// Returns the TLS alert carried by a `CRYPTO_ERROR` transport error code.
+fn crypto_alert(error_code: u16) -> Option<u8> {
+    match error_code {
+        0x100..=0x1ff => Some((error_code - 0x100) as u8),
+
+        _ => None,
+    }
+}
+
+/// Statistics about the connection.
+///
+/// A connections's statistics can be collected using the [`stats()`] method.
+///
+/// [`stats()`]: struct.Connection.html#method.stats
+#[derive(Clone)]
+pub struct Stats {
+    /// The number of QUIC packets sent on this connection.
+    pub sent: usize,
+
+    /// The number of QUIC packets that were lost.
+    pub lost: usize,
+
+    /// The number of QUIC packets that were declared lost, but were later
+    /// acknowledged.
+    pub spurious_lost: usize,
+
+    /// The estimated rounf-trip time of the connection.
+    pub rtt: time::Duration,
+
+    /// The minimum round-trip time observed on the connection, or zero if no
+    /// RTT sample has been taken yet.
+    pub min_rtt: time::Duration,
+
+    /// The estimated round-trip time variation of the connection.
+    pub rttvar: time::Duration,
+
+    /// The number of times persistent congestion was detected.
+    pub persistent_congestion: usize,
+
+    /// The number of QUIC packets received marked with ECT(0).
+    pub ect0_recv: usize,
+
+    /// The number of QUIC packets received marked with ECT(1).
+    pub ect1_recv: usize,
+
+    /// The number of QUIC packets received marked with CE.
+    pub ce_recv: usize,
+
+    /// An estimate of the memory used by the connection, in bytes, including
+    /// stream buffers and data waiting to be acknowledged.
+    pub mem_usage: usize,
+
+    /// The number of duplicate QUIC packets received and dropped.
+    pub dup_recv: usize,
+
+    /// The number of times the peer's address changed without the peer
+    /// migrating, e.g. because of NAT rebinding.
+    pub rebinds: usize,
+
+    /// The number of padding bytes added to packets by the padding policy.
+    pub padding: usize,
+}
+
+impl std::fmt::Debug for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "sent={} lost={} spurious_lost={} rtt={:?} min_rtt={:?} \
+                   rttvar={:?} persistent_congestion={} ect0_recv={} \
+                   ect1_recv={} ce_recv={} mem_usage={} dup_recv={} \
+                   rebinds={} padding={}",
+               self.sent, self.lost, self.spurious_lost, self.rtt,
+               self.min_rtt, self.rttvar, self.persistent_congestion,
+               self.ect0_recv, self.ect1_recv, self.ce_recv, self.mem_usage,
+               self.dup_recv, self.rebinds, self.padding)
+    }
+}
+
+/// The transport parameters advertised by the peer, as returned by
+/// [`peer_transport_params()`].
+///
+/// Parameters the peer didn't send have their default value.
+///
+/// [`peer_transport_params()`]:
+/// struct.Connection.html#method.peer_transport_params
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerTransportParams {
+    /// The idle timeout, in seconds, or 0 if disabled.
+    pub idle_timeout: u64,
+
+    /// The maximum size of the packets the peer is willing to receive.
+    pub max_packet_size: u64,
+
+    /// The initial connection-level flow control limit.
+    pub initial_max_data: u64,
+
+    /// The initial flow control limit of bidirectional streams opened by the
+    /// peer.
+    pub initial_max_stream_data_bidi_local: u64,
+
+    /// The initial flow control limit of bidirectional streams opened by the
+    /// local endpoint.
+    pub initial_max_stream_data_bidi_remote: u64,
+
+    /// The initial flow control limit of unidirectional streams.
+    pub initial_max_stream_data_uni: u64,
+
+    /// The number of bidirectional streams the local endpoint can open.
+    pub initial_max_streams_bidi: u64,
+
+    /// The number of unidirectional streams the local endpoint can open.
+    pub initial_max_streams_uni: u64,
+
+    /// The exponent used to decode the peer's ACK delays.
+    pub ack_delay_exponent: u64,
+
+    /// The maximum time, in milliseconds, the peer delays sending ACKs.
+    pub max_ack_delay: u64,
+
+    /// Whether the peer doesn't support connection migration.
+    pub disable_migration: bool,
+
+    /// The number of connection IDs the peer is willing to store.
+    pub active_conn_id_limit: u64,
+}
+
+/// The validation state of a network path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathState {
+    /// The path hasn't been validated, and no validation is in progress.
+    Unvalidated,
+
+    /// A PATH_CHALLENGE was sent on the path, and the response is pending.
+    Validating,
+
+    /// The peer is known to be reachable on the path.
+    Validated,
+
+    /// The path validation timed out.
+    Failed,
+}
+
+/// Statistics about a network path, as returned by [`paths()`].
+///
+/// [`paths()`]: struct.Connection.html#method.paths
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathStats {
+    /// The local address on the path, if known.
+    pub local_addr: Option<net::SocketAddr>,
+
+    /// The peer's address on the path.
+    pub peer_addr: net::SocketAddr,
+
+    /// The validation state of the path.
+    pub state: PathState,
+
+    /// Whether packets are currently sent on the path.
+    pub active: bool,
+
+    /// The estimated round-trip time of the path. For inactive paths this is
+    /// the last estimate taken while the path was active.
+    pub rtt: time::Duration,
+
+    /// The congestion window of the path, in bytes. For inactive paths this
+    /// is the last window used while the path was active.
+    pub cwnd: usize,
+
+    /// The number of bytes sent on the path.
+    pub sent_bytes: usize,
+
+    /// The number of bytes received on the path.
+    pub recv_bytes: usize,
+}
+
+/// The padding added to outgoing packets, see [`set_padding_policy()`].
+///
+/// [`set_padding_policy()`]: struct.Config.html#method.set_padding_policy
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PaddingPolicy {
+    /// Pads Initial and Handshake packets to the maximum packet size, so
+    /// that the size of the handshake flights doesn't reveal e.g. the
+    /// length of the certificate chain.
+    pub handshake: bool,
+
+    /// Adds a random number of padding bytes, between the two bounds
+    /// inclusive, to 1-RTT packets.
+    pub random: Option<(usize, usize)>,
+
+    /// Pads 1-RTT packets to a multiple of this size, in bytes, after the
+    /// random padding is added. 0 disables bucketing.
+    pub bucket: usize,
+}
+
+/// Path characteristics saved from a previous connection.
+///
+/// These can be used to resume the congestion window of a new connection to
+/// the same peer over the same network path, instead of probing for the
+/// available bandwidth from scratch. See [`path_params()`] and
+/// [`set_resume_params()`].
+///
+/// [`path_params()`]: struct.Connection.html#method.path_params
+/// [`set_resume_params()`]: struct.Connection.html#method.set_resume_params
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathParams {
+    /// The congestion window, in bytes.
+    pub cwnd: usize,
+
+    /// The minimum round-trip time observed on the path.
+    pub min_rtt: time::Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct TransportParams {
+    pub original_connection_id: Option<Vec<u8>>,
+    pub idle_timeout: u64,
+    pub stateless_reset_token: Option<Vec<u8>>,
+    pub max_packet_size: u64,
+    pub initial_max_data: u64,
+    pub initial_max_stream_data_bidi_local: u64,
     pub initial_max_stream_data_bidi_remote: u64,
     pub initial_max_stream_data_uni: u64,
     pub initial_max_streams_bidi: u64,
@@ -1849,345 +5610,2572 @@ struct TransportParams {
     pub ack_delay_exponent: u64,
     pub max_ack_delay: u64,
     pub disable_migration: bool,
+    pub active_conn_id_limit: u64,
     // pub preferred_address: ...
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl Default for TransportParams {
+    fn default() -> TransportParams {
+        TransportParams {
+            original_connection_id: None,
+            idle_timeout: 0,
+            stateless_reset_token: None,
+            max_packet_size: 65527,
+            initial_max_data: 0,
+            initial_max_stream_data_bidi_local: 0,
+            initial_max_stream_data_bidi_remote: 0,
+            initial_max_stream_data_uni: 0,
+            initial_max_streams_bidi: 0,
+            initial_max_streams_uni: 0,
+            ack_delay_exponent: 3,
+            max_ack_delay: 25,
+            disable_migration: false,
+            active_conn_id_limit: 0,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl TransportParams {
+    fn decode(buf: &mut [u8], _version: u32, is_server: bool)
+                                                -> Result<TransportParams> {
+        let mut b = octets::OctetsMut::with_slice(buf);
+
+        // TODO: check version
+        let _tp_version = b.get_u32()?;
+
+        if !is_server {
+            // Ignore supported versions from server.
+            b.get_bytes_with_u8_length()?;
+        }
+
+        let mut tp = TransportParams::default();
+
+        let mut params = b.get_bytes_with_u16_length()?;
+
+        let mut seen = BTreeSet::new();
+
+        while params.cap() > 0 {
+            let id = params.get_u16()?;
+
+            let mut val = params.get_bytes_with_u16_length()?;
+
+            // Each parameter can only appear once.
+            if !seen.insert(id) {
+                return Err(Error::InvalidTransportParam);
+            }
+
+            match id {
+                0x0000 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.original_connection_id = Some(val.to_vec());
+                },
+
+                0x0001 => {
+                    tp.idle_timeout = val.get_varint()?;
+                },
+
+                0x0002 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.stateless_reset_token = Some(val.get_bytes(16)?.to_vec());
+                },
+
+                0x0003 => {
+                    tp.max_packet_size = val.get_varint()?;
+
+                    if tp.max_packet_size < MIN_UDP_PAYLOAD_SIZE {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
+
+                0x0004 => {
+                    tp.initial_max_data = val.get_varint()?;
+                },
+
+                0x0005 => {
+                    tp.initial_max_stream_data_bidi_local = val.get_varint()?;
+                },
+
+                0x0006 => {
+                    tp.initial_max_stream_data_bidi_remote = val.get_varint()?;
+                },
+
+                0x0007 => {
+                    tp.initial_max_stream_data_uni = val.get_varint()?;
+                },
+
+                0x0008 => {
+                    tp.initial_max_streams_bidi = val.get_varint()?;
+
+                    if tp.initial_max_streams_bidi > MAX_STREAMS {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
+
+                0x0009 => {
+                    tp.initial_max_streams_uni = val.get_varint()?;
+
+                    if tp.initial_max_streams_uni > MAX_STREAMS {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
+
+                0x000a => {
+                    tp.ack_delay_exponent = val.get_varint()?;
+
+                    if tp.ack_delay_exponent > MAX_ACK_DELAY_EXPONENT {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
+
+                0x000b => {
+                    tp.max_ack_delay = val.get_varint()?;
+
+                    if tp.max_ack_delay >= MAX_ACK_DELAY {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
+
+                0x000c => {
+                    tp.disable_migration = true;
+                },
+
+                0x000d => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    // TODO: decode preferred_address
+                    let len = val.cap();
+                    val.get_bytes(len)?;
+                },
+
+                0x000e => {
+                    tp.active_conn_id_limit = val.get_varint()?;
+                },
+
+                // Unknown parameters are kept, as they might advertise
+                // extensions.
+                _ => {
+                    tp.extensions.push((id, val.to_vec()));
+
+                    continue;
+                },
+            }
+
+            // The value must be fully consumed by the parameter's decoding,
+            // e.g. the zero-length `disable_migration` parameter can't carry
+            // any data.
+            if val.cap() != 0 {
+                return Err(Error::InvalidTransportParam);
+            }
+        }
+
+        Ok(tp)
+    }
+
+    fn encode<'a>(tp: &TransportParams, version: u32, is_server: bool,
+                  out: &'a mut [u8]) -> Result<&'a mut [u8]> {
+        let mut params: [u8; 512] = [0; 512];
+
+        let params_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut params);
+
+            if is_server {
+                if let Some(ref odcid) = tp.original_connection_id {
+                    b.put_u16(0x0000)?;
+                    b.put_u16(odcid.len() as u16)?;
+                    b.put_bytes(&odcid)?;
+                }
+            };
+
+            if tp.idle_timeout != 0 {
+                b.put_u16(0x0001)?;
+                b.put_u16(octets::varint_len(tp.idle_timeout) as u16)?;
+                b.put_varint(tp.idle_timeout)?;
+            }
+
+            if let Some(ref token) = tp.stateless_reset_token {
+                if is_server {
+                    b.put_u16(0x0002)?;
+                    b.put_u16(token.len() as u16)?;
+                    b.put_bytes(&token)?;
+                }
+            }
+
+            if tp.max_packet_size != 0 {
+                b.put_u16(0x0003)?;
+                b.put_u16(octets::varint_len(tp.max_packet_size) as u16)?;
+                b.put_varint(tp.max_packet_size)?;
+            }
+
+            if tp.initial_max_data != 0 {
+                b.put_u16(0x0004)?;
+                b.put_u16(octets::varint_len(tp.initial_max_data) as u16)?;
+                b.put_varint(tp.initial_max_data)?;
+            }
+
+            if tp.initial_max_stream_data_bidi_local != 0 {
+                b.put_u16(0x0005)?;
+                b.put_u16(octets::varint_len(tp.initial_max_stream_data_bidi_local) as u16)?;
+                b.put_varint(tp.initial_max_stream_data_bidi_local)?;
+            }
+
+            if tp.initial_max_stream_data_bidi_remote != 0 {
+                b.put_u16(0x0006)?;
+                b.put_u16(octets::varint_len(tp.initial_max_stream_data_bidi_remote) as u16)?;
+                b.put_varint(tp.initial_max_stream_data_bidi_remote)?;
+            }
+
+            if tp.initial_max_stream_data_uni != 0 {
+                b.put_u16(0x0007)?;
+                b.put_u16(octets::varint_len(tp.initial_max_stream_data_uni) as u16)?;
+                b.put_varint(tp.initial_max_stream_data_uni)?;
+            }
+
+            if tp.initial_max_streams_bidi != 0 {
+                b.put_u16(0x0008)?;
+                b.put_u16(octets::varint_len(tp.initial_max_streams_bidi) as u16)?;
+                b.put_varint(tp.initial_max_streams_bidi)?;
+            }
+
+            if tp.initial_max_streams_uni != 0 {
+                b.put_u16(0x0009)?;
+                b.put_u16(octets::varint_len(tp.initial_max_streams_uni) as u16)?;
+                b.put_varint(tp.initial_max_streams_uni)?;
+            }
+
+            if tp.ack_delay_exponent != 0 {
+                b.put_u16(0x000a)?;
+                b.put_u16(octets::varint_len(tp.ack_delay_exponent) as u16)?;
+                b.put_varint(tp.ack_delay_exponent)?;
+            }
+
+            if tp.max_ack_delay != 0 {
+                b.put_u16(0x000b)?;
+                b.put_u16(octets::varint_len(tp.max_ack_delay) as u16)?;
+                b.put_varint(tp.max_ack_delay)?;
+            }
+
+            if tp.disable_migration {
+                b.put_u16(0x000c)?;
+                b.put_u16(0)?;
+            }
+
+            // TODO: encode preferred_address
+
+            if tp.active_conn_id_limit != 0 {
+                b.put_u16(0x000e)?;
+                b.put_u16(octets::varint_len(tp.active_conn_id_limit) as u16)?;
+                b.put_varint(tp.active_conn_id_limit)?;
+            }
+
+            for (id, val) in &tp.extensions {
+                b.put_u16(*id)?;
+                b.put_u16(val.len() as u16)?;
+                b.put_bytes(&val)?;
+            }
+
+            b.off()
+        };
+
+        let out_len = {
+            let mut b = octets::OctetsMut::with_slice(out);
+
+            b.put_u32(version)?;
+
+            if is_server {
+                b.put_u8(mem::size_of::<u32>() as u8)?;
+                b.put_u32(version)?;
+            };
+
+            b.put_u16(params_len as u16)?;
+            b.put_bytes(&params[..params_len])?;
+
+            b.off()
+        };
+
+        Ok(&mut out[..out_len])
+    }
 }
 
-impl Default for TransportParams {
-    fn default() -> TransportParams {
-        TransportParams {
-            original_connection_id: None,
-            idle_timeout: 0,
-            stateless_reset_token: None,
-            max_packet_size: 65527,
-            initial_max_data: 0,
-            initial_max_stream_data_bidi_local: 0,
-            initial_max_stream_data_bidi_remote: 0,
-            initial_max_stream_data_uni: 0,
-            initial_max_streams_bidi: 0,
-            initial_max_streams_uni: 0,
-            ack_delay_exponent: 3,
-            max_ack_delay: 25,
-            disable_migration: false,
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_params() {
+        let tp = TransportParams {
+            original_connection_id: None,
+            idle_timeout: 30,
+            stateless_reset_token: Some(vec![0xba; 16]),
+            max_packet_size: 23_421,
+            initial_max_data: 424_645_563,
+            initial_max_stream_data_bidi_local: 154_323_123,
+            initial_max_stream_data_bidi_remote: 6_587_456,
+            initial_max_stream_data_uni: 2_461_234,
+            initial_max_streams_bidi: 12_231,
+            initial_max_streams_uni: 18_473,
+            ack_delay_exponent: 12,
+            max_ack_delay: 1234,
+            disable_migration: true,
+            active_conn_id_limit: 8,
+            extensions: Vec::new(),
+        };
+
+        let mut raw_params: [u8; 256] = [42; 256];
+        let mut raw_params = TransportParams::encode(&tp, VERSION_DRAFT17, true,
+                                              &mut raw_params).unwrap();
+        assert_eq!(raw_params.len(), 110);
+
+        let new_tp = TransportParams::decode(&mut raw_params, VERSION_DRAFT17,
+                                             false).unwrap();
+
+        assert_eq!(new_tp, tp);
+    }
+
+    fn create_conn(is_server: bool) -> Box<Connection> {
+        let mut config = Config::new(VERSION_DRAFT17).unwrap();
+        config.load_cert_chain_from_pem_file("examples/cert.crt").unwrap();
+        config.load_priv_key_from_pem_file("examples/cert.key").unwrap();
+        config.verify_peer(false);
+
+        create_conn_with_config(&mut config, is_server)
+    }
+
+    fn create_conn_with_config(config: &mut Config, is_server: bool)
+                                                        -> Box<Connection> {
+        let mut scid: [u8; 16] = [0; 16];
+        rand::rand_bytes(&mut scid[..]);
+
+        Connection::new(&scid, None, config, is_server,
+                        time::Instant::now()).unwrap()
+    }
+
+    fn recv_send(conn: &mut Connection, buf: &mut [u8], len: usize) -> usize {
+        let mut left = len;
+
+        while left > 0 {
+            let read = conn.recv(&mut buf[len - left..len]).unwrap();
+
+            left -= read;
+        }
+
+        let mut off = 0;
+
+        while off < buf.len() {
+            let write = match conn.send(&mut buf[off..]) {
+                Ok(v)   => v,
+
+                Err(Error::Done) => { break; },
+
+                Err(e)  => panic!("SEND FAILED: {:?}", e),
+            };
+
+            off += write;
+        }
+
+        off
+    }
+
+    #[test]
+    fn self_handshake() {
+        let mut buf = [0; 65535];
+
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() && !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        assert!(true);
+    }
+
+    #[test]
+    fn new_token() {
+        let mut buf = [0; 65535];
+
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
+
+        assert_eq!(cln.send_new_token(b"token"), Err(Error::InvalidState));
+        assert_eq!(srv.send_new_token(b"token"), Err(Error::InvalidState));
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        assert_eq!(cln.set_token(b"token"), Err(Error::InvalidState));
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        assert_eq!(srv.send_new_token(b"token"), Ok(()));
+
+        let len = recv_send(&mut srv, &mut buf, len);
+        recv_send(&mut cln, &mut buf, len);
+
+        assert_eq!(cln.new_token(), Some(&b"token"[..]));
+    }
+
+    #[test]
+    fn transport_params_validation() {
+        // Appends the given raw parameter to the encoded parameters, whose
+        // length field is at offset `off`.
+        fn append(raw: &[u8], off: usize, extra: &[u8]) -> Vec<u8> {
+            let mut buf = raw.to_vec();
+
+            let params_len = u16::from_be_bytes([buf[off], buf[off + 1]]);
+            let params_len = params_len + extra.len() as u16;
+
+            buf[off..off + 2].copy_from_slice(&params_len.to_be_bytes());
+            buf.extend_from_slice(extra);
+
+            buf
+        }
+
+        let mut raw_params: [u8; 256] = [42; 256];
+
+        // Server parameters start with the version (4 bytes), followed by
+        // the supported versions (1 + 4 bytes).
+        let raw = TransportParams::encode(&TransportParams::default(),
+                                          VERSION_DRAFT17, true,
+                                          &mut raw_params).unwrap().to_vec();
+
+        // Duplicated parameter.
+        let mut buf = append(&raw, 9, &[0x00, 0x01, 0x00, 0x01, 0x05]);
+        assert!(TransportParams::decode(&mut buf, VERSION_DRAFT17,
+                                        false).is_ok());
+
+        let mut buf = append(&buf, 9, &[0x00, 0x01, 0x00, 0x01, 0x05]);
+        assert_eq!(TransportParams::decode(&mut buf, VERSION_DRAFT17, false),
+                   Err(Error::InvalidTransportParam));
+
+        // Zero-length parameter with a value.
+        let mut buf = append(&raw, 9, &[0x00, 0x0c, 0x00, 0x01, 0x05]);
+        assert_eq!(TransportParams::decode(&mut buf, VERSION_DRAFT17, false),
+                   Err(Error::InvalidTransportParam));
+
+        // Client parameters start with the version (4 bytes) only.
+        let raw = TransportParams::encode(&TransportParams::default(),
+                                          VERSION_DRAFT17, false,
+                                          &mut raw_params).unwrap().to_vec();
+
+        // The client can't send server-only parameters.
+        let mut token = vec![0x00, 0x02, 0x00, 0x10];
+        token.extend_from_slice(&[0xba; 16]);
+
+        let mut buf = append(&raw, 4, &token);
+        assert_eq!(TransportParams::decode(&mut buf, VERSION_DRAFT17, true),
+                   Err(Error::InvalidTransportParam));
+
+        let mut tp = TransportParams::default();
+        tp.initial_max_streams_bidi = MAX_STREAMS + 1;
+
+        let mut raw = TransportParams::encode(&tp, VERSION_DRAFT17, true,
+                                              &mut raw_params).unwrap();
+        assert_eq!(TransportParams::decode(&mut raw, VERSION_DRAFT17, false),
+                   Err(Error::InvalidTransportParam));
+    }
+
+    #[test]
+    fn max_udp_payload_size() {
+        let mut tp = TransportParams::default();
+        tp.max_packet_size = 1199;
+
+        let mut raw_params: [u8; 256] = [42; 256];
+        let mut raw_params = TransportParams::encode(&tp, VERSION_DRAFT17, true,
+                                              &mut raw_params).unwrap();
+
+        assert_eq!(TransportParams::decode(&mut raw_params, VERSION_DRAFT17,
+                                           false),
+                   Err(Error::InvalidTransportParam));
+
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        assert_eq!(cln.stream_send(4, &[0xba; 5000], true), Ok(5000));
+
+        let len = cln.send(&mut buf).unwrap();
+        assert!(len <= DEFAULT_MAX_SEND_UDP_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn active_connection_id_limit() {
+        let mut buf = [0; 65535];
+
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
+
+        cln.local_transport_params.active_conn_id_limit = 2;
+        srv.local_transport_params.disable_migration = true;
+
+        assert_eq!(srv.new_source_cid(&[0xab; 16], &[0xcd; 16]),
+                   Err(Error::InvalidState));
+        assert_eq!(srv.on_peer_migrated(), Ok(()));
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        // The client allows a single additional connection ID.
+        assert_eq!(srv.new_source_cid(&[0xab; 16], &[0xcd; 16]), Ok(1));
+        assert_eq!(srv.new_source_cid(&[0xac; 16], &[0xce; 16]),
+                   Err(Error::InvalidState));
+
+        let len = recv_send(&mut srv, &mut buf, len);
+        recv_send(&mut cln, &mut buf, len);
+
+        // The server doesn't allow any additional connection IDs.
+        assert_eq!(cln.new_source_cid(&[0xab; 16], &[0xcd; 16]),
+                   Err(Error::InvalidState));
+
+        assert_eq!(srv.on_peer_migrated(), Err(Error::InvalidState));
+        assert_eq!(cln.on_peer_migrated(), Ok(()));
+    }
+
+    #[test]
+    fn cid_rotation() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_active_connection_id_limit(2);
+        config.set_cid_rotation_packets(2);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let dcid = pipe.client.destination_id().to_vec();
+
+        // No spare connection ID to rotate to yet.
+        for _ in 0..3 {
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+            let len = pipe.client.send(&mut buf).unwrap();
+            assert_eq!(pipe.server.recv(&mut buf[..len]), Ok(len));
+        }
+
+        assert_eq!(pipe.client.destination_id(), &dcid[..]);
+
+        assert_eq!(pipe.server.new_source_cid(&[0xab; 16], &[0xcd; 16]), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.destination_id(), &[0xab; 16][..]);
+
+        let events: Vec<Event> = std::iter::from_fn(|| pipe.client.poll())
+                                           .collect();
+        assert!(events.contains(&Event::ConnectionIdRotated));
+
+        // The previous connection ID was retired.
+        assert_eq!(pipe.server.active_scid_seqs,
+                   [1].iter().cloned().collect());
+    }
+
+    #[test]
+    fn peer_transport_params() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.client.peer_transport_params(), None);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let params = pipe.client.peer_transport_params().unwrap();
+        assert_eq!(params.initial_max_data, 30000);
+        assert_eq!(params.initial_max_stream_data_bidi_local, 15000);
+        assert_eq!(params.initial_max_stream_data_bidi_remote, 15000);
+        assert_eq!(params.initial_max_stream_data_uni, 15000);
+        assert_eq!(params.initial_max_streams_bidi, 3);
+        assert_eq!(params.initial_max_streams_uni, 3);
+        assert_eq!(params.idle_timeout, 0);
+        assert!(!params.disable_migration);
+
+        assert_eq!(pipe.server.peer_transport_params(), Some(params));
+    }
+
+    #[test]
+    fn stateless_reset_key() {
+        let mut config = testing::default_config().unwrap();
+
+        assert_eq!(config.stateless_reset_token(&[0xba; 16]), None);
+
+        config.set_stateless_reset_key(&[0x42; 32]);
+
+        let mut other = Config::new(VERSION_DRAFT17).unwrap();
+        other.set_stateless_reset_key(&[0x42; 32]);
+
+        let token = config.stateless_reset_token(&[0xba; 16]);
+        assert!(token.is_some());
+        assert_eq!(token, other.stateless_reset_token(&[0xba; 16]));
+        assert_ne!(token, config.stateless_reset_token(&[0xbb; 16]));
+
+        let scid = [0xba; 16];
+        let server = accept(&scid, None, &mut config).unwrap();
+        assert_eq!(server.local_transport_params.stateless_reset_token,
+                   token.map(|t| t.to_vec()));
+    }
+
+    #[test]
+    fn ack_delay_params() {
+        let mut raw_params: [u8; 256] = [42; 256];
+
+        let mut tp = TransportParams::default();
+        tp.ack_delay_exponent = 21;
+
+        let mut raw = TransportParams::encode(&tp, VERSION_DRAFT17, true,
+                                              &mut raw_params).unwrap();
+        assert_eq!(TransportParams::decode(&mut raw, VERSION_DRAFT17, false),
+                   Err(Error::InvalidTransportParam));
+
+        let mut tp = TransportParams::default();
+        tp.max_ack_delay = 1 << 14;
+
+        let mut raw = TransportParams::encode(&tp, VERSION_DRAFT17, true,
+                                              &mut raw_params).unwrap();
+        assert_eq!(TransportParams::decode(&mut raw, VERSION_DRAFT17, false),
+                   Err(Error::InvalidTransportParam));
+    }
+
+    #[test]
+    fn delayed_ack() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        cln.application.do_ack = false;
+        cln.ack_timer = None;
+        cln.ack_eliciting_since_ack = 0;
+
+        // The first ack-eliciting packet arms the ACK timer.
+        assert_eq!(srv.stream_send(1, b"hello", false), Ok(5));
+
+        let len = srv.send(&mut buf).unwrap();
+        assert_eq!(cln.recv(&mut buf[..len]), Ok(len));
+
+        assert!(!cln.application.do_ack);
+        assert!(cln.ack_timer.is_some());
+        assert!(cln.timeout().unwrap() <= time::Duration::from_millis(25));
+
+        // The second one is ACK'd immediately.
+        assert_eq!(srv.stream_send(1, b"world", false), Ok(5));
+
+        let len = srv.send(&mut buf).unwrap();
+        assert_eq!(cln.recv(&mut buf[..len]), Ok(len));
+
+        assert!(cln.application.do_ack);
+
+        assert!(cln.send(&mut buf).is_ok());
+        assert!(cln.ack_timer.is_none());
+    }
+
+    #[test]
+    fn delayed_ack_reordered() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        cln.application.do_ack = false;
+        cln.ack_timer = None;
+        cln.ack_eliciting_since_ack = 0;
+
+        assert_eq!(srv.stream_send(1, b"hello", false), Ok(5));
+        let first_len = srv.send(&mut buf).unwrap();
+
+        let mut second = [0; 65535];
+        assert_eq!(srv.stream_send(5, b"world", false), Ok(5));
+        let second_len = srv.send(&mut second).unwrap();
+
+        // A packet received after a gap is ACK'd immediately.
+        assert_eq!(cln.recv(&mut second[..second_len]), Ok(second_len));
+        assert!(cln.application.do_ack);
+
+        assert!(cln.send(&mut second).is_ok());
+        assert!(cln.ack_timer.is_none());
+        assert!(!cln.application.do_ack);
+
+        // And so is the late packet that filled it.
+        assert_eq!(cln.recv(&mut buf[..first_len]), Ok(first_len));
+        assert!(cln.application.do_ack);
+    }
+
+    #[test]
+    fn keepalive() {
+        let mut buf = [0; 65535];
+
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
+
+        cln.keepalive = Some(time::Duration::from_millis(1));
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        while cln.send(&mut buf).is_ok() {}
+
+        std::thread::sleep(time::Duration::from_millis(2));
+
+        cln.on_timeout();
+
+        assert!(cln.ping_pending);
+
+        let len = cln.send(&mut buf).unwrap();
+        assert!(!cln.ping_pending);
+
+        // The server ACKs the PING.
+        srv.application.do_ack = false;
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+        assert!(srv.ack_timer.is_some());
+    }
+
+    #[test]
+    fn stream_burst_fairness() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(50000);
+        config.set_initial_max_stream_data_bidi_remote(50000);
+        config.set_stream_burst(2000);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, &[0xaa; 30000], false),
+                   Ok(30000));
+        assert_eq!(pipe.client.stream_send(8, &[0xbb; 30000], false),
+                   Ok(30000));
+
+        // Both streams make progress within the first few packets, whichever
+        // of them is picked first.
+        for _ in 0..4 {
+            let len = pipe.client.send(&mut buf).unwrap();
+            assert_eq!(pipe.server.recv(&mut buf[..len]), Ok(len));
+        }
+
+        assert!(pipe.server.stream_readable(4));
+        assert!(pipe.server.stream_readable(8));
+
+        assert_eq!(pipe.client.stream_burst(12, 100),
+                   Err(Error::InvalidStreamState));
+        assert_eq!(pipe.client.stream_burst(4, 100), Ok(()));
+    }
+
+    #[test]
+    fn stream_frames_coalesced() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_stream_burst(500);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let traces = sync::Arc::new(sync::Mutex::new(Vec::new()));
+
+        let cln_traces = traces.clone();
+        pipe.client.packet_trace_cb =
+            Some(sync::Arc::new(move |_, t: &PacketTrace| {
+                cln_traces.lock().unwrap().push(t.clone());
+            }));
+
+        // Many small writes.
+        for _ in 0..10 {
+            assert_eq!(pipe.client.stream_send(4, &[0xaa; 100], false),
+                       Ok(100));
+        }
+
+        let len = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server.recv(&mut buf[..len]), Ok(len));
+
+        // The two bursts of the stream went into a single frame.
+        let traces = traces.lock().unwrap();
+        let frames: Vec<&String> = traces[0].frames.iter()
+            .filter(|f| f.starts_with("STREAM"))
+            .collect();
+        assert_eq!(frames, vec!["STREAM id=4 off=0 len=1000 fin=false"]);
+
+        let data = pipe.server.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(&data[..], &[0xaa; 1000][..]);
+    }
+
+    struct CountingRandom(sync::atomic::AtomicUsize);
+
+    impl SecureRandom for CountingRandom {
+        fn fill(&self, buf: &mut [u8]) {
+            for b in buf.iter_mut() {
+                *b = self.0.fetch_add(1, sync::atomic::Ordering::SeqCst) as u8;
+            }
+        }
+    }
+
+    #[test]
+    fn custom_random() {
+        let mut config = Config::new(VERSION_DRAFT17).unwrap();
+        config.set_cid_len(4).unwrap();
+        config.set_random(sync::Arc::new(
+            CountingRandom(sync::atomic::AtomicUsize::new(0))));
+
+        assert_eq!(config.new_cid(), vec![0, 1, 2, 3]);
+        assert_eq!(config.new_cid(), vec![4, 5, 6, 7]);
+
+        let scid = config.new_cid();
+        let conn = connect(None, &scid, &mut config).unwrap();
+
+        // The initial destination connection ID comes from the same source.
+        let dcid: Vec<u8> = (12..28).collect();
+        assert_eq!(conn.destination_id(), &dcid[..]);
+    }
+
+    #[test]
+    fn supported_versions() {
+        assert!(version_is_supported(VERSION_DRAFT17));
+        assert!(!version_is_supported(0xbaba_baba));
+
+        // The key schedule of every supported version is known.
+        for version in super::supported_versions() {
+            assert!(crypto::derive_initial_key_material(*version, &[0xba; 8],
+                                                        true).is_ok());
+        }
+
+        assert!(crypto::derive_initial_key_material(0xbaba_baba, &[0xba; 8],
+                                                    true).is_err());
+
+        let mut buf = [0; 128];
+        let len = negotiate_version(&[0xba; 8], &[0xab; 8], &mut buf).unwrap();
+
+        let hdr = packet::Header::from_slice(&mut buf[..len], 8).unwrap();
+        assert_eq!(hdr.versions, Some(super::supported_versions().to_vec()));
+    }
+
+    struct RecordingExtension(sync::Mutex<Vec<(u64, Vec<u8>)>>);
+
+    impl FrameExtension for RecordingExtension {
+        fn on_frame(&self, ty: u64, payload: &[u8]) -> Result<()> {
+            self.0.lock().unwrap().push((ty, payload.to_vec()));
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frame_extension() {
+        let ext =
+            sync::Arc::new(RecordingExtension(sync::Mutex::new(Vec::new())));
+
+        let mut config = testing::default_config().unwrap();
+
+        assert_eq!(config.register_frame_extension(0x1c, 0x4242, b"",
+                                                   ext.clone()),
+                   Err(Error::InvalidState));
+        assert_eq!(config.register_frame_extension(0x4242, 0x0003, b"",
+                                                   ext.clone()),
+                   Err(Error::InvalidState));
+        assert_eq!(config.register_frame_extension(0x4242, 0x4242, b"v1",
+                                                   ext.clone()),
+                   Ok(()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        // Not negotiated yet.
+        assert_eq!(pipe.client.send_extension_frame(0x4242, b"hello"),
+                   Err(Error::InvalidState));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_extension_param(0x4242), Some(&b"v1"[..]));
+        assert_eq!(pipe.client.send_extension_frame(0x4243, b"hello"),
+                   Err(Error::InvalidState));
+
+        assert_eq!(pipe.client.send_extension_frame(0x4242, b"hello"), Ok(()));
+        assert_eq!(pipe.server.send_extension_frame(0x4242, b""), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut frames = ext.0.lock().unwrap().clone();
+        frames.sort();
+        assert_eq!(frames, vec![(0x4242, b"".to_vec()),
+                                (0x4242, b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn frame_extension_not_negotiated() {
+        let ext =
+            sync::Arc::new(RecordingExtension(sync::Mutex::new(Vec::new())));
+
+        let mut client_config = Config::new(VERSION_DRAFT17).unwrap();
+        client_config.verify_peer(false);
+        client_config.register_frame_extension(0x4242, 0x4242, b"",
+                                               ext.clone()).unwrap();
+
+        let mut server_config = Config::new(VERSION_DRAFT17).unwrap();
+        server_config.load_cert_chain_from_pem_file("examples/cert.crt")
+                     .unwrap();
+        server_config.load_priv_key_from_pem_file("examples/cert.key")
+                     .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config, &mut server_config).unwrap();
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The server didn't advertise the extension.
+        assert_eq!(pipe.client.peer_extension_param(0x4242), None);
+        assert_eq!(pipe.client.send_extension_frame(0x4242, b"hello"),
+                   Err(Error::InvalidState));
+    }
+
+    #[test]
+    fn timer_coalescing() {
+        let mut conn = create_conn(false);
+
+        let now = time::Instant::now();
+
+        conn.handshake_timer = None;
+        conn.idle_timer = Some(now + time::Duration::from_secs(30));
+        conn.ack_timer = Some(now + time::Duration::from_millis(10));
+        conn.pacing_timer = Some(now + time::Duration::from_millis(12));
+        conn.rate_limit_timer = Some(now + time::Duration::from_millis(50));
+
+        // No coalescing by default.
+        assert_eq!(conn.next_event_time(),
+                   Some(now + time::Duration::from_millis(10)));
+
+        conn.timer_granularity = time::Duration::from_millis(5);
+
+        assert_eq!(conn.timeout_instant(),
+                   Some(now + time::Duration::from_millis(10)));
+        assert_eq!(conn.next_event_time(),
+                   Some(now + time::Duration::from_millis(12)));
+
+        // A single wake up handles both timers.
+        conn.on_timeout_at(now + time::Duration::from_millis(12));
+
+        assert_eq!(conn.ack_timer, None);
+        assert_eq!(conn.pacing_timer, None);
+        assert_eq!(conn.next_event_time(),
+                   Some(now + time::Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn packet_trace() {
+        let mut buf = [0; 65535];
+
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
+
+        let traces = sync::Arc::new(sync::Mutex::new(Vec::new()));
+
+        let cln_traces = traces.clone();
+        cln.packet_trace_cb = Some(sync::Arc::new(move |_, t: &PacketTrace| {
+            cln_traces.lock().unwrap().push(t.clone());
+        }));
+
+        let srv_traces = traces.clone();
+        srv.packet_trace_cb = Some(sync::Arc::new(move |_, t: &PacketTrace| {
+            srv_traces.lock().unwrap().push(t.clone());
+        }));
+
+        let len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        let traces = traces.lock().unwrap();
+        assert_eq!(traces.len(), 2);
+
+        assert_eq!(traces[0].event, PacketEvent::Sent);
+        assert_eq!(traces[0].ty, Type::Initial);
+        assert_eq!(traces[0].pkt_num, 0);
+        assert!(traces[0].frames[0].starts_with("CRYPTO"));
+
+        assert_eq!(traces[1].event, PacketEvent::Received);
+        assert_eq!(traces[1].ty, Type::Initial);
+        assert_eq!(traces[1].pkt_num, 0);
+        assert!(traces[1].frames[0].starts_with("CRYPTO"));
+    }
+
+    #[test]
+    fn error_to_wire() {
+        assert_eq!(Error::InvalidFrame { ty: 0x02 }.to_wire(), 0x7);
+        assert_eq!(Error::FinalSize.to_wire(), 0x6);
+        assert_eq!(Error::CryptoFail { alert: 40 }.to_wire(), 0x128);
+        assert_eq!(crypto::INTERNAL_ERROR.to_wire(), 0x150);
+        assert_eq!(Error::InvalidPacket.to_wire(), 0xa);
+
+        assert_eq!(format!("{}", Error::InvalidFrame { ty: 0x1e }),
+                   "InvalidFrame { ty: 30 }");
+
+        let e: Box<dyn std::error::Error> = Box::new(Error::FinalSize);
+        assert!(e.source().is_none());
+    }
+
+    #[test]
+    fn send_recv_info() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_dscp(0xff);
+
+        let mut client = create_conn(false);
+        let mut server = Connection::new(&[0xba; 16], None, &mut config, true,
+                                         time::Instant::now()).unwrap();
+
+        let (len, info) = client.send_with_info(&mut buf).unwrap();
+        assert_eq!(info, SendInfo::default());
+        assert_eq!(info.tos(), 0);
+
+        let info = RecvInfo {
+            ecn: Ecn::from_tos(0x03),
+            from: None,
+            to: None,
+        };
+        assert_eq!(server.recv_with_info(&mut buf[..len], info), Ok(len));
+        assert_eq!(server.stats().ce_recv, 1);
+        assert_eq!(server.stats().ect0_recv, 0);
+
+        let (_, info) = server.send_with_info(&mut buf).unwrap();
+        assert_eq!(info.ecn, Ecn::NotEct);
+        assert_eq!(info.dscp, 0x3f);
+        assert_eq!(info.tos(), 0xfc);
+    }
+
+    #[test]
+    fn cipher_suites() {
+        let mut config = testing::default_config().unwrap();
+
+        assert_eq!(config.set_cipher_suites(&[]), Err(Error::TlsFail));
+        assert!(config.set_cipher_suites(&[CipherSuite::Aes128Gcm,
+                                           CipherSuite::Aes256Gcm,
+                                           CipherSuite::ChaCha20Poly1305])
+                      .is_ok());
+        assert!(config.set_curves(&["X25519", "P-256"]).is_ok());
+        assert_eq!(config.set_curves(&["not-a-curve"]), Err(Error::TlsFail));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.cipher_suite(), None);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let suite = pipe.client.cipher_suite();
+        assert!(suite.is_some());
+        assert_eq!(suite, pipe.server.cipher_suite());
+    }
+
+    #[derive(Default)]
+    struct TestSessionStore(sync::Mutex<HashMap<String, Vec<u8>>>);
+
+    impl SessionStore for TestSessionStore {
+        fn store(&self, server_name: &str, session: &[u8]) {
+            self.0.lock().unwrap().insert(server_name.to_string(),
+                                          session.to_vec());
+        }
+
+        fn load(&self, server_name: &str) -> Option<Vec<u8>> {
+            self.0.lock().unwrap().get(server_name).cloned()
+        }
+    }
+
+    fn resumption_pipe(config: &mut Config) -> testing::Pipe {
+        let client_scid = config.new_cid();
+        let server_scid = config.new_cid();
+
+        testing::Pipe {
+            client: connect(Some("quic.tech"), &client_scid, config).unwrap(),
+            server: accept(&server_scid, None, config).unwrap(),
+        }
+    }
+
+    #[test]
+    fn session_resumption() {
+        let store = sync::Arc::new(TestSessionStore::default());
+
+        let mut config = testing::default_config().unwrap();
+        config.set_session_store(store.clone());
+
+        let mut pipe = resumption_pipe(&mut config);
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.client.is_resumed());
+
+        // The session ticket was stored under the server's name.
+        assert!(store.load("quic.tech").is_some());
+
+        let mut pipe = resumption_pipe(&mut config);
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.client.is_resumed());
+        assert!(pipe.server.is_resumed());
+    }
+
+    struct CountingKey {
+        open: crypto::Open,
+        seal: crypto::Seal,
+        ops: sync::Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl PacketKey for CountingKey {
+        fn seal(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8])
+                                                            -> Result<usize> {
+            self.ops.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            self.seal.seal(nonce, ad, buf)
+        }
+
+        fn open(&self, nonce: &[u8], ad: &[u8], buf: &mut [u8])
+                                                            -> Result<usize> {
+            self.ops.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            self.open.open(nonce, ad, buf)
+        }
+    }
+
+    struct CountingProvider(sync::Arc<sync::atomic::AtomicUsize>);
+
+    impl AeadProvider for CountingProvider {
+        fn new_key(&self, suite: CipherSuite, key: &[u8])
+                                            -> Option<Box<dyn PacketKey>> {
+            let alg = match suite {
+                CipherSuite::Aes128Gcm => crypto::Algorithm::AES128_GCM,
+                CipherSuite::Aes256Gcm => crypto::Algorithm::AES256_GCM,
+                CipherSuite::ChaCha20Poly1305 =>
+                    crypto::Algorithm::ChaCha20_Poly1305,
+            };
+
+            // Nonces are computed by the connection, so the IV is unused.
+            let iv = [0; 12];
+
+            Some(Box::new(CountingKey {
+                open: crypto::Open::new(alg, key, &iv, key).ok()?,
+                seal: crypto::Seal::new(alg, key, &iv, key).ok()?,
+                ops: self.0.clone(),
+            }))
+        }
+    }
+
+    #[test]
+    fn aead_offload() {
+        let ops = sync::Arc::new(sync::atomic::AtomicUsize::new(0));
+
+        let mut config = testing::default_config().unwrap();
+        config.set_aead_provider(sync::Arc::new(CountingProvider(ops.clone())));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let before = ops.load(sync::atomic::Ordering::SeqCst);
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // 1-RTT packets are protected by the provided keys on both ends.
+        assert!(ops.load(sync::atomic::Ordering::SeqCst) >= before + 2);
+
+        let b = pipe.server.stream_recv(4, 10).unwrap();
+        assert_eq!(&b[..], b"hello");
+        assert!(b.fin());
+    }
+
+    #[test]
+    fn cipher_suite_not_allowed() {
+        let mut config = testing::default_config().unwrap();
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Allow only a suite different from the one negotiated by default.
+        let negotiated = pipe.client.cipher_suite().unwrap();
+        let other = if negotiated == CipherSuite::Aes128Gcm {
+            CipherSuite::Aes256Gcm
+        } else {
+            CipherSuite::Aes128Gcm
+        };
+
+        let mut client_config = Config::new(VERSION_DRAFT17).unwrap();
+        client_config.verify_peer(false);
+        client_config.set_cipher_suites(&[other]).unwrap();
+
+        let mut pipe =
+            testing::Pipe::with_client_and_server_config(&mut client_config,
+                                                         &mut config).unwrap();
+        for _ in 0..4 {
+            if let Ok(flight) = testing::emit_flight(&mut pipe.client) {
+                testing::process_flight(&mut pipe.server, flight).ok();
+            }
+
+            if let Ok(flight) = testing::emit_flight(&mut pipe.server) {
+                testing::process_flight(&mut pipe.client, flight).ok();
+            }
+        }
+
+        assert!(!pipe.client.is_established());
+
+        // The alert is reported on both sides.
+        assert!(pipe.client.local_tls_alert().is_some());
+        assert_eq!(pipe.server.peer_tls_alert(), pipe.client.local_tls_alert());
+        assert_eq!(pipe.server.local_tls_alert(), None);
+    }
+
+    #[test]
+    fn load_from_der() {
+        let cert = include_bytes!("../examples/cert.der");
+        let key = include_bytes!("../examples/cert.key.der");
+
+        let mut config = Config::new(VERSION_DRAFT17).unwrap();
+        assert_eq!(config.load_cert_chain_from_der(&[]), Err(Error::TlsFail));
+        assert_eq!(config.load_cert_chain_from_der(&cert[..100]),
+                   Err(Error::TlsFail));
+        assert_eq!(config.load_priv_key_from_der(&key[..100]),
+                   Err(Error::TlsFail));
+
+        config.load_cert_chain_from_der(cert).unwrap();
+        config.load_priv_key_from_der(key).unwrap();
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let chain = pipe.client.peer_cert_chain().unwrap();
+        assert_eq!(chain, vec![&cert[..]]);
+    }
+
+    #[test]
+    fn peer_cert_chain() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.client.peer_cert_chain(), None);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let chain = pipe.client.peer_cert_chain().unwrap();
+        assert!(!chain.is_empty());
+
+        // Each certificate is a DER-encoded SEQUENCE.
+        for cert in chain {
+            assert_eq!(cert[0], 0x30);
+        }
+
+        // The client didn't present a certificate.
+        assert_eq!(pipe.server.peer_cert_chain(), None);
+    }
+
+    #[test]
+    fn ocsp_and_sct() {
+        let ocsp = [0x30, 0x03, 0x0a, 0x01, 0x00];
+        let scts = [0x00, 0x04, 0x00, 0x02, 0xab, 0xcd];
+
+        let mut server_config = Config::new(VERSION_DRAFT17).unwrap();
+        server_config.load_cert_chain_from_pem_file("examples/cert.crt")
+                     .unwrap();
+        server_config.load_priv_key_from_pem_file("examples/cert.key")
+                     .unwrap();
+        server_config.set_ocsp_response(&ocsp).unwrap();
+        server_config.set_signed_cert_timestamps(&scts).unwrap();
+
+        // The SCT list must be well-formed.
+        assert_eq!(server_config.set_signed_cert_timestamps(&[0x00]),
+                   Err(Error::TlsFail));
+
+        let mut client_config = Config::new(VERSION_DRAFT17).unwrap();
+        client_config.verify_peer(false);
+
+        // Nothing is sent unless the client asks for it.
+        let mut pipe =
+            testing::Pipe::with_client_and_server_config(&mut client_config,
+                                                         &mut server_config)
+                .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.client.ocsp_response(), None);
+        assert_eq!(pipe.client.signed_cert_timestamps(), None);
+
+        client_config.enable_ocsp_stapling();
+        client_config.enable_signed_cert_timestamps();
+
+        let mut pipe =
+            testing::Pipe::with_client_and_server_config(&mut client_config,
+                                                         &mut server_config)
+                .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.client.ocsp_response(), Some(&ocsp[..]));
+        assert_eq!(pipe.client.signed_cert_timestamps(), Some(&scts[..]));
+    }
+
+    #[test]
+    fn set_trace_id() {
+        let mut conn = create_conn(false);
+
+        assert_eq!(conn.trace_id().len(), 32);
+
+        conn.set_trace_id("request-1234");
+        assert_eq!(conn.trace_id(), "request-1234");
+    }
+
+    #[derive(Default)]
+    struct CountingMetrics {
+        sent: sync::atomic::AtomicUsize,
+        received: sync::atomic::AtomicUsize,
+        handshakes: sync::atomic::AtomicUsize,
+        streams: sync::atomic::AtomicUsize,
+    }
+
+    impl Metrics for CountingMetrics {
+        fn packet_sent(&self, _len: usize) {
+            self.sent.fetch_add(1, sync::atomic::Ordering::SeqCst);
+        }
+
+        fn packet_received(&self, _len: usize) {
+            self.received.fetch_add(1, sync::atomic::Ordering::SeqCst);
+        }
+
+        fn handshake_completed(&self) {
+            self.handshakes.fetch_add(1, sync::atomic::Ordering::SeqCst);
+        }
+
+        fn stream_opened(&self) {
+            self.streams.fetch_add(1, sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn metrics() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let m = sync::Arc::new(CountingMetrics::default());
+
+        cln.metrics = Some(m.clone());
+        srv.metrics = Some(m.clone());
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        assert_eq!(m.handshakes.load(sync::atomic::Ordering::SeqCst), 2);
+
+        assert!(cln.stream_send(4, b"hello", true).is_ok());
+        len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        // Both the client and the server opened the stream.
+        assert_eq!(m.streams.load(sync::atomic::Ordering::SeqCst), 2);
+
+        let stats_sent = cln.stats().sent + srv.stats().sent;
+        assert_eq!(m.sent.load(sync::atomic::Ordering::SeqCst), stats_sent);
+        assert!(m.received.load(sync::atomic::Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn poll_events() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        assert_eq!(cln.poll(), Some(Event::HandshakeCompleted));
+        assert_eq!(cln.poll(), None);
+
+        assert_eq!(srv.poll(), Some(Event::HandshakeCompleted));
+        assert_eq!(srv.poll(), None);
+
+        assert!(cln.stream_send(4, b"hello", false).is_ok());
+        len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        assert!(cln.stream_send(4, b"world", true).is_ok());
+        len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        // The stream is only reported once while the event is pending.
+        assert_eq!(srv.poll(), Some(Event::StreamReadable(4)));
+        assert_eq!(srv.poll(), None);
+
+        assert!(srv.send_new_token(b"token").is_ok());
+        len = srv.send(&mut buf).unwrap();
+        assert_eq!(cln.recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(cln.poll(), Some(Event::NewToken(b"token".to_vec())));
+        assert_eq!(cln.poll(), None);
+
+        cln.closed = true;
+
+        assert_eq!(cln.poll(), Some(Event::ConnectionClosed));
+        assert_eq!(cln.poll(), None);
+    }
+
+    #[test]
+    fn event_queue_bounded() {
+        let mut events = EventQueue::default();
+
+        events.push(Event::StreamReadable(4));
+        events.push(Event::StreamReadable(4));
+
+        assert_eq!(events.pop(), Some(Event::StreamReadable(4)));
+        assert_eq!(events.pop(), None);
+
+        // The event can be queued again once it was delivered.
+        events.push(Event::StreamReadable(4));
+        assert_eq!(events.pop(), Some(Event::StreamReadable(4)));
+
+        for i in 0..MAX_EVENTS as u64 * 2 {
+            events.push(Event::StreamReadable(i * 4));
+        }
+
+        assert_eq!(events.queue.len(), MAX_EVENTS);
+        assert_eq!(events.queued.len(), MAX_EVENTS);
+        assert_eq!(events.pop(), Some(Event::StreamReadable(0)));
+    }
+
+    #[test]
+    fn stream_frames_fill_packet() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
+
+        // Data from multiple streams is coalesced in a single packet.
+        assert!(cln.stream_send(4, b"hello", false).is_ok());
+        assert!(cln.stream_send(8, b"world", false).is_ok());
+        len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        let mut readable: Vec<u64> = srv.readable().collect();
+        readable.sort();
+        assert_eq!(readable, vec![4, 8]);
+
+        // Large writes are split across packets that respect the maximum
+        // packet size.
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        assert!(cln.stream_send(0, &data, true).is_ok());
+
+        let mut pkts = 0;
+
+        loop {
+            let len = match cln.send(&mut buf) {
+                Ok(v) => v,
+
+                Err(Error::Done) => break,
+
+                Err(e) => panic!("SEND FAILED: {:?}", e),
+            };
+
+            assert!(len <= DEFAULT_MAX_SEND_UDP_PAYLOAD_SIZE);
+            assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+            pkts += 1;
+        }
+
+        assert!(pkts > 4);
+
+        let mut recv = Vec::new();
+
+        while let Ok(b) = srv.stream_recv(0, std::usize::MAX) {
+            if b.is_empty() {
+                break;
+            }
+
+            recv.extend_from_slice(&b);
+        }
+
+        assert_eq!(recv, data);
+    }
+
+    #[test]
+    fn accepted_streams() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+
+        let mut cln = create_conn_with_config(&mut config, false);
+        let mut srv = create_conn_with_config(&mut config, true);
+
+        let mut len = cln.send(&mut buf).unwrap();
+
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
         }
+
+        assert_eq!(srv.accepted().next(), None);
+
+        assert!(cln.stream_send(4, b"hello", false).is_ok());
+        assert!(cln.stream_send(8, b"hello", false).is_ok());
+        len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        let mut accepted: Vec<u64> = srv.accepted().collect();
+        accepted.sort();
+        assert_eq!(accepted, vec![4, 8]);
+
+        // Streams are only reported once.
+        assert!(cln.stream_send(4, b"world", false).is_ok());
+        len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Ok(len));
+
+        assert_eq!(srv.accepted().next(), None);
+
+        // Locally-initiated streams are not reported.
+        assert!(srv.stream_send(1, b"hello", false).is_ok());
+        assert_eq!(srv.accepted().next(), None);
+        assert_eq!(cln.accepted().next(), None);
     }
-}
 
-impl TransportParams {
-    fn decode(buf: &mut [u8], _version: u32, is_server: bool)
-                                                -> Result<TransportParams> {
-        let mut b = octets::Octets::with_slice(buf);
+    #[test]
+    fn idle_memory_reclamation() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let idle = pipe.client.stats().mem_usage;
+        assert!(idle > 0);
+
+        // Buffered data is accounted for.
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], true), Ok(5000));
+        assert!(pipe.client.stats().mem_usage >= idle + 5000);
+
+        assert_eq!(pipe.server.stream_send(5, &buf[..5000], true), Ok(5000));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Both endpoints had 1-RTT packets acknowledged, so handshake state
+        // is gone.
+        assert!(pipe.client.handshake.crypto_open.is_none());
+        assert!(pipe.server.handshake.crypto_open.is_none());
+
+        // Once data is acknowledged and read, the memory is released.
+        while pipe.server.stream_recv(4, buf.len()).is_ok() {}
+        assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
+
+        assert!(pipe.client.stats().mem_usage < idle + 5000);
+        assert!(pipe.server.stats().mem_usage < idle + 5000);
+    }
+
+    #[test]
+    fn max_connection_memory() {
+        let mut buf = [0; 65535];
+
+        let mut client_config = testing::default_config().unwrap();
+
+        let mut server_config = testing::default_config().unwrap();
+        server_config.set_max_connection_memory(20000);
+
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config, &mut server_config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Writes are truncated to fit in the budget, and refused once it's
+        // exhausted, without closing the connection.
+        let first = pipe.server.stream_send(1, &buf[..15000], false).unwrap();
+        let second = match pipe.server.stream_send(5, &buf[..15000], false) {
+            Ok(v) => v,
+
+            Err(e) => {
+                assert_eq!(e, Error::MemoryLimit);
+                0
+            },
+        };
+
+        assert!(first + second < 20000);
+        assert_eq!(pipe.server.stream_send(9, &buf[..15000], false),
+                   Err(Error::MemoryLimit));
+        assert!(!pipe.server.is_draining());
+
+        assert_eq!(pipe.client.stream_send(4, &buf[..15000], false),
+                   Ok(15000));
+        assert_eq!(pipe.client.stream_send(8, &buf[..15000], false),
+                   Ok(15000));
+
+        // The server can't buffer all the data the client sent, so it
+        // closes the connection.
+        assert_eq!(pipe.advance(), Err(Error::MemoryLimit));
+
+        assert!(pipe.server.send(&mut buf).is_ok());
+        assert!(pipe.server.is_draining());
+    }
+
+    #[test]
+    fn retry_policy() {
+        let mut config = testing::default_config().unwrap();
+
+        assert!(!config.retry_required());
+
+        config.set_retry_threshold(1);
+        assert!(!config.retry_required());
+
+        // Only server connections count as half-open.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(config.half_open_handshakes(), 1);
+        assert!(config.retry_required());
+
+        let other = accept(&config.new_cid(), None, &mut config).unwrap();
+        assert_eq!(config.half_open_handshakes(), 2);
+
+        drop(other);
+        assert_eq!(config.half_open_handshakes(), 1);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(config.half_open_handshakes(), 0);
+        assert!(!config.retry_required());
+
+        config.set_retry_policy(|_| true);
+        assert!(config.retry_required());
+    }
+
+    #[test]
+    fn max_half_open_handshakes() {
+        let mut config = testing::default_config().unwrap();
+        config.set_max_half_open_handshakes(2);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let other = accept(&config.new_cid(), None, &mut config).unwrap();
+        assert_eq!(config.half_open_handshakes(), 2);
+
+        assert_eq!(accept(&config.new_cid(), None, &mut config).err(),
+                   Some(Error::HandshakeLimit));
+
+        // Clients are not limited.
+        assert!(connect(None, &config.new_cid(), &mut config).is_ok());
+
+        // Completed handshakes free up a slot.
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert!(accept(&config.new_cid(), None, &mut config).is_ok());
+
+        // As do dropped connections.
+        drop(other);
+        assert!(accept(&config.new_cid(), None, &mut config).is_ok());
+
+        config.set_max_half_open_handshakes(0);
+        assert!(accept(&config.new_cid(), None, &mut config).is_ok());
+    }
+
+    #[test]
+    fn nat_rebinding() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let addrs: Vec<net::SocketAddr> = ["127.0.0.1:1000", "127.0.0.1:2000",
+                                           "127.0.0.1:3000"]
+            .iter().map(|a| a.parse().unwrap()).collect();
+
+        let send_from = |pipe: &mut testing::Pipe, addr| {
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+
+            let info = RecvInfo {
+                ecn: Ecn::NotEct,
+                from: Some(addr),
+                to: None,
+            };
+
+            for mut pkt in testing::emit_flight(&mut pipe.client).unwrap() {
+                let len = pkt.len();
+                assert_eq!(pipe.server.recv_with_info(&mut pkt, info), Ok(len));
+            }
+        };
+
+        send_from(&mut pipe, addrs[0]);
+        assert_eq!(pipe.server.peer_addr(), Some(addrs[0]));
+        assert_eq!(pipe.server.stats().rebinds, 0);
+
+        send_from(&mut pipe, addrs[1]);
+        assert_eq!(pipe.server.peer_addr(), Some(addrs[1]));
+        assert_eq!(pipe.server.stats().rebinds, 1);
+
+        let events: Vec<Event> = std::iter::from_fn(|| pipe.server.poll())
+                                           .collect();
+        assert!(events.contains(&Event::PeerAddressChanged(addrs[1])));
+
+        // The new address is validated with PATH_CHALLENGE.
+        assert!(pipe.server.path_challenge.is_some());
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.validated_addr, Some(addrs[1]));
+        assert!(pipe.server.path_validation_timer.is_none());
+
+        // Another change right away isn't validated, and the address falls
+        // back to the validated one once the timer expires.
+        send_from(&mut pipe, addrs[2]);
+        assert_eq!(pipe.server.peer_addr(), Some(addrs[2]));
+        assert!(pipe.server.path_challenge.is_none());
+
+        let timer = pipe.server.path_validation_timer.unwrap();
+        pipe.server.on_timeout_at(timer);
+
+        assert_eq!(pipe.server.peer_addr(), Some(addrs[1]));
+        assert_eq!(pipe.server.stats().rebinds, 2);
+    }
+
+    #[test]
+    fn path_stats() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.server.paths().is_empty());
+
+        let a: net::SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let b: net::SocketAddr = "127.0.0.1:2000".parse().unwrap();
+
+        let send_from = |pipe: &mut testing::Pipe, addr| {
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+
+            let info = RecvInfo {
+                ecn: Ecn::NotEct,
+                from: Some(addr),
+                to: None,
+            };
+
+            for mut pkt in testing::emit_flight(&mut pipe.client).unwrap() {
+                let len = pkt.len();
+                assert_eq!(pipe.server.recv_with_info(&mut pkt, info), Ok(len));
+            }
+        };
+
+        send_from(&mut pipe, a);
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let paths = pipe.server.paths();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].peer_addr, a);
+        assert_eq!(paths[0].state, PathState::Validated);
+        assert!(paths[0].active);
+        assert!(paths[0].recv_bytes > 0);
+        assert!(paths[0].sent_bytes > 0);
+
+        send_from(&mut pipe, b);
+
+        let paths = pipe.server.paths();
+        assert_eq!(paths.len(), 2);
+        assert!(!paths[0].active);
+        assert_eq!(paths[1].peer_addr, b);
+        assert_eq!(paths[1].state, PathState::Validating);
+        assert!(paths[1].active);
+        assert_eq!(paths[1].sent_bytes, 0);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let paths = pipe.server.paths();
+        assert_eq!(paths[1].state, PathState::Validated);
+        assert!(paths[1].sent_bytes > 0);
+    }
+
+    #[test]
+    fn local_addr_change() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let peer: net::SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let a: net::SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let b: net::SocketAddr = "10.0.0.2:443".parse().unwrap();
+
+        let send_to = |pipe: &mut testing::Pipe, addr| {
+            assert_eq!(pipe.client.stream_send(4, b"a", false), Ok(1));
+
+            let info = RecvInfo {
+                ecn: Ecn::NotEct,
+                from: Some(peer),
+                to: Some(addr),
+            };
+
+            for mut pkt in testing::emit_flight(&mut pipe.client).unwrap() {
+                let len = pkt.len();
+                assert_eq!(pipe.server.recv_with_info(&mut pkt, info), Ok(len));
+            }
+        };
+
+        send_to(&mut pipe, a);
+        assert_eq!(pipe.server.local_addr(), Some(a));
+
+        let mut buf = [0; 1500];
+
+        assert_eq!(pipe.server.stream_send(4, b"b", false), Ok(1));
+        let (_, info) = pipe.server.send_with_info(&mut buf).unwrap();
+        assert_eq!(info.from, Some(a));
+        assert_eq!(info.to, Some(peer));
+
+        // The peer switching to another local address isn't a rebind, and
+        // the peer's address doesn't need to be validated again.
+        send_to(&mut pipe, b);
+        assert_eq!(pipe.server.local_addr(), Some(b));
+        assert_eq!(pipe.server.peer_addr(), Some(peer));
+        assert_eq!(pipe.server.stats().rebinds, 0);
+        assert!(pipe.server.path_challenge.is_none());
+
+        let paths = pipe.server.paths();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].local_addr, Some(a));
+        assert!(!paths[0].active);
+        assert_eq!(paths[1].local_addr, Some(b));
+        assert_eq!(paths[1].peer_addr, peer);
+        assert_eq!(paths[1].state, PathState::Validated);
+        assert!(paths[1].active);
+
+        assert_eq!(pipe.server.stream_send(4, b"b", false), Ok(1));
+        let (_, info) = pipe.server.send_with_info(&mut buf).unwrap();
+        assert_eq!(info.from, Some(b));
+    }
+
+    #[test]
+    fn duplicate_packets() {
+        let buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+
+        // Deliver every datagram twice.
+        assert_eq!(pipe.advance_with(|flight| {
+            let copies = flight.clone();
+            flight.extend(copies);
+        }), Ok(()));
+
+        assert!(pipe.server.stats().dup_recv > 0);
+
+        // The data was only buffered once.
+        let data = pipe.server.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(&data[..], b"hello");
+        assert!(data.fin());
+
+        assert_eq!(pipe.server.stream_recv(4, buf.len()), Err(Error::Done));
+    }
+
+    #[test]
+    fn stream_reorder_window() {
+        let buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_stream_reorder_window(1000);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], true), Ok(5000));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert!(flight.len() > 2);
+
+        let largest_rx_pkt_num = pipe.server.application.largest_rx_pkt_num;
+
+        // The first packet is delayed, so the data in the following ones is
+        // past the window, and is dropped without being buffered or
+        // acknowledged.
+        for pkt in &flight[1..] {
+            assert_eq!(pipe.server.recv(&mut pkt.clone()), Ok(pkt.len()));
+        }
+
+        assert_eq!(pipe.server.application.largest_rx_pkt_num,
+                   largest_rx_pkt_num);
+
+        assert_eq!(pipe.server.stream_recv(4, buf.len()), Err(Error::Done));
+
+        assert_eq!(pipe.server.recv(&mut flight[0].clone()),
+                   Ok(flight[0].len()));
+
+        // Once retransmitted, the data is contiguous with what was received.
+        for pkt in &flight[1..] {
+            assert_eq!(pipe.server.recv(&mut pkt.clone()), Ok(pkt.len()));
+        }
+
+        let data = pipe.server.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(data.len(), 5000);
+        assert!(data.fin());
+    }
+
+    #[test]
+    fn stream_reorder_window_other_frames() {
+        let buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_stream_reorder_window(1000);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The client's first flight is delayed.
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], false), Ok(5000));
+
+        let delayed = testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert_eq!(pipe.server.stream_send(1, b"hello", false), Ok(5));
+
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert_eq!(testing::process_flight(&mut pipe.client, flight), Ok(()));
+
+        assert!(pipe.server.application.flight.ack_eliciting_in_flight());
+
+        // The ACK for the server's data is sent along with stream data past
+        // the server's reorder window.
+        assert_eq!(pipe.client.stream_send(4, b"world", true), Ok(5));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(flight.len(), 1);
+
+        let largest_rx_pkt_num = pipe.server.application.largest_rx_pkt_num;
+
+        assert_eq!(pipe.server.recv(&mut flight[0].clone()),
+                   Ok(flight[0].len()));
+
+        // The ACK was processed, but the packet wasn't recorded as received.
+        assert!(!pipe.server.application.flight.ack_eliciting_in_flight());
+
+        assert_eq!(pipe.server.application.largest_rx_pkt_num,
+                   largest_rx_pkt_num);
+
+        assert_eq!(testing::process_flight(&mut pipe.server, delayed), Ok(()));
+
+        let data = pipe.server.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(data.len(), 5000);
+        assert!(!data.fin());
+    }
+
+    #[test]
+    fn stream_late_retransmission() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        let first = testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert_eq!(pipe.client.stream_send(8, b"world", true), Ok(5));
+        let second = testing::emit_flight(&mut pipe.client).unwrap();
+
+        // The first packet is reordered, so the server immediately ACKs the
+        // second one without it.
+        assert_eq!(testing::process_flight(&mut pipe.server, second), Ok(()));
+        let ack = testing::emit_flight(&mut pipe.server).unwrap();
+
+        // The server's ACK for the first packet is lost.
+        assert_eq!(testing::process_flight(&mut pipe.server, first), Ok(()));
+        testing::emit_flight(&mut pipe.server).unwrap();
+
+        assert_eq!(pipe.server.stream_recv(4, buf.len()).map(|b| b.len()),
+                   Ok(5));
+        assert_eq!(pipe.server.rx_data, 10);
+
+        // The client declares the first packet lost, and retransmits it.
+        assert_eq!(testing::process_flight(&mut pipe.client, ack), Ok(()));
+        pipe.client.on_timeout_at(time::Instant::now() +
+                                  time::Duration::from_secs(1));
+
+        let len = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.server.recv(&mut buf[..len]), Ok(len));
+
+        // Data that was already received doesn't count against flow
+        // control again.
+        assert_eq!(pipe.server.rx_data, 10);
+        assert_eq!(pipe.server.stream_recv(4, buf.len()), Err(Error::Done));
+    }
+
+    #[test]
+    fn padding_policy() {
+        let mut buf = [0; 65535];
+
+        let mut config = testing::default_config().unwrap();
+        config.set_padding_policy(PaddingPolicy {
+            handshake: true,
+            random: None,
+            bucket: 256,
+        }).unwrap();
+
+        // The random padding bounds must be ordered.
+        assert_eq!(config.set_padding_policy(PaddingPolicy {
+            random: Some((100, 10)),
+            ..Default::default()
+        }), Err(Error::InvalidState));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        // Handshake packets fill the whole datagram, minus the space
+        // reserved for the length field.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        assert!(flight.iter().all(|pkt| pkt.len() >= 1200 - 4));
+        testing::process_flight(&mut pipe.client, flight).unwrap();
+
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let padding = pipe.client.stats().padding;
+        assert!(padding > 0);
+
+        // 1-RTT packets are padded to the bucket size.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.client.send(&mut buf), Ok(256));
+        assert!(pipe.client.stats().padding > padding);
+    }
+
+    #[test]
+    fn stream_send_flow_control() {
+        let buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Only the data covered by the peer's credit is accepted, and the
+        // FIN is dropped along with the rest.
+        assert_eq!(pipe.client.stream_send(4, &buf[..20000], true),
+                   Ok(15000));
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], true),
+                   Err(Error::Done));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let data = pipe.server.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(data.len(), 15000);
+        assert!(!data.fin());
+
+        // Reading the data gives the client more credit, so the rest of it
+        // can be written.
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.poll(), Some(Event::HandshakeCompleted));
+        assert_eq!(pipe.client.poll(), Some(Event::StreamWritable(4)));
+
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], true), Ok(5000));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let data = pipe.server.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(data.len(), 5000);
+        assert!(data.fin());
+    }
+
+    #[test]
+    fn stream_splice() {
+        let buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.server.stream_splice(4, 4, buf.len()),
+                   Err(Error::InvalidStreamState));
+
+        assert_eq!(pipe.client.stream_send(4, &buf[..20000], true),
+                   Ok(15000));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let max_rx_data = pipe.server.max_rx_data;
+
+        // Echo the data back on the same stream, limited by the length.
+        assert_eq!(pipe.server.stream_splice(4, 4, 10000), Ok(10000));
+        assert_eq!(pipe.server.stream_splice(4, 4, buf.len()), Ok(5000));
+        assert_eq!(pipe.server.stream_splice(4, 4, buf.len()),
+                   Err(Error::Done));
+
+        // Both reads are credited to the connection.
+        assert_eq!(pipe.server.new_max_rx_data, max_rx_data + 15000);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let data = pipe.client.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(data.len(), 15000);
+        assert!(!data.fin());
+
+        // The rest of the data and the FIN are forwarded once the server's
+        // read gave the client more credit.
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], true), Ok(5000));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_splice(4, 4, buf.len()), Ok(5000));
+        assert!(pipe.server.stream_finished(4));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let data = pipe.client.stream_recv(4, buf.len()).unwrap();
+        assert_eq!(data.len(), 5000);
+        assert!(data.fin());
+    }
+
+    #[test]
+    fn stream_state_queries() {
+        let buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(!pipe.server.stream_finished(4));
+        assert!(!pipe.server.stream_readable(4));
+        assert_eq!(pipe.client.stream_writable(4, 1),
+                   Err(Error::InvalidStreamState));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.client.stream_writable(4, 14995), Ok(true));
+        assert_eq!(pipe.client.stream_writable(4, 14996), Ok(false));
+
+        // The connection's credit and memory budget are accounted for too.
+        pipe.client.max_tx_data = 100;
+        assert_eq!(pipe.client.stream_writable(4, 100), Ok(true));
+        assert_eq!(pipe.client.stream_writable(4, 101), Ok(false));
+        pipe.client.max_tx_data = 30000;
+
+        pipe.client.max_conn_mem = Some(0);
+        assert_eq!(pipe.client.stream_writable(4, 1), Ok(false));
+        pipe.client.max_conn_mem = None;
+
+        // The server can't write to the client's unidirectional streams.
+        assert_eq!(pipe.server.stream_writable(2, 1),
+                   Err(Error::InvalidStreamState));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(pipe.server.stream_readable(4));
+        assert!(!pipe.server.stream_finished(4));
+
+        assert!(pipe.server.stream_recv(4, buf.len()).is_ok());
+
+        assert!(!pipe.server.stream_readable(4));
+        assert!(pipe.server.stream_finished(4));
+    }
+
+    #[test]
+    fn concurrency_introspection() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.peer_streams_left_bidi(), 3);
+        assert_eq!(pipe.client.peer_streams_left_uni(), 3);
+        assert_eq!(pipe.server.local_streams_left_bidi(), 3);
+        assert_eq!(pipe.client.tx_credit(), 30000);
+        assert_eq!(pipe.server.rx_credit(), 30000);
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(8, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.stream_send(2, b"hello", false), Ok(5));
+
+        // The peer's stream limit is enforced.
+        assert_eq!(pipe.client.stream_send(12, b"hello", false),
+                   Err(Error::StreamLimit));
+
+        assert_eq!(pipe.client.peer_streams_left_bidi(), 0);
+        assert_eq!(pipe.client.peer_streams_left_uni(), 2);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.tx_credit(), 30000 - 20);
+
+        assert_eq!(pipe.server.local_streams_left_bidi(), 0);
+        assert_eq!(pipe.server.local_streams_left_uni(), 2);
+        assert_eq!(pipe.server.pending_accepted_streams(), 4);
+        assert_eq!(pipe.server.rx_credit(), 30000 - 20);
+
+        assert_eq!(pipe.server.accepted().count(), 4);
+        assert_eq!(pipe.server.pending_accepted_streams(), 0);
+    }
+
+    #[test]
+    fn stream_limit_by_id() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Opening stream 8 implicitly opens streams 0 and 4.
+        assert_eq!(pipe.client.stream_send(8, b"hello", false), Ok(5));
+        assert_eq!(pipe.client.peer_streams_left_bidi(), 0);
+
+        // Implicitly opened streams can still be used.
+        assert_eq!(pipe.client.stream_send(0, b"hello", false), Ok(5));
+
+        assert_eq!(pipe.client.stream_send(12, b"hello", false),
+                   Err(Error::StreamLimit));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.local_streams_left_bidi(), 0);
+
+        // The server enforces the limit on its side too.
+        pipe.client.peer_max_streams_bidi = 4;
+
+        assert_eq!(pipe.client.stream_send(12, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Err(Error::StreamLimit));
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let now = time::Instant::now();
 
-        // TODO: check version
-        let _tp_version = b.get_u32()?;
+        pipe.client.set_rate_limiter(sync::Arc::new(TokenBucket::new(1000,
+                                                                     1000)));
 
-        if !is_server {
-            // Ignore supported versions from server.
-            b.get_bytes_with_u8_length()?;
-        }
+        assert_eq!(pipe.client.stream_send(4, &buf[..5000], false),
+                   Ok(5000));
 
-        let mut tp = TransportParams::default();
+        // A single packet is sent before the bucket is exhausted.
+        assert!(pipe.client.send_at(&mut buf, now).is_ok());
+        assert_eq!(pipe.client.send_at(&mut buf, now), Err(Error::Done));
 
-        let mut params = b.get_bytes_with_u16_length()?;
+        assert!(pipe.client.rate_limit_timer.unwrap() > now);
 
-        while params.cap() > 0 {
-            let id = params.get_u16()?;
+        // Sending resumes once enough tokens were added.
+        let later = now + time::Duration::from_secs(1);
 
-            let mut val = params.get_bytes_with_u16_length()?;
+        pipe.client.on_timeout_at(later);
+        assert!(pipe.client.send_at(&mut buf, later).is_ok());
+    }
 
-            // TODO: forbid duplicated param
+    #[test]
+    fn leased_buffers() {
+        let pool = HeapPool::new(1, 1350);
 
-            match id {
-                0x0000 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-                    tp.original_connection_id = Some(val.to_vec());
-                },
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
 
-                0x0001 => {
-                    tp.idle_timeout = val.get_varint()?;
-                },
+        let buf = pipe.client.send_leased(&pool).unwrap();
+        assert!(!buf.is_empty());
 
-                0x0002 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        // The only buffer is in use.
+        assert_eq!(pipe.client.send_leased(&pool).err(),
+                   Some(Error::BufferTooShort));
 
-                    tp.stateless_reset_token = Some(val.get_bytes(16)?.to_vec());
-                },
+        let len = buf.len();
+        assert_eq!(pipe.server.recv_leased(&pool, buf), Ok(len));
+        assert_eq!(pool.available(), 1);
 
-                0x0003 => {
-                    tp.max_packet_size = val.get_varint()?;
-                },
+        assert!(pipe.server.stream_readable(4));
 
-                0x0004 => {
-                    tp.initial_max_data = val.get_varint()?;
-                },
+        // Buffers are released when there's nothing to send.
+        assert_eq!(pipe.client.send_leased(&pool).err(), Some(Error::Done));
+        assert_eq!(pool.available(), 1);
+    }
 
-                0x0005 => {
-                    tp.initial_max_stream_data_bidi_local = val.get_varint()?;
-                },
+    #[test]
+    fn closing_and_draining() {
+        let mut buf = [0; 65535];
 
-                0x0006 => {
-                    tp.initial_max_stream_data_bidi_remote = val.get_varint()?;
-                },
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
 
-                0x0007 => {
-                    tp.initial_max_stream_data_uni = val.get_varint()?;
-                },
+        let mut len = cln.send(&mut buf).unwrap();
 
-                0x0008 => {
-                    tp.initial_max_streams_bidi = val.get_varint()?;
-                },
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
 
-                0x0009 => {
-                    tp.initial_max_streams_uni = val.get_varint()?;
-                },
+        while cln.send(&mut buf).is_ok() {}
 
-                0x000a => {
-                    tp.ack_delay_exponent = val.get_varint()?;
-                },
+        assert_eq!(srv.close(true, 0x42, b"bye"), Ok(()));
 
-                0x000b => {
-                    tp.max_ack_delay = val.get_varint()?;
-                },
+        let len = srv.send(&mut buf).unwrap();
 
-                0x000c => {
-                    tp.disable_migration = true;
-                },
+        assert!(srv.is_draining());
+        assert!(!srv.is_closed());
+        assert_eq!(srv.close(true, 0x42, b"bye"), Err(Error::Done));
 
-                0x000d => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        // The CONNECTION_CLOSE is not retransmitted unprompted.
+        assert_eq!(srv.send(&mut buf), Err(Error::Done));
 
-                    // TODO: decode preferred_address
-                },
+        // The client enters the draining state, and doesn't send anything.
+        assert_eq!(cln.recv(&mut buf[..len]), Ok(len));
+        assert!(cln.is_draining());
+        assert_eq!(cln.send(&mut buf), Err(Error::Done));
 
-                // Ignore unknown parameters.
-                _ => (),
-            }
-        }
+        // Stray packets received while closing trigger a retransmission.
+        assert_eq!(srv.recv(&mut buf[..100]), Ok(100));
+        assert!(srv.send(&mut buf).is_ok());
+        assert_eq!(srv.send(&mut buf), Err(Error::Done));
 
-        Ok(tp)
+        // Retransmissions are rate-limited.
+        assert_eq!(srv.recv(&mut buf[..100]), Ok(100));
+        assert!(srv.send(&mut buf).is_ok());
+
+        assert_eq!(srv.recv(&mut buf[..100]), Ok(100));
+        assert_eq!(srv.send(&mut buf), Err(Error::Done));
+
+        assert!(srv.timeout().unwrap() <= srv.recovery.pto() * 3);
     }
 
-    fn encode<'a>(tp: &TransportParams, version: u32, is_server: bool,
-                  out: &'a mut [u8]) -> Result<&'a mut [u8]> {
-        let mut params: [u8; 128] = [0; 128];
+    #[test]
+    fn drain() {
+        let buf = [0; 65535];
 
-        let params_len = {
-            let mut b = octets::Octets::with_slice(&mut params);
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            if is_server {
-                if let Some(ref odcid) = tp.original_connection_id {
-                    b.put_u16(0x0000)?;
-                    b.put_u16(odcid.len() as u16)?;
-                    b.put_bytes(&odcid)?;
-                }
-            };
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            if tp.idle_timeout != 0 {
-                b.put_u16(0x0001)?;
-                b.put_u16(octets::varint_len(tp.idle_timeout) as u16)?;
-                b.put_varint(tp.idle_timeout)?;
-            }
+        let now = time::Instant::now();
 
-            if let Some(ref token) = tp.stateless_reset_token {
-                if is_server {
-                    b.put_u16(0x0002)?;
-                    b.put_u16(token.len() as u16)?;
-                    b.put_bytes(&token)?;
-                }
-            }
+        pipe.server.drain(now + time::Duration::from_secs(60));
 
-            if tp.max_packet_size != 0 {
-                b.put_u16(0x0003)?;
-                b.put_u16(octets::varint_len(tp.max_packet_size) as u16)?;
-                b.put_varint(tp.max_packet_size)?;
-            }
+        // Streams opened by the client from now on are refused.
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            if tp.initial_max_data != 0 {
-                b.put_u16(0x0004)?;
-                b.put_u16(octets::varint_len(tp.initial_max_data) as u16)?;
-                b.put_varint(tp.initial_max_data)?;
-            }
+        assert!(!pipe.server.stream_readable(8));
+        assert_eq!(pipe.server.accepted().collect::<Vec<u64>>(), vec![4]);
 
-            if tp.initial_max_stream_data_bidi_local != 0 {
-                b.put_u16(0x0005)?;
-                b.put_u16(octets::varint_len(tp.initial_max_stream_data_bidi_local) as u16)?;
-                b.put_varint(tp.initial_max_stream_data_bidi_local)?;
-            }
+        // The existing stream is still served.
+        assert!(pipe.server.stream_recv(4, buf.len()).is_ok());
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            if tp.initial_max_stream_data_bidi_remote != 0 {
-                b.put_u16(0x0006)?;
-                b.put_u16(octets::varint_len(tp.initial_max_stream_data_bidi_remote) as u16)?;
-                b.put_varint(tp.initial_max_stream_data_bidi_remote)?;
-            }
+        assert!(pipe.client.stream_recv(4, buf.len()).is_ok());
+        assert!(!pipe.server.is_draining());
 
-            if tp.initial_max_stream_data_uni != 0 {
-                b.put_u16(0x0007)?;
-                b.put_u16(octets::varint_len(tp.initial_max_stream_data_uni) as u16)?;
-                b.put_varint(tp.initial_max_stream_data_uni)?;
-            }
+        // Once the response is acknowledged, the server closes.
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            if tp.initial_max_streams_bidi != 0 {
-                b.put_u16(0x0008)?;
-                b.put_u16(octets::varint_len(tp.initial_max_streams_bidi) as u16)?;
-                b.put_varint(tp.initial_max_streams_bidi)?;
-            }
+        assert!(pipe.server.is_draining());
+        assert_eq!(pipe.client.peer_error, Some(0x0));
 
-            if tp.initial_max_streams_uni != 0 {
-                b.put_u16(0x0009)?;
-                b.put_u16(octets::varint_len(tp.initial_max_streams_uni) as u16)?;
-                b.put_varint(tp.initial_max_streams_uni)?;
-            }
+        // Streams that aren't complete by the deadline are cut short.
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            if tp.ack_delay_exponent != 0 {
-                b.put_u16(0x000a)?;
-                b.put_u16(octets::varint_len(tp.ack_delay_exponent) as u16)?;
-                b.put_varint(tp.ack_delay_exponent)?;
-            }
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            if tp.max_ack_delay != 0 {
-                b.put_u16(0x000b)?;
-                b.put_u16(octets::varint_len(tp.max_ack_delay) as u16)?;
-                b.put_varint(tp.max_ack_delay)?;
-            }
+        pipe.server.drain(now);
+        assert!(pipe.server.timeout_instant().is_some());
 
-            if tp.disable_migration {
-                b.put_u16(0x000c)?;
-                b.put_u16(0)?;
-            }
+        pipe.server.on_timeout_at(now);
+        assert_eq!(pipe.advance(), Ok(()));
 
-            // TODO: encode preferred_address
+        assert!(pipe.server.is_draining());
+        assert_eq!(pipe.client.peer_error, Some(0x0));
+    }
 
-            b.off()
-        };
+    #[test]
+    fn forbidden_frame() {
+        let mut buf = [0; 65535];
 
-        let out_len = {
-            let mut b = octets::Octets::with_slice(out);
+        let mut cln = create_conn(false);
+        let mut srv = create_conn(true);
 
-            b.put_u32(version)?;
+        let mut len = cln.send(&mut buf).unwrap();
 
-            if is_server {
-                b.put_u8(mem::size_of::<u32>() as u8)?;
-                b.put_u32(version)?;
-            };
+        while !cln.is_established() || !srv.is_established() {
+            len = recv_send(&mut srv, &mut buf, len);
+            len = recv_send(&mut cln, &mut buf, len);
+        }
 
-            b.put_u16(params_len as u16)?;
-            b.put_bytes(&params[..params_len])?;
+        while cln.send(&mut buf).is_ok() {}
 
-            b.off()
-        };
+        // Clients are not allowed to send NEW_TOKEN frames.
+        cln.new_tokens.push(b"token".to_vec());
 
-        Ok(&mut out[..out_len])
-    }
-}
+        let len = cln.send(&mut buf).unwrap();
+        assert_eq!(srv.recv(&mut buf[..len]), Err(Error::InvalidPacket));
 
+        assert_eq!(srv.error, Some(0xa));
+        assert_eq!(srv.error_frame_type, 0x07);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // The application can't override the error.
+        assert_eq!(srv.close(false, 0x0, b""), Err(Error::Done));
+
+        let len = srv.send(&mut buf).unwrap();
+        assert!(srv.is_draining());
+
+        assert_eq!(cln.recv(&mut buf[..len]), Ok(len));
+        assert!(cln.is_draining());
+    }
 
     #[test]
-    fn transport_params() {
-        let tp = TransportParams {
-            original_connection_id: None,
-            idle_timeout: 30,
-            stateless_reset_token: Some(vec![0xba; 16]),
-            max_packet_size: 23_421,
-            initial_max_data: 424_645_563,
-            initial_max_stream_data_bidi_local: 154_323_123,
-            initial_max_stream_data_bidi_remote: 6_587_456,
-            initial_max_stream_data_uni: 2_461_234,
-            initial_max_streams_bidi: 12_231,
-            initial_max_streams_uni: 18_473,
-            ack_delay_exponent: 123,
-            max_ack_delay: 1234,
-            disable_migration: true,
-        };
+    fn max_packet_size() {
+        let mut config = Config::new(VERSION_DRAFT17).unwrap();
 
-        let mut raw_params: [u8; 256] = [42; 256];
-        let mut raw_params = TransportParams::encode(&tp, VERSION_DRAFT17, true,
-                                              &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 106);
+        config.set_max_packet_size(1199);
+        assert_eq!(config.local_transport_params.max_packet_size, 1200);
 
-        let new_tp = TransportParams::decode(&mut raw_params, VERSION_DRAFT17,
-                                             false).unwrap();
+        config.set_max_packet_size(1200);
+        assert_eq!(config.local_transport_params.max_packet_size, 1200);
 
-        assert_eq!(new_tp, tp);
+        config.set_max_packet_size(1201);
+        assert_eq!(config.local_transport_params.max_packet_size, 1201);
+
+        config.set_max_packet_size(0);
+        assert_eq!(config.local_transport_params.max_packet_size, 1200);
     }
 
-    fn create_conn(is_server: bool) -> Box<Connection> {
-        let mut scid: [u8; 16] = [0; 16];
-        rand::rand_bytes(&mut scid[..]);
+    #[test]
+    fn handshake_timeout() {
+        let mut buf = [0; 65535];
 
         let mut config = Config::new(VERSION_DRAFT17).unwrap();
-        config.load_cert_chain_from_pem_file("examples/cert.crt").unwrap();
-        config.load_priv_key_from_pem_file("examples/cert.key").unwrap();
-        config.verify_peer(false);
+        config.set_handshake_timeout(1);
 
-        Connection::new(&scid, None, &mut config, is_server).unwrap()
+        let mut cln = connect(None, &[0xba; 16], &mut config).unwrap();
+
+        cln.send(&mut buf).unwrap();
+
+        assert!(cln.timeout().unwrap() <= time::Duration::from_millis(1));
+
+        std::thread::sleep(time::Duration::from_millis(2));
+
+        cln.on_timeout();
+
+        assert!(cln.is_closed());
+        assert_eq!(cln.timeout(), None);
+        assert_eq!(cln.send(&mut buf), Err(Error::HandshakeTimeout));
+        assert_eq!(cln.recv(&mut buf), Err(Error::HandshakeTimeout));
     }
 
-    fn recv_send(conn: &mut Connection, buf: &mut [u8], len: usize) -> usize {
-        let mut left = len;
+    #[test]
+    fn simulated_clock() {
+        let mut buf = [0; 65535];
 
-        while left > 0 {
-            let read = conn.recv(&mut buf[len - left..len]).unwrap();
+        let mut config = Config::new(VERSION_DRAFT17).unwrap();
+        config.set_idle_timeout(30);
 
-            left -= read;
-        }
+        let mut cln = connect(None, &[0xba; 16], &mut config).unwrap();
 
-        let mut off = 0;
+        let start = time::Instant::now();
 
-        while off < buf.len() {
-            let write = match conn.send(&mut buf[off..]) {
-                Ok(v)   => v,
+        cln.send_at(&mut buf, start).unwrap();
 
-                Err(Error::Done) => { break; },
+        // The server never replies, so the loss detection timer fires at
+        // the simulated time, triggering a retransmission of the Initial.
+        let timeout = cln.timeout_instant().unwrap();
+        assert!(timeout > start);
+        assert_eq!(cln.stats().sent, 1);
 
-                Err(e)  => panic!("SEND FAILED: {:?}", e),
-            };
+        cln.on_timeout_at(timeout);
+        assert!(cln.send_at(&mut buf, timeout).is_ok());
+        assert_eq!(cln.stats().sent, 2);
 
-            off += write;
-        }
+        // Each retransmission backs off the timer.
+        let next = cln.timeout_instant().unwrap();
+        assert!(next.duration_since(timeout) > timeout.duration_since(start));
 
-        off
+        assert!(!cln.is_closed());
     }
 
     #[test]
-    fn self_handshake() {
-        let mut buf = [0; 65535];
+    fn cid_generation() {
+        let mut config = Config::new(VERSION_DRAFT17).unwrap();
 
-        let mut cln = create_conn(false);
-        let mut srv = create_conn(true);
+        assert_eq!(config.new_cid().len(), DEFAULT_CONN_ID_LEN);
 
-        let mut len = cln.send(&mut buf).unwrap();
+        assert_eq!(config.set_cid_len(2), Err(Error::InvalidState));
+        assert_eq!(config.set_cid_len(MAX_CONN_ID_LEN + 1),
+                   Err(Error::InvalidState));
 
-        while !cln.is_established() && !srv.is_established() {
-            len = recv_send(&mut srv, &mut buf, len);
-            len = recv_send(&mut cln, &mut buf, len);
-        }
+        assert_eq!(config.set_cid_len(8), Ok(()));
+        assert_eq!(config.cid_len(), 8);
 
-        assert!(true);
+        config.set_cid_generator(|cid| {
+            for (i, b) in cid.iter_mut().enumerate() {
+                *b = i as u8;
+            }
+        });
+
+        let scid = config.new_cid();
+        assert_eq!(scid, [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        let conn = accept(&scid, None, &mut config).unwrap();
+        assert_eq!(conn.source_id(), &scid[..]);
+
+        assert!(accept(&[0; MAX_CONN_ID_LEN + 1], None, &mut config).is_err());
     }
 }
 
+pub use crate::pktbuf::BufferPool;
+pub use crate::pktbuf::HeapBuf;
+pub use crate::pktbuf::HeapPool;
+pub use crate::pktbuf::PacketBuf;
+pub use crate::ratelimit::RateLimiter;
+pub use crate::ratelimit::TokenBucket;
 pub use crate::stream::RangeBuf;
 pub use crate::stream::Readable;
+pub use crate::stream::Accepted;
 pub use crate::packet::Header;
 pub use crate::packet::Type;
 
+pub mod altsvc;
 mod crypto;
 mod ffi;
 mod frame;
-mod octets;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod lb;
+pub mod octets;
 mod packet;
+mod pktbuf;
+pub mod race;
 mod rand;
 mod ranges;
+pub mod ratelimit;
 mod recovery;
+pub mod scheduler;
 mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod tls;
@@ -25,7 +25,17 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-/// Zero-copy abstraction for parsing and constructing network packets.
+//! Zero-copy abstraction for parsing and constructing network packets.
+//!
+//! [`Octets`] provides read-only access to a borrowed buffer, while
+//! [`OctetsMut`] additionally allows writing to it. Both keep track of an
+//! offset that advances as bytes are read or written, and all operations are
+//! bounds-checked: reading or writing past the end of the buffer returns
+//! [`BufferTooShort`] instead of panicking.
+//!
+//! [`Octets`]: struct.Octets.html
+//! [`OctetsMut`]: struct.OctetsMut.html
+//! [`BufferTooShort`]: ../enum.Error.html#variant.BufferTooShort
 
 use std::mem;
 use std::ptr;
@@ -63,6 +73,29 @@ macro_rules! get_u {
     });
 }
 
+macro_rules! get_varint {
+    ($b:expr) => ({
+        let first = $b.peek_u8()?;
+
+        let len = varint_parse_len(first);
+
+        if len > $b.cap() {
+            return Err(Error::BufferTooShort);
+        }
+
+        // Mask the 2 most significant bits to remove the encoded length.
+        let out = match len {
+            1 => u64::from($b.get_u8()?),
+            2 => u64::from($b.get_u16()? & 0x3fff),
+            4 => u64::from($b.get_u32()? & 0x3fff_ffff),
+            8 => $b.get_u64()? & 0x3fff_ffff_ffff_ffff,
+            _ => unreachable!(),
+        };
+
+        Ok(out)
+    });
+}
+
 macro_rules! put_u {
     ($b:expr, $ty:ty, $v:expr, $len:expr) => ({
         let dst = &mut $b.buf[$b.off..];
@@ -84,29 +117,233 @@ macro_rules! put_u {
     });
 }
 
-/// A zero-copy mutable byte buffer.
+/// A zero-copy immutable byte buffer.
 ///
 /// `Octets` wraps an in-memory buffer of bytes and provides utility functions
+/// for parsing it. The underlying buffer is provided by the user and is not
+/// copied when creating an `Octets`. Operations are panic-free and will avoid
+/// indexing the buffer past its end.
+///
+/// Additionally, an offset (initially set to the start of the buffer) is
+/// incremented as bytes are read from the buffer, to allow for sequential
+/// operations.
+#[derive(Debug, PartialEq)]
+pub struct Octets<'a> {
+    buf: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Octets<'a> {
+    /// Creates an `Octets` from the given slice, without copying.
+    pub fn with_slice(buf: &'a [u8]) -> Octets<'a> {
+        Octets { buf, off: 0 }
+    }
+
+    /// Reads an unsigned 8-bit integer from the current offset and advances
+    /// the buffer.
+    pub fn get_u8(&mut self) -> Result<u8> {
+        get_u!(self, u8, 1)
+    }
+
+    /// Reads an unsigned 8-bit integer from the current offset without
+    /// advancing the buffer.
+    pub fn peek_u8(&mut self) -> Result<u8> {
+        peek_u!(self, u8, 1)
+    }
+
+    /// Reads an unsigned 16-bit integer in network byte-order from the current
+    /// offset and advances the buffer.
+    pub fn get_u16(&mut self) -> Result<u16> {
+        get_u!(self, u16, 2)
+    }
+
+    /// Reads an unsigned 24-bit integer in network byte-order from the current
+    /// offset and advances the buffer.
+    pub fn get_u24(&mut self) -> Result<u32> {
+        get_u!(self, u32, 3)
+    }
+
+    /// Reads an unsigned 32-bit integer in network byte-order from the current
+    /// offset and advances the buffer.
+    pub fn get_u32(&mut self) -> Result<u32> {
+        get_u!(self, u32, 4)
+    }
+
+    /// Reads an unsigned 64-bit integer in network byte-order from the current
+    /// offset and advances the buffer.
+    pub fn get_u64(&mut self) -> Result<u64> {
+        get_u!(self, u64, 8)
+    }
+
+    /// Reads an unsigned variable-length integer in network byte-order from
+    /// the current offset and advances the buffer.
+    pub fn get_varint(&mut self) -> Result<u64> {
+        get_varint!(self)
+    }
+
+    /// Reads an unsigned variable-length integer in network byte-order from
+    /// the current offset, without advancing the buffer.
+    pub fn peek_varint(&mut self) -> Result<u64> {
+        let off = self.off;
+
+        let v = self.get_varint();
+
+        self.off = off;
+
+        v
+    }
+
+    /// Reads `len` bytes from the current offset without copying and advances
+    /// the buffer.
+    pub fn get_bytes(&mut self, len: usize) -> Result<Octets<'a>> {
+        if self.cap() < len {
+            return Err(Error::BufferTooShort);
+        }
+
+        let out = Octets {
+            buf: &self.buf[self.off..self.off + len],
+            off: 0,
+        };
+
+        self.off += len;
+
+        Ok(out)
+    }
+
+    /// Reads `len` bytes from the current offset without copying and advances
+    /// the buffer, where `len` is an unsigned 8-bit integer prefix.
+    pub fn get_bytes_with_u8_length(&mut self) -> Result<Octets<'a>> {
+        let len = self.get_u8()?;
+        self.get_bytes(len as usize)
+    }
+
+    /// Reads `len` bytes from the current offset without copying and advances
+    /// the buffer, where `len` is an unsigned 16-bit integer prefix in network
+    /// byte-order.
+    pub fn get_bytes_with_u16_length(&mut self) -> Result<Octets<'a>> {
+        let len = self.get_u16()?;
+        self.get_bytes(len as usize)
+    }
+
+    /// Reads `len` bytes from the current offset without copying and advances
+    /// the buffer, where `len` is an unsigned variable-length integer prefix
+    /// in network byte-order.
+    pub fn get_bytes_with_varint_length(&mut self) -> Result<Octets<'a>> {
+        let len = self.get_varint()?;
+        self.get_bytes(len as usize)
+    }
+
+    /// Reads `len` bytes from the current offset without copying and without
+    /// advancing the buffer.
+    pub fn peek_bytes(&self, len: usize) -> Result<Octets<'a>> {
+        if self.cap() < len {
+            return Err(Error::BufferTooShort);
+        }
+
+        let out = Octets {
+            buf: &self.buf[self.off..self.off + len],
+            off: 0,
+        };
+
+        Ok(out)
+    }
+
+    /// Advances the buffer by `len` bytes without reading them.
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        if len > self.cap() {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.off += len;
+
+        Ok(())
+    }
+
+    /// Splits the buffer in two at the given absolute offset.
+    pub fn split_at(&self, off: usize) -> Result<(Octets<'a>, Octets<'a>)> {
+        if self.len() < off {
+            return Err(Error::BufferTooShort);
+        }
+
+        let (left, right) = self.buf.split_at(off);
+
+        let first = Octets {
+            buf: left,
+            off: 0,
+        };
+
+        let last = Octets {
+            buf: right,
+            off: 0,
+        };
+
+        Ok((first, last))
+    }
+
+    /// Returns a slice of `len` elements from the current offset.
+    pub fn slice(&self, len: usize) -> Result<&'a [u8]> {
+        if len > self.cap() {
+            return Err(Error::BufferTooShort);
+        }
+
+        Ok(&self.buf[self.off..self.off + len])
+    }
+
+    /// Returns the remaining capacity in the buffer.
+    pub fn cap(&self) -> usize {
+        self.buf.len() - self.off
+    }
+
+    /// Returns the total length of the buffer.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns `true` if the buffer has a length of zero.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the current offset of the buffer.
+    pub fn off(&self) -> usize {
+        self.off
+    }
+
+    /// Copies the buffer from the current offset into a new `Vec<u8>`.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_ref().to_vec()
+    }
+}
+
+impl<'a> AsRef<[u8]> for Octets<'a> {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[self.off..]
+    }
+}
+
+/// A zero-copy mutable byte buffer.
+///
+/// `OctetsMut` wraps an in-memory buffer of bytes and provides utility functions
 /// for manipulating it. The underlying buffer is provided by the user and is
-/// not copied when creating an `Octets`. Operations are panic-free and will
+/// not copied when creating an `OctetsMut`. Operations are panic-free and will
 /// avoid indexing the buffer past its end.
 ///
 /// Additionally, an offset (initially set to the start of the buffer) is
 /// incremented as bytes are read from / written to the buffer, to allow for
 /// sequential operations.
 #[derive(Debug, PartialEq)]
-pub struct Octets<'a> {
+pub struct OctetsMut<'a> {
     buf: &'a mut [u8],
     off: usize,
 }
 
-impl<'a> Octets<'a> {
-    /// Creates an `Octets` from the given slice, without copying.
+impl<'a> OctetsMut<'a> {
+    /// Creates an `OctetsMut` from the given slice, without copying.
     ///
     /// Since there's no copy, the input slice needs to be mutable to allow
     /// modifications.
-    pub fn with_slice(buf: &'a mut [u8]) -> Octets {
-        Octets { buf, off: 0 }
+    pub fn with_slice(buf: &'a mut [u8]) -> OctetsMut {
+        OctetsMut { buf, off: 0 }
     }
 
     /// Reads an unsigned 8-bit integer from the current offset and advances
@@ -178,32 +415,19 @@ impl<'a> Octets<'a> {
     /// Reads an unsigned variable-length integer in network byte-order from
     /// the current offset and advances the buffer.
     pub fn get_varint(&mut self) -> Result<u64> {
-        let first = self.peek_u8()?;
-
-        let len = match first >> 6 {
-            0 => 1,
-            1 => 2,
-            2 => 4,
-            3 => 8,
-            _ => return Err(Error::BufferTooShort),
-        };
-
-        let mut vec = self.get_bytes(len)?.to_vec();
+        get_varint!(self)
+    }
 
-        // Mask the 2 most significant bits to remove the encoded length.
-        vec[0] &= 0x3f;
+    /// Reads an unsigned variable-length integer in network byte-order from
+    /// the current offset, without advancing the buffer.
+    pub fn peek_varint(&mut self) -> Result<u64> {
+        let off = self.off;
 
-        let mut b = Octets::with_slice(&mut vec);
+        let v = self.get_varint();
 
-        let out = match len {
-            1 => u64::from(b.get_u8()?),
-            2 => u64::from(b.get_u16()?),
-            4 => u64::from(b.get_u32()?),
-            8 => b.get_u64()?,
-            _ => return Err(Error::BufferTooShort),
-        };
+        self.off = off;
 
-        Ok(out)
+        v
     }
 
     /// Writes an unsigned variable-length integer in network byte-order at the
@@ -233,12 +457,12 @@ impl<'a> Octets<'a> {
 
     /// Reads `len` bytes from the current offset without copying and advances
     /// the buffer.
-    pub fn get_bytes(&mut self, len: usize) -> Result<Octets> {
+    pub fn get_bytes(&mut self, len: usize) -> Result<OctetsMut> {
         if self.cap() < len {
             return Err(Error::BufferTooShort)
         }
 
-        let out = Octets {
+        let out = OctetsMut {
             buf: &mut self.buf[self.off..self.off + len],
             off: 0,
         };
@@ -250,7 +474,7 @@ impl<'a> Octets<'a> {
 
     /// Reads `len` bytes from the current offset without copying and advances
     /// the buffer, where `len` is an unsigned 8-bit integer prefix.
-    pub fn get_bytes_with_u8_length(&mut self) -> Result<Octets> {
+    pub fn get_bytes_with_u8_length(&mut self) -> Result<OctetsMut> {
         let len = self.get_u8()?;
         self.get_bytes(len as usize)
     }
@@ -258,7 +482,7 @@ impl<'a> Octets<'a> {
     /// Reads `len` bytes from the current offset without copying and advances
     /// the buffer, where `len` is an unsigned 16-bit integer prefix in network
     /// byte-order.
-    pub fn get_bytes_with_u16_length(&mut self) -> Result<Octets> {
+    pub fn get_bytes_with_u16_length(&mut self) -> Result<OctetsMut> {
         let len = self.get_u16()?;
         self.get_bytes(len as usize)
     }
@@ -266,19 +490,19 @@ impl<'a> Octets<'a> {
     /// Reads `len` bytes from the current offset without copying and advances
     /// the buffer, where `len` is an unsigned variable-length integer prefix
     /// in network byte-order.
-    pub fn get_bytes_with_varint_length(&mut self) -> Result<Octets> {
+    pub fn get_bytes_with_varint_length(&mut self) -> Result<OctetsMut> {
         let len = self.get_varint()?;
         self.get_bytes(len as usize)
     }
 
     /// Reads `len` bytes from the current offset without copying and without
     /// advancing the buffer.
-    pub fn peek_bytes(&mut self, len: usize) -> Result<Octets> {
+    pub fn peek_bytes(&mut self, len: usize) -> Result<OctetsMut> {
         if self.cap() < len {
             return Err(Error::BufferTooShort)
         }
 
-        let out = Octets {
+        let out = OctetsMut {
             buf: &mut self.buf[self.off..self.off + len],
             off: 0,
         };
@@ -286,6 +510,17 @@ impl<'a> Octets<'a> {
         Ok(out)
     }
 
+    /// Advances the buffer by `len` bytes without reading them.
+    pub fn skip(&mut self, len: usize) -> Result<()> {
+        if len > self.cap() {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.off += len;
+
+        Ok(())
+    }
+
     /// Writes `len` bytes from the current offset without copying and advances
     /// the buffer.
     pub fn put_bytes(&mut self, v: &[u8]) -> Result<()> {
@@ -307,19 +542,19 @@ impl<'a> Octets<'a> {
     }
 
     /// Splits the buffer in two at the given absolute offset.
-    pub fn split_at(&mut self, off: usize) -> Result<(Octets, Octets)> {
+    pub fn split_at(&mut self, off: usize) -> Result<(OctetsMut, OctetsMut)> {
         if self.len() < off {
             return Err(Error::BufferTooShort);
         }
 
         let (left, right) = self.buf.split_at_mut(off);
 
-        let first = Octets {
+        let first = OctetsMut {
             buf: left,
             off: 0,
         };
 
-        let last = Octets {
+        let last = OctetsMut {
             buf: right,
             off: 0,
         };
@@ -367,18 +602,29 @@ impl<'a> Octets<'a> {
     }
 }
 
-impl<'a> AsRef<[u8]> for Octets<'a> {
+impl<'a> AsRef<[u8]> for OctetsMut<'a> {
     fn as_ref(&self) -> &[u8] {
         &self.buf[self.off..]
     }
 }
 
-impl<'a> AsMut<[u8]> for Octets<'a> {
+impl<'a> AsMut<[u8]> for OctetsMut<'a> {
     fn as_mut(&mut self) -> &mut [u8] {
         &mut self.buf[self.off..]
     }
 }
 
+/// Returns how long the variable-length integer starting with the given byte
+/// is.
+pub fn varint_parse_len(first: u8) -> usize {
+    match first >> 6 {
+        0 => 1,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    }
+}
+
 /// Returns how many bytes it would take to encode `v` as a variable-length
 /// integer.
 pub fn varint_len(v: u64) -> usize {
@@ -405,7 +651,7 @@ mod tests {
         let mut d: [u8; 18] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14,
                                15, 16, 17, 18];
 
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.cap(), 18);
         assert_eq!(b.off(), 0);
 
@@ -436,11 +682,86 @@ mod tests {
         assert!(b.get_u64().is_err());
     }
 
+    #[test]
+    fn get_u_immutable() {
+        let d: [u8; 18] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14,
+                           15, 16, 17, 18];
+
+        let mut b = Octets::with_slice(&d);
+        assert_eq!(b.cap(), 18);
+        assert_eq!(b.off(), 0);
+
+        assert_eq!(b.get_u8().unwrap(), 1);
+        assert_eq!(b.get_u16().unwrap(), 0x203);
+        assert_eq!(b.get_u24().unwrap(), 0x40506);
+        assert_eq!(b.get_u32().unwrap(), 0x0708090a);
+        assert_eq!(b.get_u64().unwrap(), 0x0b0c0d0e0f101112);
+        assert_eq!(b.cap(), 0);
+        assert_eq!(b.off(), 18);
+
+        assert!(b.get_u8().is_err());
+        assert!(b.get_u64().is_err());
+    }
+
+    #[test]
+    fn get_bytes_immutable() {
+        let d: [u8; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+        let mut b = Octets::with_slice(&d);
+
+        // The returned buffers can outlive the parent.
+        let first = b.get_bytes(5).unwrap();
+        let second = b.peek_bytes(3).unwrap();
+        assert_eq!(b.off(), 5);
+
+        assert!(b.skip(3).is_ok());
+        assert_eq!(b.off(), 8);
+        assert!(b.skip(3).is_err());
+        assert_eq!(b.off(), 8);
+
+        assert_eq!(b.slice(2).unwrap(), [9, 10]);
+        assert!(b.slice(3).is_err());
+
+        assert_eq!(first.as_ref(), [1, 2, 3, 4, 5]);
+        assert_eq!(second.as_ref(), [6, 7, 8]);
+
+        let (left, right) = b.split_at(4).unwrap();
+        assert_eq!(left.as_ref(), [1, 2, 3, 4]);
+        assert_eq!(right.as_ref(), [5, 6, 7, 8, 9, 10]);
+        assert!(b.split_at(11).is_err());
+    }
+
+    #[test]
+    fn get_varint_immutable() {
+        let d = [0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c, 0x40, 0x25,
+                 0x9d, 0x7f];
+
+        let mut b = Octets::with_slice(&d);
+        assert_eq!(b.peek_varint().unwrap(), 151288809941952652);
+        assert_eq!(b.off(), 0);
+        assert_eq!(b.get_varint().unwrap(), 151288809941952652);
+        assert_eq!(b.get_varint().unwrap(), 37);
+
+        // Truncated varint.
+        assert!(b.get_varint().is_err());
+        assert_eq!(b.off(), 10);
+    }
+
+    #[test]
+    fn skip() {
+        let mut d: [u8; 4] = [1, 2, 3, 4];
+
+        let mut b = OctetsMut::with_slice(&mut d);
+        assert!(b.skip(3).is_ok());
+        assert_eq!(b.get_u8().unwrap(), 4);
+        assert!(b.skip(1).is_err());
+    }
+
     #[test]
     fn peek_u() {
         let mut d: [u8; 2] = [1, 2];
 
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.cap(), 2);
         assert_eq!(b.off(), 0);
 
@@ -460,7 +781,7 @@ mod tests {
     #[test]
     fn get_bytes() {
         let mut d: [u8; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.cap(), 10);
         assert_eq!(b.off(), 0);
 
@@ -486,7 +807,7 @@ mod tests {
     #[test]
     fn peek_bytes() {
         let mut d: [u8; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.cap(), 10);
         assert_eq!(b.off(), 0);
 
@@ -504,31 +825,31 @@ mod tests {
     #[test]
     fn get_varint() {
         let mut d: [u8; 8] = [0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.get_varint().unwrap(), 151288809941952652);
         assert_eq!(b.cap(), 0);
         assert_eq!(b.off(), 8);
 
         let mut d: [u8; 4] = [0x9d, 0x7f, 0x3e, 0x7d];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.get_varint().unwrap(), 494878333);
         assert_eq!(b.cap(), 0);
         assert_eq!(b.off(), 4);
 
         let mut d: [u8; 2] = [0x7b, 0xbd];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.get_varint().unwrap(), 15293);
         assert_eq!(b.cap(), 0);
         assert_eq!(b.off(), 2);
 
         let mut d: [u8; 2] = [0x40, 0x25];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.get_varint().unwrap(), 37);
         assert_eq!(b.cap(), 0);
         assert_eq!(b.off(), 2);
 
         let mut d: [u8; 1] = [0x25];
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.get_varint().unwrap(), 37);
         assert_eq!(b.cap(), 0);
         assert_eq!(b.off(), 1);
@@ -538,7 +859,7 @@ mod tests {
     fn put_varint() {
         let mut d: [u8; 8] = [0; 8];
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.put_varint(151288809941952652).is_ok());
             assert_eq!(b.cap(), 0);
             assert_eq!(b.off(), 8);
@@ -548,7 +869,7 @@ mod tests {
 
         let mut d: [u8; 4] = [0; 4];
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.put_varint(494878333).is_ok());
             assert_eq!(b.cap(), 0);
             assert_eq!(b.off(), 4);
@@ -558,7 +879,7 @@ mod tests {
 
         let mut d: [u8; 2] = [0; 2];
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.put_varint(15293).is_ok());
             assert_eq!(b.cap(), 0);
             assert_eq!(b.off(), 2);
@@ -568,7 +889,7 @@ mod tests {
 
         let mut d: [u8; 1] = [0; 1];
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.put_varint(37).is_ok());
             assert_eq!(b.cap(), 0);
             assert_eq!(b.off(), 1);
@@ -578,7 +899,7 @@ mod tests {
 
         let mut d: [u8; 3] = [0; 3];
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.put_varint(151288809941952652).is_err());
             assert_eq!(b.cap(), 3);
             assert_eq!(b.off(), 0);
@@ -592,7 +913,7 @@ mod tests {
         let mut d: [u8; 18] = [0; 18];
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert_eq!(b.cap(), 18);
             assert_eq!(b.off(), 0);
 
@@ -629,7 +950,7 @@ mod tests {
         let mut d: [u8; 5] = [0; 5];
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert_eq!(b.cap(), 5);
             assert_eq!(b.off(), 0);
 
@@ -649,7 +970,7 @@ mod tests {
     fn split() {
         let mut d: [u8; 10] = *b"helloworld";
 
-        let mut b = Octets::with_slice(&mut d);
+        let mut b = OctetsMut::with_slice(&mut d);
         assert_eq!(b.cap(), 10);
         assert_eq!(b.off(), 0);
         assert_eq!(b.as_ref(), b"helloworld");
@@ -676,7 +997,7 @@ mod tests {
         let mut d: [u8; 10] = *b"helloworld";
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let (first, second) = b.split_at(5).unwrap();
 
             let mut exp1: [u8; 5] = *b"hello";
@@ -687,7 +1008,7 @@ mod tests {
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let (first, second) = b.split_at(10).unwrap();
 
             let mut exp1: [u8; 10] = *b"helloworld";
@@ -698,7 +1019,7 @@ mod tests {
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let (first, second) = b.split_at(9).unwrap();
 
             let mut exp1: [u8; 9] = *b"helloworl";
@@ -709,7 +1030,7 @@ mod tests {
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.split_at(11).is_err());
         }
     }
@@ -719,19 +1040,19 @@ mod tests {
         let mut d: [u8; 10] = *b"helloworld";
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let mut exp: [u8; 5] = *b"hello";
             assert_eq!(b.slice(5), Ok(&mut exp[..]));
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let mut exp: [u8; 0] = *b"";
             assert_eq!(b.slice(0), Ok(&mut exp[..]));
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             b.get_bytes(5).unwrap();
 
             let mut exp: [u8; 5] = *b"world";
@@ -739,7 +1060,7 @@ mod tests {
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.slice(11).is_err());
         }
     }
@@ -749,31 +1070,31 @@ mod tests {
         let mut d: [u8; 10] = *b"helloworld";
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let mut exp: [u8; 4] = *b"orld";
             assert_eq!(b.slice_last(4), Ok(&mut exp[..]));
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let mut exp: [u8; 1] = *b"d";
             assert_eq!(b.slice_last(1), Ok(&mut exp[..]));
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let mut exp: [u8; 0] = *b"";
             assert_eq!(b.slice_last(0), Ok(&mut exp[..]));
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             let mut exp: [u8; 10] = *b"helloworld";
             assert_eq!(b.slice_last(10), Ok(&mut exp[..]));
         }
 
         {
-            let mut b = Octets::with_slice(&mut d);
+            let mut b = OctetsMut::with_slice(&mut d);
             assert!(b.slice_last(11).is_err());
         }
     }
@@ -47,6 +47,7 @@ const PKT_NUM_MASK: u8 = 0x03;
 const MAX_CID_LEN: u8 = 18;
 
 const MAX_PKT_NUM_LEN: usize = 4;
+const MAX_PKT_NUM: u64 = 1 << 62;
 const SAMPLE_LEN: usize = 16;
 
 /// QUIC packet type.
@@ -105,11 +106,11 @@ impl Header {
     /// The `dcil` parameter is the length of the destionation connection ID,
     /// required to parse short header packets.
     pub fn from_slice(buf: &mut [u8], dcil: usize) -> Result<Header> {
-        let mut b = octets::Octets::with_slice(buf);
+        let mut b = octets::OctetsMut::with_slice(buf);
         Header::from_bytes(&mut b, dcil)
     }
 
-    pub(crate) fn from_bytes(b: &mut octets::Octets, dcil: usize) -> Result<Header> {
+    pub(crate) fn from_bytes(b: &mut octets::OctetsMut, dcil: usize) -> Result<Header> {
         let first = b.get_u8()?;
 
         if !Header::is_long(first) {
@@ -225,7 +226,7 @@ impl Header {
         })
     }
 
-    pub(crate) fn to_bytes(&self, out: &mut octets::Octets) -> Result<()> {
+    pub(crate) fn to_bytes(&self, out: &mut octets::OctetsMut) -> Result<()> {
         let mut first = 0;
 
         // Encode pkt num length.
@@ -368,12 +369,27 @@ impl std::fmt::Debug for Header {
     }
 }
 
-pub fn pkt_num_len(pn: u64) -> Result<usize> {
-    let len = if pn < u64::from(std::u8::MAX) {
+/// Returns the number of bytes needed to encode the given packet number,
+/// based on the largest packet number acknowledged by the peer in the same
+/// packet number space.
+pub fn pkt_num_len(pn: u64, largest_acked: Option<u64>) -> Result<usize> {
+    let num_unacked = match largest_acked {
+        Some(largest_acked) => pn.saturating_sub(largest_acked),
+
+        None => pn + 1,
+    };
+
+    // Use twice the range of unacknowledged packets, so that the peer can
+    // decode the packet number even when packets are reordered.
+    let range = num_unacked.saturating_mul(2);
+
+    let len = if range <= u64::from(std::u8::MAX) {
         1
-    } else if pn < u64::from(std::u16::MAX) {
+    } else if range <= u64::from(std::u16::MAX) {
         2
-    } else if pn < u64::from(std::u32::MAX) {
+    } else if range <= 0x00ff_ffff {
+        3
+    } else if range <= u64::from(std::u32::MAX) {
         4
     } else {
         return Err(Error::InvalidPacket);
@@ -382,7 +398,7 @@ pub fn pkt_num_len(pn: u64) -> Result<usize> {
     Ok(len)
 }
 
-pub fn decrypt_hdr(b: &mut octets::Octets, hdr: &mut Header, aead: &crypto::Open)
+pub fn decrypt_hdr(b: &mut octets::OctetsMut, hdr: &mut Header, aead: &crypto::Open)
                                                     -> Result<()> {
     let mut first = {
         let (first_buf, _) = b.split_at(1)?;
@@ -439,6 +455,8 @@ pub fn decrypt_hdr(b: &mut octets::Octets, hdr: &mut Header, aead: &crypto::Open
     Ok(())
 }
 
+/// Reconstructs the full packet number from its truncated encoding, picking
+/// the candidate closest to the next expected packet number in the space.
 pub fn decode_pkt_num(largest_pn: u64, truncated_pn: u64, pn_len: usize) -> u64 {
     let pn_nbits     = pn_len * 8;
     let expected_pn  = largest_pn + 1;
@@ -447,25 +465,29 @@ pub fn decode_pkt_num(largest_pn: u64, truncated_pn: u64, pn_len: usize) -> u64
     let pn_mask      = pn_win - 1;
     let candidate_pn = (expected_pn & !pn_mask) | truncated_pn;
 
-    if candidate_pn + pn_hwin <= expected_pn {
+    if candidate_pn + pn_hwin <= expected_pn &&
+       candidate_pn <= MAX_PKT_NUM - pn_win {
          return candidate_pn + pn_win;
     }
 
-    if candidate_pn > expected_pn + pn_hwin && candidate_pn > pn_win {
+    if candidate_pn > expected_pn + pn_hwin && candidate_pn >= pn_win {
         return candidate_pn - pn_win;
     }
 
     candidate_pn
 }
 
-pub fn decrypt_pkt<'a>(b: &'a mut octets::Octets, pn: u64, pn_len: usize,
+pub fn decrypt_pkt<'a>(b: &'a mut octets::OctetsMut, pn: u64, pn_len: usize,
                        payload_len: usize, aead: &crypto::Open)
-                                                -> Result<octets::Octets<'a>> {
+                                                -> Result<octets::OctetsMut<'a>> {
     let payload_offset = b.off();
 
     let (header, mut payload) = b.split_at(payload_offset)?;
 
-    let mut ciphertext = payload.peek_bytes(payload_len - pn_len)?;
+    let ciphertext_len = payload_len.checked_sub(pn_len)
+                                    .ok_or(Error::InvalidPacket)?;
+
+    let mut ciphertext = payload.peek_bytes(ciphertext_len)?;
 
     let payload_len =
         aead.open_with_u64_counter(pn, header.as_ref(), ciphertext.as_mut())?;
@@ -473,7 +495,7 @@ pub fn decrypt_pkt<'a>(b: &'a mut octets::Octets, pn: u64, pn_len: usize,
     b.get_bytes(payload_len)
 }
 
-pub fn encrypt_hdr(b: &mut octets::Octets, pn_len: usize, payload: &[u8],
+pub fn encrypt_hdr(b: &mut octets::OctetsMut, pn_len: usize, payload: &[u8],
                    aead: &crypto::Seal) -> Result<()> {
     let sample = &payload[4 - pn_len..16 + (4 - pn_len)];
 
@@ -497,7 +519,7 @@ pub fn encrypt_hdr(b: &mut octets::Octets, pn_len: usize, payload: &[u8],
     Ok(())
 }
 
-pub fn encrypt_pkt(b: &mut octets::Octets, pn: u64, pn_len: usize,
+pub fn encrypt_pkt(b: &mut octets::OctetsMut, pn: u64, pn_len: usize,
                    payload_len: usize, payload_offset: usize,
                    aead: &crypto::Seal) -> Result<usize> {
     let (mut header, mut payload) = b.split_at(payload_offset)?;
@@ -511,9 +533,8 @@ pub fn encrypt_pkt(b: &mut octets::Octets, pn: u64, pn_len: usize,
     Ok(payload_offset + payload_len)
 }
 
-pub fn encode_pkt_num(pn: u64, b: &mut octets::Octets) -> Result<()> {
-    let len = pkt_num_len(pn)?;
-
+pub fn encode_pkt_num(pn: u64, len: usize, b: &mut octets::OctetsMut)
+                                                            -> Result<()> {
     match len {
         1 => b.put_u8(pn as u8)?,
 
@@ -530,7 +551,7 @@ pub fn encode_pkt_num(pn: u64, b: &mut octets::Octets) -> Result<()> {
 }
 
 pub fn negotiate_version(scid: &[u8], dcid: &[u8], out: &mut [u8]) -> Result<usize> {
-    let mut b = octets::Octets::with_slice(out);
+    let mut b = octets::OctetsMut::with_slice(out);
 
     let first = rand::rand_u8() | FORM_BIT;
 
@@ -550,13 +571,16 @@ pub fn negotiate_version(scid: &[u8], dcid: &[u8], out: &mut [u8]) -> Result<usi
     b.put_u8(cil)?;
     b.put_bytes(&scid)?;
     b.put_bytes(&dcid)?;
-    b.put_u32(crate::VERSION_DRAFT17)?;
+
+    for version in crate::supported_versions() {
+        b.put_u32(*version)?;
+    }
 
     Ok(b.off())
 }
 
 pub fn retry(scid: &[u8], dcid: &[u8], new_scid: &[u8], token: &[u8], out: &mut [u8]) -> Result<usize> {
-    let mut b = octets::Octets::with_slice(out);
+    let mut b = octets::OctetsMut::with_slice(out);
 
     let hdr = Header {
         ty: Type::Retry,
@@ -600,15 +624,16 @@ pub struct PktNumSpace {
 }
 
 impl PktNumSpace {
-    pub fn new(crypto_level: crypto::Level) -> PktNumSpace {
+    pub fn new(crypto_level: crypto::Level, max_ack_ranges: usize,
+               now: time::Instant) -> PktNumSpace {
         PktNumSpace {
             largest_rx_pkt_num: 0,
 
-            largest_rx_pkt_time: time::Instant::now(),
+            largest_rx_pkt_time: now,
 
             next_pkt_num: 0,
 
-            recv_pkt_need_ack: ranges::RangeSet::default(),
+            recv_pkt_need_ack: ranges::RangeSet::new(max_ack_ranges),
 
             recv_pkt_num: PktNumWindow::default(),
 
@@ -638,8 +663,20 @@ impl PktNumSpace {
     pub fn ready(&self) -> bool {
         self.crypto_stream.writable() || !self.flight.lost.is_empty() || self.do_ack
     }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.flight.shrink_to_fit();
+        self.crypto_stream.shrink_to_fit();
+    }
+
+    pub fn mem_usage(&self) -> usize {
+        self.flight.mem_usage() + self.crypto_stream.mem_usage()
+    }
 }
 
+// The number of packet numbers tracked by `PktNumWindow`.
+const WINDOW_BITS: u64 = std::mem::size_of::<u128>() as u64 * 8;
+
 #[derive(Clone, Copy, Default)]
 pub struct PktNumWindow {
     lower: u64,
@@ -658,8 +695,13 @@ impl PktNumWindow {
             let diff = seq - self.upper();
             self.lower += diff;
 
-            self.window = self.window.checked_shl(diff as u32)
-                                     .unwrap_or(0);
+            // Jumps past the end of the window clear it entirely, including
+            // ones that wouldn't fit in the shift amount.
+            self.window = if diff < WINDOW_BITS {
+                self.window << diff
+            } else {
+                0
+            };
         }
 
         let mask = 1_u128 << (self.upper() - seq);
@@ -682,7 +724,7 @@ impl PktNumWindow {
     }
 
     fn upper(&self) -> u64 {
-        self.lower.checked_add(std::mem::size_of::<u128>() as u64 * 8)
+        self.lower.checked_add(WINDOW_BITS)
                   .unwrap_or(std::u64::MAX) - 1
     }
 }
@@ -712,10 +754,10 @@ mod tests {
 
         let mut d: [u8; 50] = [0; 50];
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(hdr.to_bytes(&mut b).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
@@ -736,10 +778,10 @@ mod tests {
 
         let mut d: [u8; 50] = [0; 50];
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(hdr.to_bytes(&mut b).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
@@ -760,10 +802,10 @@ mod tests {
 
         let mut d: [u8; 50] = [0; 50];
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(hdr.to_bytes(&mut b).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
@@ -784,10 +826,10 @@ mod tests {
 
         let mut d: [u8; 50] = [0; 50];
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert!(hdr.to_bytes(&mut b).is_ok());
 
-        let mut b = octets::Octets::with_slice(&mut d);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
@@ -795,6 +837,27 @@ mod tests {
     fn pkt_num_decode() {
         let pn = decode_pkt_num(0xa82f30ea, 0x9b32, 2);
         assert_eq!(pn, 0xa82f9b32);
+
+        // Wrap around the encoding window in both directions.
+        assert_eq!(decode_pkt_num(0xff, 0x00, 1), 0x100);
+        assert_eq!(decode_pkt_num(0x100, 0xff, 1), 0xff);
+        assert_eq!(decode_pkt_num(0x1_fffe, 0x0001, 2), 0x2_0001);
+
+        // Packet numbers can't go below 0.
+        assert_eq!(decode_pkt_num(0x10, 0xf0, 1), 0xf0);
+    }
+
+    #[test]
+    fn pkt_num_encode_len() {
+        assert_eq!(pkt_num_len(0, None), Ok(1));
+        assert_eq!(pkt_num_len(126, None), Ok(1));
+        assert_eq!(pkt_num_len(127, None), Ok(2));
+
+        assert_eq!(pkt_num_len(0xac5c02, Some(0xabe8bc)), Ok(2));
+        assert_eq!(pkt_num_len(0xace8fe, Some(0xabe8bc)), Ok(3));
+        assert_eq!(pkt_num_len(0x1_0000_0005, Some(0x1_0000_0000)), Ok(1));
+
+        assert!(pkt_num_len(0x1_0000_0000, None).is_err());
     }
 
     #[test]
@@ -903,11 +966,30 @@ mod tests {
         assert!(win.contains(std::u64::MAX - 1));
     }
 
+    #[test]
+    fn pkt_num_window_large_jump() {
+        let mut win = PktNumWindow::default();
+
+        for pn in 0..4 {
+            win.insert(pn);
+        }
+
+        // The jump doesn't fit in a u32, and truncating it would keep stale
+        // packet numbers in the window.
+        let pn = (1 << 32) + 129;
+
+        win.insert(pn);
+        assert_eq!(win.lower, (1 << 32) + 2);
+        assert!(win.contains(pn));
+        assert!(!win.contains(win.lower));
+        assert!(!win.contains(win.lower + 1));
+    }
+
     fn test_decrypt_pkt(pkt: &mut [u8], dcid: &[u8], is_server: bool,
                          expected_frames: &[u8],
                          expected_pn: u64,
                          expected_pn_len: usize) {
-        let mut b = octets::Octets::with_slice(pkt);
+        let mut b = octets::OctetsMut::with_slice(pkt);
 
         let mut hdr = Header::from_bytes(&mut b, 0).unwrap();
         assert_eq!(hdr.ty, Type::Initial);
@@ -915,7 +997,8 @@ mod tests {
         let payload_len = b.get_varint().unwrap() as usize;
 
         let (aead, _) =
-            crypto::derive_initial_key_material(dcid, is_server).unwrap();
+            crypto::derive_initial_key_material(crate::VERSION_DRAFT17, dcid,
+                                                is_server).unwrap();
 
         decrypt_hdr(&mut b, &mut hdr, &aead).unwrap();
         let pn = decode_pkt_num(0, hdr.pkt_num, hdr.pkt_num_len);
@@ -1138,4 +1221,19 @@ mod tests {
 
         test_decrypt_pkt(&mut pkt, &dcid, false, &frames, 1, 2);
     }
+
+    #[test]
+    fn decrypt_pkt_short_payload() {
+        let mut d = [0; 50];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let (aead, _) =
+            crypto::derive_initial_key_material(crate::VERSION_DRAFT17,
+                                                &[0xba; 8], true).unwrap();
+
+        // Payload length smaller than the packet number length.
+        assert_eq!(decrypt_pkt(&mut b, 0, 4, 2, &aead).err(),
+                   Some(Error::InvalidPacket));
+    }
 }
@@ -0,0 +1,170 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Externally owned packet buffers.
+//!
+//! Frameworks doing zero-copy IO (e.g. AF_XDP or io_uring with registered
+//! buffers) own the memory packets are read into and written from. The
+//! [`PacketBuf`] trait lets such buffers be passed directly to
+//! [`Connection::recv_buf()`] and [`Connection::send_buf()`], and the
+//! [`BufferPool`] trait lets connections lease buffers to write packets into
+//! and return them once they are no longer needed.
+//!
+//! [`PacketBuf`]: trait.PacketBuf.html
+//! [`BufferPool`]: trait.BufferPool.html
+//! [`Connection::recv_buf()`]: ../struct.Connection.html#method.recv_buf
+//! [`Connection::send_buf()`]: ../struct.Connection.html#method.send_buf
+
+use std::sync::Mutex;
+
+/// A packet buffer.
+pub trait PacketBuf {
+    /// Returns the whole memory area of the buffer.
+    fn buf_mut(&mut self) -> &mut [u8];
+
+    /// Returns the length of the packet stored in the buffer.
+    fn len(&self) -> usize;
+
+    /// Returns true if the buffer doesn't contain a packet.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets the length of the packet stored in the buffer.
+    fn set_len(&mut self, len: usize);
+}
+
+/// A pool of packet buffers.
+pub trait BufferPool {
+    /// The type of buffers in the pool.
+    type Buf: PacketBuf;
+
+    /// Leases an empty buffer from the pool, if one is available.
+    fn lease(&self) -> Option<Self::Buf>;
+
+    /// Returns a buffer to the pool.
+    fn release(&self, buf: Self::Buf);
+}
+
+/// A heap allocated packet buffer.
+pub struct HeapBuf {
+    data: Box<[u8]>,
+
+    len: usize,
+}
+
+impl HeapBuf {
+    /// Creates an empty buffer of the given capacity.
+    pub fn new(capacity: usize) -> HeapBuf {
+        HeapBuf {
+            data: vec![0; capacity].into_boxed_slice(),
+            len: 0,
+        }
+    }
+
+    /// Returns the packet stored in the buffer.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl PacketBuf for HeapBuf {
+    fn buf_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.len = std::cmp::min(len, self.data.len());
+    }
+}
+
+/// A fixed size pool of heap allocated buffers.
+pub struct HeapPool {
+    free: Mutex<Vec<HeapBuf>>,
+}
+
+impl HeapPool {
+    /// Creates a pool of `count` buffers of the given capacity.
+    pub fn new(count: usize, capacity: usize) -> HeapPool {
+        let free = (0..count).map(|_| HeapBuf::new(capacity)).collect();
+
+        HeapPool {
+            free: Mutex::new(free),
+        }
+    }
+
+    /// Returns the number of buffers available for lease.
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+impl BufferPool for HeapPool {
+    type Buf = HeapBuf;
+
+    fn lease(&self) -> Option<HeapBuf> {
+        self.free.lock().unwrap().pop()
+    }
+
+    fn release(&self, mut buf: HeapBuf) {
+        buf.set_len(0);
+
+        self.free.lock().unwrap().push(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_pool() {
+        let pool = HeapPool::new(2, 1350);
+
+        let mut a = pool.lease().unwrap();
+        let b = pool.lease().unwrap();
+        assert!(pool.lease().is_none());
+
+        a.buf_mut()[..5].copy_from_slice(b"hello");
+        a.set_len(5);
+        assert_eq!(a.as_slice(), b"hello");
+
+        // The length can't exceed the capacity.
+        a.set_len(2000);
+        assert_eq!(a.len(), 1350);
+
+        pool.release(a);
+        pool.release(b);
+        assert_eq!(pool.available(), 2);
+
+        assert!(pool.lease().unwrap().is_empty());
+    }
+}
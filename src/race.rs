@@ -0,0 +1,482 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Racing connection attempts.
+//!
+//! A client that resolved multiple addresses for a server (e.g. both IPv6
+//! and IPv4 ones), or that supports multiple versions, can use a [`Race`] to
+//! try them in the style of Happy Eyeballs: attempts are started in order of
+//! preference, each one after the previous one was given some time to
+//! complete, and the first one to complete the handshake wins. The other
+//! attempts are then closed, so the servers they reached don't have to wait
+//! for them to time out.
+//!
+//! Like [`Connection`], a race doesn't do any I/O by itself. The application
+//! sends the packets returned by [`send_at()`] to the address of the
+//! matching attempt, and passes packets received from that address to
+//! [`recv_at()`].
+//!
+//! [`Race`]: struct.Race.html
+//! [`Connection`]: ../struct.Connection.html
+//! [`send_at()`]: struct.Race.html#method.send_at
+//! [`recv_at()`]: struct.Race.html#method.recv_at
+
+use std::net;
+use std::time;
+
+use crate::Connection;
+use crate::Error;
+use crate::Result;
+
+/// The recommended delay between connection attempts.
+pub const DEFAULT_ATTEMPT_DELAY: time::Duration =
+    time::Duration::from_millis(250);
+
+struct Attempt {
+    addr: net::SocketAddr,
+
+    conn: Box<Connection>,
+
+    started: bool,
+
+    // Whether the attempt lost the race and is being closed.
+    abandoned: bool,
+}
+
+impl Attempt {
+    fn failed(&self) -> bool {
+        !self.conn.is_established() &&
+            (self.conn.is_draining() || self.conn.is_closed())
+    }
+}
+
+/// A set of connection attempts racing to complete the handshake.
+pub struct Race {
+    attempts: Vec<Option<Attempt>>,
+
+    delay: time::Duration,
+
+    last_start: Option<time::Instant>,
+
+    winner: Option<usize>,
+}
+
+impl Race {
+    /// Creates an empty race, where each attempt is started `delay` after
+    /// the previous one.
+    pub fn new(delay: time::Duration) -> Race {
+        Race {
+            attempts: Vec::new(),
+            delay,
+            last_start: None,
+            winner: None,
+        }
+    }
+
+    /// Adds a connection attempt to `addr`, and returns its ID.
+    ///
+    /// Attempts are started in the order they are added, so the most
+    /// preferred one should be added first.
+    pub fn add(&mut self, addr: net::SocketAddr, conn: Box<Connection>)
+                                                                    -> usize {
+        self.attempts.push(Some(Attempt {
+            addr,
+            conn,
+            started: false,
+            abandoned: false,
+        }));
+
+        self.attempts.len() - 1
+    }
+
+    /// Returns the address of the given attempt.
+    pub fn addr(&self, id: usize) -> Option<net::SocketAddr> {
+        self.attempt(id).map(|a| a.addr)
+    }
+
+    /// Returns the ID of the attempt that completed the handshake first.
+    pub fn winner(&self) -> Option<usize> {
+        self.winner
+    }
+
+    /// Returns true if all the attempts failed.
+    pub fn is_failed(&self) -> bool {
+        self.winner.is_none() &&
+            self.attempts.iter().flatten().all(|a| a.started && a.failed())
+    }
+
+    /// Returns true if there is a winner and all the other attempts were
+    /// closed.
+    ///
+    /// The winning connection can then be retrieved with [`into_winner()`].
+    ///
+    /// [`into_winner()`]: struct.Race.html#method.into_winner
+    pub fn is_done(&self) -> bool {
+        self.winner.is_some() && self.attempts.iter().flatten().count() == 1
+    }
+
+    /// Consumes the race, and returns the address and connection of the
+    /// winning attempt.
+    pub fn into_winner(mut self) -> Option<(net::SocketAddr, Box<Connection>)> {
+        let a = self.attempts.get_mut(self.winner?)?.take()?;
+
+        Some((a.addr, a.conn))
+    }
+
+    /// Processes packets received from the given attempt's address.
+    ///
+    /// This is the same as [`Connection::recv_at()`]. If the packets cause
+    /// the attempt to complete the handshake and it's the first one to do
+    /// so, it becomes the winner and the other attempts are closed.
+    ///
+    /// [`Connection::recv_at()`]: ../struct.Connection.html#method.recv_at
+    pub fn recv_at(&mut self, id: usize, buf: &mut [u8], now: time::Instant)
+                                                        -> Result<usize> {
+        let winner = self.winner;
+
+        let a = self.attempt_mut(id).ok_or(Error::InvalidState)?;
+
+        if !a.started {
+            return Err(Error::InvalidState);
+        }
+
+        let res = a.conn.recv_at(buf, now);
+
+        if winner.is_none() && a.conn.is_established() {
+            trace!("{} won the race to {}", a.conn.trace_id(), a.addr);
+
+            self.winner = Some(id);
+            self.abandon_losers();
+        }
+
+        res
+    }
+
+    /// Writes a single packet for one of the attempts.
+    ///
+    /// On success the ID of the attempt and the number of bytes written are
+    /// returned, and the packet should be sent to the attempt's address.
+    /// [`Done`] is returned if no attempt has anything to send.
+    ///
+    /// New attempts are started as needed: when the delay since the last
+    /// attempt was started expired, or as soon as all the attempts that were
+    /// started failed.
+    ///
+    /// [`Done`]: ../enum.Error.html#variant.Done
+    pub fn send_at(&mut self, out: &mut [u8], now: time::Instant)
+                                                    -> Result<(usize, usize)> {
+        self.maybe_start(now);
+
+        for id in 0..self.attempts.len() {
+            let a = match self.attempts[id] {
+                Some(ref mut v) if v.started => v,
+
+                _ => continue,
+            };
+
+            match a.conn.send_at(out, now) {
+                Ok(len) => return Ok((id, len)),
+
+                // Once the CONNECTION_CLOSE frame was sent, there's no need
+                // to keep an abandoned attempt around.
+                Err(Error::Done) if a.abandoned => self.attempts[id] = None,
+
+                Err(Error::Done) => (),
+
+                Err(e) => {
+                    trace!("{} send failed: {:?}", a.conn.trace_id(), e);
+
+                    a.conn.close(false, e.to_wire(), b"").ok();
+                },
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the earliest time at which an attempt's timer expires, or the
+    /// next attempt needs to be started.
+    pub fn timeout_instant(&self) -> Option<time::Instant> {
+        let timers = self.attempts.iter()
+                                  .flatten()
+                                  .filter(|a| a.started)
+                                  .filter_map(|a| a.conn.timeout_instant());
+
+        let next_start = match self.last_start {
+            Some(t) if self.has_pending() => Some(t + self.delay),
+
+            _ => None,
+        };
+
+        timers.chain(next_start).min()
+    }
+
+    /// Processes the timeout events of the attempts whose timer expired at
+    /// `now`.
+    pub fn on_timeout_at(&mut self, now: time::Instant) {
+        for a in self.attempts.iter_mut().flatten() {
+            if !a.started {
+                continue;
+            }
+
+            match a.conn.timeout_instant() {
+                Some(t) if t <= now => a.conn.on_timeout_at(now),
+
+                _ => (),
+            }
+        }
+
+        // Abandoned attempts that timed out while closing are gone.
+        for a in self.attempts.iter_mut() {
+            if a.as_ref().map_or(false, |a| a.abandoned && a.conn.is_closed()) {
+                *a = None;
+            }
+        }
+    }
+
+    fn attempt(&self, id: usize) -> Option<&Attempt> {
+        self.attempts.get(id)?.as_ref()
+    }
+
+    fn attempt_mut(&mut self, id: usize) -> Option<&mut Attempt> {
+        self.attempts.get_mut(id)?.as_mut()
+    }
+
+    // Returns true if there are attempts that still need to be started.
+    fn has_pending(&self) -> bool {
+        self.winner.is_none() &&
+            self.attempts.iter().flatten().any(|a| !a.started)
+    }
+
+    fn maybe_start(&mut self, now: time::Instant) {
+        if !self.has_pending() {
+            return;
+        }
+
+        let all_failed =
+            self.attempts.iter().flatten().all(|a| !a.started || a.failed());
+
+        let delay_expired = match self.last_start {
+            Some(t) => now >= t + self.delay,
+
+            None => true,
+        };
+
+        if !all_failed && !delay_expired {
+            return;
+        }
+
+        let next = self.attempts.iter_mut().flatten().find(|a| !a.started);
+
+        if let Some(a) = next {
+            trace!("{} starting attempt to {}", a.conn.trace_id(), a.addr);
+
+            a.started = true;
+
+            self.last_start = Some(now);
+        }
+    }
+
+    fn abandon_losers(&mut self) {
+        let winner = self.winner;
+
+        for (id, slot) in self.attempts.iter_mut().enumerate() {
+            if Some(id) == winner {
+                continue;
+            }
+
+            let started = match slot {
+                Some(a) => a.started,
+
+                None => continue,
+            };
+
+            // Attempts that never sent anything can just be dropped.
+            if !started {
+                *slot = None;
+                continue;
+            }
+
+            if let Some(a) = slot {
+                a.abandoned = true;
+
+                a.conn.close(false, 0x0, b"").ok();
+            }
+        }
+    }
+}
+
+/// Orders addresses so that IPv6 and IPv4 ones alternate, starting with the
+/// family of the first address, while preserving the relative order of each
+/// family.
+pub fn interleave(addrs: &[net::SocketAddr]) -> Vec<net::SocketAddr> {
+    let first_v6 = addrs.first().map_or(true, |a| a.is_ipv6());
+
+    let (first, second): (Vec<net::SocketAddr>, Vec<net::SocketAddr>) =
+        addrs.iter().cloned().partition(|a| a.is_ipv6() == first_v6);
+
+    let mut out = Vec::with_capacity(addrs.len());
+
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    fn addr(s: &str) -> net::SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn interleave_families() {
+        let addrs = [
+            addr("[::1]:443"),
+            addr("[::2]:443"),
+            addr("[::3]:443"),
+            addr("127.0.0.1:443"),
+            addr("127.0.0.2:443"),
+        ];
+
+        assert_eq!(interleave(&addrs), vec![
+            addr("[::1]:443"),
+            addr("127.0.0.1:443"),
+            addr("[::2]:443"),
+            addr("127.0.0.2:443"),
+            addr("[::3]:443"),
+        ]);
+
+        assert_eq!(interleave(&[]), vec![]);
+    }
+
+    #[test]
+    fn interleave_mixed() {
+        let addrs = [
+            addr("127.0.0.1:443"),
+            addr("127.0.0.2:443"),
+            addr("[::1]:443"),
+            addr("127.0.0.3:443"),
+            addr("[::2]:443"),
+            addr("[::3]:443"),
+            addr("[::4]:443"),
+        ];
+
+        assert_eq!(interleave(&addrs), vec![
+            addr("127.0.0.1:443"),
+            addr("[::1]:443"),
+            addr("127.0.0.2:443"),
+            addr("[::2]:443"),
+            addr("127.0.0.3:443"),
+            addr("[::3]:443"),
+            addr("[::4]:443"),
+        ]);
+
+        // A single family is left untouched.
+        assert_eq!(interleave(&addrs[..2]), addrs[..2].to_vec());
+    }
+
+    #[test]
+    fn second_attempt_wins() {
+        let mut buf = [0; 65535];
+
+        let now = time::Instant::now();
+
+        // The first attempt goes into a black hole.
+        let black_hole = testing::Pipe::new().unwrap();
+        let mut pipe = testing::Pipe::new().unwrap();
+
+        let mut race = Race::new(DEFAULT_ATTEMPT_DELAY);
+
+        let first = race.add(addr("[::1]:443"), black_hole.client);
+        let second = race.add(addr("127.0.0.1:443"), pipe.client);
+
+        // Only the first attempt is started.
+        loop {
+            match race.send_at(&mut buf, now) {
+                Ok((id, _)) => assert_eq!(id, first),
+
+                Err(Error::Done) => break,
+
+                Err(e) => panic!("SEND FAILED: {:?}", e),
+            }
+        }
+
+        assert!(race.timeout_instant().unwrap() <= now + DEFAULT_ATTEMPT_DELAY);
+
+        // Once the delay expires, the second attempt is started and
+        // completes the handshake.
+        let now = now + DEFAULT_ATTEMPT_DELAY;
+
+        while race.winner().is_none() {
+            loop {
+                match race.send_at(&mut buf, now) {
+                    Ok((id, len)) if id == second => {
+                        pipe.server.recv(&mut buf[..len]).unwrap();
+                    },
+
+                    Ok(_) => (),
+
+                    Err(Error::Done) => break,
+
+                    Err(e) => panic!("SEND FAILED: {:?}", e),
+                }
+            }
+
+            let flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+            for mut pkt in flight {
+                race.recv_at(second, &mut pkt, now).unwrap();
+            }
+        }
+
+        assert_eq!(race.winner(), Some(second));
+        assert!(!race.is_done());
+
+        // The losing attempt sends its CONNECTION_CLOSE and goes away.
+        while race.send_at(&mut buf, now).is_ok() {}
+
+        assert!(race.is_done());
+        assert!(!race.is_failed());
+
+        let (winner_addr, conn) = race.into_winner().unwrap();
+        assert_eq!(winner_addr, addr("127.0.0.1:443"));
+        assert!(conn.is_established());
+    }
+}
@@ -31,12 +31,23 @@ use std::collections::Bound;
 use std::collections::btree_map;
 use std::collections::BTreeMap;
 
-#[derive(Clone, Default, PartialEq, PartialOrd)]
+#[derive(Clone, PartialEq, PartialOrd)]
 pub struct RangeSet {
     inner: BTreeMap<u64, u64>,
+
+    capacity: usize,
 }
 
 impl RangeSet {
+    /// Creates a set that holds at most `capacity` ranges. Once full, the
+    /// range with the smallest values is dropped to make room for new ones.
+    pub fn new(capacity: usize) -> RangeSet {
+        RangeSet {
+            inner: BTreeMap::new(),
+            capacity,
+        }
+    }
+
     // TODO: use RangeInclusive
     pub fn insert(&mut self, item: Range<u64>) {
         let mut start = item.start;
@@ -74,6 +85,11 @@ impl RangeSet {
         }
 
         self.inner.insert(start, end);
+
+        if self.inner.len() > self.capacity {
+            let smallest = *self.inner.keys().next().unwrap();
+            self.inner.remove(&smallest);
+        }
     }
 
     pub fn remove_until(&mut self, largest: u64) {
@@ -128,6 +144,12 @@ impl RangeSet {
     }
 }
 
+impl Default for RangeSet {
+    fn default() -> RangeSet {
+        RangeSet::new(std::usize::MAX)
+    }
+}
+
 impl std::fmt::Debug for RangeSet {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{:?}", self.iter().map(|mut r| { r.end -= 1; r })
@@ -457,4 +479,25 @@ mod tests {
         r.remove_until(20);
         assert_eq!(&r.flatten().collect::<Vec<u64>>(), &[]);
     }
+
+    #[test]
+    fn capacity() {
+        let mut r = RangeSet::new(3);
+
+        r.insert(4..7);
+        r.insert(9..12);
+        r.insert(15..19);
+        assert_eq!(r.inner.len(), 3);
+
+        // The smallest range is dropped.
+        r.insert(20..22);
+        assert_eq!(&r.flatten().collect::<Vec<u64>>(),
+                   &[9, 10, 11, 15, 16, 17, 18, 20, 21]);
+
+        // Merging doesn't drop anything.
+        r.insert(12..15);
+        assert_eq!(&r.flatten().collect::<Vec<u64>>(),
+                   &[9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 20, 21]);
+        assert_eq!(r.inner.len(), 2);
+    }
 }
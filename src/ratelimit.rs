@@ -0,0 +1,184 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Send rate limiting.
+//!
+//! A [`RateLimiter`] can be attached to a connection with
+//! [`set_rate_limiter()`] in order to cap the rate at which it sends data,
+//! independently of (and below) what congestion control allows. The same
+//! limiter can be shared by multiple connections, e.g. to enforce a single
+//! cap across all the connections of a customer.
+//!
+//! [`TokenBucket`] is a simple implementation based on the token bucket
+//! algorithm.
+//!
+//! [`RateLimiter`]: trait.RateLimiter.html
+//! [`set_rate_limiter()`]: ../struct.Connection.html#method.set_rate_limiter
+//! [`TokenBucket`]: struct.TokenBucket.html
+
+use std::cmp;
+use std::sync;
+use std::time;
+
+/// A limiter consulted before sending ack-eliciting 1-RTT packets.
+///
+/// Packets that only carry ACK or CONNECTION_CLOSE frames are never held
+/// back by the limiter.
+pub trait RateLimiter: Send + Sync {
+    /// Returns the time at which the next packet can be sent, or `None` if
+    /// it can be sent at `now`.
+    fn send_time(&self, now: time::Instant) -> Option<time::Instant>;
+
+    /// Accounts for a packet of `len` bytes sent at `now`.
+    fn on_sent(&self, len: usize, now: time::Instant);
+}
+
+/// A token bucket rate limiter.
+///
+/// Tokens, measured in bytes, are added to the bucket at a fixed rate, up to
+/// the size of the bucket. A packet can be sent as long as the bucket isn't
+/// empty, and removes as many tokens as its size, so the bucket can go into
+/// debt by at most one packet.
+pub struct TokenBucket {
+    rate: u64,
+
+    burst: u64,
+
+    state: sync::Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: i64,
+
+    last: Option<time::Instant>,
+}
+
+impl TokenBucket {
+    /// Creates a token bucket that allows sending `rate` bytes per second on
+    /// average, and bursts of up to `burst` bytes.
+    ///
+    /// The bucket starts full. A `rate` of 0 is treated as 1.
+    pub fn new(rate: u64, burst: u64) -> TokenBucket {
+        TokenBucket {
+            rate: cmp::max(rate, 1),
+
+            burst,
+
+            state: sync::Mutex::new(BucketState {
+                tokens: burst as i64,
+                last: None,
+            }),
+        }
+    }
+
+    /// Adds the tokens accumulated since the last update.
+    fn refill(&self, state: &mut BucketState, now: time::Instant) {
+        if let Some(last) = state.last {
+            if now <= last {
+                return;
+            }
+
+            let elapsed = now.duration_since(last);
+            let elapsed = elapsed.as_secs() * 1_000_000_000 +
+                          u64::from(elapsed.subsec_nanos());
+
+            let tokens = (u128::from(elapsed) * u128::from(self.rate) /
+                          1_000_000_000) as u64;
+
+            // Don't advance the clock when no whole token was added, so that
+            // fractions of tokens aren't lost.
+            if tokens == 0 {
+                return;
+            }
+
+            state.tokens = cmp::min(state.tokens.saturating_add(tokens as i64),
+                                    self.burst as i64);
+        }
+
+        state.last = Some(now);
+    }
+}
+
+impl RateLimiter for TokenBucket {
+    fn send_time(&self, now: time::Instant) -> Option<time::Instant> {
+        let mut state = self.state.lock().unwrap();
+
+        self.refill(&mut state, now);
+
+        if state.tokens > 0 {
+            return None;
+        }
+
+        // Wait until the debt is repaid, plus one token.
+        let missing = (1 - state.tokens) as u64;
+
+        let wait = u128::from(missing) * 1_000_000_000 /
+                   u128::from(self.rate);
+
+        Some(now + time::Duration::from_nanos(wait as u64))
+    }
+
+    fn on_sent(&self, len: usize, now: time::Instant) {
+        let mut state = self.state.lock().unwrap();
+
+        self.refill(&mut state, now);
+
+        state.tokens = state.tokens.saturating_sub(len as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let now = time::Instant::now();
+
+        let bucket = TokenBucket::new(1000, 1500);
+
+        // The bucket starts full.
+        assert_eq!(bucket.send_time(now), None);
+        bucket.on_sent(1200, now);
+        assert_eq!(bucket.send_time(now), None);
+
+        // The bucket can go into debt by a single packet.
+        bucket.on_sent(1200, now);
+        assert_eq!(bucket.send_time(now),
+                   Some(now + time::Duration::from_millis(901)));
+
+        // Tokens are refilled over time.
+        let now = now + time::Duration::from_millis(901);
+        assert_eq!(bucket.send_time(now), None);
+
+        // But never above the bucket size.
+        let now = now + time::Duration::from_secs(10);
+        bucket.on_sent(1500, now);
+        assert_eq!(bucket.send_time(now),
+                   Some(now + time::Duration::from_millis(1)));
+    }
+}
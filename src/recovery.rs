@@ -36,11 +36,15 @@ use crate::frame;
 use crate::ranges;
 
 // Loss Recovery
-const PACKET_THRESHOLD: u64 = 3;
+pub const PACKET_THRESHOLD: u64 = 3;
+
+const MAX_PACKET_THRESHOLD: u64 = 20;
+
+pub const TIME_THRESHOLD: f64 = 9.0 / 8.0;
 
 const GRANULARITY: Duration = Duration::from_millis(1);
 
-const INITIAL_RTT: Duration = Duration::from_millis(100);
+pub const INITIAL_RTT: Duration = Duration::from_millis(100);
 
 // Congestion Control
 const MAX_DATAGRAM_SIZE: usize = 1452;
@@ -48,7 +52,15 @@ const MAX_DATAGRAM_SIZE: usize = 1452;
 const INITIAL_WINDOW: usize = 10 * MAX_DATAGRAM_SIZE;
 const MINIMUM_WINDOW: usize = 2 * MAX_DATAGRAM_SIZE;
 
-const PERSISTENT_CONGESTION_THRESHOLD: u32 = 2;
+const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
+
+// Packets are paced at 5/4 of the congestion window per RTT, so that pacing
+// doesn't prevent the window from being fully used.
+const PACING_GAIN_NUM: u64 = 5;
+const PACING_GAIN_DEN: u64 = 4;
+
+// Amount of data that can be sent back-to-back, e.g. after an idle period.
+const PACING_BURST: usize = INITIAL_WINDOW;
 
 #[derive(Debug)]
 pub struct Sent {
@@ -87,6 +99,16 @@ pub struct InFlight {
     pub acked: Vec<frame::Frame>,
 
     pub lost_count: usize,
+
+    // Packet numbers and sizes of the packets declared lost, used for tracing
+    // and metrics.
+    pub lost_pkts: Vec<(u64, usize)>,
+
+    pub largest_acked: Option<u64>,
+
+    // Packets declared lost, along with their sent time and the congestion
+    // episode they triggered, used to detect spurious losses.
+    declared_lost: BTreeMap<u64, (Instant, u64)>,
 }
 
 impl Default for InFlight {
@@ -97,11 +119,22 @@ impl Default for InFlight {
             acked: Vec::new(),
 
             lost_count: 0,
+
+            lost_pkts: Vec::new(),
+
+            largest_acked: None,
+
+            declared_lost: BTreeMap::new(),
         }
     }
 }
 
 impl InFlight {
+    /// Returns true if ack-eliciting packets are waiting to be acknowledged.
+    pub fn ack_eliciting_in_flight(&self) -> bool {
+        self.sent.values().any(|p| p.ack_eliciting)
+    }
+
     pub fn retransmit_unacked_crypto(&mut self, trace_id: &str) -> usize {
         let mut unacked_bytes = 0;
 
@@ -122,6 +155,8 @@ impl InFlight {
 
         self.lost_count += self.sent.len();
 
+        self.lost_pkts.extend(self.sent.values().map(|p| (p.pkt_num, p.size)));
+
         self.sent.clear();
 
         unacked_bytes
@@ -145,6 +180,39 @@ impl InFlight {
 
         (crypto_unacked_bytes, unacked_bytes)
     }
+
+    /// Releases the memory held by empty frame queues.
+    pub fn shrink_to_fit(&mut self) {
+        if self.lost.is_empty() {
+            self.lost.shrink_to_fit();
+        }
+
+        if self.acked.is_empty() {
+            self.acked.shrink_to_fit();
+        }
+
+        if self.lost_pkts.is_empty() {
+            self.lost_pkts.shrink_to_fit();
+        }
+    }
+
+    /// Returns an estimate of the memory used by the frames waiting to be
+    /// acknowledged or retransmitted.
+    pub fn mem_usage(&self) -> usize {
+        let frames = self.sent.values()
+                              .flat_map(|p| p.frames.iter())
+                              .chain(self.lost.iter())
+                              .chain(self.acked.iter());
+
+        let frames_len = self.sent.values()
+                                  .map(|p| p.frames.capacity())
+                                  .sum::<usize>() +
+                         self.lost.capacity() + self.acked.capacity();
+
+        frames_len * std::mem::size_of::<frame::Frame>() +
+            self.sent.len() * std::mem::size_of::<Sent>() +
+            frames.map(|f| f.mem_usage()).sum::<usize>()
+    }
 }
 
 pub struct Recovery {
@@ -172,6 +240,12 @@ pub struct Recovery {
 
     pub max_ack_delay: Duration,
 
+    initial_rtt: Duration,
+
+    pkt_thresh: u64,
+
+    time_thresh: f64,
+
     loss_time: Option<Instant>,
 
     bytes_in_flight: usize,
@@ -184,13 +258,31 @@ pub struct Recovery {
 
     ssthresh: usize,
 
+    persistent_congestion_count: usize,
+
+    congestion_episode: u64,
+
+    episode_lost: usize,
+
+    episode_spurious: usize,
+
+    prior_cwnd: usize,
+
+    prior_ssthresh: usize,
+
+    spurious_lost_count: usize,
+
+    pacing: bool,
+
+    pacing_next: Instant,
+
+    resume_params: Option<crate::PathParams>,
+
     pub probes: usize,
 }
 
-impl Default for Recovery {
-    fn default() -> Recovery {
-        let now = Instant::now();
-
+impl Recovery {
+    pub fn new(config: &crate::Config, now: Instant) -> Recovery {
         Recovery {
             loss_detection_timer: None,
 
@@ -210,12 +302,18 @@ impl Default for Recovery {
 
             smoothed_rtt: Duration::new(0, 0),
 
-            min_rtt: Duration::from_secs(std::u64::MAX),
+            min_rtt: Duration::new(0, 0),
 
             rttvar: Duration::new(0, 0),
 
             max_ack_delay: Duration::from_millis(25),
 
+            initial_rtt: config.initial_rtt,
+
+            pkt_thresh: config.pkt_thresh,
+
+            time_thresh: config.time_thresh,
+
             loss_time: None,
 
             bytes_in_flight: 0,
@@ -228,12 +326,30 @@ impl Default for Recovery {
 
             ssthresh: std::usize::MAX,
 
+            persistent_congestion_count: 0,
+
+            congestion_episode: 0,
+
+            episode_lost: 0,
+
+            episode_spurious: 0,
+
+            prior_cwnd: INITIAL_WINDOW,
+
+            prior_ssthresh: std::usize::MAX,
+
+            spurious_lost_count: 0,
+
+            pacing: config.pacing,
+
+            pacing_next: now,
+
+            resume_params: None,
+
             probes: 0,
         }
     }
-}
 
-impl Recovery {
     pub fn on_packet_sent(&mut self, pkt: Sent, flight: &mut InFlight,
                           now: Instant, trace_id: &str) {
         let pkt_num = pkt.pkt_num;
@@ -257,6 +373,8 @@ impl Recovery {
             // OnPacketSentCC
             self.bytes_in_flight += sent_bytes;
 
+            self.on_paced_packet_sent(sent_bytes, now);
+
             self.set_loss_detection_timer();
         }
 
@@ -268,10 +386,12 @@ impl Recovery {
         self.largest_acked_pkt = cmp::max(self.largest_acked_pkt,
                                           ranges.largest().unwrap());
 
+        flight.largest_acked = cmp::max(flight.largest_acked, ranges.largest());
+
         if let Some(pkt) = flight.sent.get(&self.largest_acked_pkt) {
             if pkt.ack_eliciting {
                 let ack_delay = Duration::from_micros(ack_delay);
-                self.update_rtt(pkt.time.elapsed(), ack_delay);
+                self.update_rtt(now - pkt.time, ack_delay);
             }
         }
 
@@ -283,9 +403,18 @@ impl Recovery {
 
             if newly_acked {
                 trace!("{} packet newly acked {}", trace_id, pn);
+            } else if let Some((_, episode)) = flight.declared_lost.remove(&pn) {
+                trace!("{} packet spuriously lost {}", trace_id, pn);
+
+                self.on_spurious_loss(pn, episode);
             }
         }
 
+        // Forget about lost packets that are too old to be ACK'd.
+        let pto = self.pto();
+        flight.declared_lost.retain(|_, (time, _)|
+            now.duration_since(*time) <= pto * PERSISTENT_CONGESTION_THRESHOLD);
+
         if !has_newly_acked {
             return;
         }
@@ -355,40 +484,147 @@ impl Recovery {
         self.cwnd - self.bytes_in_flight
     }
 
+    /// Returns the time at which the pacer will allow the next ack-eliciting
+    /// packet to be sent, or `None` if it can be sent at `now`.
+    ///
+    /// Probe packets are never paced.
+    pub fn pacing_time(&self, now: Instant) -> Option<Instant> {
+        if !self.pacing || self.probes > 0 {
+            return None;
+        }
+
+        // Up to a burst worth of packets can be sent ahead of the pacing
+        // schedule.
+        let burst = self.pacing_interval(PACING_BURST);
+
+        if self.pacing_next <= now + burst {
+            return None;
+        }
+
+        Some(self.pacing_next - burst)
+    }
+
+    /// Returns the time it takes to send `bytes` at the pacing rate.
+    fn pacing_interval(&self, bytes: usize) -> Duration {
+        let rtt = self.rtt();
+        let rtt = rtt.as_secs() * 1_000_000_000 + u64::from(rtt.subsec_nanos());
+
+        let cwnd = cmp::max(self.cwnd, MINIMUM_WINDOW) as u64;
+
+        Duration::from_nanos(bytes as u64 * rtt * PACING_GAIN_DEN /
+                             (cwnd * PACING_GAIN_NUM))
+    }
+
+    fn on_paced_packet_sent(&mut self, sent_bytes: usize, now: Instant) {
+        self.pacing_next = cmp::max(self.pacing_next, now) +
+                           self.pacing_interval(sent_bytes);
+    }
+
+    pub fn spurious_lost_count(&self) -> usize {
+        self.spurious_lost_count
+    }
+
+    pub fn persistent_congestion_count(&self) -> usize {
+        self.persistent_congestion_count
+    }
+
     pub fn rtt(&self) -> Duration {
         let zero = Duration::new(0, 0);
 
         if self.smoothed_rtt == zero {
-            return INITIAL_RTT;
+            return self.initial_rtt;
         }
 
         self.smoothed_rtt
     }
 
+    /// Returns the minimum RTT observed so far, or zero if no RTT sample has
+    /// been taken yet.
+    pub fn min_rtt(&self) -> Duration {
+        self.min_rtt
+    }
+
+    /// Returns the RTT variation estimate.
+    ///
+    /// Before the first RTT sample this is half of the initial RTT.
+    pub fn rttvar(&self) -> Duration {
+        let zero = Duration::new(0, 0);
+
+        if self.smoothed_rtt == zero {
+            return self.initial_rtt / 2;
+        }
+
+        self.rttvar
+    }
+
+    pub fn path_params(&self) -> crate::PathParams {
+        crate::PathParams {
+            cwnd: self.cwnd,
+            min_rtt: self.min_rtt,
+        }
+    }
+
+    pub fn set_resume_params(&mut self, params: crate::PathParams) {
+        if self.smoothed_rtt == Duration::new(0, 0) {
+            self.resume_params = Some(params);
+        }
+    }
+
+    // Careful resume: jumps to half the saved congestion window, but only if
+    // the first RTT sample shows that the path is similar to the saved one.
+    fn careful_resume(&mut self, params: crate::PathParams, rtt: Duration) {
+        if rtt > params.min_rtt * 10 || rtt * 2 < params.min_rtt {
+            return;
+        }
+
+        let cwnd = params.cwnd / 2;
+
+        if cwnd > self.cwnd {
+            self.cwnd = cwnd;
+
+            // The window is already sized for the path, so there's no need
+            // for slow start.
+            self.ssthresh = cwnd;
+        }
+    }
+
     fn update_rtt(&mut self, latest_rtt: Duration, ack_delay: Duration) {
         let zero = Duration::new(0, 0);
 
+        self.latest_rtt = latest_rtt;
+
+        // The first sample initializes the estimator.
+        if self.smoothed_rtt == zero {
+            self.min_rtt = latest_rtt;
+
+            self.smoothed_rtt = latest_rtt;
+
+            self.rttvar = latest_rtt / 2;
+
+            if let Some(params) = self.resume_params.take() {
+                self.careful_resume(params, latest_rtt);
+            }
+
+            return;
+        }
+
+        // The minimum RTT ignores the peer's ACK delay, as it's only used to
+        // bound how much of the delay can be subtracted from later samples.
         self.min_rtt = cmp::min(self.min_rtt, latest_rtt);
 
         let ack_delay = cmp::min(self.max_ack_delay, ack_delay);
 
-        self.latest_rtt = if latest_rtt - self.min_rtt > ack_delay {
+        let adjusted_rtt = if latest_rtt > self.min_rtt + ack_delay {
             latest_rtt - ack_delay
         } else {
             latest_rtt
         };
 
-        if self.smoothed_rtt == zero {
-            self.rttvar = self.latest_rtt / 2;
-
-            self.smoothed_rtt = self.latest_rtt;
-        } else {
-            let rttvar_sample = sub_abs(self.smoothed_rtt, self.latest_rtt);
+        let rttvar_sample = sub_abs(self.smoothed_rtt, adjusted_rtt);
 
-            self.rttvar = (self.rttvar * 3 + rttvar_sample) / 4;
+        self.rttvar = (self.rttvar * 3 + rttvar_sample) / 4;
 
-            self.smoothed_rtt = (self.smoothed_rtt * 7 + self.latest_rtt) / 8;
-        }
+        self.smoothed_rtt = (self.smoothed_rtt * 7 + adjusted_rtt) / 8;
     }
 
     fn set_loss_detection_timer(&mut self) {
@@ -402,7 +638,7 @@ impl Recovery {
         if self.crypto_bytes_in_flight > 0 {
             // Crypto retransmission timer.
             let mut timeout = if self.smoothed_rtt == zero {
-                INITIAL_RTT * 2
+                self.initial_rtt * 2
             } else {
                 self.smoothed_rtt * 2
             };
@@ -423,12 +659,7 @@ impl Recovery {
         }
 
         // PTO timer.
-        let mut timeout = self.smoothed_rtt +
-                          (self.rttvar * 4) +
-                          self.max_ack_delay;
-
-        timeout = cmp::max(timeout, GRANULARITY);
-        timeout *= 2_u32.pow(self.pto_count);
+        let timeout = self.pto() * 2_u32.pow(self.pto_count);
 
         self.loss_detection_timer =
             Some(self.time_of_last_sent_ack_eliciting_pkt + timeout);
@@ -440,11 +671,12 @@ impl Recovery {
 
         let largest_acked = self.largest_acked_pkt;
 
-        let loss_delay = (cmp::max(self.latest_rtt, self.smoothed_rtt) * 9) / 8;
+        let loss_delay = cmp::max(self.latest_rtt, self.smoothed_rtt)
+                            .mul_f64(self.time_thresh);
 
         let lost_send_time = now - loss_delay;
 
-        let lost_pkt_num = largest_acked.checked_sub(PACKET_THRESHOLD)
+        let lost_pkt_num = largest_acked.checked_sub(self.pkt_thresh)
                                         .unwrap_or(0);
 
         self.loss_time = None;
@@ -472,6 +704,11 @@ impl Recovery {
         }
     }
 
+    pub fn pto(&self) -> Duration {
+        self.rtt() + cmp::max(self.rttvar() * 4, GRANULARITY) +
+            self.max_ack_delay
+    }
+
     fn in_recovery(&self, sent_time: Instant) -> bool {
         match self.recovery_start_time {
             Some(recovery_start_time) => sent_time <= recovery_start_time,
@@ -519,15 +756,46 @@ impl Recovery {
         // congestion event later.
         let mut largest_lost_pkt_sent_time: Option<Instant> = None;
 
+        // Persistent congestion is declared when all packets sent over a
+        // period longer than the congestion period are lost. Since ACK'd
+        // packets are removed from the flight, a run of consecutive packet
+        // numbers guarantees that none of the packets in between was ACK'd.
+        let congestion_period = self.pto() * PERSISTENT_CONGESTION_THRESHOLD;
+
+        let mut prev_lost: Option<u64> = None;
+        let mut lost_period_start: Option<Instant> = None;
+
+        let mut persistent_congestion = false;
+
+        // Remember the lost packets, in case they turn out to be ACK'd later.
+        let mut lost_ack_eliciting: Vec<(u64, Instant)> = Vec::new();
+
         for lost in lost_pkt {
             let mut p = flight.sent.remove(&lost).unwrap();
 
             flight.lost_count += 1;
 
+            flight.lost_pkts.push((lost, p.size));
+
+            if prev_lost.map_or(false, |pn| pn + 1 != lost) {
+                lost_period_start = None;
+            }
+
+            prev_lost = Some(lost);
+
             if !p.ack_eliciting {
                 continue;
             }
 
+            match lost_period_start {
+                Some(start) =>
+                    if p.time.duration_since(start) > congestion_period {
+                        persistent_congestion = true;
+                    },
+
+                None => lost_period_start = Some(p.time),
+            }
+
             self.bytes_in_flight -= p.size;
 
             if p.is_crypto {
@@ -536,6 +804,8 @@ impl Recovery {
 
             flight.lost.append(&mut p.frames);
 
+            lost_ack_eliciting.push((p.pkt_num, p.time));
+
             largest_lost_pkt_sent_time = Some(p.time);
         }
 
@@ -547,13 +817,59 @@ impl Recovery {
         if !self.in_recovery(largest_lost_pkt_sent_time.unwrap()) {
             self.recovery_start_time = Some(now);
 
+            // Save the congestion state so it can be restored in case the
+            // loss turns out to be spurious.
+            self.congestion_episode += 1;
+            self.episode_lost = 0;
+            self.episode_spurious = 0;
+
+            self.prior_cwnd = self.cwnd;
+            self.prior_ssthresh = self.ssthresh;
+
             self.cwnd /= 2;
             self.cwnd = cmp::max(self.cwnd, MINIMUM_WINDOW);
             self.ssthresh = self.cwnd;
+        }
 
-            if self.pto_count > PERSISTENT_CONGESTION_THRESHOLD {
-                self.cwnd = MINIMUM_WINDOW;
-            }
+        self.episode_lost += lost_ack_eliciting.len();
+
+        for (pkt_num, time) in lost_ack_eliciting {
+            flight.declared_lost.insert(pkt_num,
+                                        (time, self.congestion_episode));
+        }
+
+        // Persistent congestion can only be established after an RTT sample
+        // has been collected.
+        if persistent_congestion && self.smoothed_rtt != Duration::new(0, 0) {
+            self.cwnd = MINIMUM_WINDOW;
+
+            self.persistent_congestion_count += 1;
+        }
+    }
+
+    fn on_spurious_loss(&mut self, pkt_num: u64, episode: u64) {
+        self.spurious_lost_count += 1;
+
+        // Increase the reordering threshold so that a packet reordered by the
+        // same amount won't be declared lost again.
+        let reordering = self.largest_acked_pkt.saturating_sub(pkt_num) + 1;
+
+        self.pkt_thresh = cmp::min(cmp::max(self.pkt_thresh, reordering),
+                                   MAX_PACKET_THRESHOLD);
+
+        if episode != self.congestion_episode {
+            return;
+        }
+
+        self.episode_spurious += 1;
+
+        // All the packets lost during the latest congestion episode were ACK'd
+        // in the end, so undo the congestion response.
+        if self.episode_spurious == self.episode_lost {
+            self.cwnd = cmp::max(self.cwnd, self.prior_cwnd);
+            self.ssthresh = self.prior_ssthresh;
+
+            self.recovery_start_time = None;
         }
     }
 }
@@ -561,20 +877,9 @@ impl Recovery {
 impl std::fmt::Debug for Recovery {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self.loss_detection_timer {
-            Some(v) => {
-                let now = Instant::now();
+            Some(v) => write!(f, "timer={:?} ", v)?,
 
-                if v > now {
-                    let d = v.duration_since(now);
-                    write!(f, "timer={:?} ", d)?;
-                } else {
-                    write!(f, "timer=exp ")?;
-                }
-            },
-
-            None => {
-                write!(f, "timer=none ")?;
-            },
+            None => write!(f, "timer=none ")?,
         };
 
         write!(f, "crypto={} ", self.crypto_bytes_in_flight)?;
@@ -586,6 +891,10 @@ impl std::fmt::Debug for Recovery {
         write!(f, "rttvar={:?} ", self.rttvar)?;
         write!(f, "probes={} ", self.probes)?;
 
+        if self.pacing {
+            write!(f, "pacing={:?} ", self.pacing_next)?;
+        }
+
         Ok(())
     }
 }
@@ -597,3 +906,247 @@ fn sub_abs(lhs: Duration, rhs: Duration) -> Duration {
         rhs - lhs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(pkt_num: u64, time: Instant) -> Sent {
+        Sent::new(pkt_num, vec![frame::Frame::Ping], 1000, true, false, time)
+    }
+
+    #[test]
+    fn careful_resume() {
+        let config = crate::Config::new(crate::VERSION_DRAFT17).unwrap();
+
+        let params = crate::PathParams {
+            cwnd: 100 * MAX_DATAGRAM_SIZE,
+            min_rtt: Duration::from_millis(100),
+        };
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(0);
+
+        let now = Instant::now();
+
+        // The RTT matches, so the window is resumed.
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        r.set_resume_params(params);
+
+        r.on_packet_sent(sent(0, now - Duration::from_millis(120)),
+                         &mut flight, now, "");
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        assert!(r.path_params().cwnd >= 50 * MAX_DATAGRAM_SIZE);
+        assert_eq!(r.path_params().min_rtt, Duration::from_millis(120));
+
+        // The RTT is much higher than the saved one, so the path changed.
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        r.set_resume_params(params);
+
+        r.on_packet_sent(sent(0, now - Duration::from_secs(2)),
+                         &mut flight, now, "");
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        assert!(r.path_params().cwnd < 50 * MAX_DATAGRAM_SIZE);
+
+        // Parameters set after the first RTT sample are ignored.
+        r.set_resume_params(params);
+        assert_eq!(r.resume_params, None);
+    }
+
+    #[test]
+    fn rtt_estimation() {
+        let config = crate::Config::new(crate::VERSION_DRAFT17).unwrap();
+
+        let now = Instant::now();
+
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        assert_eq!(r.rtt(), INITIAL_RTT);
+        assert_eq!(r.rttvar(), INITIAL_RTT / 2);
+        assert_eq!(r.min_rtt(), Duration::new(0, 0));
+
+        r.on_packet_sent(sent(0, now - Duration::from_millis(100)),
+                         &mut flight, now, "");
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(0);
+
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        assert_eq!(r.rtt(), Duration::from_millis(100));
+        assert_eq!(r.rttvar(), Duration::from_millis(50));
+        assert_eq!(r.min_rtt(), Duration::from_millis(100));
+        assert_eq!(r.latest_rtt, Duration::from_millis(100));
+
+        // The peer's ACK delay is subtracted from the sample, but not from
+        // the minimum RTT.
+        r.on_packet_sent(sent(1, now - Duration::from_millis(140)),
+                         &mut flight, now, "");
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(1);
+
+        r.on_ack_received(&acked, 20_000, &mut flight, now, "");
+
+        assert_eq!(r.latest_rtt, Duration::from_millis(140));
+        assert_eq!(r.min_rtt(), Duration::from_millis(100));
+        assert_eq!(r.rtt(), Duration::from_micros(102_500));
+        assert_eq!(r.rttvar(), Duration::from_micros(42_500));
+
+        // ACK delay that would bring the sample below the minimum RTT is
+        // ignored.
+        r.on_packet_sent(sent(2, now - Duration::from_millis(110)),
+                         &mut flight, now, "");
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(2);
+
+        r.on_ack_received(&acked, 20_000, &mut flight, now, "");
+
+        assert_eq!(r.min_rtt(), Duration::from_millis(100));
+        assert_eq!(r.rtt(), (Duration::from_micros(102_500) * 7 +
+                             Duration::from_millis(110)) / 8);
+    }
+
+    #[test]
+    fn persistent_congestion() {
+        let config = crate::Config::new(crate::VERSION_DRAFT17).unwrap();
+
+        let now = Instant::now();
+
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        // Collect an RTT sample.
+        r.on_packet_sent(sent(0, now - Duration::from_millis(100)),
+                         &mut flight, now, "");
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(0);
+
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+        assert_eq!(r.persistent_congestion_count(), 0);
+
+        // Packets sent over a period longer than the congestion period.
+        for (pn, ago) in (1..6).zip(&[3000, 2500, 2000, 1500, 1000]) {
+            r.on_packet_sent(sent(pn, now - Duration::from_millis(*ago)),
+                             &mut flight, now, "");
+        }
+
+        r.on_packet_sent(sent(6, now - Duration::from_millis(10)),
+                         &mut flight, now, "");
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(6);
+
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        assert_eq!(flight.lost_count, 5);
+        assert_eq!(r.persistent_congestion_count(), 1);
+        assert_eq!(r.cwnd(), MINIMUM_WINDOW);
+    }
+
+    #[test]
+    fn spurious_loss() {
+        let config = crate::Config::new(crate::VERSION_DRAFT17).unwrap();
+
+        let now = Instant::now();
+
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        // Collect an RTT sample.
+        r.on_packet_sent(sent(0, now - Duration::from_millis(100)),
+                         &mut flight, now, "");
+
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(0);
+
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        for pn in 1..6 {
+            r.on_packet_sent(sent(pn, now - Duration::from_millis(10)),
+                             &mut flight, now, "");
+        }
+
+        // Packets 1 and 2 are declared lost due to reordering.
+        let mut acked = ranges::RangeSet::default();
+        acked.push_item(5);
+
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        assert_eq!(flight.lost_count, 2);
+        assert_eq!(r.cwnd, (INITIAL_WINDOW + 2000) / 2);
+
+        // Packets 1 and 2 are ACK'd after all.
+        acked.insert(1..3);
+
+        r.on_ack_received(&acked, 0, &mut flight, now, "");
+
+        assert_eq!(r.spurious_lost_count(), 2);
+        assert_eq!(r.cwnd, INITIAL_WINDOW + 2000);
+        assert_eq!(r.pkt_thresh, 5);
+    }
+
+    fn can_send(r: &Recovery, now: Instant) -> bool {
+        r.pacing_time(now).is_none()
+    }
+
+    #[test]
+    fn pacing() {
+        let config = crate::Config::new(crate::VERSION_DRAFT17).unwrap();
+
+        let now = Instant::now();
+
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        assert!(can_send(&r, now));
+
+        // An initial burst can be sent without delay.
+        let mut pn = 0;
+
+        while can_send(&r, now) {
+            r.on_packet_sent(sent(pn, now), &mut flight, now, "");
+            pn += 1;
+        }
+
+        assert_eq!(pn as usize, PACING_BURST / 1000 + 1);
+
+        // Afterwards packets are spaced according to the pacing rate, which
+        // sends a congestion window worth of data in 4/5 of the RTT.
+        let next = r.pacing_time(now).unwrap();
+        assert!(next > now);
+        assert!(next <= now + INITIAL_RTT);
+
+        assert!(!can_send(&r, next - Duration::from_millis(1)));
+        assert!(can_send(&r, next));
+
+        r.on_packet_sent(sent(pn, next), &mut flight, next, "");
+        assert!(!can_send(&r, next));
+
+        // Probes are not paced.
+        r.probes = 1;
+        assert!(can_send(&r, next));
+
+        // Pacing can be disabled.
+        let mut config = crate::Config::new(crate::VERSION_DRAFT17).unwrap();
+        config.enable_pacing(false);
+
+        let mut r = Recovery::new(&config, now);
+        let mut flight = InFlight::default();
+
+        for pn in 0..100 {
+            r.on_packet_sent(sent(pn, now), &mut flight, now, "");
+        }
+
+        assert!(can_send(&r, now));
+    }
+}
@@ -0,0 +1,572 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Packet scheduling across multiple connections.
+//!
+//! Servers that multiplex many connections over a single socket need to
+//! decide which connection gets to send the next packet. A [`Scheduler`]
+//! makes that decision, and a [`ConnectionSet`] drives a set of connections
+//! according to it.
+//!
+//! Two schedulers are provided: [`DeficitRoundRobin`], which shares the
+//! socket between connections in proportion to their weight, and
+//! [`StrictPriority`], which always serves the connections with the highest
+//! priority first.
+//!
+//! [`Scheduler`]: trait.Scheduler.html
+//! [`ConnectionSet`]: struct.ConnectionSet.html
+//! [`DeficitRoundRobin`]: struct.DeficitRoundRobin.html
+//! [`StrictPriority`]: struct.StrictPriority.html
+
+use std::time;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use crate::Connection;
+use crate::Error;
+use crate::Result;
+
+/// Decides which connection sends the next packet.
+///
+/// Connections are identified by the ID assigned by the [`ConnectionSet`].
+/// A connection is active when it might have packets to send. Only active
+/// connections are returned by [`next()`], and they become inactive once
+/// they report having nothing to send.
+///
+/// [`ConnectionSet`]: struct.ConnectionSet.html
+/// [`next()`]: trait.Scheduler.html#tymethod.next
+pub trait Scheduler {
+    /// Adds an inactive connection with the given weight.
+    ///
+    /// The meaning of the weight depends on the scheduler.
+    fn insert(&mut self, id: u64, weight: u32);
+
+    /// Removes a connection.
+    fn remove(&mut self, id: u64);
+
+    /// Marks a connection as active.
+    fn wake(&mut self, id: u64);
+
+    /// Returns the active connection that should send the next packet.
+    fn next(&mut self) -> Option<u64>;
+
+    /// Accounts for a packet of `len` bytes sent by a connection.
+    ///
+    /// A `len` of 0 means the connection had nothing to send, and marks it
+    /// as inactive.
+    fn on_sent(&mut self, id: u64, len: usize);
+}
+
+struct Flow {
+    weight: u32,
+
+    deficit: i64,
+
+    active: bool,
+}
+
+/// A deficit round robin scheduler.
+///
+/// Active connections take turns sending packets. On each turn a connection
+/// is given a quantum of bytes multiplied by its weight, and keeps sending
+/// until it runs out of them, so connections share the socket in proportion
+/// to their weight regardless of their packet sizes.
+pub struct DeficitRoundRobin {
+    quantum: usize,
+
+    flows: HashMap<u64, Flow>,
+
+    queue: VecDeque<u64>,
+}
+
+impl DeficitRoundRobin {
+    /// Creates a scheduler that gives each connection `quantum` bytes per
+    /// turn and unit of weight.
+    ///
+    /// The quantum should be at least the size of a packet.
+    pub fn new(quantum: usize) -> DeficitRoundRobin {
+        DeficitRoundRobin {
+            quantum: std::cmp::max(quantum, 1),
+            flows: HashMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for DeficitRoundRobin {
+    fn insert(&mut self, id: u64, weight: u32) {
+        self.flows.insert(id, Flow {
+            weight: std::cmp::max(weight, 1),
+            deficit: 0,
+            active: false,
+        });
+    }
+
+    fn remove(&mut self, id: u64) {
+        if self.flows.remove(&id).is_some() {
+            self.queue.retain(|&v| v != id);
+        }
+    }
+
+    fn wake(&mut self, id: u64) {
+        if let Some(flow) = self.flows.get_mut(&id) {
+            if !flow.active {
+                flow.active = true;
+                self.queue.push_back(id);
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            let id = *self.queue.front()?;
+
+            let flow = self.flows.get_mut(&id).unwrap();
+
+            if flow.deficit > 0 {
+                return Some(id);
+            }
+
+            // The connection's turn is over, so it goes to the back of the
+            // queue with a new quantum for its next turn.
+            flow.deficit += (self.quantum * flow.weight as usize) as i64;
+
+            self.queue.rotate_left(1);
+        }
+    }
+
+    fn on_sent(&mut self, id: u64, len: usize) {
+        let flow = match self.flows.get_mut(&id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if len > 0 {
+            flow.deficit -= len as i64;
+            return;
+        }
+
+        // Idle connections don't accumulate credit.
+        if flow.active {
+            flow.active = false;
+            flow.deficit = 0;
+
+            self.queue.retain(|&v| v != id);
+        }
+    }
+}
+
+/// A strict priority scheduler.
+///
+/// The weight of a connection is its priority: connections with a higher
+/// priority always send before the ones with a lower priority, and
+/// connections with the same priority take turns sending one packet each.
+#[derive(Default)]
+pub struct StrictPriority {
+    priorities: HashMap<u64, (u32, bool)>,
+
+    levels: BTreeMap<u32, VecDeque<u64>>,
+}
+
+impl StrictPriority {
+    /// Creates a strict priority scheduler.
+    pub fn new() -> StrictPriority {
+        StrictPriority::default()
+    }
+
+    fn deactivate(&mut self, id: u64, priority: u32) {
+        let empty = match self.levels.get_mut(&priority) {
+            Some(level) => {
+                level.retain(|&v| v != id);
+                level.is_empty()
+            },
+
+            None => false,
+        };
+
+        if empty {
+            self.levels.remove(&priority);
+        }
+    }
+}
+
+impl Scheduler for StrictPriority {
+    fn insert(&mut self, id: u64, weight: u32) {
+        self.remove(id);
+
+        self.priorities.insert(id, (weight, false));
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some((priority, _)) = self.priorities.remove(&id) {
+            self.deactivate(id, priority);
+        }
+    }
+
+    fn wake(&mut self, id: u64) {
+        if let Some((priority, active)) = self.priorities.get_mut(&id) {
+            if !*active {
+                *active = true;
+
+                self.levels.entry(*priority)
+                           .or_insert_with(VecDeque::new)
+                           .push_back(id);
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<u64> {
+        self.levels.values().next_back()?.front().cloned()
+    }
+
+    fn on_sent(&mut self, id: u64, len: usize) {
+        let priority = match self.priorities.get_mut(&id) {
+            Some((priority, active)) if *active => {
+                if len == 0 {
+                    *active = false;
+                }
+
+                *priority
+            },
+
+            _ => return,
+        };
+
+        if len == 0 {
+            self.deactivate(id, priority);
+            return;
+        }
+
+        // Let the next connection with the same priority send.
+        if let Some(level) = self.levels.get_mut(&priority) {
+            level.rotate_left(1);
+        }
+    }
+}
+
+/// A set of connections sending packets according to a [`Scheduler`].
+///
+/// Connections are woken up whenever they might have new packets to send:
+/// when they are inserted, when packets are received, when their timers
+/// expire, and when they are accessed mutably (e.g. to write to streams).
+///
+/// [`Scheduler`]: trait.Scheduler.html
+pub struct ConnectionSet<S: Scheduler> {
+    conns: HashMap<u64, Box<Connection>>,
+
+    scheduler: S,
+
+    next_id: u64,
+}
+
+impl<S: Scheduler> ConnectionSet<S> {
+    /// Creates an empty set using the given scheduler.
+    pub fn new(scheduler: S) -> ConnectionSet<S> {
+        ConnectionSet {
+            conns: HashMap::new(),
+            scheduler,
+            next_id: 0,
+        }
+    }
+
+    /// Adds a connection with the given scheduling weight, and returns the
+    /// ID assigned to it.
+    pub fn insert(&mut self, conn: Box<Connection>, weight: u32) -> u64 {
+        let id = self.next_id;
+
+        self.next_id += 1;
+
+        self.conns.insert(id, conn);
+
+        self.scheduler.insert(id, weight);
+        self.scheduler.wake(id);
+
+        id
+    }
+
+    /// Removes a connection from the set.
+    pub fn remove(&mut self, id: u64) -> Option<Box<Connection>> {
+        self.scheduler.remove(id);
+
+        self.conns.remove(&id)
+    }
+
+    /// Returns a reference to a connection.
+    pub fn get(&self, id: u64) -> Option<&Connection> {
+        self.conns.get(&id).map(|c| c.as_ref())
+    }
+
+    /// Returns a mutable reference to a connection, and wakes it up.
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Connection> {
+        let conn = self.conns.get_mut(&id)?;
+
+        self.scheduler.wake(id);
+
+        Some(conn.as_mut())
+    }
+
+    /// Returns the number of connections in the set.
+    pub fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    /// Returns true if the set contains no connections.
+    pub fn is_empty(&self) -> bool {
+        self.conns.is_empty()
+    }
+
+    /// Processes packets received for a connection, and wakes it up.
+    ///
+    /// This is the same as [`Connection::recv()`].
+    ///
+    /// [`Connection::recv()`]: ../struct.Connection.html#method.recv
+    pub fn recv(&mut self, id: u64, buf: &mut [u8]) -> Result<usize> {
+        let conn = self.conns.get_mut(&id).ok_or(Error::InvalidState)?;
+
+        self.scheduler.wake(id);
+
+        conn.recv(buf)
+    }
+
+    /// Writes a single packet from the connection picked by the scheduler.
+    ///
+    /// On success the ID of the connection and the number of bytes written
+    /// are returned, or [`Done`] if no connection has anything to send. If
+    /// a connection fails to send, it is closed with the matching error
+    /// code, and another connection is picked. A connection that keeps
+    /// failing once closed isn't picked again until it's woken up.
+    ///
+    /// [`BufferTooShort`] is returned as is, without closing any connection,
+    /// as it's caused by the output buffer.
+    ///
+    /// [`Done`]: ../enum.Error.html#variant.Done
+    /// [`BufferTooShort`]: ../enum.Error.html#variant.BufferTooShort
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(u64, usize)> {
+        while let Some(id) = self.scheduler.next() {
+            let conn = match self.conns.get_mut(&id) {
+                Some(v) => v,
+
+                None => {
+                    self.scheduler.remove(id);
+                    continue;
+                },
+            };
+
+            match conn.send(out) {
+                Ok(len) => {
+                    self.scheduler.on_sent(id, len);
+
+                    return Ok((id, len));
+                },
+
+                Err(Error::Done) => self.scheduler.on_sent(id, 0),
+
+                Err(Error::BufferTooShort) => return Err(Error::BufferTooShort),
+
+                Err(e) => {
+                    trace!("{} send failed: {:?}", conn.trace_id(), e);
+
+                    // The CONNECTION_CLOSE frame will be sent on a later
+                    // turn. If the connection was already closed, it has
+                    // nothing more to send.
+                    if conn.close(false, e.to_wire(), b"").is_err() {
+                        self.scheduler.on_sent(id, 0);
+                    }
+                },
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the earliest time at which a connection's timer expires.
+    pub fn timeout_instant(&self) -> Option<time::Instant> {
+        self.conns.values().filter_map(|c| c.timeout_instant()).min()
+    }
+
+    /// Processes the timeout events of the connections whose timer expired
+    /// at `now`, and wakes them up.
+    pub fn on_timeout_at(&mut self, now: time::Instant) {
+        for (id, conn) in self.conns.iter_mut() {
+            match conn.timeout_instant() {
+                Some(t) if t <= now => {
+                    conn.on_timeout_at(now);
+
+                    self.scheduler.wake(*id);
+                },
+
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    #[test]
+    fn deficit_round_robin() {
+        let mut drr = DeficitRoundRobin::new(1000);
+
+        drr.insert(0, 1);
+        drr.insert(1, 2);
+
+        // Inactive connections are not scheduled.
+        assert_eq!(drr.next(), None);
+
+        drr.wake(0);
+        drr.wake(1);
+
+        let mut sent = [0; 2];
+
+        for _ in 0..30 {
+            let id = drr.next().unwrap();
+
+            drr.on_sent(id, 500);
+            sent[id as usize] += 500;
+        }
+
+        // Connections are served in proportion to their weight.
+        assert_eq!(sent, [5000, 10000]);
+
+        // Idle connections are skipped.
+        let id = drr.next().unwrap();
+        drr.on_sent(id, 0);
+        assert_eq!(drr.next(), Some(1 - id));
+
+        drr.remove(1 - id);
+        assert_eq!(drr.next(), None);
+    }
+
+    #[test]
+    fn strict_priority() {
+        let mut sp = StrictPriority::new();
+
+        sp.insert(0, 1);
+        sp.insert(1, 1);
+        sp.insert(2, 5);
+
+        sp.wake(0);
+        sp.wake(1);
+        sp.wake(2);
+
+        // The highest priority connection is always served first.
+        assert_eq!(sp.next(), Some(2));
+        sp.on_sent(2, 1200);
+        assert_eq!(sp.next(), Some(2));
+        sp.on_sent(2, 0);
+
+        // Connections with the same priority take turns.
+        assert_eq!(sp.next(), Some(0));
+        sp.on_sent(0, 1200);
+        assert_eq!(sp.next(), Some(1));
+        sp.on_sent(1, 1200);
+        assert_eq!(sp.next(), Some(0));
+
+        sp.wake(2);
+        assert_eq!(sp.next(), Some(2));
+
+        sp.remove(2);
+        assert_eq!(sp.next(), Some(0));
+    }
+
+    #[test]
+    fn connection_set() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let mut set = ConnectionSet::new(DeficitRoundRobin::new(1200));
+
+        let id = set.insert(pipe.client, 1);
+        assert_eq!(set.len(), 1);
+
+        assert_eq!(set.get_mut(id).unwrap().stream_send(4, b"hello", true),
+                   Ok(5));
+
+        let mut sent = 0;
+
+        loop {
+            match set.send(&mut buf) {
+                Ok((v, len)) => {
+                    assert_eq!(v, id);
+
+                    assert_eq!(pipe.server.recv(&mut buf[..len]), Ok(len));
+
+                    sent += 1;
+                },
+
+                Err(Error::Done) => break,
+
+                Err(e) => panic!("SEND FAILED: {:?}", e),
+            }
+        }
+
+        assert!(sent > 0);
+        assert!(pipe.server.stream_readable(4));
+
+        assert!(set.remove(id).is_some());
+        assert!(set.is_empty());
+        assert_eq!(set.send(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    fn connection_set_handshake_timeout() {
+        let mut buf = [0; 65535];
+
+        let mut config = crate::testing::default_config().unwrap();
+        config.set_handshake_timeout(1);
+
+        let pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        let mut set = ConnectionSet::new(DeficitRoundRobin::new(1200));
+
+        let id = set.insert(pipe.client, 1);
+
+        // The server never answers, so the handshake times out.
+        set.on_timeout_at(time::Instant::now() + time::Duration::from_secs(1));
+        assert!(set.get(id).unwrap().is_closed());
+
+        // The connection can't send anymore, but isn't picked forever.
+        assert_eq!(set.send(&mut buf), Err(Error::Done));
+
+        // Output buffers that are too short are reported to the caller.
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let id = set.insert(pipe.client, 1);
+        assert_eq!(set.send(&mut buf[..0]), Err(Error::BufferTooShort));
+        assert!(!set.get(id).unwrap().is_closed());
+    }
+}
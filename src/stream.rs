@@ -26,8 +26,10 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::cmp;
+use std::time;
 
 use std::collections::hash_map;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::BinaryHeap;
 
@@ -46,6 +48,14 @@ pub struct Stream {
     rx_data: usize,
     max_rx_data: usize,
     new_max_rx_data: usize,
+
+    window: usize,
+    last_window_update: Option<time::Instant>,
+
+    max_burst: usize,
+    burst: usize,
+
+    accepted: bool,
 }
 
 impl Stream {
@@ -58,6 +68,14 @@ impl Stream {
             max_rx_data,
             new_max_rx_data: max_rx_data,
 
+            window: max_rx_data,
+            last_window_update: None,
+
+            max_burst: std::usize::MAX,
+            burst: 0,
+
+            accepted: false,
+
             max_tx_data,
         }
     }
@@ -72,6 +90,26 @@ impl Stream {
         self.recv.push(buf)
     }
 
+    /// Returns true if buffering `len` bytes received at `off` would leave
+    /// out-of-order data further than `max_reorder` bytes ahead of the read
+    /// offset.
+    pub fn exceeds_reorder_window(&self, off: usize, len: usize,
+                                  max_reorder: usize) -> bool {
+        off > self.recv.contiguous_off() &&
+            off + len > self.recv.off.saturating_add(max_reorder)
+    }
+
+    pub fn recv_push_slice(&mut self, data: &[u8], off: usize, fin: bool)
+                                                            -> Result<()> {
+        if off + data.len() > self.max_rx_data {
+            return Err(Error::FlowControl);
+        }
+
+        self.rx_data = cmp::max(self.rx_data, off + data.len());
+
+        self.recv.push_slice(data, off, fin)
+    }
+
     pub fn recv_pop(&mut self, max_len: usize) -> Result<RangeBuf> {
         let buf = self.recv.pop(max_len)?;
 
@@ -81,32 +119,137 @@ impl Stream {
         Ok(buf)
     }
 
-    pub fn recv_update_max_data(&mut self) -> usize {
+    /// Returns the largest offset of the data received on the stream.
+    pub fn recv_max_off(&self) -> usize {
+        self.rx_data
+    }
+
+    /// Returns the amount of data held in the receive buffer.
+    pub fn recv_buffered(&self) -> usize {
+        self.recv.buffered()
+    }
+
+    /// Returns the limit to advertise in the next MAX_STREAM_DATA frame,
+    /// using the given receive window.
+    pub fn recv_max_data_next(&self, window: usize) -> usize {
+        self.new_max_rx_data.saturating_add(window.saturating_sub(self.window))
+    }
+
+    /// Applies the limit advertised in a MAX_STREAM_DATA frame sent at `now`.
+    ///
+    /// This must only be called once the frame was actually queued, so the
+    /// window is not grown by updates that are never sent.
+    pub fn recv_update_max_data(&mut self, window: usize,
+                                now: time::Instant) -> usize {
+        self.new_max_rx_data = self.recv_max_data_next(window);
         self.max_rx_data = self.new_max_rx_data;
 
+        self.window = cmp::max(self.window, window);
+        self.last_window_update = Some(now);
+
         self.new_max_rx_data
     }
 
+    /// Returns the receive window to use for the next MAX_STREAM_DATA update.
+    ///
+    /// The window is doubled, up to `max_window`, if the previous update was
+    /// sent less than two RTTs ago, i.e. if the application reads data fast
+    /// enough that the window would limit throughput.
+    pub fn autotune_window(&self, now: time::Instant, rtt: time::Duration,
+                           max_window: usize) -> usize {
+        match self.last_window_update {
+            Some(last) if last + rtt * 2 > now && self.window < max_window =>
+                cmp::min(self.window.saturating_mul(2), max_window),
+
+            _ => self.window,
+        }
+    }
+
     pub fn send_push(&mut self, data: &[u8], fin: bool) -> Result<()> {
         self.send.push_slice(data, fin)
     }
 
+    /// Appends a buffer popped from another stream's receive buffer, without
+    /// copying its data.
+    pub fn send_push_buf(&mut self, buf: RangeBuf) {
+        self.send.push_buf(buf);
+    }
+
     pub fn send_pop(&mut self, max_len: usize) -> Result<RangeBuf> {
-        self.send.pop(max_len, self.max_tx_data)
+        let buf = self.send.pop(max_len, self.max_tx_data)?;
+
+        self.burst = self.burst.saturating_add(buf.len());
+
+        Ok(buf)
+    }
+
+    /// Sets the maximum amount of data sent in a single scheduling round.
+    pub fn set_max_burst(&mut self, max_burst: usize) {
+        self.max_burst = cmp::max(max_burst, 1);
+    }
+
+    /// Returns how much more data can be sent in the current scheduling
+    /// round.
+    pub fn burst_left(&self) -> usize {
+        self.max_burst.saturating_sub(self.burst)
+    }
+
+    /// Starts a new scheduling round.
+    pub fn reset_burst(&mut self) {
+        self.burst = 0;
+    }
+
+    /// Returns the offset of the next data to be sent.
+    pub fn send_off(&self) -> usize {
+        self.send.off()
+    }
+
+    /// Returns how much more data can be written to the stream without
+    /// exceeding the peer's flow control limit.
+    pub fn send_capacity(&self) -> usize {
+        self.max_tx_data.saturating_sub(self.send.max_off())
     }
 
     pub fn send_push_front(&mut self, buf: RangeBuf) -> Result<()> {
         self.send.push(buf)
     }
 
-    pub fn send_max_data(&mut self, max_data: usize) {
-        self.max_tx_data = cmp::max(self.max_tx_data, max_data);
+    pub fn send_max_data(&mut self, max_data: usize) -> bool {
+        if max_data <= self.max_tx_data {
+            return false;
+        }
+
+        self.max_tx_data = max_data;
+
+        true
     }
 
     pub fn readable(&self) -> bool {
         self.recv.ready()
     }
 
+    /// Returns true if all the data has been read, up to the final size.
+    pub fn is_finished(&self) -> bool {
+        self.recv.is_fin()
+    }
+
+    /// Returns true if the stream was already returned to the application
+    /// as a newly opened stream.
+    pub fn is_accepted(&self) -> bool {
+        self.accepted
+    }
+
+    /// Marks the stream as returned to the application, and returns whether
+    /// it wasn't already.
+    pub fn accept(&mut self) -> bool {
+        !std::mem::replace(&mut self.accepted, true)
+    }
+
+    /// Returns true if the FIN was written, and all of the data was sent.
+    pub fn is_flushed(&self) -> bool {
+        self.send.fin && !self.send.ready()
+    }
+
     pub fn writable(&self) -> bool {
         self.send.ready() && self.send.off() <= self.max_tx_data
     }
@@ -117,6 +260,18 @@ impl Stream {
         self.new_max_rx_data != self.max_rx_data &&
         self.new_max_rx_data / 2 > self.max_rx_data - self.rx_data
     }
+
+    /// Releases the memory held by the stream's send buffer, if it is empty.
+    ///
+    /// The receive buffer's memory is released as data is read.
+    pub fn shrink_to_fit(&mut self) {
+        self.send.shrink_to_fit();
+    }
+
+    /// Returns an estimate of the memory used by the stream's buffers.
+    pub fn mem_usage(&self) -> usize {
+        self.recv.mem_usage() + self.send.mem_usage()
+    }
 }
 
 pub fn is_local(id: u64, is_server: bool) -> bool {
@@ -127,6 +282,27 @@ pub fn is_bidi(id: u64) -> bool {
     (id & 0x2) == 0
 }
 
+/// An iterator over the streams opened by the peer.
+pub struct Accepted {
+    streams: std::vec::IntoIter<u64>,
+}
+
+impl Accepted {
+    pub(crate) fn new(streams: Vec<u64>) -> Accepted {
+        Accepted {
+            streams: streams.into_iter(),
+        }
+    }
+}
+
+impl Iterator for Accepted {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.streams.next()
+    }
+}
+
 /// An iterator over the streams that have outstanding data to read.
 pub struct Readable<'a> {
     streams: hash_map::Iter<'a, u64, Stream>,
@@ -156,14 +332,22 @@ impl<'a> Iterator for Readable<'a> {
 
 #[derive(Default)]
 struct RecvBuf {
-    data: BinaryHeap<RangeBuf>,
+    // Buffered data keyed by offset. Contiguous ranges are merged as they
+    // are received, so heavily fragmented streams only hold one entry per
+    // hole.
+    data: BTreeMap<usize, RangeBuf>,
     off: usize,
     len: usize,
+    // Total length of the buffered data. Buffered ranges never overlap.
+    buffered: usize,
+    max_off: usize,
+    fin_off: Option<usize>,
 }
 
 impl RecvBuf {
-    fn push(&mut self, buf: RangeBuf) -> Result<()> {
-        // TODO: discard duplicated data (e.g. using RangeSet)
+    fn push(&mut self, mut buf: RangeBuf) -> Result<()> {
+        self.check_final_size(buf.max_off(), buf.fin())?;
+
         if self.off >= buf.off() + buf.len() {
             // Data is fully duplicate.
             return Ok(());
@@ -171,7 +355,112 @@ impl RecvBuf {
 
         self.len = cmp::max(self.len, buf.off + buf.len());
 
-        self.data.push(buf);
+        // Drop the data that was already read by the application.
+        if buf.off < self.off {
+            buf.skip(self.off - buf.off);
+        }
+
+        if let Some((_, v)) = self.data.range_mut(..=buf.off).next_back() {
+            if v.max_off() >= buf.max_off() {
+                // Data is fully duplicate, but might carry the final size.
+                v.fin = v.fin || (buf.fin && v.max_off() == buf.max_off());
+                return Ok(());
+            }
+        }
+
+        // Drop the data overlapping with the previous range.
+        if let Some((_, v)) = self.data.range(..buf.off).next_back() {
+            if v.max_off() > buf.off {
+                let len = v.max_off() - buf.off;
+                buf.skip(len);
+            }
+        }
+
+        // Drop the ranges fully covered by the new data, and the new data
+        // overlapping with the following range, so that buffered ranges
+        // never overlap.
+        let overlaps: Vec<usize> = self.data.range(buf.off..buf.max_off())
+                                            .map(|(off, _)| *off)
+                                            .collect();
+
+        for off in overlaps {
+            if self.data[&off].max_off() > buf.max_off() {
+                buf.truncate(off - buf.off);
+                break;
+            }
+
+            let v = self.data.remove(&off).unwrap();
+            self.buffered -= v.len();
+
+            buf.fin = buf.fin || v.fin;
+        }
+
+        // Merge with the range ending where the new data starts, if any.
+        let prev = self.data.range(..buf.off)
+                            .next_back()
+                            .filter(|(_, v)| v.max_off() == buf.off)
+                            .map(|(off, _)| *off);
+
+        if let Some(off) = prev {
+            let mut prev = self.data.remove(&off).unwrap();
+            self.buffered -= prev.len();
+
+            prev.append(buf);
+            buf = prev;
+        }
+
+        // Merge with the range starting where the new data ends, if any.
+        if let Some(next) = self.data.remove(&buf.max_off()) {
+            self.buffered -= next.len();
+
+            buf.append(next);
+        }
+
+        self.buffered += buf.len();
+
+        self.data.insert(buf.off, buf);
+
+        Ok(())
+    }
+
+    fn push_slice(&mut self, data: &[u8], off: usize, fin: bool)
+                                                            -> Result<()> {
+        self.check_final_size(off + data.len(), fin)?;
+
+        if self.off >= off + data.len() {
+            // Data is fully duplicate.
+            return Ok(());
+        }
+
+        // Only copy the data that was not already read by the application.
+        let skip = self.off.saturating_sub(off);
+
+        self.push(RangeBuf::from(&data[skip..], off + skip, fin))
+    }
+
+    /// Checks data ending at `max_off` against the stream's final size, and
+    /// records the final size if `fin` is set.
+    fn check_final_size(&mut self, max_off: usize, fin: bool) -> Result<()> {
+        match self.fin_off {
+            // The final size can't change, and no data can be sent past it.
+            Some(fin_off) =>
+                if max_off > fin_off || (fin && max_off != fin_off) {
+                    return Err(Error::FinalSize);
+                },
+
+            None if fin => {
+                // Data was already received past the final size.
+                if max_off < self.max_off {
+                    return Err(Error::FinalSize);
+                }
+
+                self.fin_off = Some(max_off);
+            },
+
+            None => (),
+        }
+
+        self.max_off = cmp::max(self.max_off, max_off);
 
         Ok(())
     }
@@ -181,11 +470,13 @@ impl RecvBuf {
         let mut out_len = max_len;
 
         while out_len > 0 && self.ready() {
-            let mut buf = match self.data.pop() {
+            let mut buf = match self.data.remove(&self.off) {
                 Some(v) => v,
                 None => break,
             };
 
+            self.buffered -= buf.len();
+
             if buf.len() > out_len {
                 let new_buf = RangeBuf {
                     data: buf.data.split_off(out_len),
@@ -195,7 +486,9 @@ impl RecvBuf {
 
                 buf.fin = false;
 
-                self.data.push(new_buf);
+                self.buffered += new_buf.len();
+
+                self.data.insert(new_buf.off, new_buf);
             }
 
             if out.is_empty() {
@@ -209,25 +502,44 @@ impl RecvBuf {
 
             out.fin = out.fin || buf.fin();
 
-            out.data.append(&mut buf.data);
+            // The first range is handed over without copying it, only the
+            // following ones, if any, are appended to it.
+            if out.data.is_empty() {
+                out.data = buf.data;
+            } else {
+                out.data.append(&mut buf.data);
+            }
         }
 
         Ok(out)
     }
 
     fn ready(&self) -> bool {
-        let buf = match self.data.peek() {
-            Some(v) => v,
-            None => return false,
-        };
+        self.data.contains_key(&self.off)
+    }
 
-        buf.off == self.off
+    /// Returns the offset up to which data was received without gaps.
+    fn contiguous_off(&self) -> usize {
+        self.data.get(&self.off).map_or(self.off, |b| b.max_off())
+    }
+
+    fn is_fin(&self) -> bool {
+        self.fin_off == Some(self.off)
     }
 
     #[allow(dead_code)]
     fn len(&self) -> usize {
         self.len
     }
+
+    fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    fn mem_usage(&self) -> usize {
+        self.data.len() * std::mem::size_of::<(usize, RangeBuf)>() +
+            self.data.values().map(|b| b.mem_usage()).sum::<usize>()
+    }
 }
 
 #[derive(Default)]
@@ -235,6 +547,7 @@ struct SendBuf {
     data: BinaryHeap<RangeBuf>,
     off: usize,
     len: usize,
+    fin: bool,
 }
 
 impl SendBuf {
@@ -263,8 +576,22 @@ impl SendBuf {
         Ok(())
     }
 
+    fn push_buf(&mut self, mut buf: RangeBuf) {
+        let len = buf.len();
+
+        buf.off = self.off;
+
+        self.len += buf.len();
+        self.fin = self.fin || buf.fin();
+
+        self.data.push(buf);
+
+        self.off += len;
+    }
+
     fn push(&mut self, buf: RangeBuf) -> Result<()> {
         self.len += buf.len();
+        self.fin = self.fin || buf.fin();
 
         self.data.push(buf);
 
@@ -329,10 +656,30 @@ impl SendBuf {
         }
     }
 
+    /// Returns the offset following the last byte written to the buffer.
+    fn max_off(&self) -> usize {
+        self.off
+    }
+
     #[allow(dead_code)]
     fn len(&self) -> usize {
         self.len
     }
+
+    fn shrink_to_fit(&mut self) {
+        if self.data.is_empty() {
+            self.data.shrink_to_fit();
+        }
+    }
+
+    fn mem_usage(&self) -> usize {
+        heap_mem_usage(&self.data)
+    }
+}
+
+fn heap_mem_usage(data: &BinaryHeap<RangeBuf>) -> usize {
+    data.capacity() * std::mem::size_of::<RangeBuf>() +
+        data.iter().map(|b| b.mem_usage()).sum::<usize>()
 }
 
 /// Buffer holding data at a specific offset.
@@ -376,6 +723,31 @@ impl RangeBuf {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Returns the amount of heap memory allocated for `self`'s data.
+    pub(crate) fn mem_usage(&self) -> usize {
+        self.data.capacity()
+    }
+
+    /// Drops the first `len` bytes of `self`.
+    pub(crate) fn skip(&mut self, len: usize) {
+        self.data.drain(..len);
+        self.off += len;
+    }
+
+    /// Drops the data of `self` past its first `len` bytes.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.fin = false;
+    }
+
+    /// Appends the data of `other`, which must start where `self` ends.
+    pub(crate) fn append(&mut self, other: RangeBuf) {
+        debug_assert_eq!(self.max_off(), other.off());
+
+        self.data.extend_from_slice(&other.data);
+        self.fin = other.fin;
+    }
 }
 
 impl std::ops::Deref for RangeBuf {
@@ -451,6 +823,53 @@ mod tests {
         assert_eq!(read.len(), 0);
     }
 
+    #[test]
+    fn push_slice_skips_read_data() {
+        let mut buf = RecvBuf::default();
+
+        assert!(buf.push_slice(b"hello", 0, false).is_ok());
+
+        let read = buf.pop(std::usize::MAX).unwrap();
+        assert_eq!(&read[..], b"hello");
+
+        // Fully duplicate data is dropped.
+        assert!(buf.push_slice(b"hel", 0, false).is_ok());
+        assert_eq!(buf.data.len(), 0);
+
+        // Only the part that wasn't read yet is stored.
+        assert!(buf.push_slice(b"helloworld", 0, true).is_ok());
+        assert_eq!(buf.data[&5].off(), 5);
+        assert_eq!(buf.data[&5].len(), 5);
+
+        let read = buf.pop(std::usize::MAX).unwrap();
+        assert_eq!(read.off(), 5);
+        assert_eq!(&read[..], b"world");
+        assert!(read.fin());
+    }
+
+    #[test]
+    fn final_size() {
+        let mut buf = RecvBuf::default();
+
+        assert!(buf.push(RangeBuf::from(b"helloworld", 0, false)).is_ok());
+
+        // Final size below data already received.
+        assert_eq!(buf.push(RangeBuf::from(b"", 5, true)),
+                   Err(Error::FinalSize));
+
+        assert!(buf.push(RangeBuf::from(b"", 10, true)).is_ok());
+
+        // Same final size is fine, even if retransmitted.
+        assert!(buf.push_slice(b"world", 5, true).is_ok());
+
+        // Data past the final size.
+        assert_eq!(buf.push(RangeBuf::from(b"!", 10, false)),
+                   Err(Error::FinalSize));
+
+        // Final size changed.
+        assert_eq!(buf.push_slice(b"hello", 0, true), Err(Error::FinalSize));
+    }
+
     #[test]
     fn split_read() {
         let mut buf = RecvBuf::default();
@@ -531,6 +950,190 @@ mod tests {
         assert_eq!(read.fin(), true);
     }
 
+    #[test]
+    fn coalesced_read() {
+        let mut buf = RecvBuf::default();
+
+        assert!(buf.push(RangeBuf::from(b"world", 10, false)).is_ok());
+        assert!(buf.push(RangeBuf::from(b"something", 20, true)).is_ok());
+        assert_eq!(buf.data.len(), 2);
+
+        // Fills the hole between the two ranges, merging them.
+        assert!(buf.push(RangeBuf::from(b"helloworld", 10, false)).is_ok());
+
+        // Data already buffered is dropped.
+        assert!(buf.push(RangeBuf::from(b"!!!!!", 15, false)).is_ok());
+        assert_eq!(buf.data.len(), 1);
+        assert_eq!(buf.data[&10].len(), 19);
+        assert!(buf.data[&10].fin());
+
+        assert!(!buf.ready());
+
+        // Contiguous with the buffered range, which is appended to it.
+        assert!(buf.push(RangeBuf::from(b"0123456789", 0, false)).is_ok());
+        assert_eq!(buf.data.len(), 1);
+        assert!(buf.ready());
+
+        let read = buf.pop(std::usize::MAX).unwrap();
+        assert_eq!(read.off(), 0);
+        assert_eq!(read.fin(), true);
+        assert_eq!(&read[..], b"0123456789helloworldsomething");
+        assert!(buf.data.is_empty());
+    }
+
+    #[test]
+    fn overlapping_read() {
+        let mut buf = RecvBuf::default();
+
+        assert!(buf.push(RangeBuf::from(b"lowor", 13, false)).is_ok());
+        assert!(buf.push(RangeBuf::from(b"thing", 24, true)).is_ok());
+        assert_eq!(buf.buffered(), 10);
+
+        // Overlaps the end of the first range and the start of the second
+        // one, only the data in between is kept.
+        assert!(buf.push(RangeBuf::from(b"orldsomet", 16, false)).is_ok());
+        assert_eq!(buf.data.len(), 1);
+        assert_eq!(buf.buffered(), 16);
+
+        // Overlaps the start of the buffered range.
+        assert!(buf.push(RangeBuf::from(b"hello", 10, false)).is_ok());
+        assert_eq!(buf.data.len(), 1);
+        assert_eq!(buf.buffered(), 19);
+
+        assert!(buf.push(RangeBuf::from(b"0123456789", 0, false)).is_ok());
+        assert_eq!(buf.buffered(), 29);
+
+        let read = buf.pop(12).unwrap();
+        assert_eq!(&read[..], b"0123456789he");
+
+        // Data overlapping with what was already read is trimmed.
+        assert!(buf.push(RangeBuf::from(b"89hellowo", 8, false)).is_ok());
+        assert_eq!(buf.data.len(), 1);
+        assert_eq!(buf.buffered(), 17);
+
+        let read = buf.pop(std::usize::MAX).unwrap();
+        assert_eq!(read.off(), 12);
+        assert_eq!(read.fin(), true);
+        assert_eq!(&read[..], b"lloworldsomething");
+        assert_eq!(buf.buffered(), 0);
+        assert!(buf.data.is_empty());
+    }
+
+    #[test]
+    fn overlapping_read_under_loss() {
+        let mut buf = RecvBuf::default();
+
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+
+        // Data is first sent in 20 bytes frames, of which every other one
+        // is lost, and then retransmitted in 30 bytes frames, in reverse
+        // order, as if coalesced with new data.
+        for off in (0..200).step_by(40) {
+            let fin = off + 20 == data.len();
+            let frame = RangeBuf::from(&data[off..off + 20], off, fin);
+            assert!(buf.push(frame).is_ok());
+        }
+
+        for off in (5..200).step_by(30).rev() {
+            let end = cmp::min(off + 30, data.len());
+            let fin = end == data.len();
+            let frame = RangeBuf::from(&data[off..end], off, fin);
+            assert!(buf.push(frame).is_ok());
+
+            assert_eq!(buf.data.values().map(|b| b.len()).sum::<usize>(),
+                       buf.buffered());
+        }
+
+        // Partial reads interleaved with more duplicates.
+        let mut read = Vec::new();
+
+        while !buf.is_fin() {
+            let off = read.len();
+            let end = cmp::min(off + 25, data.len());
+            let frame = RangeBuf::from(&data[off.saturating_sub(5)..end],
+                                       off.saturating_sub(5), false);
+            assert!(buf.push(frame).is_ok());
+
+            let out = buf.pop(7).unwrap();
+            assert_eq!(out.off(), read.len());
+            read.extend_from_slice(&out);
+        }
+
+        assert_eq!(read, data);
+        assert_eq!(buf.buffered(), 0);
+        assert!(buf.data.is_empty());
+    }
+
+    #[test]
+    fn pop_moves_data() {
+        let mut buf = RecvBuf::default();
+
+        assert!(buf.push(RangeBuf::from(b"helloworld", 0, true)).is_ok());
+
+        let ptr = buf.data[&0].data.as_ptr();
+
+        // Data popped from the start of a range keeps its allocation.
+        let read = buf.pop(5).unwrap();
+        assert_eq!(&read[..], b"hello");
+        assert_eq!(read.data.as_ptr(), ptr);
+
+        let ptr = buf.data[&5].data.as_ptr();
+
+        let read = buf.pop(std::usize::MAX).unwrap();
+        assert_eq!(&read[..], b"world");
+        assert!(read.fin());
+        assert_eq!(read.data.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn reorder_window() {
+        let mut stream = Stream::new(1000, 0);
+
+        assert!(!stream.exceeds_reorder_window(0, 100, 50));
+
+        // Out-of-order data is limited to the window.
+        assert!(!stream.exceeds_reorder_window(10, 40, 50));
+        assert!(stream.exceeds_reorder_window(10, 41, 50));
+
+        assert!(stream.recv_push_slice(&[0; 30], 0, false).is_ok());
+
+        // Data contiguous with what was received is always accepted.
+        assert!(!stream.exceeds_reorder_window(30, 100, 50));
+        assert!(stream.exceeds_reorder_window(31, 20, 50));
+
+        // The window moves forward as data is read.
+        assert!(stream.recv_pop(20).is_ok());
+        assert!(!stream.exceeds_reorder_window(31, 20, 50));
+        assert!(stream.exceeds_reorder_window(31, 40, 50));
+    }
+
+    #[test]
+    fn buffered_len() {
+        let mut buf = RecvBuf::default();
+
+        assert!(buf.push(RangeBuf::from(b"world", 10, false)).is_ok());
+        assert_eq!(buf.buffered(), 5);
+
+        // Longer data at the same offset replaces the buffered range.
+        assert!(buf.push(RangeBuf::from(b"worldwide", 10, false)).is_ok());
+        assert_eq!(buf.buffered(), 9);
+
+        // Duplicate data isn't counted.
+        assert!(buf.push(RangeBuf::from(b"wide", 15, false)).is_ok());
+        assert_eq!(buf.buffered(), 9);
+
+        assert!(buf.push(RangeBuf::from(b"0123456789", 0, false)).is_ok());
+        assert_eq!(buf.buffered(), 19);
+
+        let read = buf.pop(5).unwrap();
+        assert_eq!(read.len(), 5);
+        assert_eq!(buf.buffered(), 14);
+
+        let read = buf.pop(std::usize::MAX).unwrap();
+        assert_eq!(read.len(), 14);
+        assert_eq!(buf.buffered(), 0);
+    }
+
     #[test]
     fn empty_write() {
         let mut buf = SendBuf::default();
@@ -746,13 +1349,77 @@ mod tests {
 
         assert!(stream.more_credit());
 
-        assert_eq!(stream.recv_update_max_data(), 25);
+        let now = time::Instant::now();
+        assert_eq!(stream.recv_update_max_data(15, now), 25);
         assert!(!stream.more_credit());
 
         let third = RangeBuf::from(b"something", 10, false);
         assert_eq!(stream.recv_push(third), Ok(()));
     }
 
+    #[test]
+    fn recv_window_autotuning() {
+        let now = time::Instant::now();
+        let rtt = time::Duration::from_millis(100);
+
+        let mut stream = Stream::new(10, 0);
+
+        assert_eq!(stream.recv_push_slice(b"helloworld", 0, false), Ok(()));
+        assert!(stream.recv_pop(std::usize::MAX).is_ok());
+
+        // The first update only records the time.
+        let window = stream.autotune_window(now, rtt, 40);
+        assert_eq!(stream.recv_update_max_data(window, now), 20);
+
+        assert_eq!(stream.recv_push_slice(b"helloworld", 10, false), Ok(()));
+        assert!(stream.recv_pop(std::usize::MAX).is_ok());
+
+        // The window was consumed within two RTTs, so it's doubled.
+        let window = stream.autotune_window(now + rtt, rtt, 40);
+        assert_eq!(stream.recv_update_max_data(window, now + rtt), 40);
+
+        assert_eq!(stream.recv_push_slice(&[0; 20], 20, false), Ok(()));
+        assert!(stream.recv_pop(std::usize::MAX).is_ok());
+
+        // The window grows up to the maximum.
+        let window = stream.autotune_window(now + rtt * 2, rtt, 40);
+        assert_eq!(stream.recv_update_max_data(window, now + rtt * 2), 80);
+
+        assert_eq!(stream.recv_push_slice(&[0; 40], 40, false), Ok(()));
+        assert!(stream.recv_pop(std::usize::MAX).is_ok());
+
+        // Slow readers don't grow the window.
+        let window = stream.autotune_window(now + rtt * 10, rtt, 100);
+        assert_eq!(stream.recv_update_max_data(window, now + rtt * 10), 120);
+    }
+
+    #[test]
+    fn recv_window_autotuning_unsent_update() {
+        let now = time::Instant::now();
+        let rtt = time::Duration::from_millis(100);
+
+        let mut stream = Stream::new(10, 0);
+
+        assert_eq!(stream.recv_push_slice(b"helloworld", 0, false), Ok(()));
+        assert!(stream.recv_pop(std::usize::MAX).is_ok());
+
+        let window = stream.autotune_window(now, rtt, 40);
+        assert_eq!(stream.recv_update_max_data(window, now), 20);
+
+        assert_eq!(stream.recv_push_slice(b"helloworld", 10, false), Ok(()));
+        assert!(stream.recv_pop(std::usize::MAX).is_ok());
+
+        // An update that doesn't fit in the packet leaves the window alone.
+        let window = stream.autotune_window(now + rtt, rtt, 40);
+        assert_eq!(stream.recv_max_data_next(window), 40);
+        assert!(stream.more_credit());
+
+        // Once the update is sent later, it's still based on the last update
+        // that was actually sent.
+        let window = stream.autotune_window(now + rtt * 3, rtt, 40);
+        assert_eq!(stream.recv_update_max_data(window, now + rtt * 3), 30);
+    }
+
     #[test]
     fn send_flow_control() {
         let mut stream = Stream::new(0, 15);
@@ -0,0 +1,839 @@
+// Copyright (C) 2018, Cloudflare, Inc.
+// Copyright (C) 2018, Alessandro Ghedini
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright
+//       notice, this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! In-memory test harness.
+//!
+//! A [`Pipe`] connects a client and a server [`Connection`] in memory, so
+//! that protocol features can be exercised end to end without sockets. The
+//! datagrams of each flight can be dropped, reordered or duplicated before
+//! being delivered, in order to simulate adverse network conditions.
+//!
+//! For more realistic scenarios, [`Pipe::simulate()`] runs both endpoints
+//! against a [`NetworkModel`] on a simulated clock, with configurable delay,
+//! jitter, loss, duplication, reordering and bandwidth.
+//!
+//! Datagrams captured from a connection, e.g. from a pcap of an interop
+//! run, can be turned back into frames with a [`Decryptor`], given the
+//! connection's logged secrets.
+//!
+//! [`Pipe`]: struct.Pipe.html
+//! [`Connection`]: ../struct.Connection.html
+//! [`Pipe::simulate()`]: struct.Pipe.html#method.simulate
+//! [`NetworkModel`]: struct.NetworkModel.html
+//! [`Decryptor`]: struct.Decryptor.html
+
+use std::cmp;
+use std::time;
+
+use crate::CipherSuite;
+use crate::Config;
+use crate::Connection;
+use crate::Error;
+use crate::Result;
+use crate::Type;
+
+use crate::crypto;
+use crate::frame;
+use crate::octets;
+use crate::packet;
+
+/// Returns the config used by [`Pipe::new()`], with default flow control
+/// limits, and without peer verification.
+///
+/// The config uses the certificate and key found in the `examples/`
+/// directory, so this must be run from the root of the source tree.
+///
+/// [`Pipe::new()`]: struct.Pipe.html#method.new
+pub fn default_config() -> Result<Config> {
+    let mut config = Config::new(crate::VERSION_DRAFT17)?;
+    config.load_cert_chain_from_pem_file("examples/cert.crt")?;
+    config.load_priv_key_from_pem_file("examples/cert.key")?;
+    config.set_initial_max_data(30000);
+    config.set_initial_max_stream_data_bidi_local(15000);
+    config.set_initial_max_stream_data_bidi_remote(15000);
+    config.set_initial_max_stream_data_uni(15000);
+    config.set_initial_max_streams_bidi(3);
+    config.set_initial_max_streams_uni(3);
+    config.verify_peer(false);
+
+    Ok(config)
+}
+
+/// A flight of datagrams sent by one endpoint.
+pub type Flight = Vec<Vec<u8>>;
+
+/// A client and a server connected in memory.
+pub struct Pipe {
+    /// The client side of the pipe.
+    pub client: Box<Connection>,
+
+    /// The server side of the pipe.
+    pub server: Box<Connection>,
+}
+
+impl Pipe {
+    /// Creates a pipe using [`default_config()`].
+    ///
+    /// [`default_config()`]: fn.default_config.html
+    pub fn new() -> Result<Pipe> {
+        Pipe::with_config(&mut default_config()?)
+    }
+
+    /// Creates a pipe where both endpoints use the given config.
+    pub fn with_config(config: &mut Config) -> Result<Pipe> {
+        let client_scid = config.new_cid();
+        let server_scid = config.new_cid();
+
+        Ok(Pipe {
+            client: crate::connect(None, &client_scid, config)?,
+            server: crate::accept(&server_scid, None, config)?,
+        })
+    }
+
+    /// Creates a pipe from separate client and server configs.
+    pub fn with_client_and_server_config(client_config: &mut Config,
+                                         server_config: &mut Config)
+                                                        -> Result<Pipe> {
+        let client_scid = client_config.new_cid();
+        let server_scid = server_config.new_cid();
+
+        Ok(Pipe {
+            client: crate::connect(None, &client_scid, client_config)?,
+            server: crate::accept(&server_scid, None, server_config)?,
+        })
+    }
+
+    /// Exchanges packets until both endpoints complete the handshake.
+    pub fn handshake(&mut self) -> Result<()> {
+        while !self.client.is_established() || !self.server.is_established() {
+            let flight = emit_flight(&mut self.client)?;
+            process_flight(&mut self.server, flight)?;
+
+            let flight = emit_flight(&mut self.server)?;
+            process_flight(&mut self.client, flight)?;
+        }
+
+        Ok(())
+    }
+
+    /// Exchanges packets until neither endpoint has anything left to send.
+    pub fn advance(&mut self) -> Result<()> {
+        self.advance_with(|_| ())
+    }
+
+    /// Exchanges packets until neither endpoint has anything left to send,
+    /// passing each flight to `filter` before delivering it.
+    ///
+    /// The filter can drop, reorder, duplicate or corrupt datagrams in order
+    /// to simulate loss and reordering.
+    pub fn advance_with<F>(&mut self, mut filter: F) -> Result<()>
+                                                where F: FnMut(&mut Flight) {
+        loop {
+            let mut client_flight = emit_flight(&mut self.client)?;
+            let mut server_flight = emit_flight(&mut self.server)?;
+
+            if client_flight.is_empty() && server_flight.is_empty() {
+                return Ok(());
+            }
+
+            filter(&mut client_flight);
+            process_flight(&mut self.server, client_flight)?;
+
+            filter(&mut server_flight);
+            process_flight(&mut self.client, server_flight)?;
+        }
+    }
+
+    /// Runs both endpoints over the given network model on a simulated
+    /// clock, until `cond` returns `true`, or `max_time` of simulated time
+    /// has elapsed.
+    ///
+    /// The `cond` closure is called every time the simulation makes
+    /// progress, and can also be used to drive the application (e.g. by
+    /// writing to streams once the handshake completes).
+    ///
+    /// Returns whether `cond` was met.
+    pub fn simulate<F>(&mut self, model: &NetworkModel, max_time: time::Duration,
+                       mut cond: F) -> Result<bool>
+                                            where F: FnMut(&mut Pipe) -> bool {
+        let mut rng = Rng::new(model.seed);
+
+        let mut to_server = Link::default();
+        let mut to_client = Link::default();
+
+        let start = time::Instant::now();
+        let mut now = start;
+
+        loop {
+            if cond(self) {
+                return Ok(true);
+            }
+
+            send_all(&mut self.client, &mut to_server, now, model, &mut rng)?;
+            send_all(&mut self.server, &mut to_client, now, model, &mut rng)?;
+
+            let next = [to_server.next_delivery(), to_client.next_delivery(),
+                        self.client.timeout_instant(),
+                        self.server.timeout_instant()]
+                .iter().filter_map(|&t| t).min();
+
+            let next = match next {
+                Some(v) => std::cmp::max(v, now),
+
+                None => return Ok(false),
+            };
+
+            if next.duration_since(start) > max_time {
+                return Ok(false);
+            }
+
+            now = next;
+
+            for mut pkt in to_server.due(now) {
+                recv_one(&mut self.server, &mut pkt, now)?;
+            }
+
+            for mut pkt in to_client.due(now) {
+                recv_one(&mut self.client, &mut pkt, now)?;
+            }
+
+            for conn in [&mut self.client, &mut self.server].iter_mut() {
+                if conn.timeout_instant().map_or(false, |t| t <= now) {
+                    conn.on_timeout_at(now);
+                }
+            }
+        }
+    }
+}
+
+fn send_all(conn: &mut Connection, link: &mut Link, now: time::Instant,
+            model: &NetworkModel, rng: &mut Rng) -> Result<()> {
+    loop {
+        let mut out = vec![0; 65535];
+
+        let len = match conn.send_at(&mut out, now) {
+            Ok(v) => v,
+
+            Err(Error::Done) => return Ok(()),
+
+            Err(e) => return Err(e),
+        };
+
+        out.truncate(len);
+        link.send(out, now, model, rng);
+    }
+}
+
+fn recv_one(conn: &mut Connection, pkt: &mut [u8], now: time::Instant)
+                                                                -> Result<()> {
+    match conn.recv_at(pkt, now) {
+        Ok(_) | Err(Error::Done) => Ok(()),
+
+        Err(e) => Err(e),
+    }
+}
+
+/// A model of the network path between the two endpoints of a pipe.
+///
+/// The same model applies to both directions, but each direction is
+/// simulated independently.
+#[derive(Clone, Debug)]
+pub struct NetworkModel {
+    /// The one-way propagation delay.
+    pub delay: time::Duration,
+
+    /// The maximum random delay added to each datagram.
+    pub jitter: time::Duration,
+
+    /// The probability of a datagram being dropped, between 0 and 1.
+    pub loss: f64,
+
+    /// The probability of a datagram being delivered twice, between 0 and 1.
+    pub duplicate: f64,
+
+    /// The probability of a datagram being held back, between 0 and 1.
+    pub reorder: f64,
+
+    /// The maximum number of later datagrams a held back datagram is
+    /// delivered after.
+    pub reorder_window: usize,
+
+    /// The link bandwidth in bytes per second, or `None` for unlimited.
+    pub bandwidth: Option<u64>,
+
+    /// The seed of the random number generator, so that runs are
+    /// reproducible.
+    pub seed: u64,
+}
+
+impl Default for NetworkModel {
+    fn default() -> NetworkModel {
+        NetworkModel {
+            delay: time::Duration::from_millis(10),
+            jitter: time::Duration::new(0, 0),
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_window: 0,
+            bandwidth: None,
+            seed: 1,
+        }
+    }
+}
+
+/// A deterministic xorshift* pseudo-random number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // The state must never be zero.
+        Rng(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number uniformly distributed in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.next_f64() < p
+    }
+}
+
+/// One direction of the simulated network path.
+#[derive(Default)]
+struct Link {
+    /// Datagrams in flight, along with their delivery time.
+    queue: Vec<(time::Instant, Vec<u8>)>,
+
+    /// Held back datagrams, along with the number of later datagrams that
+    /// still need to be queued before them.
+    held: Vec<(usize, Vec<u8>)>,
+
+    /// The time the link finishes transmitting the last queued datagram.
+    busy_until: Option<time::Instant>,
+}
+
+impl Link {
+    fn send(&mut self, pkt: Vec<u8>, now: time::Instant, model: &NetworkModel,
+            rng: &mut Rng) {
+        if rng.chance(model.loss) {
+            return;
+        }
+
+        if model.reorder_window > 0 && rng.chance(model.reorder) {
+            let after = 1 + (rng.next_u64() % model.reorder_window as u64);
+            self.held.push((after as usize, pkt));
+            return;
+        }
+
+        let copies = if rng.chance(model.duplicate) { 2 } else { 1 };
+
+        for _ in 0..copies {
+            self.enqueue(pkt.clone(), now, model, rng);
+        }
+
+        // Release held back datagrams whose turn has come.
+        for held in self.held.iter_mut() {
+            held.0 -= 1;
+        }
+
+        while let Some(i) = self.held.iter().position(|h| h.0 == 0) {
+            let (_, pkt) = self.held.remove(i);
+            self.enqueue(pkt, now, model, rng);
+        }
+    }
+
+    fn enqueue(&mut self, pkt: Vec<u8>, now: time::Instant,
+               model: &NetworkModel, rng: &mut Rng) {
+        let mut sent = now;
+
+        if let Some(bandwidth) = model.bandwidth {
+            let start = self.busy_until.map_or(now, |t| std::cmp::max(t, now));
+
+            let nanos = pkt.len() as u64 * 1_000_000_000 / bandwidth;
+            sent = start + time::Duration::from_nanos(nanos);
+
+            self.busy_until = Some(sent);
+        }
+
+        let jitter = model.jitter.as_nanos() as u64;
+        let jitter = if jitter > 0 { rng.next_u64() % jitter } else { 0 };
+
+        let deliver_at =
+            sent + model.delay + time::Duration::from_nanos(jitter);
+
+        self.queue.push((deliver_at, pkt));
+    }
+
+    fn next_delivery(&self) -> Option<time::Instant> {
+        self.queue.iter().map(|(t, _)| *t).min()
+    }
+
+    /// Removes the datagrams due by `now`, in delivery order.
+    fn due(&mut self, now: time::Instant) -> Flight {
+        let mut due: Vec<(time::Instant, Vec<u8>)> = Vec::new();
+
+        let mut i = 0;
+
+        while i < self.queue.len() {
+            if self.queue[i].0 <= now {
+                due.push(self.queue.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+
+        due.sort_by_key(|(t, _)| *t);
+
+        due.into_iter().map(|(_, pkt)| pkt).collect()
+    }
+}
+
+/// Collects all the datagrams the given connection has to send.
+pub fn emit_flight(conn: &mut Connection) -> Result<Flight> {
+    let mut flight = Vec::new();
+
+    loop {
+        let mut out = vec![0; 65535];
+
+        let len = match conn.send(&mut out) {
+            Ok(v) => v,
+
+            Err(Error::Done) => break,
+
+            Err(e) => return Err(e),
+        };
+
+        out.truncate(len);
+        flight.push(out);
+    }
+
+    Ok(flight)
+}
+
+/// Delivers the given datagrams to the connection.
+pub fn process_flight(conn: &mut Connection, flight: Flight) -> Result<()> {
+    for mut pkt in flight {
+        match conn.recv(&mut pkt) {
+            Ok(_) | Err(Error::Done) => (),
+
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// A packet decrypted by a [`Decryptor`].
+///
+/// [`Decryptor`]: struct.Decryptor.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecryptedPacket {
+    /// The type of the packet.
+    pub ty: Type,
+
+    /// The packet number.
+    pub pkt_num: u64,
+
+    /// A summary of the frames in the packet.
+    pub frames: Vec<String>,
+}
+
+/// Decrypts captured datagrams of a connection.
+///
+/// Initial packets only need the original destination connection ID chosen
+/// by the client, while the keys for Handshake and 1-RTT packets are derived
+/// from the secrets logged by [`log_keys()`].
+///
+/// [`log_keys()`]: ../struct.Config.html#method.log_keys
+pub struct Decryptor {
+    cid_len: usize,
+
+    // Opening keys, indexed by packet number space and by whether the
+    // packets were sent by the server.
+    keys: [[Option<crypto::Open>; 2]; 3],
+
+    largest_pn: [[u64; 2]; 3],
+}
+
+impl Decryptor {
+    /// Creates a decryptor for the connection whose first Initial packet
+    /// was sent to `odcid`.
+    ///
+    /// Both endpoints' connection IDs are expected to be `cid_len` bytes
+    /// long, which is needed to parse short header packets.
+    pub fn new(odcid: &[u8], cid_len: usize) -> Result<Decryptor> {
+        // Packets sent by the client are opened with the server's keys, and
+        // the other way round.
+        let version = crate::VERSION_DRAFT17;
+
+        let (client, _) =
+            crypto::derive_initial_key_material(version, odcid, true)?;
+        let (server, _) =
+            crypto::derive_initial_key_material(version, odcid, false)?;
+
+        Ok(Decryptor {
+            cid_len,
+            keys: [[Some(client), Some(server)], [None, None], [None, None]],
+            largest_pn: [[0; 2]; 3],
+        })
+    }
+
+    /// Adds the secrets found in `keylog`, as written by [`log_keys()`] for
+    /// a connection that negotiated `suite`.
+    ///
+    /// Lines with unknown labels are ignored.
+    ///
+    /// [`log_keys()`]: ../struct.Config.html#method.log_keys
+    pub fn add_keylog(&mut self, keylog: &str, suite: CipherSuite)
+                                                            -> Result<()> {
+        for line in keylog.lines() {
+            let mut fields = line.split_whitespace();
+
+            let label = match fields.next() {
+                Some(v) => v.trim_start_matches("QUIC_"),
+
+                None => continue,
+            };
+
+            let (space, server) = match label {
+                "CLIENT_HANDSHAKE_TRAFFIC_SECRET" => (1, false),
+                "SERVER_HANDSHAKE_TRAFFIC_SECRET" => (1, true),
+                "CLIENT_TRAFFIC_SECRET_0" => (2, false),
+                "SERVER_TRAFFIC_SECRET_0" => (2, true),
+                _ => continue,
+            };
+
+            let secret = fields.nth(1).ok_or(Error::InvalidState)?;
+            let secret = hex_decode(secret).ok_or(Error::InvalidState)?;
+
+            self.keys[space][server as usize] =
+                Some(derive_open(suite.algorithm(), &secret)?);
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts the packets coalesced in a datagram.
+    ///
+    /// Packets for which no keys are known are skipped.
+    pub fn decrypt(&mut self, datagram: &[u8], from_server: bool)
+                                            -> Result<Vec<DecryptedPacket>> {
+        let mut buf = datagram.to_vec();
+
+        let mut pkts = Vec::new();
+
+        let mut done = 0;
+
+        while done < buf.len() {
+            let mut b = octets::OctetsMut::with_slice(&mut buf[done..]);
+
+            let mut hdr = packet::Header::from_bytes(&mut b, self.cid_len)?;
+
+            let space = match hdr.ty {
+                Type::Initial => 0,
+
+                Type::Handshake => 1,
+
+                Type::Application => 2,
+
+                // Retry and Version Negotiation packets are never coalesced
+                // and carry no frames.
+                _ => break,
+            };
+
+            let payload_len = if hdr.ty == Type::Application {
+                b.cap()
+            } else {
+                let len = b.get_varint()?;
+
+                if len > b.cap() as u64 {
+                    return Err(Error::BufferTooShort);
+                }
+
+                len as usize
+            };
+
+            let pkt_len = b.off() + payload_len;
+
+            let aead = match self.keys[space][from_server as usize] {
+                Some(ref v) => v,
+
+                None => {
+                    done += pkt_len;
+                    continue;
+                },
+            };
+
+            packet::decrypt_hdr(&mut b, &mut hdr, aead)?;
+
+            let largest_pn = &mut self.largest_pn[space][from_server as usize];
+
+            let pn = packet::decode_pkt_num(*largest_pn, hdr.pkt_num,
+                                            hdr.pkt_num_len);
+
+            *largest_pn = cmp::max(*largest_pn, pn);
+
+            let mut payload = packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len,
+                                                  payload_len, aead)?;
+
+            let mut frames = Vec::new();
+
+            while payload.cap() > 0 {
+                let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+
+                frames.push(format!("{:?}", frame));
+            }
+
+            pkts.push(DecryptedPacket {
+                ty: hdr.ty,
+                pkt_num: pn,
+                frames,
+            });
+
+            done += pkt_len;
+        }
+
+        Ok(pkts)
+    }
+}
+
+fn derive_open(alg: crypto::Algorithm, secret: &[u8]) -> Result<crypto::Open> {
+    let mut key = vec![0; alg.key_len()];
+    let mut iv = vec![0; alg.nonce_len()];
+    let mut hp_key = vec![0; alg.key_len()];
+
+    let version = crate::VERSION_DRAFT17;
+
+    crypto::derive_pkt_key(version, alg, secret, &mut key)?;
+    crypto::derive_pkt_iv(version, alg, secret, &mut iv)?;
+    crypto::derive_hdr_key(version, alg, secret, &mut hp_key)?;
+
+    crypto::Open::new(alg, &key, &iv, &hp_key)
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len()).step_by(2)
+                .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+                .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync;
+
+    #[test]
+    fn handshake() {
+        let mut pipe = Pipe::new().unwrap();
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+    }
+
+    #[test]
+    fn stream_data() {
+        let mut pipe = Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        let data = pipe.server.stream_recv(4, 10).unwrap();
+        assert_eq!(&data[..], b"hello");
+        assert!(data.fin());
+    }
+
+    fn transfer(model: &NetworkModel) -> bool {
+        let mut pipe = Pipe::new().unwrap();
+
+        let mut sent = false;
+        let mut received = Vec::new();
+
+        pipe.simulate(model, time::Duration::from_secs(60), |pipe| {
+            if pipe.client.is_established() && !sent {
+                pipe.client.stream_send(4, &[0xaa; 10000], true).unwrap();
+                sent = true;
+            }
+
+            while let Ok(data) = pipe.server.stream_recv(4, 65535) {
+                received.extend_from_slice(&data);
+
+                if data.fin() {
+                    return true;
+                }
+            }
+
+            false
+        }).unwrap() && received == vec![0xaa; 10000]
+    }
+
+    #[test]
+    fn simulate_clean_path() {
+        let model = NetworkModel {
+            bandwidth: Some(1_000_000),
+            .. Default::default()
+        };
+
+        assert!(transfer(&model));
+    }
+
+    #[test]
+    fn simulate_adverse_path() {
+        let model = NetworkModel {
+            delay: time::Duration::from_millis(20),
+            jitter: time::Duration::from_millis(5),
+            loss: 0.1,
+            duplicate: 0.05,
+            reorder: 0.1,
+            reorder_window: 3,
+            bandwidth: Some(500_000),
+            seed: 42,
+        };
+
+        assert!(transfer(&model));
+    }
+
+    #[test]
+    fn rng_is_deterministic() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+
+        let f = a.next_f64();
+        assert!(f >= 0.0 && f < 1.0);
+    }
+
+    #[test]
+    fn reordered_flight() {
+        let mut pipe = Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        for i in 0..4 {
+            assert_eq!(pipe.client.stream_send(4, &[0xaa; 1000], i == 3),
+                       Ok(1000));
+        }
+
+        assert_eq!(pipe.advance_with(|flight| flight.reverse()), Ok(()));
+
+        let data = pipe.server.stream_recv(4, 10000).unwrap();
+        assert_eq!(data.len(), 4000);
+        assert!(data.fin());
+    }
+
+    struct SharedBuf(sync::Arc<sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decrypt_captured() {
+        let mut config = default_config().unwrap();
+        config.log_keys();
+
+        let mut pipe = Pipe::with_config(&mut config).unwrap();
+
+        let keylog = sync::Arc::new(sync::Mutex::new(Vec::new()));
+        pipe.client.set_keylog(Box::new(SharedBuf(keylog.clone())));
+
+        let odcid = pipe.client.destination_id().to_vec();
+
+        let mut captured = Vec::new();
+
+        while !pipe.client.is_established() || !pipe.server.is_established() {
+            let flight = emit_flight(&mut pipe.client).unwrap();
+            captured.extend(flight.iter().map(|d| (false, d.clone())));
+            process_flight(&mut pipe.server, flight).unwrap();
+
+            let flight = emit_flight(&mut pipe.server).unwrap();
+            captured.extend(flight.iter().map(|d| (true, d.clone())));
+            process_flight(&mut pipe.client, flight).unwrap();
+        }
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+
+        let flight = emit_flight(&mut pipe.client).unwrap();
+        captured.extend(flight.iter().map(|d| (false, d.clone())));
+
+        let keylog = String::from_utf8(keylog.lock().unwrap().clone()).unwrap();
+
+        let cid_len = pipe.client.source_id().len();
+
+        let mut decryptor = Decryptor::new(&odcid, cid_len).unwrap();
+        assert_eq!(decryptor.add_keylog(&keylog,
+                                        pipe.client.cipher_suite().unwrap()),
+                   Ok(()));
+
+        let mut pkts = Vec::new();
+
+        for (from_server, d) in captured {
+            pkts.extend(decryptor.decrypt(&d, from_server).unwrap());
+        }
+
+        assert_eq!(pkts[0].ty, Type::Initial);
+        assert_eq!(pkts[0].pkt_num, 0);
+        assert!(pkts[0].frames[0].starts_with("CRYPTO"));
+
+        assert!(pkts.iter().any(|p| p.ty == Type::Handshake));
+
+        assert!(pkts.iter().any(|p| {
+            p.ty == Type::Application &&
+                p.frames.iter().any(|f| f.starts_with("STREAM id=4"))
+        }));
+    }
+}
@@ -29,6 +29,8 @@ use std::ffi;
 use std::ptr;
 use std::slice;
 
+use std::sync::Arc;
+
 use std::io::prelude::*;
 
 use libc::c_char;
@@ -38,7 +40,10 @@ use libc::c_void;
 
 use lazy_static;
 
+use crate::AeadProvider;
+use crate::CipherSuite;
 use crate::Connection;
+use crate::PacketKey;
 use crate::TransportParams;
 
 use crate::crypto;
@@ -56,7 +61,6 @@ pub enum Error {
 }
 
 const TLS1_3_VERSION: u16 = 0x0304;
-const TLS_ALERT_ERROR: u16 = 0x100;
 
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
@@ -74,10 +78,30 @@ struct SSL(c_void);
 #[repr(transparent)]
 struct SSL_CIPHER(c_void);
 
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct SSL_SESSION(c_void);
+
 #[allow(non_camel_case_types)]
 #[repr(transparent)]
 struct X509_VERIFY_PARAM(c_void);
 
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct X509(c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct EVP_PKEY(c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct STACK_OF(c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+struct CRYPTO_BUFFER(c_void);
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 struct SSL_QUIC_METHOD {
@@ -142,6 +166,64 @@ impl Context {
         })
     }
 
+    pub fn use_certificate_chain_der(&mut self, der: &[u8]) -> Result<()> {
+        let mut ptr = der.as_ptr();
+        let end = unsafe { ptr.add(der.len()) };
+
+        let mut leaf = true;
+
+        while ptr < end {
+            let left = end as usize - ptr as usize;
+
+            let cert = unsafe {
+                d2i_X509(ptr::null_mut(), &mut ptr, left as libc::c_long)
+            };
+
+            if cert.is_null() {
+                log_ssl_error();
+                return Err(Error::TlsFail);
+            }
+
+            let rc = if leaf {
+                unsafe { SSL_CTX_use_certificate(self.as_ptr(), cert) }
+            } else {
+                unsafe { SSL_CTX_add1_chain_cert(self.as_ptr(), cert) }
+            };
+
+            unsafe { X509_free(cert) };
+
+            map_result(rc)?;
+
+            leaf = false;
+        }
+
+        if leaf {
+            return Err(Error::TlsFail);
+        }
+
+        Ok(())
+    }
+
+    pub fn use_privkey_der(&mut self, der: &[u8]) -> Result<()> {
+        let mut ptr = der.as_ptr();
+
+        let pkey = unsafe {
+            d2i_AutoPrivateKey(ptr::null_mut(), &mut ptr,
+                               der.len() as libc::c_long)
+        };
+
+        if pkey.is_null() {
+            log_ssl_error();
+            return Err(Error::TlsFail);
+        }
+
+        let rc = unsafe { SSL_CTX_use_PrivateKey(self.as_ptr(), pkey) };
+
+        unsafe { EVP_PKEY_free(pkey) };
+
+        map_result(rc)
+    }
+
     pub fn set_verify(&mut self, verify: bool) {
         let mode = if verify {
             0x01 // SSL_VERIFY_PEER
@@ -160,6 +242,46 @@ impl Context {
         }
     }
 
+    pub fn set_ocsp_response(&mut self, v: &[u8]) -> Result<()> {
+        map_result(unsafe {
+            SSL_CTX_set_ocsp_response(self.as_ptr(), v.as_ptr(), v.len())
+        })
+    }
+
+    pub fn set_signed_cert_timestamp_list(&mut self, v: &[u8]) -> Result<()> {
+        map_result(unsafe {
+            SSL_CTX_set_signed_cert_timestamp_list(self.as_ptr(), v.as_ptr(),
+                                                   v.len())
+        })
+    }
+
+    pub fn enable_ocsp_stapling(&mut self) {
+        unsafe {
+            SSL_CTX_enable_ocsp_stapling(self.as_ptr());
+        }
+    }
+
+    pub fn enable_signed_cert_timestamps(&mut self) {
+        unsafe {
+            SSL_CTX_enable_signed_cert_timestamps(self.as_ptr());
+        }
+    }
+
+    pub fn set_curves(&mut self, curves: &str) -> Result<()> {
+        let cstr = ffi::CString::new(curves).map_err(|_| Error::TlsFail)?;
+        map_result(unsafe {
+            SSL_CTX_set1_curves_list(self.as_ptr(), cstr.as_ptr())
+        })
+    }
+
+    pub fn enable_session_cache(&mut self) {
+        unsafe {
+            // SSL_SESS_CACHE_CLIENT
+            SSL_CTX_set_session_cache_mode(self.as_ptr(), 0x0001);
+            SSL_CTX_sess_set_new_cb(self.as_ptr(), new_session);
+        }
+    }
+
     pub fn set_alpn(&mut self, v: &[Vec<u8>]) -> Result<()> {
         let mut protos: Vec<u8> = Vec::new();
 
@@ -217,7 +339,7 @@ impl Handshake {
 
         self.set_quiet_shutdown(true);
 
-        let mut raw_params: [u8; 128] = [0; 128];
+        let mut raw_params: [u8; 512] = [0; 512];
 
         let raw_params = TransportParams::encode(&conn.local_transport_params,
                                                  conn.version, conn.is_server,
@@ -285,6 +407,24 @@ impl Handshake {
         })
     }
 
+    pub fn set_session(&self, session: &[u8]) -> Result<()> {
+        unsafe {
+            let ctx = SSL_get_SSL_CTX(self.as_ptr());
+
+            let session =
+                SSL_SESSION_from_bytes(session.as_ptr(), session.len(), ctx);
+
+            if session.is_null() {
+                return Err(Error::TlsFail);
+            }
+
+            let rc = SSL_set_session(self.as_ptr(), session);
+            SSL_SESSION_free(session);
+
+            map_result(rc)
+        }
+    }
+
     pub fn set_quic_transport_params(&self, buf: &[u8]) -> Result<()> {
         map_result_ssl(self, unsafe {
             SSL_set_quic_transport_params(self.as_ptr(), buf.as_ptr(), buf.len())
@@ -323,12 +463,82 @@ impl Handshake {
         unsafe { slice::from_raw_parts(ptr, len as usize) }
     }
 
+    pub fn get_ocsp_response(&self) -> Option<&[u8]> {
+        let mut ptr: *const u8 = ptr::null();
+        let mut len: usize = 0;
+
+        unsafe {
+            SSL_get0_ocsp_response(self.as_ptr(), &mut ptr, &mut len);
+        }
+
+        if len == 0 {
+            return None;
+        }
+
+        Some(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+
+    pub fn get_signed_cert_timestamp_list(&self) -> Option<&[u8]> {
+        let mut ptr: *const u8 = ptr::null();
+        let mut len: usize = 0;
+
+        unsafe {
+            SSL_get0_signed_cert_timestamp_list(self.as_ptr(), &mut ptr,
+                                                &mut len);
+        }
+
+        if len == 0 {
+            return None;
+        }
+
+        Some(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+
+    pub fn get_peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
+        let cert_chain = unsafe {
+            let chain = SSL_get0_peer_certificates(self.as_ptr());
+            if chain.is_null() {
+                return None;
+            }
+
+            let num = sk_num(chain);
+            if num == 0 {
+                return None;
+            }
+
+            let mut cert_chain = Vec::with_capacity(num);
+
+            for i in 0..num {
+                let buffer = sk_value(chain, i) as *const CRYPTO_BUFFER;
+
+                let out_len = CRYPTO_BUFFER_len(buffer);
+                if out_len == 0 {
+                    return None;
+                }
+
+                let out = CRYPTO_BUFFER_data(buffer);
+
+                cert_chain.push(slice::from_raw_parts(out, out_len));
+            }
+
+            cert_chain
+        };
+
+        Some(cert_chain)
+    }
+
     pub fn provide_data(&self, level: crypto::Level, buf: &[u8]) -> Result<()> {
         map_result_ssl(self, unsafe {
             SSL_provide_quic_data(self.as_ptr(), level, buf.as_ptr(), buf.len())
         })
     }
 
+    pub fn process_post_handshake(&self) -> Result<()> {
+        map_result_ssl(self, unsafe {
+            SSL_process_quic_post_handshake(self.as_ptr())
+        })
+    }
+
     pub fn do_handshake(&self) -> Result<()> {
         map_result_ssl(self, unsafe {
             SSL_do_handshake(self.as_ptr())
@@ -405,6 +615,29 @@ extern fn set_encryption_secrets(ssl: *mut SSL, level: crypto::Level,
 
     trace!("{} tls set encryption secret lvl={:?}", conn.trace_id, level);
 
+    let aead = match get_cipher_from_ptr(ssl) {
+        Ok(v)  => v,
+        Err(_) => return 0,
+    };
+
+    if !conn.is_cipher_allowed(aead) {
+        trace!("{} tls cipher {:?} not allowed", conn.trace_id, aead);
+
+        // Abort the handshake with a handshake_failure alert.
+        conn.error = Some(crate::Error::CryptoFail { alert: 40 }.to_wire());
+
+        return 0;
+    }
+
+    // 1-RTT keys can be provided externally.
+    let provider = match level {
+        crypto::Level::Application => conn.aead_provider.clone(),
+
+        _ => None,
+    };
+
+    let version = conn.version;
+
     let space = match level {
         crypto::Level::Initial     => &mut conn.initial,
         // TODO: implement 0-RTT
@@ -413,11 +646,6 @@ extern fn set_encryption_secrets(ssl: *mut SSL, level: crypto::Level,
         crypto::Level::Application => &mut conn.application,
     };
 
-    let aead = match get_cipher_from_ptr(ssl) {
-        Ok(v)  => v,
-        Err(_) => return 0,
-    };
-
     let key_len = aead.key_len();
     let nonce_len = aead.nonce_len();
 
@@ -427,49 +655,61 @@ extern fn set_encryption_secrets(ssl: *mut SSL, level: crypto::Level,
 
     let secret = unsafe { slice::from_raw_parts(read_secret, secret_len) };
 
-    if crypto::derive_pkt_key(aead, &secret, &mut key).is_err() {
+    if crypto::derive_pkt_key(version, aead, &secret, &mut key).is_err() {
         return 0;
     }
 
-    if crypto::derive_pkt_iv(aead, &secret, &mut iv).is_err() {
+    if crypto::derive_pkt_iv(version, aead, &secret, &mut iv).is_err() {
         return 0;
     }
 
-    if crypto::derive_hdr_key(aead, &secret, &mut pn_key).is_err() {
+    if crypto::derive_hdr_key(version, aead, &secret, &mut pn_key).is_err() {
         return 0;
     }
 
-    let open = match crypto::Open::new(aead, &key, &iv, &pn_key) {
+    let mut open = match crypto::Open::new(aead, &key, &iv, &pn_key) {
         Ok(v)  => v,
         Err(_) => return 0,
     };
 
+    open.set_offload(offload_key(&provider, aead, &key));
+
     space.crypto_open = Some(open);
 
     let secret = unsafe { slice::from_raw_parts(write_secret, secret_len) };
 
-    if crypto::derive_pkt_key(aead, &secret, &mut key).is_err() {
+    if crypto::derive_pkt_key(version, aead, &secret, &mut key).is_err() {
         return 0;
     }
 
-    if crypto::derive_pkt_iv(aead, &secret, &mut iv).is_err() {
+    if crypto::derive_pkt_iv(version, aead, &secret, &mut iv).is_err() {
         return 0;
     }
 
-    if crypto::derive_hdr_key(aead, &secret, &mut pn_key).is_err() {
+    if crypto::derive_hdr_key(version, aead, &secret, &mut pn_key).is_err() {
         return 0;
     }
 
-    let seal = match crypto::Seal::new(aead, &key, &iv, &pn_key) {
+    let mut seal = match crypto::Seal::new(aead, &key, &iv, &pn_key) {
         Ok(v)  => v,
         Err(_) => return 0,
     };
 
+    seal.set_offload(offload_key(&provider, aead, &key));
+
     space.crypto_seal = Some(seal);
 
     1
 }
 
+fn offload_key(provider: &Option<Arc<dyn AeadProvider>>,
+               alg: crypto::Algorithm, key: &[u8])
+                                        -> Option<Box<dyn PacketKey>> {
+    let provider = provider.as_ref()?;
+
+    provider.new_key(CipherSuite::from_algorithm(alg)?, key)
+}
+
 extern fn add_handshake_data(ssl: *mut SSL, level: crypto::Level,
                              data: *const u8, len: usize) -> c_int {
     let conn = match get_ex_data_from_ptr::<Connection>(ssl, *QUICHE_EX_DATA_INDEX) {
@@ -511,22 +751,54 @@ extern fn send_alert(ssl: *mut SSL, level: crypto::Level, alert: u8) -> c_int {
     trace!("{} tls send alert lvl={:?} alert={:x}",
            conn.trace_id, level, alert);
 
-    let error: u16 = TLS_ALERT_ERROR + u16::from(alert);
-    conn.error = Some(error);
+    conn.error = Some(crate::Error::CryptoFail { alert }.to_wire());
 
     1
 }
 
-extern fn keylog(_: *mut SSL, line: *const c_char) {
+extern fn new_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
+    let conn = match get_ex_data_from_ptr::<Connection>(ssl, *QUICHE_EX_DATA_INDEX) {
+        Some(v) => v,
+        None    => return 0,
+    };
+
+    let mut out: *mut u8 = ptr::null_mut();
+    let mut out_len: usize = 0;
+
+    if unsafe { SSL_SESSION_to_bytes(session, &mut out, &mut out_len) } != 1 {
+        return 0;
+    }
+
+    trace!("{} tls new session len={}", conn.trace_id, out_len);
+
+    conn.store_session(unsafe { slice::from_raw_parts(out, out_len) });
+
+    unsafe { OPENSSL_free(out as *mut c_void) };
+
+    // We don't take ownership of the session.
+    0
+}
+
+extern fn keylog(ssl: *mut SSL, line: *const c_char) {
+    let data = unsafe {
+        ffi::CStr::from_ptr(line).to_bytes()
+    };
+
+    let conn = get_ex_data_from_ptr::<Connection>(ssl, *QUICHE_EX_DATA_INDEX);
+
+    if let Some(keylog) = conn.and_then(|c| c.keylog.as_mut()) {
+        keylog.write_all(b"QUIC_").unwrap_or(());
+        keylog.write_all(data).unwrap_or(());
+        keylog.write_all(b"\n").unwrap_or(());
+
+        return;
+    }
+
     if let Some(path) = std::env::var_os("SSLKEYLOGFILE") {
         let file = std::fs::OpenOptions::new().create(true)
                                               .append(true)
                                               .open(path);
         if let Ok(mut file) = file {
-            let data = unsafe {
-                ffi::CStr::from_ptr(line).to_bytes()
-            };
-
             file.write_all(b"QUIC_").unwrap_or(());
             file.write_all(data).unwrap_or(());
             file.write_all(b"\n").unwrap_or(());
@@ -548,7 +820,7 @@ extern fn select_alpn(ssl: *mut SSL, out: *mut *const u8, out_len: *mut u8,
         return 3; // SSL_TLSEXT_ERR_NOACK
     }
 
-    let mut protos = octets::Octets::with_slice(unsafe {
+    let mut protos = octets::OctetsMut::with_slice(unsafe {
         slice::from_raw_parts_mut(inp, in_len as usize)
     });
 
@@ -662,6 +934,12 @@ extern {
     fn SSL_CTX_use_PrivateKey_file(ctx: *mut SSL_CTX,
         file: *const c_char, ty: c_int) -> c_int;
 
+    fn SSL_CTX_use_certificate(ctx: *mut SSL_CTX, x509: *mut X509) -> c_int;
+
+    fn SSL_CTX_add1_chain_cert(ctx: *mut SSL_CTX, x509: *mut X509) -> c_int;
+
+    fn SSL_CTX_use_PrivateKey(ctx: *mut SSL_CTX, pkey: *mut EVP_PKEY) -> c_int;
+
     fn SSL_CTX_set_default_verify_paths(ctx: *mut SSL_CTX) -> c_int;
 
     fn SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int, cb: *const c_void);
@@ -669,6 +947,25 @@ extern {
     fn SSL_CTX_set_keylog_callback(ctx: *mut SSL_CTX,
         cb: extern fn(ssl: *mut SSL, line: *const c_char));
 
+    fn SSL_CTX_set_ocsp_response(ctx: *mut SSL_CTX, response: *const u8,
+                                 response_len: usize) -> c_int;
+
+    fn SSL_CTX_set_signed_cert_timestamp_list(ctx: *mut SSL_CTX,
+                                              list: *const u8,
+                                              list_len: usize) -> c_int;
+
+    fn SSL_CTX_enable_ocsp_stapling(ctx: *mut SSL_CTX);
+
+    fn SSL_CTX_enable_signed_cert_timestamps(ctx: *mut SSL_CTX);
+
+    fn SSL_CTX_set1_curves_list(ctx: *mut SSL_CTX, curves: *const c_char)
+                                                                    -> c_int;
+
+    fn SSL_CTX_set_session_cache_mode(ctx: *mut SSL_CTX, mode: c_int) -> c_int;
+
+    fn SSL_CTX_sess_set_new_cb(ctx: *mut SSL_CTX,
+        cb: extern fn(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int);
+
     fn SSL_CTX_set_alpn_protos(ctx: *mut SSL_CTX, protos: *const u8,
                                protos_len: usize) -> c_int;
 
@@ -684,6 +981,8 @@ extern {
 
     fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
 
+    fn SSL_get_SSL_CTX(ssl: *mut SSL) -> *mut SSL_CTX;
+
     fn SSL_get_error(ssl: *mut SSL, ret_code: c_int) -> c_int;
 
     fn SSL_set_accept_state(ssl: *mut SSL);
@@ -715,11 +1014,23 @@ extern {
     fn SSL_get0_alpn_selected(ssl: *mut SSL,
         out: *mut *const u8, out_len: *mut u32);
 
+    fn SSL_get0_ocsp_response(ssl: *mut SSL, out: *mut *const u8,
+                              out_len: *mut usize);
+
+    fn SSL_get0_signed_cert_timestamp_list(ssl: *mut SSL, out: *mut *const u8,
+                                           out_len: *mut usize);
+
+    fn SSL_get0_peer_certificates(ssl: *mut SSL) -> *const STACK_OF;
+
     fn SSL_provide_quic_data(ssl: *mut SSL, level: crypto::Level,
         data: *const u8, len: usize) -> c_int;
 
     fn SSL_do_handshake(ssl: *mut SSL) -> c_int;
 
+    fn SSL_process_quic_post_handshake(ssl: *mut SSL) -> c_int;
+
+    fn SSL_set_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int;
+
     fn SSL_quic_write_level(ssl: *mut SSL) -> crypto::Level;
 
     fn SSL_session_reused(ssl: *mut SSL) -> c_int;
@@ -729,13 +1040,46 @@ extern {
     fn SSL_free(ssl: *mut SSL);
 
     // SSL_CIPHER
+    // SSL_SESSION
+    fn SSL_SESSION_from_bytes(inp: *const u8, in_len: usize,
+                              ctx: *const SSL_CTX) -> *mut SSL_SESSION;
+
+    fn SSL_SESSION_to_bytes(session: *const SSL_SESSION, out: *mut *mut u8,
+                            out_len: *mut usize) -> c_int;
+
+    fn SSL_SESSION_free(session: *mut SSL_SESSION);
+
     fn SSL_CIPHER_get_id(cipher: *const SSL_CIPHER) -> c_uint;
 
+    // STACK_OF
+    fn sk_num(stack: *const STACK_OF) -> usize;
+
+    fn sk_value(stack: *const STACK_OF, idx: usize) -> *mut c_void;
+
+    // CRYPTO_BUFFER
+    fn CRYPTO_BUFFER_len(buffer: *const CRYPTO_BUFFER) -> usize;
+
+    fn CRYPTO_BUFFER_data(buffer: *const CRYPTO_BUFFER) -> *const u8;
+
+    // X509
+    fn d2i_X509(out: *mut *mut X509, inp: *mut *const u8, len: libc::c_long)
+                                                                -> *mut X509;
+
+    fn X509_free(x509: *mut X509);
+
+    // EVP_PKEY
+    fn d2i_AutoPrivateKey(out: *mut *mut EVP_PKEY, inp: *mut *const u8,
+                          len: libc::c_long) -> *mut EVP_PKEY;
+
+    fn EVP_PKEY_free(pkey: *mut EVP_PKEY);
+
     // X509_VERIFY_PARAM
     fn X509_VERIFY_PARAM_set1_host(param: *mut X509_VERIFY_PARAM,
         name: *const c_char, namelen: libc::size_t) -> c_int;
 
     // ERR
+    fn OPENSSL_free(ptr: *mut c_void);
+
     fn ERR_peek_error() -> c_uint;
 
     fn ERR_error_string_n(err: c_uint, buf: *const u8, len: usize);